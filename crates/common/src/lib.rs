@@ -1,25 +1,75 @@
 pub mod nats;
 pub mod telemetry;
 
-use pulse_types::Region;
+use pulse_types::{ControlC2S, ControlS2C, Region};
 use redis::{FromRedisValue, ToRedisArgs, ToSingleRedisArg};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct SessionData {
     pub session_id: String,
+    #[serde(default)]
+    pub user_id: String,
     pub call_id: String,
     pub assigned_server: String,
     pub can_listen: bool,
     pub can_speak: bool,
     pub can_video: bool,
     pub can_screen: bool,
+    /// Set when the session connects through a node other than the call's
+    /// own, which relays it to this one.
+    #[serde(default)]
+    pub home: Option<HomeNode>,
+}
+
+/// The node a call is hosted on, which coordinates its MLS group.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HomeNode {
+    pub id: String,
+    pub server_address: String,
+}
+
+/// What a session may send and receive on its node.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct SessionGrants {
+    pub can_listen: bool,
+    pub can_speak: bool,
+    pub can_video: bool,
+    pub can_screen: bool,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct NodeDescription {
     pub region: Region,
     pub server_address: String,
+    #[serde(default)]
+    pub draining: bool,
+}
+
+/// Load figures a node reports with every ping.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct NodeLoad {
+    pub sessions: u32,
+    pub calls: u32,
+    /// Outbound bandwidth in bits per second
+    pub egress_bps: u64,
+    /// CPU utilisation across all cores, from 0.0 to 1.0
+    pub cpu: f32,
+}
+
+/// A call a node is hosting, as reported in its inventory.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CallInventory {
+    pub call_id: String,
+    pub sessions: Vec<SessionInventory>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SessionInventory {
+    pub id: String,
+    pub user_id: String,
+    pub grants: SessionGrants,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -32,9 +82,17 @@ pub struct NodeEvent {
 #[serde(tag = "type")]
 pub enum NodeEventKind {
     Description(NodeDescription), // when a node becomes available
-    Ping,                         // periodic ping from node
+    Ping {
+        #[serde(default)]
+        load: NodeLoad,
+        #[serde(default)]
+        draining: bool,
+    }, // periodic ping from node, a draining node accepts no new calls
     Disconnect,                   // when a node goes offline
     Query,                        // when the main server requests all available nodes
+    Inventory {
+        calls: Vec<CallInventory>,
+    }, // periodic list of the calls and sessions a node is hosting, so the main server can reconcile
     UserConnect {
         // IMPORTANT: this is the session id, not the user id
         // one user may connect several times to one call
@@ -50,14 +108,46 @@ pub enum NodeEventKind {
         muted: bool,
         deafened: bool,
     }, // The main server handles state and notifies the node of a user mute/deafen state change
+    UserGrantsChange {
+        id: String,
+        grants: SessionGrants,
+    }, // The main server notifies the node that a channel manager restricted (or unrestricted) a session
     UserMoved {
         id: String,
         target_server: String,
         target_token: String,
     }, // The main server notifies the node that a user has moved regions
+    SessionMoved {
+        id: String,
+        call_id: String,
+        target_token: String,
+        // where the other call is hosted, empty for the same node
+        #[serde(default)]
+        target_server: String,
+    }, // The main server notifies the node that a session moved to another call
     CallEnded {
         call_id: String,
     }, // The main server notifies the node that a call has ended, disconnecting all users in that call
+    RelayJoin {
+        // the connection on the relaying node, which the call's node uses as the session's unique id
+        connection: String,
+        token: String,
+        session: SessionData,
+        key_package: Vec<u8>,
+        #[serde(default)]
+        media_header_version: u8,
+    }, // A relaying node forwards a session joining to the call's node
+    RelayC2S {
+        connection: String,
+        message: ControlC2S,
+    }, // A relaying node forwards a control message from a session to the call's node
+    RelayS2C {
+        connection: String,
+        message: ControlS2C,
+    }, // The call's node sends a control message to a session on a relaying node
+    RelayClose {
+        connection: String,
+    }, // Either side closes a relayed session
 }
 
 impl ToSingleRedisArg for SessionData {}
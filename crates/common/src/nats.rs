@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
 use async_nats::jetstream::{self, stream};
 use serde::{Deserialize, Serialize};
@@ -24,6 +24,10 @@ pub struct EventEnvelope {
     pub id: String,
     pub recipients: Vec<String>,
     pub payload: Vec<u8>,
+    /// The sequence number of the event for each recipient, if it was
+    /// recorded for replay.
+    #[serde(default)]
+    pub sequences: HashMap<String, u64>,
 }
 
 pub async fn connect() -> async_nats::Client {
@@ -1,8 +1,20 @@
+use harmony_types::attachments::{
+    Attachment, CreateAttachmentMethod, CreateAttachmentResponse, DeleteAttachmentMethod,
+    DeleteAttachmentResponse, DownloadChunkMethod, DownloadChunkResponse, FinishAttachmentMethod,
+    FinishAttachmentResponse, UploadChunkMethod, UploadChunkResponse,
+};
 use harmony_types::channels::{
-    ChannelInformation, CreateChannelMethod, CreateChannelResponse, DeleteChannelMethod,
-    DeleteChannelResponse, EditChannelMethod, EditChannelResponse, GetChannelMethod,
-    GetChannelResponse, GetChannelsMethod, GetChannelsResponse, LeaveChannelMethod,
-    LeaveChannelResponse,
+    ApproveMemberMethod, ApproveMemberResponse, BanMemberMethod, BanMemberResponse,
+    ChannelInformation, ChannelRole, CreateChannelMethod, CreateChannelResponse, CreateRoleMethod,
+    CreateRoleResponse, DeleteChannelMethod, DeleteChannelResponse, DeleteRoleMethod,
+    DeleteRoleResponse, EditChannelMethod, EditChannelResponse, EditRoleMethod, EditRoleResponse,
+    GetChannelMethod, GetChannelResponse, GetChannelsMethod, GetChannelsResponse,
+    GetGroupKeysMethod, GetGroupKeysResponse, GroupKeyShare, KickMemberMethod, KickMemberResponse,
+    LeaveChannelMethod, LeaveChannelResponse, RejectMemberMethod, RejectMemberResponse,
+    RotateGroupKeyMethod, RotateGroupKeyResponse, SetDefaultPermissionsMethod,
+    SetDefaultPermissionsResponse, SetMemberRolesMethod, SetMemberRolesResponse,
+    ShareGroupKeyMethod, ShareGroupKeyResponse, UnbanMemberMethod, UnbanMemberResponse,
+    WrappedGroupKey,
 };
 use harmony_types::invites::{
     AcceptInviteMethod, AcceptInviteResponse, CreateInviteMethod, CreateInviteResponse,
@@ -10,25 +22,38 @@ use harmony_types::invites::{
     GetInvitesResponse,
 };
 use harmony_types::messages::{
-    DeleteMessageMethod, DeleteMessageResponse, EditMessageMethod, EditMessageResponse,
-    GetMessagesMethod, GetMessagesResponse, SendMessageMethod, SendMessageResponse,
+    AddReactionMethod, AddReactionResponse, DeleteMessageMethod, DeleteMessageResponse,
+    EditMessageMethod, EditMessageResponse, GetMessagesMethod, GetMessagesResponse,
+    GetThreadMethod, GetThreadResponse, MarkReadMethod, MarkReadResponse, ReadState,
+    RemoveReactionMethod, RemoveReactionResponse, SendMessageMethod, SendMessageResponse,
+    SetTypingMethod, SetTypingResponse,
+};
+use harmony_types::mls::{
+    AckPendingMethod, AckPendingResponse, FetchPendingMethod, FetchPendingResponse, PendingMessage,
+    PendingMessageKind, SendHandshakeMethod, SendHandshakeResponse,
 };
+use harmony_types::permissions::PermissionSet;
 use harmony_types::users::{
     AddContactMethod, AddContactResponse, AddContactStage, BlockContactMethod,
     BlockContactResponse, ContactExtended, CurrentUserResponse, GetContactsMethod,
     GetContactsResponse, GetCurrentUserMethod, GetUserMethod, GetUserResponse, GetUsersMethod,
-    GetUsersResponse, RemoveContactMethod, RemoveContactResponse, SetKeyPackageMethod,
-    SetKeyPackageResponse, UnblockContactMethod, UnblockContactResponse,
+    GetUsersResponse, Presence, RemoveContactMethod, RemoveContactResponse, SetKeyPackageMethod,
+    SetKeyPackageResponse, SetPresenceMethod, SetPresenceResponse, Status, UnblockContactMethod,
+    UnblockContactResponse,
 };
 use harmony_types::voice::{
-    CreateCallTokenMethod, CreateCallTokenResponse, EndCallMethod, EndCallResponse,
-    GetCallMembersMethod, GetCallMembersResponse, StartCallMethod, StartCallResponse,
-    UpdateVoiceStateMethod, UpdateVoiceStateResponse,
+    CallRecord, CallRoom, CreateCallTokenMethod, CreateCallTokenResponse, DeclineCallMethod,
+    DeclineCallResponse, DisconnectCallSessionMethod, DisconnectCallSessionResponse, EndCallMethod,
+    EndCallResponse, GetCallHistoryMethod, GetCallHistoryResponse, GetCallMembersMethod,
+    GetCallMembersResponse, GetCallsMethod, GetCallsResponse, MoveSessionMethod,
+    MoveSessionResponse, RestrictCallSessionMethod, RestrictCallSessionResponse,
+    SessionRestrictions, StartCallMethod, StartCallResponse, UpdateVoiceStateMethod,
+    UpdateVoiceStateResponse,
 };
 use pulse_types::Region;
 
 use crate::error::Result;
-use crate::{ChannelData, EncryptionHint, HarmonyClient, Message};
+use crate::{ChannelData, EncryptionHint, HarmonyClient, Message, Reaction};
 
 impl HarmonyClient {
     /// Get a specific channel by ID
@@ -46,11 +71,9 @@ impl HarmonyClient {
     }
 
     /// Get all channels the user has access to
-    pub async fn get_channels(&self) -> Result<Vec<ChannelData>> {
-        let response: GetChannelsResponse = self
-            .send_request("GET_CHANNELS", GetChannelsMethod {})
-            .await?;
-        Ok(response.channels)
+    pub async fn get_channels(&self) -> Result<GetChannelsResponse> {
+        self.send_request("GET_CHANNELS", GetChannelsMethod {})
+            .await
     }
 
     /// Get messages from a channel
@@ -80,12 +103,49 @@ impl HarmonyClient {
 
     /// Send a message to a channel
     pub async fn send_message(&self, channel_id: &str, content: Vec<u8>) -> Result<Message> {
+        self.send_message_with_attachments(channel_id, content, Vec::new())
+            .await
+    }
+
+    /// Send a message with uploaded attachments. They are deleted along with
+    /// the message.
+    pub async fn send_message_with_attachments(
+        &self,
+        channel_id: &str,
+        content: Vec<u8>,
+        attachment_ids: Vec<String>,
+    ) -> Result<Message> {
+        let response: SendMessageResponse = self
+            .send_request(
+                "SEND_MESSAGE",
+                SendMessageMethod {
+                    channel_id: channel_id.to_string(),
+                    content,
+                    attachment_ids,
+                    parent_id: None,
+                },
+            )
+            .await?;
+
+        Ok(response.message)
+    }
+
+    /// Reply in the thread started by `parent_id`
+    pub async fn send_thread_reply(
+        &self,
+        channel_id: &str,
+        parent_id: &str,
+        content: Vec<u8>,
+        attachment_ids: Vec<String>,
+    ) -> Result<Message> {
         let response: SendMessageResponse = self
             .send_request(
                 "SEND_MESSAGE",
                 SendMessageMethod {
                     channel_id: channel_id.to_string(),
                     content,
+                    attachment_ids,
+                    parent_id: Some(parent_id.to_string()),
                 },
             )
             .await?;
@@ -93,6 +153,144 @@ impl HarmonyClient {
         Ok(response.message)
     }
 
+    /// Get replies in the thread started by a message
+    pub async fn get_thread(
+        &self,
+        message_id: &str,
+        limit: Option<i64>,
+        latest: Option<bool>,
+        before: Option<String>,
+        after: Option<String>,
+    ) -> Result<Vec<Message>> {
+        let response: GetThreadResponse = self
+            .send_request(
+                "GET_THREAD",
+                GetThreadMethod {
+                    message_id: message_id.to_string(),
+                    limit,
+                    latest,
+                    before,
+                    after,
+                },
+            )
+            .await?;
+
+        Ok(response.messages)
+    }
+
+    /// Reserve space for an encrypted file of `size` bytes split into
+    /// `chunk_count` chunks
+    pub async fn create_attachment(
+        &self,
+        channel_id: &str,
+        size: u64,
+        chunk_count: u32,
+        expires_in: Option<u64>,
+    ) -> Result<Attachment> {
+        let response: CreateAttachmentResponse = self
+            .send_request(
+                "CREATE_ATTACHMENT",
+                CreateAttachmentMethod {
+                    channel_id: channel_id.to_string(),
+                    size,
+                    chunk_count,
+                    expires_in,
+                },
+            )
+            .await?;
+
+        Ok(response.attachment)
+    }
+
+    /// Upload one encrypted chunk of an attachment
+    pub async fn upload_chunk(&self, attachment_id: &str, index: u32, data: Vec<u8>) -> Result<()> {
+        let _: UploadChunkResponse = self
+            .send_request(
+                "UPLOAD_CHUNK",
+                UploadChunkMethod {
+                    attachment_id: attachment_id.to_string(),
+                    index,
+                    data,
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Check that every chunk of an attachment was uploaded
+    pub async fn finish_attachment(&self, attachment_id: &str) -> Result<Attachment> {
+        let response: FinishAttachmentResponse = self
+            .send_request(
+                "FINISH_ATTACHMENT",
+                FinishAttachmentMethod {
+                    attachment_id: attachment_id.to_string(),
+                },
+            )
+            .await?;
+
+        Ok(response.attachment)
+    }
+
+    /// Download one encrypted chunk of an attachment
+    pub async fn download_chunk(&self, attachment_id: &str, index: u32) -> Result<Vec<u8>> {
+        let response: DownloadChunkResponse = self
+            .send_request(
+                "DOWNLOAD_CHUNK",
+                DownloadChunkMethod {
+                    attachment_id: attachment_id.to_string(),
+                    index,
+                },
+            )
+            .await?;
+
+        Ok(response.data)
+    }
+
+    /// Delete an attachment we uploaded
+    pub async fn delete_attachment(&self, attachment_id: &str) -> Result<()> {
+        let _: DeleteAttachmentResponse = self
+            .send_request(
+                "DELETE_ATTACHMENT",
+                DeleteAttachmentMethod {
+                    attachment_id: attachment_id.to_string(),
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Tell the other members that we are typing in a channel. The server
+    /// drops calls made within a few seconds of the previous one.
+    pub async fn set_typing(&self, channel_id: &str) -> Result<()> {
+        let _: SetTypingResponse = self
+            .send_request(
+                "SET_TYPING",
+                SetTypingMethod {
+                    channel_id: channel_id.to_string(),
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Mark every message up to `message_id` in a channel as read
+    pub async fn mark_read(&self, channel_id: &str, message_id: &str) -> Result<ReadState> {
+        let response: MarkReadResponse = self
+            .send_request(
+                "MARK_READ",
+                MarkReadMethod {
+                    channel_id: channel_id.to_string(),
+                    message_id: message_id.to_string(),
+                },
+            )
+            .await?;
+
+        Ok(response.read_state)
+    }
+
     /// Create an invite for a channel
     pub async fn create_invite(
         &self,
@@ -157,6 +355,7 @@ impl HarmonyClient {
         &self,
         channel_id: &str,
         preferred_region: Option<Region>,
+        name: Option<String>,
     ) -> Result<StartCallResponse> {
         let response: StartCallResponse = self
             .send_request(
@@ -164,6 +363,7 @@ impl HarmonyClient {
                 StartCallMethod {
                     id: channel_id.to_string(),
                     preferred_region,
+                    name,
                 },
             )
             .await?;
@@ -175,16 +375,20 @@ impl HarmonyClient {
     pub async fn create_call_token(
         &self,
         channel_id: &str,
+        call_id: Option<&str>,
         initial_muted: bool,
         initial_deafened: bool,
+        preferred_region: Option<Region>,
     ) -> Result<CreateCallTokenResponse> {
         let response: CreateCallTokenResponse = self
             .send_request(
                 "CREATE_CALL_TOKEN",
                 CreateCallTokenMethod {
                     id: channel_id.to_string(),
+                    call_id: call_id.map(str::to_string),
                     initial_muted,
                     initial_deafened,
+                    preferred_region,
                 },
             )
             .await?;
@@ -193,12 +397,13 @@ impl HarmonyClient {
     }
 
     /// End a call in a channel (requires manager permission)
-    pub async fn end_call(&self, channel_id: &str) -> Result<()> {
+    pub async fn end_call(&self, channel_id: &str, call_id: Option<&str>) -> Result<()> {
         let _: EndCallResponse = self
             .send_request(
                 "END_CALL",
                 EndCallMethod {
                     id: channel_id.to_string(),
+                    call_id: call_id.map(str::to_string),
                 },
             )
             .await?;
@@ -210,6 +415,7 @@ impl HarmonyClient {
     pub async fn update_voice_state(
         &self,
         channel_id: &str,
+        call_id: Option<&str>,
         muted: Option<bool>,
         deafened: Option<bool>,
     ) -> Result<UpdateVoiceStateResponse> {
@@ -218,6 +424,7 @@ impl HarmonyClient {
                 "UPDATE_VOICE_STATE",
                 UpdateVoiceStateMethod {
                     id: channel_id.to_string(),
+                    call_id: call_id.map(str::to_string),
                     muted,
                     deafened,
                 },
@@ -228,12 +435,17 @@ impl HarmonyClient {
     }
 
     /// Get all members currently in a call
-    pub async fn get_call_members(&self, channel_id: &str) -> Result<Vec<crate::CallMember>> {
+    pub async fn get_call_members(
+        &self,
+        channel_id: &str,
+        call_id: Option<&str>,
+    ) -> Result<Vec<crate::CallMember>> {
         let response: GetCallMembersResponse = self
             .send_request(
                 "GET_CALL_MEMBERS",
                 GetCallMembersMethod {
                     id: channel_id.to_string(),
+                    call_id: call_id.map(str::to_string),
                 },
             )
             .await?;
@@ -241,6 +453,115 @@ impl HarmonyClient {
         Ok(response.members)
     }
 
+    /// Get every call going on in a channel
+    pub async fn get_calls(&self, channel_id: &str) -> Result<Vec<CallRoom>> {
+        let response: GetCallsResponse = self
+            .send_request(
+                "GET_CALLS",
+                GetCallsMethod {
+                    id: channel_id.to_string(),
+                },
+            )
+            .await?;
+
+        Ok(response.calls)
+    }
+
+    /// Move one of our sessions to another call in the same channel. Pulse
+    /// reconnects the session to the new call.
+    pub async fn move_session(
+        &self,
+        channel_id: &str,
+        session_id: &str,
+        call_id: &str,
+    ) -> Result<()> {
+        let _: MoveSessionResponse = self
+            .send_request(
+                "MOVE_SESSION",
+                MoveSessionMethod {
+                    id: channel_id.to_string(),
+                    session_id: session_id.to_string(),
+                    call_id: call_id.to_string(),
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Decline a call that is ringing in a channel
+    pub async fn decline_call(&self, channel_id: &str) -> Result<()> {
+        let _: DeclineCallResponse = self
+            .send_request(
+                "DECLINE_CALL",
+                DeclineCallMethod {
+                    id: channel_id.to_string(),
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get past calls in a channel, newest first
+    pub async fn get_call_history(
+        &self,
+        channel_id: &str,
+        limit: Option<i64>,
+        before: Option<String>,
+    ) -> Result<Vec<CallRecord>> {
+        let response: GetCallHistoryResponse = self
+            .send_request(
+                "GET_CALL_HISTORY",
+                GetCallHistoryMethod {
+                    id: channel_id.to_string(),
+                    limit,
+                    before,
+                },
+            )
+            .await?;
+
+        Ok(response.calls)
+    }
+
+    /// Restrict what the user behind a session in a call may do (requires
+    /// manager permission). Replaces any earlier restrictions on them and
+    /// holds for every session they have or open in the call.
+    pub async fn restrict_call_session(
+        &self,
+        channel_id: &str,
+        session_id: &str,
+        restrictions: SessionRestrictions,
+    ) -> Result<crate::CallMember> {
+        let response: RestrictCallSessionResponse = self
+            .send_request(
+                "RESTRICT_CALL_SESSION",
+                RestrictCallSessionMethod {
+                    id: channel_id.to_string(),
+                    session_id: session_id.to_string(),
+                    restrictions,
+                },
+            )
+            .await?;
+
+        Ok(response.member)
+    }
+
+    /// Disconnect one session from a call (requires manager permission)
+    pub async fn disconnect_call_session(&self, channel_id: &str, session_id: &str) -> Result<()> {
+        let _: DisconnectCallSessionResponse = self
+            .send_request(
+                "DISCONNECT_CALL_SESSION",
+                DisconnectCallSessionMethod {
+                    id: channel_id.to_string(),
+                    session_id: session_id.to_string(),
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
     /// Create a new private channel with another user
     pub async fn create_private_channel(&self, target_id: &str) -> Result<ChannelData> {
         let response: CreateChannelResponse = self
@@ -321,6 +642,235 @@ impl HarmonyClient {
         Ok(())
     }
 
+    pub async fn kick_member(&self, channel_id: &str, user_id: &str) -> Result<()> {
+        let _: KickMemberResponse = self
+            .send_request(
+                "KICK_MEMBER",
+                KickMemberMethod {
+                    channel_id: channel_id.to_string(),
+                    user_id: user_id.to_string(),
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn ban_member(&self, channel_id: &str, user_id: &str) -> Result<()> {
+        let _: BanMemberResponse = self
+            .send_request(
+                "BAN_MEMBER",
+                BanMemberMethod {
+                    channel_id: channel_id.to_string(),
+                    user_id: user_id.to_string(),
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn unban_member(&self, channel_id: &str, user_id: &str) -> Result<()> {
+        let _: UnbanMemberResponse = self
+            .send_request(
+                "UNBAN_MEMBER",
+                UnbanMemberMethod {
+                    channel_id: channel_id.to_string(),
+                    user_id: user_id.to_string(),
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn approve_member(&self, channel_id: &str, user_id: &str) -> Result<()> {
+        let _: ApproveMemberResponse = self
+            .send_request(
+                "APPROVE_MEMBER",
+                ApproveMemberMethod {
+                    channel_id: channel_id.to_string(),
+                    user_id: user_id.to_string(),
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn reject_member(&self, channel_id: &str, user_id: &str) -> Result<()> {
+        let _: RejectMemberResponse = self
+            .send_request(
+                "REJECT_MEMBER",
+                RejectMemberMethod {
+                    channel_id: channel_id.to_string(),
+                    user_id: user_id.to_string(),
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Share the current group key with members or invited contacts
+    pub async fn share_group_key(
+        &self,
+        channel_id: &str,
+        key_id: Option<String>,
+        shares: Vec<GroupKeyShare>,
+    ) -> Result<()> {
+        let _: ShareGroupKeyResponse = self
+            .send_request(
+                "SHARE_GROUP_KEY",
+                ShareGroupKeyMethod {
+                    channel_id: channel_id.to_string(),
+                    key_id,
+                    shares,
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get every group key share addressed to us in a channel
+    pub async fn get_group_keys(&self, channel_id: &str) -> Result<Vec<WrappedGroupKey>> {
+        let response: GetGroupKeysResponse = self
+            .send_request(
+                "GET_GROUP_KEYS",
+                GetGroupKeysMethod {
+                    channel_id: channel_id.to_string(),
+                },
+            )
+            .await?;
+
+        Ok(response.keys)
+    }
+
+    /// Replace the group key of a channel (managers only)
+    pub async fn rotate_group_key(
+        &self,
+        channel_id: &str,
+        previous_key_id: Option<String>,
+        key_id: String,
+        metadata: Vec<u8>,
+        shares: Vec<GroupKeyShare>,
+    ) -> Result<ChannelData> {
+        let response: RotateGroupKeyResponse = self
+            .send_request(
+                "ROTATE_GROUP_KEY",
+                RotateGroupKeyMethod {
+                    channel_id: channel_id.to_string(),
+                    previous_key_id,
+                    key_id,
+                    metadata,
+                    shares,
+                },
+            )
+            .await?;
+
+        Ok(response.channel)
+    }
+
+    /// Create a role in a group channel
+    pub async fn create_role(
+        &self,
+        channel_id: &str,
+        name: &str,
+        permissions: PermissionSet,
+    ) -> Result<ChannelRole> {
+        let response: CreateRoleResponse = self
+            .send_request(
+                "CREATE_ROLE",
+                CreateRoleMethod {
+                    channel_id: channel_id.to_string(),
+                    name: name.to_string(),
+                    permissions,
+                },
+            )
+            .await?;
+
+        Ok(response.role)
+    }
+
+    /// Rename a role or change its permissions
+    pub async fn edit_role(
+        &self,
+        channel_id: &str,
+        role_id: &str,
+        name: Option<String>,
+        permissions: Option<PermissionSet>,
+    ) -> Result<ChannelRole> {
+        let response: EditRoleResponse = self
+            .send_request(
+                "EDIT_ROLE",
+                EditRoleMethod {
+                    channel_id: channel_id.to_string(),
+                    role_id: role_id.to_string(),
+                    name,
+                    permissions,
+                },
+            )
+            .await?;
+
+        Ok(response.role)
+    }
+
+    /// Delete a role, unassigning it from every member
+    pub async fn delete_role(&self, channel_id: &str, role_id: &str) -> Result<()> {
+        let _: DeleteRoleResponse = self
+            .send_request(
+                "DELETE_ROLE",
+                DeleteRoleMethod {
+                    channel_id: channel_id.to_string(),
+                    role_id: role_id.to_string(),
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Replace the roles assigned to a member
+    pub async fn set_member_roles(
+        &self,
+        channel_id: &str,
+        user_id: &str,
+        role_ids: Vec<String>,
+    ) -> Result<()> {
+        let _: SetMemberRolesResponse = self
+            .send_request(
+                "SET_MEMBER_ROLES",
+                SetMemberRolesMethod {
+                    channel_id: channel_id.to_string(),
+                    user_id: user_id.to_string(),
+                    role_ids,
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Set the permissions every member of a group channel has
+    pub async fn set_default_permissions(
+        &self,
+        channel_id: &str,
+        permissions: PermissionSet,
+    ) -> Result<()> {
+        let _: SetDefaultPermissionsResponse = self
+            .send_request(
+                "SET_DEFAULT_PERMISSIONS",
+                SetDefaultPermissionsMethod {
+                    channel_id: channel_id.to_string(),
+                    permissions,
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
     /// Edit a message (author only)
     pub async fn edit_message(&self, message_id: &str, content: Vec<u8>) -> Result<Message> {
         let response: EditMessageResponse = self
@@ -350,6 +900,99 @@ impl HarmonyClient {
         Ok(())
     }
 
+    /// Add an encrypted reaction to a message
+    pub async fn add_reaction(&self, message_id: &str, data: Vec<u8>) -> Result<Reaction> {
+        let response: AddReactionResponse = self
+            .send_request(
+                "ADD_REACTION",
+                AddReactionMethod {
+                    message_id: message_id.to_string(),
+                    data,
+                },
+            )
+            .await?;
+
+        Ok(response.reaction)
+    }
+
+    /// Remove one of our own reactions, identified by its encrypted payload
+    pub async fn remove_reaction(&self, message_id: &str, data: Vec<u8>) -> Result<()> {
+        let _: RemoveReactionResponse = self
+            .send_request(
+                "REMOVE_REACTION",
+                RemoveReactionMethod {
+                    message_id: message_id.to_string(),
+                    data,
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Fetch MLS messages we have not yet acknowledged, oldest first
+    pub async fn fetch_pending(
+        &self,
+        channel_id: Option<&str>,
+        limit: Option<i64>,
+    ) -> Result<Vec<PendingMessage>> {
+        let response: FetchPendingResponse = self
+            .send_request(
+                "FETCH_PENDING",
+                FetchPendingMethod {
+                    channel_id: channel_id.map(|s| s.to_string()),
+                    limit,
+                    device_id: Some(self.device_id().to_string()),
+                },
+            )
+            .await?;
+
+        Ok(response.messages)
+    }
+
+    /// Acknowledge MLS messages so the server stops holding them for us
+    pub async fn ack_pending(&self, message_ids: Vec<String>) -> Result<()> {
+        let _: AckPendingResponse = self
+            .send_request(
+                "ACK_PENDING",
+                AckPendingMethod {
+                    message_ids,
+                    device_id: Some(self.device_id().to_string()),
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Relay an MLS handshake message (a key package, or a commit with the
+    /// welcome for any members it adds)
+    pub async fn send_handshake(
+        &self,
+        channel_id: &str,
+        kind: PendingMessageKind,
+        content: Vec<u8>,
+        epoch: Option<u64>,
+        added_member_ids: Vec<String>,
+        welcome: Option<Vec<u8>>,
+    ) -> Result<PendingMessage> {
+        let response: SendHandshakeResponse = self
+            .send_request(
+                "SEND_HANDSHAKE",
+                SendHandshakeMethod {
+                    channel_id: channel_id.to_string(),
+                    kind,
+                    content,
+                    epoch,
+                    added_member_ids,
+                    welcome,
+                },
+            )
+            .await?;
+
+        Ok(response.message)
+    }
+
     /// Accept an invite by code
     pub async fn accept_invite(&self, code: &str) -> Result<(bool, String)> {
         let response: AcceptInviteResponse = self
@@ -479,4 +1122,17 @@ impl HarmonyClient {
 
         Ok(response.contact)
     }
+
+    /// Change the status and/or custom message shown to contacts.
+    pub async fn set_presence(
+        &self,
+        status: Option<Status>,
+        message: Option<String>,
+    ) -> Result<Presence> {
+        let response: SetPresenceResponse = self
+            .send_request("SET_PRESENCE", SetPresenceMethod { status, message })
+            .await?;
+
+        Ok(response.presence)
+    }
 }
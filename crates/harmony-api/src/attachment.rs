@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+use crate::{HarmonyError, PersistentEncryption, Result};
+
+/// Plaintext bytes per chunk. Each chunk is encrypted on its own, so files can
+/// be uploaded and downloaded without holding the ciphertext twice.
+pub const ATTACHMENT_CHUNK_SIZE: usize = 1024 * 1024;
+
+// nonce and Poly1305 tag added to every chunk, making full chunks
+// `harmony_types::attachments::CHUNK_SIZE` long
+const CHUNK_OVERHEAD: usize = 24 + 16;
+
+/// Everything needed to fetch and decrypt an attachment. It is sent inside
+/// the encrypted message, so the server never learns the key or file name.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct AttachmentReference {
+    pub id: String,
+    #[serde_as(as = "[_; 32]")]
+    pub key: [u8; 32],
+    pub name: String,
+    pub mime_type: String,
+    // size of the decrypted file in bytes
+    pub size: u64,
+    pub chunk_count: u32,
+}
+
+impl AttachmentReference {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        serde_cbor_2::from_slice(bytes).map_err(|e| HarmonyError::Serialization(Box::new(e)))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_cbor_2::to_vec(self).unwrap()
+    }
+}
+
+/// Binds a chunk to its attachment and position, so chunks cannot be swapped
+/// or reordered by the server.
+fn chunk_aad(attachment_id: &str, index: u32) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(21 + 8 + attachment_id.len() + 4);
+    aad.extend_from_slice(b"harmony-attachment-v1");
+    aad.extend_from_slice(&(attachment_id.len() as u64).to_le_bytes());
+    aad.extend_from_slice(attachment_id.as_bytes());
+    aad.extend_from_slice(&index.to_le_bytes());
+    aad
+}
+
+/// Size of a file once split into chunks and encrypted.
+pub(crate) fn encrypted_size(plaintext_size: usize) -> u64 {
+    let chunks = plaintext_size.div_ceil(ATTACHMENT_CHUNK_SIZE);
+    (plaintext_size + chunks * CHUNK_OVERHEAD) as u64
+}
+
+pub(crate) fn encrypt_chunk(
+    key: &[u8; 32],
+    attachment_id: &str,
+    index: u32,
+    chunk: &[u8],
+) -> Vec<u8> {
+    PersistentEncryption::encrypt_with_key(key, chunk, &chunk_aad(attachment_id, index))
+}
+
+pub(crate) fn decrypt_chunk(
+    key: &[u8; 32],
+    attachment_id: &str,
+    index: u32,
+    data: &[u8],
+) -> Result<Vec<u8>> {
+    Ok(PersistentEncryption::decrypt_with_key(
+        key,
+        data,
+        &chunk_aad(attachment_id, index),
+    )?)
+}
@@ -5,30 +5,66 @@ use std::sync::{
 
 use quick_cache::sync::Cache;
 
+use chacha20poly1305::{Key, aead::Generate};
+
 use crate::{
-    Result,
+    CryptoError, HarmonyError, MlsError, Result,
+    attachment::{
+        ATTACHMENT_CHUNK_SIZE, AttachmentReference, decrypt_chunk, encrypt_chunk, encrypted_size,
+    },
     encrypted_client::Core,
-    models::{ChannelData, Message},
+    message_body::MessageBody,
+    models::{ChannelData, EncryptionHint, Message, PendingMessage, PendingMessageKind, Reaction},
+    search::{SearchIndex, SearchResult},
 };
 
 const MESSAGE_CACHE_CAPACITY: usize = 1000;
+const PENDING_PAGE_SIZE: i64 = 100;
+const THREAD_PAGE_SIZE: i64 = 50;
 
 #[derive(Clone, Debug)]
 pub struct DecryptedMessage {
     pub message: Message,
     pub content: Vec<u8>,
+    pub body: MessageBody,
+    pub reactions: Vec<DecryptedReaction>,
+}
+
+impl DecryptedMessage {
+    fn new(message: Message, content: Vec<u8>, reactions: Vec<DecryptedReaction>) -> Self {
+        // a body that fails to decode is shown as empty rather than hiding the message
+        let body = MessageBody::from_bytes(&content).unwrap_or_else(|e| {
+            tracing::warn!("failed to decode body of message {}: {e}", message.id);
+            MessageBody::default()
+        });
+        Self {
+            message,
+            content,
+            body,
+            reactions,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecryptedReaction {
+    pub user_id: String,
+    pub content: Vec<u8>,
 }
 
 struct MessageStore {
     loaded: AtomicBool,
     messages: Cache<String, DecryptedMessage>,
+    // shared by every channel; outlives the cache so old messages stay searchable
+    search: Arc<SearchIndex>,
 }
 
 impl MessageStore {
-    fn new() -> Self {
+    fn new(search: Arc<SearchIndex>) -> Self {
         Self {
             loaded: AtomicBool::new(false),
             messages: Cache::new(MESSAGE_CACHE_CAPACITY),
+            search,
         }
     }
 
@@ -45,6 +81,7 @@ impl MessageStore {
 
     fn store_history(&self, history: &[DecryptedMessage]) {
         for message in history {
+            self.search.index(message);
             self.messages
                 .insert(message.message.id.clone(), message.clone());
         }
@@ -52,14 +89,55 @@ impl MessageStore {
     }
 
     fn upsert(&self, message: DecryptedMessage) {
+        self.search.index(&message);
         if self.is_loaded() {
             self.messages.insert(message.message.id.clone(), message);
         }
     }
 
+    /// Cache a message even before history is loaded. MLS channels have no
+    /// history to load, so this cache is all there is.
+    fn insert(&self, message: DecryptedMessage) {
+        self.search.index(&message);
+        self.messages.insert(message.message.id.clone(), message);
+    }
+
     fn remove(&self, message_id: &str) {
+        self.search.remove(message_id);
         self.messages.remove(message_id);
     }
+
+    fn get(&self, message_id: &str) -> Option<DecryptedMessage> {
+        self.messages.get(message_id)
+    }
+
+    fn update_reactions(
+        &self,
+        message_id: &str,
+        reaction: &Reaction,
+        decrypted: &DecryptedReaction,
+        added: bool,
+    ) {
+        let Some(mut message) = self.messages.get(message_id) else {
+            return;
+        };
+        if added {
+            // our own reactions are applied locally and then echoed back by the server
+            if message.message.reactions.contains(reaction) {
+                return;
+            }
+            message.message.reactions.push(reaction.clone());
+            message.reactions.push(decrypted.clone());
+        } else {
+            if let Some(i) = message.message.reactions.iter().position(|r| r == reaction) {
+                message.message.reactions.remove(i);
+            }
+            if let Some(i) = message.reactions.iter().position(|r| r == decrypted) {
+                message.reactions.remove(i);
+            }
+        }
+        self.messages.insert(message_id.to_string(), message);
+    }
 }
 
 #[derive(Clone)]
@@ -79,8 +157,8 @@ impl Channel {
     pub(crate) fn new(data: ChannelData, core: Arc<Core>) -> Self {
         Self {
             data,
+            messages: Arc::new(MessageStore::new(core.search.clone())),
             core,
-            messages: Arc::new(MessageStore::new()),
         }
     }
 
@@ -100,10 +178,23 @@ impl Channel {
         self.data.id()
     }
 
+    fn is_mls(&self) -> bool {
+        matches!(
+            self.data,
+            ChannelData::GroupChannel {
+                encryption_hint: EncryptionHint::Mls,
+                ..
+            }
+        )
+    }
+
     pub async fn messages(&self) -> Result<Vec<DecryptedMessage>> {
         if self.messages.is_loaded() {
             return Ok(self.messages.snapshot());
         }
+        if self.is_mls() {
+            return self.sync_pending().await;
+        }
 
         let messages = self
             .core
@@ -113,32 +204,274 @@ impl Channel {
 
         let mut result = Vec::with_capacity(messages.len());
         for message in messages {
-            let content = self.core.decrypt_content(&self.data, &message).await?;
-            result.push(DecryptedMessage { message, content });
+            result.push(self.decrypt_full(message).await?);
         }
 
         self.messages.store_history(&result);
         Ok(self.messages.snapshot())
     }
 
-    fn cache_message(&self, message: &Message, content: &[u8]) {
-        self.messages.upsert(DecryptedMessage {
-            message: message.clone(),
-            content: content.to_vec(),
-        });
+    /// Up to a page of replies in the thread started by `parent_id` that were
+    /// sent before `before`, oldest first.
+    pub async fn thread(
+        &self,
+        parent_id: &str,
+        before: Option<String>,
+    ) -> Result<Vec<DecryptedMessage>> {
+        if self.is_mls() {
+            // MLS threads only exist in what this client has decrypted
+            let mut replies: Vec<DecryptedMessage> = self
+                .messages()
+                .await?
+                .into_iter()
+                .filter(|m| m.message.parent_id.as_deref() == Some(parent_id))
+                .filter(|m| before.as_ref().is_none_or(|b| &m.message.id < b))
+                .collect();
+            let start = replies.len().saturating_sub(THREAD_PAGE_SIZE as usize);
+            return Ok(replies.split_off(start));
+        }
+
+        let mut messages = self
+            .core
+            .client
+            .get_thread(parent_id, Some(THREAD_PAGE_SIZE), Some(true), before, None)
+            .await?;
+        messages.reverse();
+
+        let mut result = Vec::with_capacity(messages.len());
+        for message in messages {
+            let decrypted = self.decrypt_full(message).await?;
+            self.messages.upsert(decrypted.clone());
+            result.push(decrypted);
+        }
+        Ok(result)
+    }
+
+    /// Search the messages of this channel decrypted so far.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchResult> {
+        self.core.search.search(query, Some(self.id()), limit)
+    }
+
+    /// Drain everything the server is still holding for us in this MLS
+    /// channel, in order, and return what has been decrypted so far.
+    async fn sync_pending(&self) -> Result<Vec<DecryptedMessage>> {
+        loop {
+            let pending = self
+                .core
+                .client
+                .fetch_pending(Some(self.id()), Some(PENDING_PAGE_SIZE))
+                .await?;
+            let count = pending.len() as i64;
+            let mut ids = Vec::with_capacity(pending.len());
+            for message in &pending {
+                // a message that failed once will never decrypt, so it is acked regardless
+                if let Err(e) = self.receive_pending(message).await {
+                    tracing::warn!("failed to process pending message {}: {e}", message.id);
+                }
+                ids.push(message.id.clone());
+            }
+            if !ids.is_empty() {
+                self.core.client.ack_pending(ids).await?;
+            }
+            if count < PENDING_PAGE_SIZE {
+                break;
+            }
+        }
+        self.messages.store_history(&[]);
+        Ok(self.messages.snapshot())
+    }
+
+    /// Apply one message from the MLS mailbox without acknowledging it.
+    pub(crate) async fn receive_pending(
+        &self,
+        pending: &PendingMessage,
+    ) -> Result<Option<DecryptedMessage>> {
+        if pending.kind != PendingMessageKind::Application {
+            self.core.process_handshake(pending).await?;
+            return Ok(None);
+        }
+        let message = Message {
+            id: pending.id.clone(),
+            content: pending.content.clone(),
+            author_id: pending.author_id.clone(),
+            edited_at: None,
+            channel_id: pending.channel_id.clone(),
+            key_id: None,
+            reactions: Vec::new(),
+            parent_id: pending.parent_id.clone(),
+        };
+        match self.receive_mls_message(&message).await {
+            Ok(decrypted) => Ok(Some(decrypted)),
+            // sent from this device before the message was cached
+            Err(HarmonyError::Mls(MlsError::OwnMessage)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Each MLS ciphertext can only be opened once, so anything already seen
+    /// (including our own messages) is served from the cache.
+    async fn receive_mls_message(&self, msg: &Message) -> Result<DecryptedMessage> {
+        if let Some(cached) = self.messages.get(&msg.id) {
+            return Ok(cached);
+        }
+        let decrypted = self.decrypt_full(msg.clone()).await?;
+        self.messages.insert(decrypted.clone());
+        Ok(decrypted)
+    }
+
+    async fn decrypt_full(&self, message: Message) -> Result<DecryptedMessage> {
+        let content = self.core.decrypt_content(&self.data, &message).await?;
+        let reactions = self.decrypt_reactions(&message).await;
+        Ok(DecryptedMessage::new(message, content, reactions))
+    }
+
+    async fn cache_message(&self, message: &Message, content: &[u8]) {
+        if !self.messages.is_loaded() {
+            return;
+        }
+        let reactions = self.decrypt_reactions(message).await;
+        self.messages.upsert(DecryptedMessage::new(
+            message.clone(),
+            content.to_vec(),
+            reactions,
+        ));
     }
 
     pub async fn send_message(&self, content: &[u8]) -> Result<Message> {
+        self.send_message_with_attachments(content, &[]).await
+    }
+
+    /// Send a message that keeps `attachments` alive for as long as it
+    /// exists. The references themselves (and their keys) must be part of
+    /// `content`; the server only learns the attachment IDs. Prefer
+    /// [`Channel::send_body`], which takes care of both.
+    pub async fn send_message_with_attachments(
+        &self,
+        content: &[u8],
+        attachments: &[AttachmentReference],
+    ) -> Result<Message> {
+        self.post(content, attachments, None).await
+    }
+
+    /// Reply in the thread started by `parent_id`.
+    pub async fn send_reply(&self, parent_id: &str, body: &MessageBody) -> Result<Message> {
+        self.post(&body.to_bytes(), &body.attachments, Some(parent_id))
+            .await
+    }
+
+    async fn post(
+        &self,
+        content: &[u8],
+        attachments: &[AttachmentReference],
+        parent_id: Option<&str>,
+    ) -> Result<Message> {
         let encrypted = self.core.encrypt_content(&self.data, content).await?;
-        let message = self.core.client.send_message(self.id(), encrypted).await?;
-        self.cache_message(&message, content);
+        let attachment_ids = attachments.iter().map(|a| a.id.clone()).collect();
+        let message = match parent_id {
+            Some(parent_id) => {
+                self.core
+                    .client
+                    .send_thread_reply(self.id(), parent_id, encrypted, attachment_ids)
+                    .await?
+            }
+            None => {
+                self.core
+                    .client
+                    .send_message_with_attachments(self.id(), encrypted, attachment_ids)
+                    .await?
+            }
+        };
+        if self.is_mls() {
+            self.messages.insert(DecryptedMessage::new(
+                message.clone(),
+                content.to_vec(),
+                Vec::new(),
+            ));
+        } else {
+            self.cache_message(&message, content).await;
+        }
         Ok(message)
     }
 
+    /// Encrypt `data` under a fresh key and upload it in chunks. Nothing is
+    /// visible to other members until the returned reference is sent with a
+    /// message, and unsent uploads are deleted once they expire.
+    pub async fn upload_attachment(
+        &self,
+        data: &[u8],
+        name: &str,
+        mime_type: &str,
+        expires_in: Option<u64>,
+    ) -> Result<AttachmentReference> {
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&Key::generate());
+        let chunk_count = data.len().div_ceil(ATTACHMENT_CHUNK_SIZE) as u32;
+        let attachment = self
+            .core
+            .client
+            .create_attachment(
+                self.id(),
+                encrypted_size(data.len()),
+                chunk_count,
+                expires_in,
+            )
+            .await?;
+        for (index, chunk) in data.chunks(ATTACHMENT_CHUNK_SIZE).enumerate() {
+            let encrypted = encrypt_chunk(&key, &attachment.id, index as u32, chunk);
+            self.core
+                .client
+                .upload_chunk(&attachment.id, index as u32, encrypted)
+                .await?;
+        }
+        self.core.client.finish_attachment(&attachment.id).await?;
+        Ok(AttachmentReference {
+            id: attachment.id,
+            key,
+            name: name.to_string(),
+            mime_type: mime_type.to_string(),
+            size: data.len() as u64,
+            chunk_count,
+        })
+    }
+
+    /// Download and decrypt an attachment sent in this channel.
+    pub async fn download_attachment(&self, attachment: &AttachmentReference) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(attachment.size as usize);
+        for index in 0..attachment.chunk_count {
+            let encrypted = self
+                .core
+                .client
+                .download_chunk(&attachment.id, index)
+                .await?;
+            data.extend(decrypt_chunk(
+                &attachment.key,
+                &attachment.id,
+                index,
+                &encrypted,
+            )?);
+        }
+        // chunks are authenticated one by one, so a truncated file would still decrypt
+        if data.len() as u64 != attachment.size {
+            return Err(CryptoError::InvalidCiphertext.into());
+        }
+        Ok(data)
+    }
+
+    /// Send a structured message. Its attachments are kept for as long as
+    /// the message exists.
+    pub async fn send_body(&self, body: &MessageBody) -> Result<Message> {
+        self.send_message_with_attachments(&body.to_bytes(), &body.attachments)
+            .await
+    }
+
+    pub async fn edit_body(&self, message_id: &str, body: &MessageBody) -> Result<Message> {
+        self.edit_message(message_id, &body.to_bytes()).await
+    }
+
     pub async fn edit_message(&self, message_id: &str, content: &[u8]) -> Result<Message> {
         let encrypted = self.core.encrypt_content(&self.data, content).await?;
         let message = self.core.client.edit_message(message_id, encrypted).await?;
-        self.cache_message(&message, content);
+        self.cache_message(&message, content).await;
         Ok(message)
     }
 
@@ -148,10 +481,108 @@ impl Channel {
         Ok(())
     }
 
-    pub(crate) async fn receive_message(&self, msg: &Message) -> Result<Vec<u8>> {
-        let content = self.decrypt_message(msg).await?;
-        self.cache_message(msg, &content);
-        Ok(content)
+    /// React to a message in this channel. `content` is encrypted with the
+    /// message's key before it is sent.
+    pub async fn add_reaction(&self, message: &Message, content: &[u8]) -> Result<Reaction> {
+        let data = self
+            .core
+            .encrypt_reaction(&self.data, message, content)
+            .await?;
+        let reaction = self.core.client.add_reaction(&message.id, data).await?;
+        self.messages.update_reactions(
+            &message.id,
+            &reaction,
+            &DecryptedReaction {
+                user_id: reaction.user_id.clone(),
+                content: content.to_vec(),
+            },
+            true,
+        );
+        Ok(reaction)
+    }
+
+    /// Remove our own reaction whose decrypted content matches `content`.
+    pub async fn remove_reaction(&self, message: &Message, content: &[u8]) -> Result<()> {
+        for reaction in message
+            .reactions
+            .iter()
+            .filter(|r| r.user_id == self.core.user_id)
+        {
+            let Ok(decrypted) = self.decrypt_reaction(message, reaction).await else {
+                continue;
+            };
+            if decrypted.content == content {
+                self.core
+                    .client
+                    .remove_reaction(&message.id, reaction.data.clone())
+                    .await?;
+                self.messages
+                    .update_reactions(&message.id, reaction, &decrypted, false);
+                return Ok(());
+            }
+        }
+        Err(HarmonyError::ReactionNotFound)
+    }
+
+    pub async fn decrypt_reaction(
+        &self,
+        message: &Message,
+        reaction: &Reaction,
+    ) -> Result<DecryptedReaction> {
+        let content = self
+            .core
+            .decrypt_reaction(&self.data, message, reaction)
+            .await?;
+        Ok(DecryptedReaction {
+            user_id: reaction.user_id.clone(),
+            content,
+        })
+    }
+
+    /// Decrypt every reaction on `message`, skipping any that fail to decrypt.
+    pub async fn decrypt_reactions(&self, message: &Message) -> Vec<DecryptedReaction> {
+        let mut result = Vec::with_capacity(message.reactions.len());
+        for reaction in &message.reactions {
+            match self.decrypt_reaction(message, reaction).await {
+                Ok(decrypted) => result.push(decrypted),
+                Err(e) => tracing::warn!("failed to decrypt reaction on {}: {e}", message.id),
+            }
+        }
+        result
+    }
+
+    pub(crate) async fn receive_message(&self, msg: &Message) -> Result<DecryptedMessage> {
+        if self.is_mls() {
+            let decrypted = self.receive_mls_message(msg).await;
+            // our own messages are held for our other devices as well
+            self.core.client.ack_pending(vec![msg.id.clone()]).await?;
+            return decrypted;
+        }
+        let decrypted = self.decrypt_full(msg.clone()).await?;
+        self.messages.upsert(decrypted.clone());
+        Ok(decrypted)
+    }
+
+    /// Decrypt a reaction pushed by the server under the key of the message
+    /// it is on, `key_id`, and apply it to the cached message, if any.
+    pub(crate) async fn receive_reaction(
+        &self,
+        message_id: &str,
+        key_id: Option<&str>,
+        reaction: &Reaction,
+        added: bool,
+    ) -> Result<DecryptedReaction> {
+        let content = self
+            .core
+            .decrypt_reaction_for(&self.data, message_id, key_id, reaction)
+            .await?;
+        let decrypted = DecryptedReaction {
+            user_id: reaction.user_id.clone(),
+            content,
+        };
+        self.messages
+            .update_reactions(message_id, reaction, &decrypted, added);
+        Ok(decrypted)
     }
 
     pub(crate) fn remove_cached(&self, message_id: &str) {
@@ -1,22 +1,34 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use chacha20poly1305::{Key, aead::Generate};
+use dashmap::DashMap;
 use quick_cache::sync::Cache;
+use tokio::time::Instant;
 
 use crate::{
     Result,
     channel::Channel,
     crypto::{GROUP_METADATA_AAD, PersistentEncryption},
-    encrypted_client::Core,
+    encrypted_client::{Core, current_key_id},
     error::HarmonyError,
-    models::{ChannelData, EncryptionHint},
+    models::{ChannelData, EncryptionHint, Message, ReadState},
+    search::SearchResult,
     user_manager::UserManager,
 };
 
+/// Matches the server, which drops typing notifications sent more often.
+const TYPING_INTERVAL: Duration = Duration::from_secs(3);
+
+/// The server stops counting unread messages here.
+const MAX_UNREAD_COUNT: u64 = 100;
+
 pub struct ChannelManager {
     core: Arc<Core>,
     users: Arc<UserManager>,
     cache: Cache<String, Channel>,
+    read_states: DashMap<String, ReadState>,
+    // channel ID -> when we last told the server we were typing
+    typing_sent: DashMap<String, Instant>,
 }
 
 impl ChannelManager {
@@ -25,6 +37,8 @@ impl ChannelManager {
             core,
             users,
             cache: Cache::new(100),
+            read_states: DashMap::new(),
+            typing_sent: DashMap::new(),
         }
     }
 
@@ -41,7 +55,11 @@ impl ChannelManager {
     }
 
     pub async fn fetch_personal(&self) -> Result<HashMap<String, Channel>> {
-        let channels = self.core.client.get_channels().await?;
+        let response = self.core.client.get_channels().await?;
+        let channels = response.channels;
+        for state in response.read_states {
+            self.read_states.insert(state.channel_id.clone(), state);
+        }
         let mut user_ids = vec![self.core.user_id.to_string()];
         for ch in &channels {
             match ch {
@@ -84,6 +102,25 @@ impl ChannelManager {
     }
 
     pub async fn create_group_channel(&self, metadata_plaintext: &[u8]) -> Result<Channel> {
+        self.create_group(metadata_plaintext, EncryptionHint::Persistent)
+            .await
+    }
+
+    /// Create a group channel whose messages are encrypted with MLS and are
+    /// not kept by the server. The group key only protects the metadata.
+    pub async fn create_mls_group_channel(&self, metadata_plaintext: &[u8]) -> Result<Channel> {
+        let channel = self
+            .create_group(metadata_plaintext, EncryptionHint::Mls)
+            .await?;
+        self.core.create_mls_group(channel.id()).await?;
+        Ok(channel)
+    }
+
+    async fn create_group(
+        &self,
+        metadata_plaintext: &[u8],
+        encryption_hint: EncryptionHint,
+    ) -> Result<Channel> {
         let gen_key = Key::generate();
         let mut key = [0u8; 32];
         key.copy_from_slice(&gen_key);
@@ -92,7 +129,7 @@ impl ChannelManager {
         let channel = self
             .core
             .client
-            .create_group_channel(encrypted_metadata, EncryptionHint::Persistent)
+            .create_group_channel(encrypted_metadata, encryption_hint)
             .await?;
         let channel_id = channel.id().to_string();
         {
@@ -112,24 +149,127 @@ impl ChannelManager {
         Ok(invite.code)
     }
 
-    pub async fn join_group(&self, invite_code: &str, group_key: &[u8]) -> Result<Channel> {
-        let (_pending, channel_id) = self.core.client.accept_invite(invite_code).await?;
-        if group_key.len() != 32 {
+    /// Create an invite that only `user_ids` can accept and wrap the group key
+    /// to each of them, so that it does not have to be passed out of band.
+    /// All of them must be established contacts.
+    pub async fn invite_contacts(&self, channel_id: &str, user_ids: &[String]) -> Result<String> {
+        let channel = self.core.client.get_channel(channel_id).await?;
+        let invite = self
+            .core
+            .client
+            .create_invite(
+                channel_id,
+                Some(user_ids.len() as i32),
+                None,
+                Some(user_ids.to_vec()),
+            )
+            .await?;
+        self.core.share_group_key(&channel, user_ids).await?;
+        self.update(channel);
+        Ok(invite.code)
+    }
+
+    /// Hand the current group key to members that did not receive it, for
+    /// example because they are not contacts of the manager that rotated it.
+    pub async fn share_group_key(&self, channel_id: &str, user_ids: &[String]) -> Result<()> {
+        let channel = self.core.client.get_channel(channel_id).await?;
+        self.core.share_group_key(&channel, user_ids).await?;
+        self.update(channel);
+        Ok(())
+    }
+
+    /// Accept an invite to a group channel. Without an out-of-band
+    /// `group_key`, the key wrapped for us by the inviter is used. For MLS
+    /// channels we only become a member once a manager commits the key
+    /// package published here.
+    pub async fn join_group(&self, invite_code: &str, group_key: Option<&[u8]>) -> Result<Channel> {
+        if let Some(group_key) = group_key
+            && group_key.len() != 32
+        {
             return Err(HarmonyError::InvalidGroupKeyLength(group_key.len()));
         }
-        let mut key = [0u8; 32];
-        key.copy_from_slice(group_key);
-        {
-            let mut ks = self.core.keystore.lock().await;
-            ks.store_group_key(&channel_id, &key);
+        let (pending, channel_id) = self.core.client.accept_invite(invite_code).await?;
+        let channel = self.core.client.get_channel(&channel_id).await?;
+        let key_id = current_key_id(&channel);
+        match group_key {
+            Some(group_key) => {
+                let mut key = [0u8; 32];
+                key.copy_from_slice(group_key);
+                {
+                    let mut ks = self.core.keystore.lock().await;
+                    ks.store_versioned_group_key(&channel_id, key_id, &key);
+                }
+                self.core.sync_keystore().await?;
+            }
+            None => {
+                self.core.group_key(&channel_id, key_id).await?;
+            }
         }
-        self.core.sync_keystore().await?;
-        self.fetch(&channel_id).await
+        if pending {
+            self.core.request_mls_join(&channel_id).await?;
+        }
+        Ok(self.update(channel))
     }
 
+    /// The current group key, which also protects the channel metadata.
     pub async fn get_group_key(&self, channel_id: &str) -> Option<Vec<u8>> {
-        let ks = self.core.keystore.lock().await;
-        ks.get_group_key(channel_id).map(|k| k.to_vec())
+        let channel = self.fetch(channel_id).await.ok()?;
+        let key_id = current_key_id(channel.data());
+        self.core
+            .group_key(channel_id, key_id)
+            .await
+            .ok()
+            .map(|k| k.to_vec())
+    }
+
+    /// Our read state in a channel, as of the last fetch or event.
+    pub fn read_state(&self, channel_id: &str) -> Option<ReadState> {
+        self.read_states.get(channel_id).map(|s| s.clone())
+    }
+
+    pub async fn mark_read(&self, channel_id: &str, message_id: &str) -> Result<ReadState> {
+        let state = self.core.client.mark_read(channel_id, message_id).await?;
+        self.update_read_state(state.clone());
+        Ok(state)
+    }
+
+    /// Let the other members know we are typing. Call it on every keystroke;
+    /// the server is only told once every few seconds.
+    pub async fn set_typing(&self, channel_id: &str) -> Result<()> {
+        let now = Instant::now();
+        if let Some(sent) = self.typing_sent.get(channel_id)
+            && now.duration_since(*sent) < TYPING_INTERVAL
+        {
+            return Ok(());
+        }
+        self.typing_sent.insert(channel_id.to_string(), now);
+        self.core.client.set_typing(channel_id).await
+    }
+
+    pub(crate) fn update_read_state(&self, state: ReadState) {
+        self.read_states.insert(state.channel_id.clone(), state);
+    }
+
+    /// Count a message from someone else as unread until it is marked read.
+    pub(crate) fn message_received(&self, channel_id: &str, message: &Message) {
+        if message.author_id == self.core.user_id {
+            return;
+        }
+        let mut state = self
+            .read_states
+            .entry(channel_id.to_string())
+            .or_insert_with(|| ReadState {
+                channel_id: channel_id.to_string(),
+                last_read_id: None,
+                unread_count: 0,
+            });
+        let already_read = state
+            .last_read_id
+            .as_deref()
+            .is_some_and(|last| message.id.as_str() <= last);
+        if !already_read {
+            state.unread_count = (state.unread_count + 1).min(MAX_UNREAD_COUNT);
+        }
     }
 
     pub(crate) fn update(&self, channel: ChannelData) -> Channel {
@@ -141,4 +281,23 @@ impl ChannelManager {
     pub(crate) fn invalidate(&self, channel_id: &str) {
         self.cache.remove(channel_id);
     }
+
+    pub(crate) fn clear_cache(&self) {
+        self.cache.clear();
+        // messages deleted while we were away would otherwise stay searchable
+        self.core.search.clear();
+    }
+
+    /// Search every message decrypted so far, or only those in `channel_id`.
+    pub fn search(&self, query: &str, channel_id: Option<&str>, limit: usize) -> Vec<SearchResult> {
+        self.core.search.search(query, channel_id, limit)
+    }
+
+    /// Forget a channel we are no longer part of.
+    pub(crate) fn forget(&self, channel_id: &str) {
+        self.invalidate(channel_id);
+        self.core.search.remove_channel(channel_id);
+        self.read_states.remove(channel_id);
+        self.typing_sent.remove(channel_id);
+    }
 }
@@ -34,6 +34,9 @@ pub struct ClientOptions {
     pub auto_reconnect: bool,
     /// Maximum number of reconnection attempts
     pub max_reconnect_attempts: u32,
+    /// Identifies this installation, which keeps its own MLS state and
+    /// mailbox position. Should be stable across restarts.
+    pub device_id: String,
 }
 
 impl ClientOptions {
@@ -43,6 +46,7 @@ impl ClientOptions {
             timeout: Duration::from_secs(30),
             auto_reconnect: true,
             max_reconnect_attempts: 5,
+            device_id: Uuid::new_v4().to_string(),
         }
     }
 
@@ -60,6 +64,11 @@ impl ClientOptions {
         self.max_reconnect_attempts = attempts;
         self
     }
+
+    pub fn with_device_id(mut self, device_id: impl Into<String>) -> Self {
+        self.device_id = device_id.into();
+        self
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -68,6 +77,8 @@ enum RpcMessageC2S {
     #[serde(rename_all = "camelCase")]
     Identify {
         token: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        resume: Option<u64>,
     },
     #[serde(rename_all = "camelCase")]
     Message {
@@ -134,6 +145,10 @@ impl HarmonyClient {
         ))
     }
 
+    pub fn device_id(&self) -> &str {
+        &self.options.device_id
+    }
+
     /// Subscribe an additional consumer to the event stream. Each receiver
     /// gets every event emitted after the point of subscription.
     pub fn subscribe_events(&self) -> broadcast::Receiver<ClientEvent> {
@@ -252,6 +267,8 @@ impl HarmonyClient {
 
         let mut first_outcome = Some(first_outcome);
         let mut attempts: u32 = 0;
+        // the sequence number of the last event received, to resume after
+        let mut cursor: Option<u64> = None;
 
         loop {
             let mut break_after = false;
@@ -292,6 +309,7 @@ impl HarmonyClient {
                 let identify = {
                     let msg = RpcMessageC2S::Identify {
                         token: token.clone(),
+                        resume: cursor,
                     };
                     serde_cbor_2::to_vec(&msg).unwrap()
                 };
@@ -334,8 +352,13 @@ impl HarmonyClient {
                                         }
                                     };
                                     match from_value(value) {
-                                        Ok(RpcMessageS2C::Identify {}) => {
+                                        Ok(RpcMessageS2C::Identify { resumed, cursor: latest }) => {
                                             if !authed {
+                                                // when resumed, the replayed events follow
+                                                // and pick up from our own cursor
+                                                if !resumed {
+                                                    cursor = latest;
+                                                }
                                                 authed = true;
                                                 authed_this_session = true;
                                                 attempts = 0;
@@ -345,7 +368,7 @@ impl HarmonyClient {
                                                     reconnecting.swap(false, Ordering::SeqCst);
                                                 tracing::debug!("authentication successful");
                                                 if was_reconnecting {
-                                                    emit(&evt_tx, LifecycleEvent::Reconnected);
+                                                    emit(&evt_tx, LifecycleEvent::Reconnected { resumed });
                                                 } else {
                                                     emit(&evt_tx, LifecycleEvent::Connected);
                                                 }
@@ -354,7 +377,14 @@ impl HarmonyClient {
                                                 }
                                             }
                                         }
-                                        Ok(RpcMessageS2C::Event { event }) => {
+                                        Ok(RpcMessageS2C::Event { event, seq }) => {
+                                            if let Some(seq) = seq {
+                                                // replayed events may overlap ones already received
+                                                if cursor.is_some_and(|c| seq <= c) {
+                                                    continue;
+                                                }
+                                                cursor = Some(seq);
+                                            }
                                             match from_value::<Event>(event) {
                                                 Ok(event) => emit(&evt_tx, event),
                                                 Err(e) => {
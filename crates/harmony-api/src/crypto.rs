@@ -17,6 +17,7 @@ pub use crate::models::{
 };
 
 const CHANNEL_KEY_SALT: &[u8] = b"harmony-persistent-channel-key-v1";
+const GROUP_KEY_WRAP_SALT: &[u8] = b"harmony-group-key-wrap-v1";
 
 pub const GROUP_METADATA_AAD: &[u8] = b"harmony-group-metadata-v1";
 
@@ -31,6 +32,38 @@ pub fn message_aad(channel_id: &str, author_id: &str) -> Vec<u8> {
     aad
 }
 
+/// Build the AAD that binds a reaction ciphertext to its message and sender.
+pub fn reaction_aad(channel_id: &str, message_id: &str, author_id: &str) -> Vec<u8> {
+    let mut aad =
+        Vec::with_capacity(19 + 24 + channel_id.len() + message_id.len() + author_id.len());
+    aad.extend_from_slice(b"harmony-reaction-v1");
+    for part in [channel_id, message_id, author_id] {
+        aad.extend_from_slice(&(part.len() as u64).to_le_bytes());
+        aad.extend_from_slice(part.as_bytes());
+    }
+    aad
+}
+
+/// Build the AAD that binds a wrapped group key to its channel, key version,
+/// sender and recipient.
+pub fn group_key_aad(
+    channel_id: &str,
+    key_id: Option<&str>,
+    sender_id: &str,
+    recipient_id: &str,
+) -> Vec<u8> {
+    let key_id = key_id.unwrap_or_default();
+    let mut aad = Vec::with_capacity(
+        20 + 32 + channel_id.len() + key_id.len() + sender_id.len() + recipient_id.len(),
+    );
+    aad.extend_from_slice(b"harmony-group-key-v1");
+    for part in [channel_id, key_id, sender_id, recipient_id] {
+        aad.extend_from_slice(&(part.len() as u64).to_le_bytes());
+        aad.extend_from_slice(part.as_bytes());
+    }
+    aad
+}
+
 pub const HYBRID_PUBLIC_KEY_BYTES: usize = 32 + MLKEM768_EK_BYTES;
 pub const HYBRID_SECRET_KEY_BYTES: usize = 32 + 64;
 
@@ -225,6 +258,56 @@ impl PersistentEncryption {
         Ok(key)
     }
 
+    /// Wrap a group key to a contact. The ML-KEM shared secret is combined
+    /// with the X25519 secret between both relationship keys, so only this
+    /// contact could have produced the share.
+    pub fn wrap_group_key(
+        &self,
+        their_pk: &HybridPublicKey,
+        group_key: &[u8; 32],
+        aad: &[u8],
+    ) -> Result<(Encapsulated, Vec<u8>), CryptoError> {
+        let (ct, ss) = Self::encapsulate_to(their_pk)?;
+        let key = self.group_key_wrapping_key(their_pk, &ss, aad);
+        Ok((ct, Self::encrypt_with_key(&key, group_key, aad)))
+    }
+
+    /// Unwrap a group key shared by the contact owning `their_pk`.
+    pub fn unwrap_group_key(
+        &self,
+        their_pk: &HybridPublicKey,
+        encapsulated: &[u8],
+        wrapped_key: &[u8],
+        aad: &[u8],
+    ) -> Result<[u8; 32], CryptoError> {
+        let ss = self.decapsulate(encapsulated)?;
+        let key = self.group_key_wrapping_key(their_pk, &ss, aad);
+        let group_key = Self::decrypt_with_key(&key, wrapped_key, aad)?;
+        group_key
+            .as_slice()
+            .try_into()
+            .map_err(|_| CryptoError::InvalidCiphertext)
+    }
+
+    fn group_key_wrapping_key(
+        &self,
+        their_pk: &HybridPublicKey,
+        ss: &[u8; 32],
+        aad: &[u8],
+    ) -> [u8; 32] {
+        let ss_x25519 = self
+            .x25519_secret
+            .diffie_hellman(&PublicKey::from(their_pk.x25519));
+        let mut ikm = [0u8; 64];
+        ikm[..32].copy_from_slice(ss_x25519.as_bytes());
+        ikm[32..].copy_from_slice(ss);
+        let hkdf = Hkdf::<Sha256>::new(Some(GROUP_KEY_WRAP_SALT), &ikm);
+        let mut key = [0u8; 32];
+        hkdf.expand(aad, &mut key)
+            .expect("HKDF expand should not fail for 32-byte output");
+        key
+    }
+
     /// Encrypt `plaintext`, authenticating `aad`.
     pub fn encrypt_with_key(key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
         let cipher = XChaCha20Poly1305::new(key.into());
@@ -1,16 +1,21 @@
-use std::sync::{
-    Arc,
-    atomic::{AtomicU64, Ordering},
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
 };
 
 use chacha20poly1305::{
-    XChaCha20Poly1305, XNonce,
+    Key, XChaCha20Poly1305, XNonce,
     aead::{Aead, Generate},
 };
 use core_api::Session;
 use harmony_types::{
     events::{
-        CallMigratedEvent, UserJoinedCallEvent, UserLeftCallEvent, UserVoiceStateChangedEvent,
+        CallEndedEvent, CallMigratedEvent, CallRingStoppedEvent, CallSessionModeratedEvent,
+        CallStartedEvent, PresenceChangedEvent, TypingStartedEvent, UserJoinedCallEvent,
+        UserLeftCallEvent, UserVoiceStateChangedEvent,
     },
     users::Encapsulated,
 };
@@ -19,17 +24,23 @@ use zeroize::Zeroizing;
 
 use crate::{
     Result,
-    channel::{Channel, DecryptedMessage},
+    channel::{Channel, DecryptedMessage, DecryptedReaction},
     channel_manager::ChannelManager,
     client::{ClientOptions, HarmonyClient},
-    crypto::{CryptoError, PersistentEncryption, message_aad},
-    error::HarmonyError,
+    crypto::{
+        CryptoError, GROUP_METADATA_AAD, PersistentEncryption, group_key_aad, message_aad,
+        reaction_aad,
+    },
+    error::{ApiError, HarmonyError},
     events::{ClientEvent, Event, LifecycleEvent},
     keystore::Keystore,
+    mls::{MlsDeviceState, MlsError, MlsGroups},
     models::{
-        AddContactResponse, AddContactStage, ChannelData, EncryptionHint, Message,
-        RelationshipState, UnifiedPublicKey,
+        AddContactResponse, AddContactStage, ChannelData, ChannelMemberRole, EncryptionHint,
+        GroupKeyShare, Message, PendingMessage, PendingMessageKind, Reaction, ReadState,
+        RelationshipState, UnifiedPublicKey, WrappedGroupKey,
     },
+    search::SearchIndex,
     user_manager::UserManager,
 };
 
@@ -41,6 +52,8 @@ const MAX_KEYSTORE_SYNC_RETRIES: u32 = 5;
 
 const EVENT_CHANNEL_CAPACITY: usize = 256;
 
+const PENDING_PAGE_SIZE: i64 = 100;
+
 fn single(event: EncryptedEvent) -> Vec<EncryptedEvent> {
     vec![event]
 }
@@ -108,6 +121,16 @@ pub enum EncryptedEvent {
         channel_id: String,
         message_id: String,
     },
+    ReactionAdded {
+        channel_id: String,
+        message_id: String,
+        reaction: DecryptedReaction,
+    },
+    ReactionRemoved {
+        channel_id: String,
+        message_id: String,
+        reaction: DecryptedReaction,
+    },
     ChannelUpdated {
         channel: Channel,
     },
@@ -126,18 +149,30 @@ pub enum EncryptedEvent {
     UserLeftCall(UserLeftCallEvent),
     UserVoiceStateChanged(UserVoiceStateChangedEvent),
     CallMigrated(CallMigratedEvent),
+    CallStarted(CallStartedEvent),
+    CallEnded(CallEndedEvent),
+    CallRingStopped(CallRingStoppedEvent),
+    CallSessionModerated(CallSessionModeratedEvent),
     ContactStateChanged {
         user_id: String,
         state: RelationshipState,
     },
     ContactAdded(AddContactOutcome),
+    PresenceChanged(PresenceChangedEvent),
+    TypingStarted(TypingStartedEvent),
+    /// One of our devices marked a channel read.
+    ReadStateUpdated(ReadState),
 }
 
 pub(crate) struct Core {
     pub(crate) client: HarmonyClient,
     pub(crate) keystore: Mutex<Keystore>,
+    // always taken after `keystore` when both are needed
+    pub(crate) mls: Mutex<MlsGroups>,
     pub(crate) generation: AtomicU64,
     pub(crate) user_id: String,
+    pub(crate) device_id: String,
+    pub(crate) search: Arc<SearchIndex>,
     session: Arc<Session>,
 }
 
@@ -168,6 +203,17 @@ impl Core {
         })
     }
 
+    /// Save a snapshot of this device's MLS state to the keystore. Taken
+    /// while holding `mls`, but stored after releasing it, so a snapshot
+    /// that lost the race to a newer one is ignored.
+    pub(crate) async fn persist_mls(&self, state: MlsDeviceState) -> Result<()> {
+        {
+            let mut ks = self.keystore.lock().await;
+            ks.store_mls_state(&self.device_id, state);
+        }
+        self.sync_keystore().await
+    }
+
     /// Fetch the current server keystore, merge it into the local one, and adopt
     /// the server's generation so the next upload's compare-and-swap can succeed.
     async fn reconcile_keystore(&self, cipher: &XChaCha20Poly1305) -> Result<()> {
@@ -187,48 +233,256 @@ impl Core {
         Ok(())
     }
 
-    pub(crate) async fn decrypt_content(
-        &self,
-        channel: &ChannelData,
-        msg: &Message,
-    ) -> Result<Vec<u8>> {
-        if msg.content.is_empty() {
-            return Err(CryptoError::InvalidCiphertext.into());
-        }
-        let aad = message_aad(&msg.channel_id, &msg.author_id);
+    /// Resolve the symmetric key for content in `channel`. For private
+    /// channels, `key_id` selects the relationship key, falling back to the
+    /// current one. For group channels, no `key_id` means the original key.
+    async fn content_key(&self, channel: &ChannelData, key_id: Option<&str>) -> Result<[u8; 32]> {
         match channel {
             ChannelData::GroupChannel {
                 encryption_hint, ..
             } => {
                 if matches!(encryption_hint, EncryptionHint::Mls) {
-                    todo!()
+                    Err(missing_key("MLS channels have no content key"))
                 } else {
-                    let ks = self.keystore.lock().await;
-                    let Some(key) = ks.get_group_key(&msg.channel_id) else {
-                        return Err(missing_key("no group key available for channel"));
-                    };
-                    Ok(PersistentEncryption::decrypt_with_key(
-                        &key,
-                        &msg.content,
-                        &aad,
-                    )?)
+                    self.group_key(channel.id(), key_id).await
                 }
             }
-            ChannelData::PrivateChannel { .. } => {
-                let Some(key_id) = &msg.key_id else {
-                    return Err(missing_key("missing key ID for private message"));
-                };
+            ChannelData::PrivateChannel { last_key_id, .. } => {
+                let key_id = key_id.unwrap_or(last_key_id);
                 let ks = self.keystore.lock().await;
-                let Some(key) = ks.get_direct_key(key_id) else {
-                    return Err(missing_key("no direct key stored for contact"));
-                };
-                Ok(PersistentEncryption::decrypt_with_key(
-                    &key,
-                    &msg.content,
-                    &aad,
-                )?)
+                ks.get_direct_key(key_id)
+                    .ok_or_else(|| missing_key("no direct key stored for contact"))
+            }
+        }
+    }
+
+    /// Look up a version of a channel's group key, fetching the shares other
+    /// members left for us if we do not hold it yet.
+    pub(crate) async fn group_key(
+        &self,
+        channel_id: &str,
+        key_id: Option<&str>,
+    ) -> Result<[u8; 32]> {
+        {
+            let ks = self.keystore.lock().await;
+            if let Some(key) = ks.get_versioned_group_key(channel_id, key_id) {
+                return Ok(key);
+            }
+        }
+        self.fetch_group_keys(channel_id).await?;
+        let ks = self.keystore.lock().await;
+        ks.get_versioned_group_key(channel_id, key_id)
+            .ok_or_else(|| missing_key("no group key available for channel"))
+    }
+
+    /// Unwrap every group key share addressed to us in `channel_id` and keep
+    /// the ones we did not have yet.
+    async fn fetch_group_keys(&self, channel_id: &str) -> Result<()> {
+        let shares = self.client.get_group_keys(channel_id).await?;
+        let shares: Vec<WrappedGroupKey> = {
+            let ks = self.keystore.lock().await;
+            shares
+                .into_iter()
+                .filter(|s| {
+                    ks.get_versioned_group_key(channel_id, s.key_id.as_deref())
+                        .is_none()
+                })
+                .collect()
+        };
+        if shares.is_empty() {
+            return Ok(());
+        }
+        let contacts = self.contact_public_keys().await?;
+        let mut stored = false;
+        {
+            let mut ks = self.keystore.lock().await;
+            for share in &shares {
+                match self.unwrap_group_key(&ks, &contacts, share) {
+                    Ok(key) => {
+                        ks.store_versioned_group_key(channel_id, share.key_id.as_deref(), &key);
+                        stored = true;
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            channel_id,
+                            sender_id = share.sender_id,
+                            "failed to unwrap group key: {e}"
+                        );
+                    }
+                }
+            }
+        }
+        if stored {
+            self.sync_keystore().await?;
+        }
+        Ok(())
+    }
+
+    fn unwrap_group_key(
+        &self,
+        ks: &Keystore,
+        contacts: &HashMap<String, UnifiedPublicKey>,
+        share: &WrappedGroupKey,
+    ) -> Result<[u8; 32]> {
+        let their_pk = contacts
+            .get(&share.sender_id)
+            .ok_or(HarmonyError::ContactNotFound)?;
+        let enc = ks
+            .get_encryption(&share.sender_id)
+            .ok_or_else(|| missing_key("no contact key for group key sender"))?;
+        let aad = group_key_aad(
+            &share.channel_id,
+            share.key_id.as_deref(),
+            &share.sender_id,
+            &self.user_id,
+        );
+        Ok(enc.unwrap_group_key(
+            &their_pk.hybrid,
+            share.encapsulated.as_slice(),
+            &share.wrapped_key,
+            &aad,
+        )?)
+    }
+
+    fn wrap_group_key(
+        &self,
+        ks: &Keystore,
+        contacts: &HashMap<String, UnifiedPublicKey>,
+        channel_id: &str,
+        key_id: Option<&str>,
+        key: &[u8; 32],
+        recipient_id: &str,
+    ) -> Result<GroupKeyShare> {
+        let their_pk = contacts
+            .get(recipient_id)
+            .ok_or(HarmonyError::ContactNotFound)?;
+        let enc = ks
+            .get_encryption(recipient_id)
+            .ok_or_else(|| missing_key("no contact key for group key recipient"))?;
+        let aad = group_key_aad(channel_id, key_id, &self.user_id, recipient_id);
+        let (encapsulated, wrapped_key) = enc.wrap_group_key(&their_pk.hybrid, key, &aad)?;
+        Ok(GroupKeyShare {
+            recipient_id: recipient_id.to_string(),
+            encapsulated,
+            wrapped_key,
+        })
+    }
+
+    /// Relationship public keys of our established contacts.
+    async fn contact_public_keys(&self) -> Result<HashMap<String, UnifiedPublicKey>> {
+        let contacts = self.client.get_contacts().await?;
+        Ok(contacts
+            .into_iter()
+            .filter_map(|c| match c.state {
+                RelationshipState::Established { public_key, .. } => Some((c.id, public_key)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Wrap the current group key to each of `user_ids`, which must all be
+    /// established contacts.
+    pub(crate) async fn share_group_key(
+        &self,
+        channel: &ChannelData,
+        user_ids: &[String],
+    ) -> Result<()> {
+        let key_id = current_key_id(channel);
+        let key = self.group_key(channel.id(), key_id).await?;
+        let contacts = self.contact_public_keys().await?;
+        let shares = {
+            let ks = self.keystore.lock().await;
+            user_ids
+                .iter()
+                .map(|id| self.wrap_group_key(&ks, &contacts, channel.id(), key_id, &key, id))
+                .collect::<Result<Vec<_>>>()?
+        };
+        self.client
+            .share_group_key(channel.id(), key_id.map(str::to_string), shares)
+            .await
+    }
+
+    /// Replace the group key of a channel we manage. The metadata is
+    /// re-encrypted and the new key is wrapped to every other member we have
+    /// an established relationship with; the rest have to be handed the key
+    /// by one of their contacts through [`Core::share_group_key`].
+    pub(crate) async fn rotate_group_key(&self, channel: &ChannelData) -> Result<ChannelData> {
+        let ChannelData::GroupChannel {
+            id,
+            metadata,
+            members,
+            last_key_id,
+            ..
+        } = channel
+        else {
+            return Err(missing_key("only group channels have a group key"));
+        };
+        let previous = self.group_key(id, last_key_id.as_deref()).await?;
+        let plaintext = Zeroizing::new(PersistentEncryption::decrypt_with_key(
+            &previous,
+            metadata,
+            GROUP_METADATA_AAD,
+        )?);
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&Key::generate());
+        let key_id = uuid::Uuid::new_v4().to_string();
+        let encrypted_metadata =
+            PersistentEncryption::encrypt_with_key(&key, &plaintext, GROUP_METADATA_AAD);
+        let contacts = self.contact_public_keys().await?;
+        let shares = {
+            let mut ks = self.keystore.lock().await;
+            // keep the key before the server starts handing out its ID
+            ks.store_versioned_group_key(id, Some(&key_id), &key);
+            members
+                .iter()
+                .filter(|m| m.id != self.user_id)
+                .filter(|m| contacts.contains_key(&m.id) && ks.has_contact(&m.id))
+                .map(|m| self.wrap_group_key(&ks, &contacts, id, Some(&key_id), &key, &m.id))
+                .collect::<Result<Vec<_>>>()?
+        };
+        self.sync_keystore().await?;
+        let unreached = members.len().saturating_sub(shares.len() + 1);
+        if unreached > 0 {
+            tracing::warn!(
+                channel_id = id,
+                unreached,
+                "rotated group key could not be wrapped to every member"
+            );
+        }
+        self.client
+            .rotate_group_key(id, last_key_id.clone(), key_id, encrypted_metadata, shares)
+            .await
+    }
+
+    pub(crate) async fn decrypt_content(
+        &self,
+        channel: &ChannelData,
+        msg: &Message,
+    ) -> Result<Vec<u8>> {
+        if msg.content.is_empty() {
+            return Err(CryptoError::InvalidCiphertext.into());
+        }
+        if matches!(channel, ChannelData::PrivateChannel { .. }) && msg.key_id.is_none() {
+            return Err(missing_key("missing key ID for private message"));
+        }
+        if is_mls(channel) {
+            let pinned = self.pinned_identity_keys().await;
+            let mut mls = self.mls.lock().await;
+            let decrypted = mls.decrypt(channel.id(), &msg.author_id, &msg.content, &pinned);
+            if msg.author_id == self.user_id {
+                // only our other devices' messages can be opened, the sender
+                // ratchet of this one is never advanced by receiving
+                return decrypted.map_err(|_| MlsError::OwnMessage.into());
             }
+            return Ok(decrypted?);
         }
+        let aad = message_aad(&msg.channel_id, &msg.author_id);
+        let key = self.content_key(channel, msg.key_id.as_deref()).await?;
+        Ok(PersistentEncryption::decrypt_with_key(
+            &key,
+            &msg.content,
+            &aad,
+        )?)
     }
 
     pub(crate) async fn encrypt_content(
@@ -236,36 +490,265 @@ impl Core {
         channel: &ChannelData,
         plaintext: &[u8],
     ) -> Result<Vec<u8>> {
+        if is_mls(channel) {
+            let (ciphertext, state) = {
+                let mut mls = self.mls.lock().await;
+                let ciphertext = mls.encrypt(channel.id(), plaintext)?;
+                (ciphertext, mls.save())
+            };
+            // a restart must not reuse the sender ratchet position
+            self.persist_mls(state).await?;
+            return Ok(ciphertext);
+        }
         let aad = message_aad(channel.id(), &self.user_id);
-        match channel {
-            ChannelData::GroupChannel {
-                encryption_hint, ..
-            } => {
-                if matches!(encryption_hint, EncryptionHint::Mls) {
-                    todo!()
-                } else {
-                    let ks = self.keystore.lock().await;
-                    let Some(key) = ks.get_group_key(channel.id()) else {
-                        return Err(missing_key("no group key available for channel"));
-                    };
-                    Ok(PersistentEncryption::encrypt_with_key(
-                        &key, plaintext, &aad,
-                    ))
+        let key = self.content_key(channel, current_key_id(channel)).await?;
+        Ok(PersistentEncryption::encrypt_with_key(
+            &key, plaintext, &aad,
+        ))
+    }
+
+    /// Reactions are encrypted with the key of the message they belong to.
+    pub(crate) async fn encrypt_reaction(
+        &self,
+        channel: &ChannelData,
+        msg: &Message,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>> {
+        let aad = reaction_aad(channel.id(), &msg.id, &self.user_id);
+        let key = self.content_key(channel, msg.key_id.as_deref()).await?;
+        Ok(PersistentEncryption::encrypt_with_key(
+            &key, plaintext, &aad,
+        ))
+    }
+
+    pub(crate) async fn decrypt_reaction(
+        &self,
+        channel: &ChannelData,
+        msg: &Message,
+        reaction: &Reaction,
+    ) -> Result<Vec<u8>> {
+        self.decrypt_reaction_for(channel, &msg.id, msg.key_id.as_deref(), reaction)
+            .await
+    }
+
+    pub(crate) async fn decrypt_reaction_for(
+        &self,
+        channel: &ChannelData,
+        message_id: &str,
+        key_id: Option<&str>,
+        reaction: &Reaction,
+    ) -> Result<Vec<u8>> {
+        let aad = reaction_aad(channel.id(), message_id, &reaction.user_id);
+        let key = self.content_key(channel, key_id).await?;
+        Ok(PersistentEncryption::decrypt_with_key(
+            &key,
+            &reaction.data,
+            &aad,
+        )?)
+    }
+
+    async fn pinned_identity_keys(&self) -> HashMap<String, [u8; 32]> {
+        let ks = self.keystore.lock().await;
+        ks.pinned_identity_keys()
+    }
+
+    /// Start a new MLS group for a channel we just created.
+    pub(crate) async fn create_mls_group(&self, channel_id: &str) -> Result<()> {
+        let state = {
+            let mut mls = self.mls.lock().await;
+            mls.create_group(channel_id)?;
+            mls.save()
+        };
+        self.persist_mls(state).await
+    }
+
+    /// Publish a key package so that a manager of `channel_id` can add us.
+    pub(crate) async fn request_mls_join(&self, channel_id: &str) -> Result<()> {
+        let (key_package, state) = {
+            let mut mls = self.mls.lock().await;
+            (mls.serialized_key_package()?, mls.save())
+        };
+        // the welcome can only be opened with the key package's private key
+        self.persist_mls(state).await?;
+        self.client
+            .send_handshake(
+                channel_id,
+                PendingMessageKind::KeyPackage,
+                key_package,
+                None,
+                Vec::new(),
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Apply a key package, commit or welcome relayed through the server.
+    pub(crate) async fn process_handshake(&self, msg: &PendingMessage) -> Result<()> {
+        let pinned = self.pinned_identity_keys().await;
+        let state = match msg.kind {
+            PendingMessageKind::Commit => {
+                let mut mls = self.mls.lock().await;
+                mls.process_commit(&msg.channel_id, &msg.content, &pinned)?;
+                mls.save()
+            }
+            PendingMessageKind::Welcome => {
+                let mut mls = self.mls.lock().await;
+                // welcomes go to all of a user's devices, but only the one
+                // that asked to join still needs it
+                if mls.has_group(&msg.channel_id) {
+                    return Ok(());
                 }
+                mls.join_from_welcome(&msg.channel_id, &msg.content, &pinned)?;
+                mls.save()
             }
-            ChannelData::PrivateChannel { last_key_id, .. } => {
-                let ks = self.keystore.lock().await;
-                let Some(key) = ks.get_direct_key(last_key_id) else {
-                    return Err(missing_key("no direct key stored for contact"));
-                };
-                Ok(PersistentEncryption::encrypt_with_key(
-                    &key, plaintext, &aad,
-                ))
+            PendingMessageKind::KeyPackage => match self.add_mls_member(msg, &pinned).await? {
+                Some(state) => state,
+                None => return Ok(()),
+            },
+            PendingMessageKind::Application => return Ok(()),
+        };
+        self.persist_mls(state).await
+    }
+
+    /// Ask to be added to the groups of MLS channels we are a member of but
+    /// that this device has no group for, such as when it is new.
+    pub(crate) async fn join_missing_mls_groups(&self) -> Result<()> {
+        let channels = self.client.get_channels().await?.channels;
+        let missing: Vec<String> = {
+            let mls = self.mls.lock().await;
+            channels
+                .iter()
+                .filter(|channel| is_mls(channel) && !mls.has_group(channel.id()))
+                .filter(|channel| match channel {
+                    ChannelData::GroupChannel { members, .. } => {
+                        members.iter().any(|m| m.id == self.user_id)
+                    }
+                    ChannelData::PrivateChannel { .. } => false,
+                })
+                .map(|channel| channel.id().to_string())
+                .collect()
+        };
+        for channel_id in missing {
+            if let Err(e) = self.request_mls_join(&channel_id).await {
+                tracing::warn!(channel_id, "failed to ask to rejoin MLS group: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Commit the removal of a departed member. Does nothing if they never
+    /// made it into the group.
+    async fn remove_mls_member(&self, channel_id: &str, user_id: &str) -> Result<()> {
+        let mut mls = self.mls.lock().await;
+        if !mls.has_group(channel_id) {
+            return Ok(());
+        }
+        let Some(commit) = mls.remove_members(channel_id, &[user_id.to_string()])? else {
+            return Ok(());
+        };
+        let state = match self
+            .client
+            .send_handshake(
+                channel_id,
+                PendingMessageKind::Commit,
+                commit.commit,
+                Some(commit.epoch),
+                vec![],
+                None,
+            )
+            .await
+        {
+            Ok(_) => {
+                mls.merge_pending_commit(channel_id)?;
+                mls.save()
+            }
+            Err(e) => {
+                mls.discard_pending_commit(channel_id);
+                return Err(e);
+            }
+        };
+        drop(mls);
+        self.persist_mls(state).await
+    }
+
+    /// Commit the key package of a pending member, or of a member's device
+    /// that is not in the group yet, if we manage the channel. Returns the
+    /// state to save if the group changed.
+    async fn add_mls_member(
+        &self,
+        msg: &PendingMessage,
+        pinned: &HashMap<String, [u8; 32]>,
+    ) -> Result<Option<MlsDeviceState>> {
+        let channel = self.client.get_channel(&msg.channel_id).await?;
+        let ChannelData::GroupChannel {
+            members,
+            pending_members,
+            ..
+        } = &channel
+        else {
+            return Ok(None);
+        };
+        let is_manager = members
+            .iter()
+            .any(|m| m.id == self.user_id && m.role == ChannelMemberRole::Manager);
+        let may_join = pending_members.contains(&msg.author_id)
+            || members.iter().any(|m| m.id == msg.author_id);
+        if !is_manager || !may_join {
+            return Ok(None);
+        }
+        // hold the group until the server has sequenced the commit
+        let mut mls = self.mls.lock().await;
+        if !mls.has_group(&msg.channel_id) {
+            return Ok(None);
+        }
+        // another manager may already have added the device
+        let Some(commit) = mls.add_member(&msg.channel_id, &msg.author_id, &msg.content, pinned)?
+        else {
+            return Ok(None);
+        };
+        match self
+            .client
+            .send_handshake(
+                &msg.channel_id,
+                PendingMessageKind::Commit,
+                commit.commit,
+                Some(commit.epoch),
+                vec![msg.author_id.clone()],
+                commit.welcome,
+            )
+            .await
+        {
+            Ok(_) => {
+                mls.merge_pending_commit(&msg.channel_id)?;
+                Ok(Some(mls.save()))
+            }
+            Err(e) => {
+                mls.discard_pending_commit(&msg.channel_id);
+                Err(e)
             }
         }
     }
 }
 
+/// The key new content in `channel` is encrypted with.
+pub(crate) fn current_key_id(channel: &ChannelData) -> Option<&str> {
+    match channel {
+        ChannelData::PrivateChannel { last_key_id, .. } => Some(last_key_id),
+        ChannelData::GroupChannel { last_key_id, .. } => last_key_id.as_deref(),
+    }
+}
+
+fn is_mls(channel: &ChannelData) -> bool {
+    matches!(
+        channel,
+        ChannelData::GroupChannel {
+            encryption_hint: EncryptionHint::Mls,
+            ..
+        }
+    )
+}
+
 /// End-to-end-encryption layer over [`HarmonyClient`].
 #[derive(Clone)]
 pub struct EncryptedClient {
@@ -283,6 +766,7 @@ impl EncryptedClient {
         session: Arc<Session>,
         options: ClientOptions,
     ) -> Result<(Arc<Self>, broadcast::Receiver<EncryptedEvent>)> {
+        let device_id = options.device_id.clone();
         let (client, consumer_rx) = HarmonyClient::new(session.clone(), options).await?;
 
         let current = client.get_current_user().await?;
@@ -301,12 +785,26 @@ impl EncryptedClient {
             (ks, generation)
         };
 
+        let identity_seed = keystore.identity_seed();
+        let restored = keystore.get_mls_state(&device_id).and_then(|state| {
+            MlsGroups::restore(&user_id, &identity_seed, state)
+                .inspect_err(|e| tracing::warn!("failed to restore MLS state: {e}"))
+                .ok()
+        });
+        // without its old state, the device rejoins its channels as a new one
+        let mls = match restored {
+            Some(mls) => mls,
+            None => MlsGroups::new(&user_id, &identity_seed)?,
+        };
         let core = Arc::new(Core {
             client,
             session: session.clone(),
             keystore: Mutex::new(keystore),
+            mls: Mutex::new(mls),
             generation: AtomicU64::new(generation),
             user_id,
+            device_id,
+            search: Arc::new(SearchIndex::default()),
         });
         let users = Arc::new(UserManager::new(core.clone(), session.clone()));
         let channels = Arc::new(ChannelManager::new(core.clone(), users.clone()));
@@ -318,6 +816,7 @@ impl EncryptedClient {
             events_tx,
         });
         this.spawn_event_pump(consumer_rx);
+        this.spawn_pending_sync();
         Ok((this, events_rx))
     }
 
@@ -332,9 +831,22 @@ impl EncryptedClient {
                         };
                         let events = match client_event {
                             ClientEvent::Lifecycle(lifecycle) => {
-                                vec![EncryptedEvent::Lifecycle(lifecycle)]
+                                let reconnected = match lifecycle {
+                                    LifecycleEvent::Reconnected { resumed } => Some(resumed),
+                                    _ => None,
+                                };
+                                if reconnected == Some(false) {
+                                    // updates were missed, so cached channels may be stale
+                                    this.channels.clear_cache();
+                                }
+                                let mut events = vec![EncryptedEvent::Lifecycle(lifecycle)];
+                                if reconnected.is_some() {
+                                    // pick up MLS traffic that arrived while we were away
+                                    events.extend(this.sync_pending().await);
+                                }
+                                events
                             }
-                            ClientEvent::Event(event) => match this.handle_event(event).await {
+                            ClientEvent::Event(event) => match this.handle_event(*event).await {
                                 Ok(events) => events,
                                 Err(e) => {
                                     tracing::warn!("failed to process event: {e}");
@@ -343,6 +855,13 @@ impl EncryptedClient {
                             },
                         };
                         for event in events {
+                            if let EncryptedEvent::NewMessage {
+                                channel_id,
+                                message,
+                            } = &event
+                            {
+                                this.channels.message_received(channel_id, &message.message);
+                            }
                             let _ = this.events_tx.send(event);
                         }
                     }
@@ -355,6 +874,80 @@ impl EncryptedClient {
         });
     }
 
+    fn spawn_pending_sync(self: &Arc<Self>) {
+        let weak = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let Some(this) = weak.upgrade() else {
+                return;
+            };
+            for event in this.sync_pending().await {
+                let _ = this.events_tx.send(event);
+            }
+            // after the mailbox, which may hold the welcome we are waiting for
+            if let Err(e) = this.core.join_missing_mls_groups().await {
+                tracing::warn!("failed to check for missing MLS groups: {e}");
+            }
+        });
+    }
+
+    /// Drain the MLS mailbox across all channels, applying handshakes and
+    /// decrypting application messages in the order they were sent.
+    async fn sync_pending(&self) -> Vec<EncryptedEvent> {
+        let mut events = Vec::new();
+        loop {
+            let pending = match self
+                .core
+                .client
+                .fetch_pending(None, Some(PENDING_PAGE_SIZE))
+                .await
+            {
+                Ok(pending) => pending,
+                Err(e) => {
+                    tracing::warn!("failed to fetch pending MLS messages: {e}");
+                    break;
+                }
+            };
+            let count = pending.len() as i64;
+            let mut ids = Vec::with_capacity(pending.len());
+            for message in &pending {
+                match self.receive_pending(message).await {
+                    Ok(received) => events.extend(received),
+                    Err(e) => {
+                        tracing::warn!("failed to process pending message {}: {e}", message.id)
+                    }
+                }
+                ids.push(message.id.clone());
+            }
+            if !ids.is_empty()
+                && let Err(e) = self.core.client.ack_pending(ids).await
+            {
+                tracing::warn!("failed to acknowledge pending MLS messages: {e}");
+                break;
+            }
+            if count < PENDING_PAGE_SIZE {
+                break;
+            }
+        }
+        events
+    }
+
+    async fn receive_pending(&self, message: &PendingMessage) -> Result<Vec<EncryptedEvent>> {
+        if message.kind != PendingMessageKind::Application {
+            self.core.process_handshake(message).await?;
+            return Ok(Vec::new());
+        }
+        let channel = self.channels.fetch(&message.channel_id).await?;
+        Ok(channel
+            .receive_pending(message)
+            .await?
+            .map(|decrypted| EncryptedEvent::NewMessage {
+                channel_id: message.channel_id.clone(),
+                message: decrypted,
+            })
+            .into_iter()
+            .collect())
+    }
+
     pub fn client(&self) -> &HarmonyClient {
         &self.core.client
     }
@@ -567,26 +1160,24 @@ impl EncryptedClient {
                 events.extend(outcome.map(EncryptedEvent::ContactAdded));
                 events
             }
+            Event::PresenceChanged(e) => {
+                self.users.update_presence(&e.user_id, e.presence.clone());
+                single(EncryptedEvent::PresenceChanged(e))
+            }
             Event::NewMessage(e) => {
                 let channel = self.channels.fetch(&e.channel_id).await?;
-                let content = channel.receive_message(&e.message).await?;
+                let message = channel.receive_message(&e.message).await?;
                 single(EncryptedEvent::NewMessage {
                     channel_id: e.channel_id,
-                    message: DecryptedMessage {
-                        message: e.message,
-                        content,
-                    },
+                    message,
                 })
             }
             Event::MessageEdited(e) => {
                 let channel = self.channels.fetch(&e.channel_id).await?;
-                let content = channel.receive_message(&e.message).await?;
+                let message = channel.receive_message(&e.message).await?;
                 single(EncryptedEvent::MessageEdited {
                     channel_id: e.channel_id,
-                    message: DecryptedMessage {
-                        message: e.message,
-                        content,
-                    },
+                    message,
                 })
             }
             Event::MessageDeleted(e) => {
@@ -598,12 +1189,39 @@ impl EncryptedClient {
                     message_id: e.message_id,
                 })
             }
+            Event::ReactionAdded(e) => {
+                let channel = self.channels.fetch(&e.channel_id).await?;
+                let reaction = channel
+                    .receive_reaction(&e.message_id, e.key_id.as_deref(), &e.reaction, true)
+                    .await?;
+                single(EncryptedEvent::ReactionAdded {
+                    channel_id: e.channel_id,
+                    message_id: e.message_id,
+                    reaction,
+                })
+            }
+            Event::ReactionRemoved(e) => {
+                let channel = self.channels.fetch(&e.channel_id).await?;
+                let reaction = channel
+                    .receive_reaction(&e.message_id, e.key_id.as_deref(), &e.reaction, false)
+                    .await?;
+                single(EncryptedEvent::ReactionRemoved {
+                    channel_id: e.channel_id,
+                    message_id: e.message_id,
+                    reaction,
+                })
+            }
+            Event::PendingMessage(e) => {
+                let received = self.receive_pending(&e.message).await;
+                self.core.client.ack_pending(vec![e.message.id]).await?;
+                received?
+            }
             Event::ChannelUpdated(e) => {
                 let channel = self.channels.update(e.channel);
                 single(EncryptedEvent::ChannelUpdated { channel })
             }
             Event::ChannelDeleted(e) => {
-                self.channels.invalidate(&e.channel_id);
+                self.channels.forget(&e.channel_id);
                 single(EncryptedEvent::ChannelDeleted {
                     channel_id: e.channel_id,
                 })
@@ -616,7 +1234,12 @@ impl EncryptedClient {
                 })
             }
             Event::MemberLeft(e) => {
-                self.channels.invalidate(&e.channel_id);
+                if e.user_id == self.core.user_id {
+                    self.channels.forget(&e.channel_id);
+                } else {
+                    self.channels.invalidate(&e.channel_id);
+                    self.rotate_after_departure(&e.channel_id, &e.user_id).await;
+                }
                 single(EncryptedEvent::MemberLeft {
                     channel_id: e.channel_id,
                     user_id: e.user_id,
@@ -626,9 +1249,55 @@ impl EncryptedClient {
             Event::UserLeftCall(e) => single(EncryptedEvent::UserLeftCall(e)),
             Event::UserVoiceStateChanged(e) => single(EncryptedEvent::UserVoiceStateChanged(e)),
             Event::CallMigrated(e) => single(EncryptedEvent::CallMigrated(e)),
+            Event::CallStarted(e) => single(EncryptedEvent::CallStarted(e)),
+            Event::CallEnded(e) => single(EncryptedEvent::CallEnded(e)),
+            Event::CallRingStopped(e) => single(EncryptedEvent::CallRingStopped(e)),
+            Event::CallSessionModerated(e) => single(EncryptedEvent::CallSessionModerated(e)),
+            Event::TypingStarted(e) => single(EncryptedEvent::TypingStarted(e)),
+            Event::ReadStateUpdated(e) => {
+                self.channels.update_read_state(e.read_state.clone());
+                single(EncryptedEvent::ReadStateUpdated(e.read_state))
+            }
         })
     }
 
+    /// Rotate the group key once a member is gone so they cannot read what is
+    /// sent afterwards, or commit their removal from the MLS group instead.
+    /// Every online manager tries, and the server only accepts the first
+    /// rotation or commit, so losing the race is fine.
+    async fn rotate_after_departure(&self, channel_id: &str, user_id: &str) {
+        let channel = match self.channels.fetch(channel_id).await {
+            Ok(channel) => channel,
+            Err(e) => {
+                tracing::warn!(channel_id, "failed to fetch channel for key rotation: {e}");
+                return;
+            }
+        };
+        let ChannelData::GroupChannel { members, .. } = channel.data() else {
+            return;
+        };
+        let is_manager = members
+            .iter()
+            .any(|m| m.id == self.core.user_id && m.role == ChannelMemberRole::Manager);
+        if !is_manager {
+            return;
+        }
+        if is_mls(channel.data()) {
+            match self.core.remove_mls_member(channel_id, user_id).await {
+                Ok(()) | Err(HarmonyError::Api(ApiError::StaleEpoch)) => {}
+                Err(e) => tracing::warn!(channel_id, "failed to remove member from MLS group: {e}"),
+            }
+            return;
+        }
+        match self.core.rotate_group_key(channel.data()).await {
+            Ok(updated) => {
+                self.channels.update(updated);
+            }
+            Err(HarmonyError::Api(ApiError::StaleGroupKey)) => {}
+            Err(e) => tracing::warn!(channel_id, "failed to rotate group key: {e}"),
+        }
+    }
+
     async fn advance_contact_handshake(
         &self,
         user_id: &str,
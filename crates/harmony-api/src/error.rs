@@ -2,6 +2,7 @@ pub use harmony_types::errors::Error as ApiError;
 use thiserror::Error;
 
 pub use crate::crypto::CryptoError;
+pub use crate::mls::MlsError;
 pub use core_api::errors::Error as CoreError;
 
 /// A type-erased error originating from a third-party crate.
@@ -58,12 +59,21 @@ pub enum HarmonyError {
     #[error("expected an Established relationship state after finalizing contact")]
     UnexpectedRelationshipState,
 
+    #[error("reaction not found")]
+    ReactionNotFound,
+
     #[error("group key must be exactly 32 bytes, got {0}")]
     InvalidGroupKeyLength(usize),
 
+    #[error("invalid message body header")]
+    InvalidMessageBody,
+
     #[error("Crypto error: {0}")]
     Crypto(#[from] CryptoError),
 
+    #[error("MLS error: {0}")]
+    Mls(#[from] MlsError),
+
     #[error("Core error: {0}")]
     Core(#[from] CoreError),
 }
@@ -9,7 +9,16 @@ pub enum RpcMessageS2C {
     /// Initial connection information
     #[serde(rename_all = "camelCase")]
     Hello {},
-    Identify {},
+    #[serde(rename_all = "camelCase")]
+    Identify {
+        /// Whether the events missed since the previous connection follow.
+        #[serde(default)]
+        resumed: bool,
+        /// The sequence number of the latest event, if the server
+        /// sequences them.
+        #[serde(default)]
+        cursor: Option<u64>,
+    },
     Heartbeat {},
     Message {
         id: String,
@@ -18,6 +27,8 @@ pub enum RpcMessageS2C {
     },
     Event {
         event: Value,
+        #[serde(default)]
+        seq: Option<u64>,
     },
 }
 
@@ -31,8 +42,10 @@ pub enum LifecycleEvent {
     Disconnected,
     /// Reconnection attempt started
     Reconnecting { attempt: u32, max_attempts: u32 },
-    /// Reconnection successful (and re-authenticated)
-    Reconnected,
+    /// Reconnection successful (and re-authenticated). Unless `resumed`,
+    /// events were missed while disconnected and state should be fetched
+    /// again.
+    Reconnected { resumed: bool },
     /// Reconnection failed permanently
     ReconnectionFailed { attempts: u32 },
 }
@@ -41,13 +54,13 @@ pub enum LifecycleEvent {
 #[derive(Clone, Debug, Serialize)]
 #[serde(untagged)]
 pub enum ClientEvent {
-    Event(Event),
+    Event(Box<Event>),
     Lifecycle(LifecycleEvent),
 }
 
 impl From<Event> for ClientEvent {
     fn from(event: Event) -> Self {
-        ClientEvent::Event(event)
+        ClientEvent::Event(Box::new(event))
     }
 }
 
@@ -12,6 +12,7 @@ use crate::{
     Result,
     crypto::{CryptoError, HYBRID_SECRET_KEY_BYTES, PersistentEncryption, UnifiedPublicKey},
     error::HarmonyError,
+    mls::MlsDeviceState,
 };
 
 const KEYSTORE_HEADER: &[u8; 4] = b"HKS\0";
@@ -41,10 +42,16 @@ pub struct Keystore {
     direct_keys: HashMap<String, [u8; 32]>,
     // group channel ID -> symmetric ChaCha20-Poly1305 key
     group_keys: HashMap<String, [u8; 32]>,
+    // key ID -> group key that replaced a channel's original key
+    #[serde(default)]
+    rotated_group_keys: HashMap<String, [u8; 32]>,
     // Ed25519 identity signing seed
     identity_seed: [u8; 32],
     // contact user ID -> pinned Ed25519 identity verifying key
     pinned_identity_keys: HashMap<String, [u8; 32]>,
+    // device ID -> that device's MLS leaf and groups
+    #[serde(default)]
+    mls_devices: HashMap<String, MlsDeviceState>,
 }
 
 impl std::fmt::Debug for Keystore {
@@ -53,7 +60,9 @@ impl std::fmt::Debug for Keystore {
             .field("negotiation_keys", &self.negotiation_keys.len())
             .field("direct_keys", &self.direct_keys.len())
             .field("group_keys", &self.group_keys.len())
+            .field("rotated_group_keys", &self.rotated_group_keys.len())
             .field("pinned_identity_keys", &self.pinned_identity_keys.len())
+            .field("mls_devices", &self.mls_devices.len())
             .finish()
     }
 }
@@ -66,6 +75,9 @@ impl Drop for Keystore {
         for key in self.group_keys.values_mut() {
             key.zeroize();
         }
+        for key in self.rotated_group_keys.values_mut() {
+            key.zeroize();
+        }
         self.identity_seed.as_mut().zeroize();
     }
 }
@@ -163,6 +175,47 @@ impl Keystore {
         self.group_keys.get(channel_id).copied()
     }
 
+    /// Store a group key by version. Channels start out without a key ID and
+    /// only get one once their key is rotated.
+    pub fn store_versioned_group_key(
+        &mut self,
+        channel_id: &str,
+        key_id: Option<&str>,
+        key: &[u8; 32],
+    ) {
+        match key_id {
+            Some(key_id) => {
+                self.rotated_group_keys.insert(key_id.to_string(), *key);
+            }
+            None => self.store_group_key(channel_id, key),
+        }
+    }
+
+    pub fn get_versioned_group_key(
+        &self,
+        channel_id: &str,
+        key_id: Option<&str>,
+    ) -> Option<[u8; 32]> {
+        match key_id {
+            Some(key_id) => self.rotated_group_keys.get(key_id).copied(),
+            None => self.get_group_key(channel_id),
+        }
+    }
+
+    pub(crate) fn get_mls_state(&self, device_id: &str) -> Option<&MlsDeviceState> {
+        self.mls_devices.get(device_id)
+    }
+
+    /// Save a device's MLS state unless a newer one is already stored.
+    pub(crate) fn store_mls_state(&mut self, device_id: &str, state: MlsDeviceState) {
+        match self.mls_devices.get(device_id) {
+            Some(stored) if stored.revision >= state.revision => {}
+            _ => {
+                self.mls_devices.insert(device_id.to_string(), state);
+            }
+        }
+    }
+
     /// Union-merge another keystore into this one.
     pub fn merge(&mut self, other: &Keystore) {
         for (contact_id, remote) in &other.negotiation_keys {
@@ -184,6 +237,11 @@ impl Keystore {
         for (channel_id, key) in &other.group_keys {
             self.group_keys.entry(channel_id.clone()).or_insert(*key);
         }
+        for (key_id, key) in &other.rotated_group_keys {
+            self.rotated_group_keys
+                .entry(key_id.clone())
+                .or_insert(*key);
+        }
         if self.identity_seed != other.identity_seed {
             tracing::warn!("identity seed conflict during merge; adopting the server copy");
             self.identity_seed = other.identity_seed;
@@ -200,6 +258,9 @@ impl Keystore {
                 }
             }
         }
+        for (device_id, state) in &other.mls_devices {
+            self.store_mls_state(device_id, state.clone());
+        }
     }
 
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
@@ -3,6 +3,7 @@
 //! A Rust client library for interacting with the Harmony chat server.
 
 pub mod api;
+pub mod attachment;
 pub mod channel;
 pub mod channel_manager;
 pub mod client;
@@ -11,11 +12,15 @@ pub mod encrypted_client;
 pub mod error;
 pub mod events;
 pub mod keystore;
+pub mod message_body;
+pub mod mls;
 pub mod models;
+pub mod search;
 pub mod user;
 pub mod user_manager;
 
-pub use channel::{Channel, DecryptedMessage};
+pub use attachment::{ATTACHMENT_CHUNK_SIZE, AttachmentReference};
+pub use channel::{Channel, DecryptedMessage, DecryptedReaction};
 pub use channel_manager::ChannelManager;
 pub use client::{ClientOptions, HarmonyClient};
 pub use crypto::{CryptoError, PersistentEncryption};
@@ -23,6 +28,9 @@ pub use encrypted_client::{AddContactOutcome, ContactAction, EncryptedClient, En
 pub use error::{HarmonyError, Result};
 pub use events::*;
 pub use keystore::{ContactPrivateKey, Keystore};
+pub use message_body::{MESSAGE_BODY_VERSION, MessageBody, SystemMessage};
+pub use mls::MlsError;
 pub use models::*;
+pub use search::{SearchIndex, SearchResult};
 pub use user::User;
 pub use user_manager::{AvatarUrl, PublicUser, UserManager};
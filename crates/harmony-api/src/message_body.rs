@@ -0,0 +1,183 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{AttachmentReference, HarmonyError, Result};
+
+const MESSAGE_BODY_HEADER: &[u8] = b"HMSG";
+pub const MESSAGE_BODY_VERSION: u16 = 1;
+
+/// The plaintext of a message, as every client should interpret it.
+///
+/// Bodies are CBOR maps behind a short header. Fields and system message
+/// kinds may be added in later versions; decoders skip whatever they do not
+/// know, so a newer body still shows its text in an older client.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct MessageBody {
+    pub text: String,
+    pub reply_to: Option<String>,
+    // user IDs mentioned in the text
+    pub mentions: Vec<String>,
+    pub attachments: Vec<AttachmentReference>,
+    pub system: Option<SystemMessage>,
+}
+
+/// Messages generated on behalf of a user rather than typed by them.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum SystemMessage {
+    CallSummary {
+        // unix timestamp in milliseconds
+        started_at: i64,
+        // length of the call in seconds
+        duration: u64,
+        participant_ids: Vec<String>,
+    },
+    /// A kind added in a later version.
+    #[serde(other)]
+    Unknown,
+}
+
+impl MessageBody {
+    pub fn text(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn system(system: SystemMessage) -> Self {
+        Self {
+            system: Some(system),
+            ..Default::default()
+        }
+    }
+
+    pub fn reply_to(mut self, message_id: impl Into<String>) -> Self {
+        self.reply_to = Some(message_id.into());
+        self
+    }
+
+    pub fn mention(mut self, user_id: impl Into<String>) -> Self {
+        self.mentions.push(user_id.into());
+        self
+    }
+
+    pub fn attach(mut self, attachment: AttachmentReference) -> Self {
+        self.attachments.push(attachment);
+        self
+    }
+
+    /// Decode a decrypted payload. Payloads without the header come from
+    /// clients that sent plain UTF-8 and are read as text, while a header
+    /// cut short or carrying version 0 is rejected.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if !bytes.starts_with(MESSAGE_BODY_HEADER) {
+            return Ok(Self::text(String::from_utf8_lossy(bytes)));
+        }
+        let Some(version) = bytes.get(4..6) else {
+            return Err(HarmonyError::InvalidMessageBody);
+        };
+        // the version only records who wrote the body; newer versions
+        // must stay readable by ignoring what they added
+        if u16::from_le_bytes([version[0], version[1]]) == 0 {
+            return Err(HarmonyError::InvalidMessageBody);
+        }
+        serde_cbor_2::from_slice(&bytes[6..]).map_err(|e| HarmonyError::Serialization(Box::new(e)))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MESSAGE_BODY_HEADER);
+        out.extend_from_slice(&MESSAGE_BODY_VERSION.to_le_bytes());
+        out.extend_from_slice(&serde_cbor_2::to_vec(self).unwrap());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_header(version: u16, body: &[u8]) -> Vec<u8> {
+        let mut bytes = MESSAGE_BODY_HEADER.to_vec();
+        bytes.extend_from_slice(&version.to_le_bytes());
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let body = MessageBody::text("hello")
+            .reply_to("01J0000000000000000000000")
+            .mention("user");
+        assert_eq!(MessageBody::from_bytes(&body.to_bytes()).unwrap(), body);
+
+        let summary = MessageBody::system(SystemMessage::CallSummary {
+            started_at: 1_700_000_000_000,
+            duration: 90,
+            participant_ids: vec!["a".to_string(), "b".to_string()],
+        });
+        assert_eq!(
+            MessageBody::from_bytes(&summary.to_bytes()).unwrap(),
+            summary
+        );
+    }
+
+    #[test]
+    fn plain_text_without_header() {
+        let body = MessageBody::from_bytes(b"hi there").unwrap();
+        assert_eq!(body, MessageBody::text("hi there"));
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let encoded = serde_cbor_2::to_vec(&MessageBody::text("hello")).unwrap();
+        assert!(matches!(
+            MessageBody::from_bytes(b"HMSG\x01"),
+            Err(HarmonyError::InvalidMessageBody)
+        ));
+        assert!(matches!(
+            MessageBody::from_bytes(&with_header(0, &encoded)),
+            Err(HarmonyError::InvalidMessageBody)
+        ));
+        assert!(matches!(
+            MessageBody::from_bytes(&with_header(MESSAGE_BODY_VERSION, b"\xff\x00")),
+            Err(HarmonyError::Serialization(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_system_kind() {
+        #[derive(Serialize)]
+        struct Poll {
+            #[serde(rename = "type")]
+            kind: &'static str,
+            question: &'static str,
+        }
+        #[derive(Serialize)]
+        struct NewerBody {
+            text: &'static str,
+            system: Poll,
+            edited: bool,
+        }
+        let newer = NewerBody {
+            text: "poll",
+            system: Poll {
+                kind: "poll",
+                question: "lunch?",
+            },
+            edited: true,
+        };
+        let bytes = with_header(
+            MESSAGE_BODY_VERSION + 1,
+            &serde_cbor_2::to_vec(&newer).unwrap(),
+        );
+        let body = MessageBody::from_bytes(&bytes).unwrap();
+        assert_eq!(body.text, "poll");
+        assert_eq!(body.system, Some(SystemMessage::Unknown));
+    }
+}
@@ -0,0 +1,567 @@
+//! Client-side MLS group state for [`EncryptionHint::Mls`] channels.
+//!
+//! Mirrors the openmls setup used for calls in `pulse-api` and shares its
+//! [`CredentialBinding`], except that channel leaves carry no context, so one
+//! key package can be used to join any channel.
+//!
+//! Every device is its own leaf. Its signature key and groups live in the
+//! openmls storage provider, which is snapshotted into the keystore with
+//! [`MlsGroups::save`] so a restarted client picks up where it left off. A
+//! device that is not in a channel's group yet asks to be added like a new
+//! member would.
+//!
+//! [`EncryptionHint::Mls`]: crate::models::EncryptionHint::Mls
+
+use std::collections::HashMap;
+
+use openmls::framing::MlsMessageBodyIn;
+use openmls::prelude::*;
+use openmls_basic_credential::SignatureKeyPair;
+use openmls_rust_crypto::OpenMlsRustCrypto;
+use openmls_traits::OpenMlsProvider;
+use pulse_api::credential::{CredentialBinding, CredentialError};
+use serde::{Deserialize, Serialize};
+use tls_codec::{Deserialize as TlsDeserialize, Serialize as TlsSerialize};
+use zeroize::Zeroize;
+
+use crate::error::BoxError;
+
+/// Errors from client-side MLS group state management.
+#[derive(Debug, thiserror::Error)]
+pub enum MlsError {
+    #[error("no MLS group state for channel {0}")]
+    NoGroup(String),
+
+    #[error(transparent)]
+    Credential(#[from] CredentialError),
+
+    #[error("credential for user {0} is bound to a call rather than their account")]
+    ScopedCredential(String),
+
+    #[error("credential for user {0} does not match their pinned identity key")]
+    IdentityKeyMismatch(String),
+
+    #[error("message attributed to {expected} was sent by {actual}")]
+    WrongSender { expected: String, actual: String },
+
+    #[error("message sender is not a member of the group")]
+    UnknownSender,
+
+    #[error("our own MLS messages cannot be decrypted")]
+    OwnMessage,
+
+    #[error("failed to generate signature key pair")]
+    SignatureKeygen,
+
+    #[error("failed to store signature keys")]
+    SignatureStore,
+
+    #[error("saved MLS state has no signature key")]
+    MissingSignatureKey,
+
+    #[error("expected a protocol message")]
+    ExpectedProtocolMessage,
+
+    #[error("expected an application message")]
+    ExpectedApplicationMessage,
+
+    #[error("expected a StagedCommitMessage from commit data")]
+    ExpectedStagedCommit,
+
+    #[error("expected a Welcome message")]
+    ExpectedWelcome,
+
+    #[error("welcome into the group of channel {actual} was delivered for channel {expected}")]
+    WelcomeWrongChannel { expected: String, actual: String },
+
+    #[error("{operation}: {source}")]
+    OpenMls {
+        operation: &'static str,
+        #[source]
+        source: BoxError,
+    },
+}
+
+impl MlsError {
+    fn op(operation: &'static str, source: impl std::error::Error + Send + Sync + 'static) -> Self {
+        MlsError::OpenMls {
+            operation,
+            source: Box::new(source),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, MlsError>;
+
+const CIPHERSUITE: Ciphersuite = Ciphersuite::MLS_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519;
+// how many past epochs can still decrypt messages that were in flight during a commit
+const MAX_PAST_EPOCHS: usize = 4;
+
+/// Verify a member's credential and return the user it belongs to.
+///
+/// Members we have pinned an identity key for must match it; other members
+/// are accepted on the strength of their self-signed binding alone.
+fn authenticate_member(
+    pinned: &HashMap<String, [u8; 32]>,
+    credential: &Credential,
+    leaf_sig_key: &[u8],
+) -> Result<String> {
+    let binding = CredentialBinding::verify(credential, leaf_sig_key)?;
+    if !binding.context.is_empty() {
+        return Err(MlsError::ScopedCredential(binding.user_id));
+    }
+    match pinned.get(&binding.user_id) {
+        Some(key) if *key != binding.identity_pk => {
+            Err(MlsError::IdentityKeyMismatch(binding.user_id))
+        }
+        _ => Ok(binding.user_id),
+    }
+}
+
+/// A commit created locally that has to be accepted by the server before it
+/// is merged.
+pub(crate) struct OutgoingCommit {
+    pub commit: Vec<u8>,
+    pub epoch: u64,
+    pub welcome: Option<Vec<u8>>,
+}
+
+/// One device's MLS state, as saved in the keystore.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub(crate) struct MlsDeviceState {
+    // bumped on every save, so merging keystores keeps the newest copy
+    pub revision: u64,
+    // public half of the leaf signature key pair kept in `storage`
+    signature_key: Vec<u8>,
+    // channel IDs of the groups kept in `storage`
+    channels: Vec<String>,
+    // contents of the openmls storage provider
+    storage: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Drop for MlsDeviceState {
+    fn drop(&mut self) {
+        for (_, value) in &mut self.storage {
+            value.zeroize();
+        }
+    }
+}
+
+/// The MLS groups of every MLS channel this device is a member of.
+pub(crate) struct MlsGroups {
+    provider: OpenMlsRustCrypto,
+    signer: SignatureKeyPair,
+    credential_with_key: CredentialWithKey,
+    groups: HashMap<String, MlsGroup>,
+    revision: u64,
+}
+
+impl MlsGroups {
+    /// Create a fresh leaf identity endorsed by the account signing key.
+    pub fn new(user_id: &str, signing_seed: &[u8; 32]) -> Result<Self> {
+        let provider = OpenMlsRustCrypto::default();
+        let signer = SignatureKeyPair::new(CIPHERSUITE.signature_algorithm())
+            .map_err(|_| MlsError::SignatureKeygen)?;
+        signer
+            .store(provider.storage())
+            .map_err(|_| MlsError::SignatureStore)?;
+
+        Ok(Self {
+            credential_with_key: Self::credential_with_key(user_id, &signer, signing_seed),
+            provider,
+            signer,
+            groups: HashMap::new(),
+            revision: 0,
+        })
+    }
+
+    /// Restore the leaf identity and groups saved by [`save`](Self::save).
+    /// Groups that fail to load are skipped, and have to be joined again.
+    pub fn restore(user_id: &str, signing_seed: &[u8; 32], state: &MlsDeviceState) -> Result<Self> {
+        let provider = OpenMlsRustCrypto::default();
+        provider
+            .storage()
+            .values
+            .write()
+            .unwrap()
+            .extend(state.storage.iter().cloned());
+        let signer = SignatureKeyPair::read(
+            provider.storage(),
+            &state.signature_key,
+            CIPHERSUITE.signature_algorithm(),
+        )
+        .ok_or(MlsError::MissingSignatureKey)?;
+
+        let mut groups = HashMap::new();
+        for channel_id in &state.channels {
+            match MlsGroup::load(
+                provider.storage(),
+                &GroupId::from_slice(channel_id.as_bytes()),
+            ) {
+                Ok(Some(group)) => {
+                    groups.insert(channel_id.clone(), group);
+                }
+                Ok(None) => tracing::warn!(channel_id, "saved MLS group is missing"),
+                Err(e) => tracing::warn!(channel_id, "failed to load saved MLS group: {e}"),
+            }
+        }
+
+        Ok(Self {
+            // signatures are deterministic, so this is the credential our
+            // leaves were created with
+            credential_with_key: Self::credential_with_key(user_id, &signer, signing_seed),
+            provider,
+            signer,
+            groups,
+            revision: state.revision,
+        })
+    }
+
+    /// Snapshot everything needed to [`restore`](Self::restore) this device.
+    pub fn save(&mut self) -> MlsDeviceState {
+        self.revision += 1;
+        MlsDeviceState {
+            revision: self.revision,
+            signature_key: self.signer.public().to_vec(),
+            channels: self.groups.keys().cloned().collect(),
+            storage: self
+                .provider
+                .storage()
+                .values
+                .read()
+                .unwrap()
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        }
+    }
+
+    fn credential_with_key(
+        user_id: &str,
+        signer: &SignatureKeyPair,
+        signing_seed: &[u8; 32],
+    ) -> CredentialWithKey {
+        let binding = CredentialBinding::sign(user_id, Vec::new(), signer.public(), signing_seed);
+        CredentialWithKey {
+            credential: binding.credential(),
+            signature_key: signer.public().into(),
+        }
+    }
+
+    pub fn has_group(&self, channel_id: &str) -> bool {
+        self.groups.contains_key(channel_id)
+    }
+
+    /// Generate a fresh KeyPackage and return its TLS-serialized bytes.
+    pub fn serialized_key_package(&self) -> Result<Vec<u8>> {
+        let bundle = KeyPackage::builder()
+            .build(
+                CIPHERSUITE,
+                &self.provider,
+                &self.signer,
+                self.credential_with_key.clone(),
+            )
+            .map_err(|e| MlsError::op("Failed to build KeyPackage", e))?;
+
+        bundle
+            .key_package()
+            .tls_serialize_detached()
+            .map_err(|e| MlsError::op("Failed to serialize KeyPackage", e))
+    }
+
+    /// Create a new group for `channel_id` with ourselves as the only member.
+    pub fn create_group(&mut self, channel_id: &str) -> Result<()> {
+        let group_config = MlsGroupCreateConfig::builder()
+            .ciphersuite(CIPHERSUITE)
+            .max_past_epochs(MAX_PAST_EPOCHS)
+            .use_ratchet_tree_extension(true)
+            .build();
+
+        let group = MlsGroup::new_with_group_id(
+            &self.provider,
+            &self.signer,
+            &group_config,
+            GroupId::from_slice(channel_id.as_bytes()),
+            self.credential_with_key.clone(),
+        )
+        .map_err(|e| MlsError::op("Failed to create MLS group", e))?;
+
+        self.groups.insert(channel_id.to_string(), group);
+        tracing::info!(channel_id, "MLS group created");
+        Ok(())
+    }
+
+    /// Commit adding the owner of `key_package`, who must be `user_id`.
+    /// Returns `None` if the device behind it is already in the group, as
+    /// happens when it asked again before its welcome arrived.
+    pub fn add_member(
+        &mut self,
+        channel_id: &str,
+        user_id: &str,
+        key_package: &[u8],
+        pinned: &HashMap<String, [u8; 32]>,
+    ) -> Result<Option<OutgoingCommit>> {
+        let group = self
+            .groups
+            .get_mut(channel_id)
+            .ok_or_else(|| MlsError::NoGroup(channel_id.to_string()))?;
+
+        let key_package = KeyPackageIn::tls_deserialize(&mut &key_package[..])
+            .map_err(|e| MlsError::op("Failed to deserialize KeyPackage", e))?
+            .validate(self.provider.crypto(), ProtocolVersion::Mls10)
+            .map_err(|e| MlsError::op("Failed to validate KeyPackage", e))?;
+        let leaf = key_package.leaf_node();
+        let owner =
+            authenticate_member(pinned, leaf.credential(), leaf.signature_key().as_slice())?;
+        if owner != user_id {
+            return Err(MlsError::WrongSender {
+                expected: user_id.to_string(),
+                actual: owner,
+            });
+        }
+        if group
+            .members()
+            .any(|member| member.signature_key == leaf.signature_key().as_slice())
+        {
+            return Ok(None);
+        }
+
+        let epoch = group.epoch().as_u64();
+        let (commit, welcome, _) = group
+            .add_members(&self.provider, &self.signer, &[key_package])
+            .map_err(|e| MlsError::op("Failed to add member", e))?;
+
+        Ok(Some(OutgoingCommit {
+            commit: commit
+                .tls_serialize_detached()
+                .map_err(|e| MlsError::op("Failed to serialize commit", e))?,
+            epoch,
+            welcome: Some(
+                welcome
+                    .to_bytes()
+                    .map_err(|e| MlsError::op("Failed to serialize welcome", e))?,
+            ),
+        }))
+    }
+
+    /// Commit removing every leaf that belongs to one of `user_ids`.
+    pub fn remove_members(
+        &mut self,
+        channel_id: &str,
+        user_ids: &[String],
+    ) -> Result<Option<OutgoingCommit>> {
+        let group = self
+            .groups
+            .get_mut(channel_id)
+            .ok_or_else(|| MlsError::NoGroup(channel_id.to_string()))?;
+
+        let leaves: Vec<LeafNodeIndex> = group
+            .members()
+            .filter(|member| {
+                CredentialBinding::decode(&member.credential)
+                    .is_ok_and(|binding| user_ids.contains(&binding.user_id))
+            })
+            .map(|member| member.index)
+            .collect();
+        if leaves.is_empty() {
+            return Ok(None);
+        }
+
+        let epoch = group.epoch().as_u64();
+        let (commit, _, _) = group
+            .remove_members(&self.provider, &self.signer, &leaves)
+            .map_err(|e| MlsError::op("Failed to remove members", e))?;
+
+        Ok(Some(OutgoingCommit {
+            commit: commit
+                .tls_serialize_detached()
+                .map_err(|e| MlsError::op("Failed to serialize commit", e))?,
+            epoch,
+            welcome: None,
+        }))
+    }
+
+    /// Merge our own commit once the server has accepted it.
+    pub fn merge_pending_commit(&mut self, channel_id: &str) -> Result<()> {
+        let group = self
+            .groups
+            .get_mut(channel_id)
+            .ok_or_else(|| MlsError::NoGroup(channel_id.to_string()))?;
+        group
+            .merge_pending_commit(&self.provider)
+            .map_err(|e| MlsError::op("Failed to merge pending commit", e))?;
+        tracing::debug!(
+            channel_id,
+            epoch = group.epoch().as_u64(),
+            "Applied own MLS commit"
+        );
+        Ok(())
+    }
+
+    /// Throw away our own commit after the server rejected it.
+    pub fn discard_pending_commit(&mut self, channel_id: &str) {
+        if let Some(group) = self.groups.get_mut(channel_id)
+            && let Err(e) = group.clear_pending_commit(self.provider.storage())
+        {
+            tracing::warn!(channel_id, "failed to clear pending commit: {e}");
+        }
+    }
+
+    /// Apply a commit sent by another member.
+    pub fn process_commit(
+        &mut self,
+        channel_id: &str,
+        commit_data: &[u8],
+        pinned: &HashMap<String, [u8; 32]>,
+    ) -> Result<()> {
+        let group = self
+            .groups
+            .get_mut(channel_id)
+            .ok_or_else(|| MlsError::NoGroup(channel_id.to_string()))?;
+
+        let protocol_message = MlsMessageIn::tls_deserialize(&mut &commit_data[..])
+            .map_err(|e| MlsError::op("Failed to deserialize commit MlsMessageIn", e))?
+            .try_into_protocol_message()
+            .map_err(|_| MlsError::ExpectedProtocolMessage)?;
+        // commits are relayed to every device, including the one that made
+        // and already merged it
+        if protocol_message.epoch() < group.epoch() {
+            tracing::debug!(channel_id, "Skipping already applied MLS commit");
+            return Ok(());
+        }
+        let processed = group
+            .process_message(&self.provider, protocol_message)
+            .map_err(|e| MlsError::op("Failed to process commit message", e))?;
+
+        match processed.into_content() {
+            ProcessedMessageContent::StagedCommitMessage(staged_commit) => {
+                for queued_add in staged_commit.add_proposals() {
+                    let leaf = queued_add.add_proposal().key_package().leaf_node();
+                    authenticate_member(
+                        pinned,
+                        leaf.credential(),
+                        leaf.signature_key().as_slice(),
+                    )?;
+                }
+                let removed_self = staged_commit.self_removed();
+                group
+                    .merge_staged_commit(&self.provider, *staged_commit)
+                    .map_err(|e| MlsError::op("Failed to merge staged commit", e))?;
+                tracing::debug!(
+                    channel_id,
+                    epoch = group.epoch().as_u64(),
+                    "Applied MLS commit"
+                );
+                if removed_self && let Some(mut group) = self.groups.remove(channel_id) {
+                    // drop its secrets from storage so they are not saved again
+                    if let Err(e) = group.delete(self.provider.storage()) {
+                        tracing::warn!(channel_id, "failed to delete MLS group state: {e}");
+                    }
+                    tracing::info!(channel_id, "Removed from MLS group");
+                }
+                Ok(())
+            }
+            _ => Err(MlsError::ExpectedStagedCommit),
+        }
+    }
+
+    /// Join the group of `channel_id` from a welcome message. Nothing is
+    /// stored unless the welcome is for that channel's group.
+    pub fn join_from_welcome(
+        &mut self,
+        channel_id: &str,
+        welcome_data: &[u8],
+        pinned: &HashMap<String, [u8; 32]>,
+    ) -> Result<()> {
+        let welcome = match MlsMessageIn::tls_deserialize(&mut &welcome_data[..])
+            .map_err(|e| MlsError::op("Failed to deserialize welcome MlsMessageIn", e))?
+            .extract()
+        {
+            MlsMessageBodyIn::Welcome(w) => w,
+            _ => return Err(MlsError::ExpectedWelcome),
+        };
+
+        let join_config = MlsGroupJoinConfig::builder()
+            .max_past_epochs(MAX_PAST_EPOCHS)
+            .build();
+        let staged = StagedWelcome::new_from_welcome(&self.provider, &join_config, welcome, None)
+            .map_err(|e| MlsError::op("Failed to stage welcome", e))?;
+
+        // the server only vouches for the channel it delivered the welcome in,
+        // so a welcome into another channel's group must not replace it
+        let group_id = staged.group_context().group_id().as_slice();
+        if group_id != channel_id.as_bytes() {
+            return Err(MlsError::WelcomeWrongChannel {
+                expected: channel_id.to_string(),
+                actual: String::from_utf8_lossy(group_id).into_owned(),
+            });
+        }
+        for member in staged.members() {
+            authenticate_member(pinned, &member.credential, &member.signature_key)?;
+        }
+
+        let group = staged
+            .into_group(&self.provider)
+            .map_err(|e| MlsError::op("Failed to create group from welcome", e))?;
+        tracing::info!(
+            channel_id,
+            epoch = group.epoch().as_u64(),
+            "Joined MLS group from welcome"
+        );
+        self.groups.insert(channel_id.to_string(), group);
+        Ok(())
+    }
+
+    /// Encrypt an application message for the group of `channel_id`.
+    pub fn encrypt(&mut self, channel_id: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let group = self
+            .groups
+            .get_mut(channel_id)
+            .ok_or_else(|| MlsError::NoGroup(channel_id.to_string()))?;
+        group
+            .create_message(&self.provider, &self.signer, plaintext)
+            .map_err(|e| MlsError::op("Failed to create application message", e))?
+            .tls_serialize_detached()
+            .map_err(|e| MlsError::op("Failed to serialize application message", e))
+    }
+
+    /// Decrypt an application message, checking that it was sent by
+    /// `author_id` as claimed by the server.
+    pub fn decrypt(
+        &mut self,
+        channel_id: &str,
+        author_id: &str,
+        ciphertext: &[u8],
+        pinned: &HashMap<String, [u8; 32]>,
+    ) -> Result<Vec<u8>> {
+        let group = self
+            .groups
+            .get_mut(channel_id)
+            .ok_or_else(|| MlsError::NoGroup(channel_id.to_string()))?;
+
+        let protocol_message = MlsMessageIn::tls_deserialize(&mut &ciphertext[..])
+            .map_err(|e| MlsError::op("Failed to deserialize MlsMessageIn", e))?
+            .try_into_protocol_message()
+            .map_err(|_| MlsError::ExpectedProtocolMessage)?;
+        let processed = group
+            .process_message(&self.provider, protocol_message)
+            .map_err(|e| MlsError::op("Failed to process application message", e))?;
+
+        let sender = group
+            .members()
+            .find(|m| m.credential == *processed.credential())
+            .ok_or(MlsError::UnknownSender)?;
+        let sender_id = authenticate_member(pinned, &sender.credential, &sender.signature_key)?;
+        if sender_id != author_id {
+            return Err(MlsError::WrongSender {
+                expected: author_id.to_string(),
+                actual: sender_id,
+            });
+        }
+
+        match processed.into_content() {
+            ProcessedMessageContent::ApplicationMessage(message) => Ok(message.into_bytes()),
+            _ => Err(MlsError::ExpectedApplicationMessage),
+        }
+    }
+}
@@ -1,8 +1,12 @@
+pub use harmony_types::attachments::Attachment;
 pub use harmony_types::channels::{
-    Channel as ChannelData, ChannelMember, ChannelMemberRole, EncryptionHint,
+    Channel as ChannelData, ChannelMember, ChannelMemberRole, ChannelRole, EncryptionHint,
+    GetChannelsResponse, GroupKeyShare, WrappedGroupKey,
 };
 pub use harmony_types::invites::{Invite, InviteInformation};
-pub use harmony_types::messages::Message;
+pub use harmony_types::messages::{Message, Reaction, ReadState};
+pub use harmony_types::mls::{PendingMessage, PendingMessageKind};
+pub use harmony_types::permissions::{Permission, PermissionSet};
 pub use harmony_types::users::{
     AddContactResponse, AddContactStage, BlockContactMethod, BlockContactResponse, Contact,
     ContactExtended, CurrentUserResponse, Encapsulated, HybridPublicKey, MLKEM768_CT_BYTES,
@@ -10,7 +14,7 @@ pub use harmony_types::users::{
     UnblockContactResponse, UnifiedPublicKey, UserProfile,
 };
 pub use harmony_types::voice::{
-    CallMember, CreateCallTokenResponse, GetCallMembersResponse, StartCallResponse,
-    UpdateVoiceStateResponse,
+    CallMember, CallRecord, CallRoom, CreateCallTokenResponse, GetCallMembersResponse, RingOutcome,
+    SessionRestrictions, StartCallResponse, UpdateVoiceStateResponse,
 };
 pub use pulse_types::Region;
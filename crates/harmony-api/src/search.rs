@@ -0,0 +1,139 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::RwLock,
+};
+
+use crate::DecryptedMessage;
+
+/// A message matched by a search.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchResult {
+    pub channel_id: String,
+    pub message_id: String,
+    pub author_id: String,
+    pub text: String,
+}
+
+#[derive(Default)]
+struct IndexState {
+    // term -> IDs of the messages containing it
+    terms: BTreeMap<String, BTreeSet<String>>,
+    messages: HashMap<String, SearchResult>,
+}
+
+/// Full-text index over decrypted messages. The server only ever sees
+/// ciphertext, so search runs locally over whatever this client has
+/// decrypted so far.
+#[derive(Default)]
+pub struct SearchIndex {
+    state: RwLock<IndexState>,
+}
+
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+}
+
+impl IndexState {
+    fn remove(&mut self, message_id: &str) {
+        let Some(indexed) = self.messages.remove(message_id) else {
+            return;
+        };
+        for term in tokenize(&indexed.text) {
+            if let Some(ids) = self.terms.get_mut(&term) {
+                ids.remove(message_id);
+                if ids.is_empty() {
+                    self.terms.remove(&term);
+                }
+            }
+        }
+    }
+
+    /// Messages with a term starting with `prefix`, so results show up while
+    /// the last word is still being typed.
+    fn matching(&self, prefix: &str) -> BTreeSet<String> {
+        self.terms
+            .range(prefix.to_string()..)
+            .take_while(|(term, _)| term.starts_with(prefix))
+            .flat_map(|(_, ids)| ids.iter().cloned())
+            .collect()
+    }
+}
+
+impl SearchIndex {
+    /// Add a message, replacing what was indexed for it before.
+    pub(crate) fn index(&self, message: &DecryptedMessage) {
+        let mut text = message.body.text.clone();
+        for attachment in &message.body.attachments {
+            text.push('\n');
+            text.push_str(&attachment.name);
+        }
+        let mut state = self.state.write().unwrap();
+        state.remove(&message.message.id);
+        if text.trim().is_empty() {
+            return;
+        }
+        for term in tokenize(&text) {
+            state
+                .terms
+                .entry(term)
+                .or_default()
+                .insert(message.message.id.clone());
+        }
+        state.messages.insert(
+            message.message.id.clone(),
+            SearchResult {
+                channel_id: message.message.channel_id.clone(),
+                message_id: message.message.id.clone(),
+                author_id: message.message.author_id.clone(),
+                text,
+            },
+        );
+    }
+
+    pub(crate) fn remove(&self, message_id: &str) {
+        self.state.write().unwrap().remove(message_id);
+    }
+
+    pub(crate) fn remove_channel(&self, channel_id: &str) {
+        let mut state = self.state.write().unwrap();
+        let ids: Vec<String> = state
+            .messages
+            .values()
+            .filter(|m| m.channel_id == channel_id)
+            .map(|m| m.message_id.clone())
+            .collect();
+        for id in ids {
+            state.remove(&id);
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        *self.state.write().unwrap() = IndexState::default();
+    }
+
+    /// Messages containing every word of `query`, newest first. Words match
+    /// case-insensitively on their prefix.
+    pub fn search(&self, query: &str, channel_id: Option<&str>, limit: usize) -> Vec<SearchResult> {
+        let state = self.state.read().unwrap();
+        let mut matches: Option<BTreeSet<String>> = None;
+        for term in tokenize(query) {
+            let found = state.matching(&term);
+            matches = Some(match matches {
+                Some(previous) => previous.intersection(&found).cloned().collect(),
+                None => found,
+            });
+        }
+        // message IDs are ULIDs, so reverse order is newest first
+        matches
+            .unwrap_or_default()
+            .iter()
+            .rev()
+            .filter_map(|id| state.messages.get(id))
+            .filter(|m| channel_id.is_none_or(|id| m.channel_id == id))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
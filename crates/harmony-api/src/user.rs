@@ -36,6 +36,10 @@ impl User {
         self.profile.presence.as_ref()
     }
 
+    pub(crate) fn set_presence(&mut self, presence: Presence) {
+        self.profile.presence = Some(presence);
+    }
+
     pub fn base(&self) -> &PublicUser {
         &self.base
     }
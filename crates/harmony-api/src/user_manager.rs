@@ -1,6 +1,7 @@
 use std::{collections::HashMap, sync::Arc};
 
 use core_api::Session;
+use harmony_types::users::Presence;
 use quick_cache::sync::Cache;
 
 use crate::{Result, encrypted_client::Core, user::User};
@@ -30,6 +31,14 @@ impl UserManager {
         self.cache.get(id)
     }
 
+    /// Keep a cached user's presence current without refetching them.
+    pub(crate) fn update_presence(&self, user_id: &str, presence: Presence) {
+        if let Some(mut user) = self.cache.get(user_id) {
+            user.set_presence(presence);
+            self.cache.insert(user_id.to_string(), user);
+        }
+    }
+
     async fn fetch_merged(&self, base: PublicUser) -> Result<User> {
         let profile = self.core.client.get_user(&base.id).await?;
         let user = User::new(base, profile);
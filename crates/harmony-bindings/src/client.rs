@@ -45,6 +45,7 @@ impl HarmonyClient {
             .inner
             .get_channels()
             .await?
+            .channels
             .into_iter()
             .map(Into::into)
             .collect();
@@ -87,6 +88,117 @@ impl HarmonyClient {
         Ok(message)
     }
 
+    pub async fn get_thread(
+        &self,
+        message_id: String,
+        limit: Option<i64>,
+        latest: Option<bool>,
+        before: Option<String>,
+        after: Option<String>,
+    ) -> HarmonyResult<Vec<Message>> {
+        let messages = self
+            .inner
+            .get_thread(&message_id, limit, latest, before, after)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        Ok(messages)
+    }
+
+    pub async fn send_thread_reply(
+        &self,
+        channel_id: String,
+        parent_id: String,
+        content: Vec<u8>,
+        attachment_ids: Vec<String>,
+    ) -> HarmonyResult<Message> {
+        Ok(self
+            .inner
+            .send_thread_reply(&channel_id, &parent_id, content, attachment_ids)
+            .await?
+            .into())
+    }
+
+    pub async fn send_message_with_attachments(
+        &self,
+        channel_id: String,
+        content: Vec<u8>,
+        attachment_ids: Vec<String>,
+    ) -> HarmonyResult<Message> {
+        Ok(self
+            .inner
+            .send_message_with_attachments(&channel_id, content, attachment_ids)
+            .await?
+            .into())
+    }
+
+    pub async fn create_attachment(
+        &self,
+        channel_id: String,
+        size: u64,
+        chunk_count: u32,
+        expires_in: Option<u64>,
+    ) -> HarmonyResult<Attachment> {
+        Ok(self
+            .inner
+            .create_attachment(&channel_id, size, chunk_count, expires_in)
+            .await?
+            .into())
+    }
+
+    pub async fn upload_chunk(
+        &self,
+        attachment_id: String,
+        index: u32,
+        data: Vec<u8>,
+    ) -> HarmonyResult<()> {
+        self.inner.upload_chunk(&attachment_id, index, data).await?;
+        Ok(())
+    }
+
+    pub async fn finish_attachment(&self, attachment_id: String) -> HarmonyResult<Attachment> {
+        Ok(self.inner.finish_attachment(&attachment_id).await?.into())
+    }
+
+    pub async fn download_chunk(
+        &self,
+        attachment_id: String,
+        index: u32,
+    ) -> HarmonyResult<Vec<u8>> {
+        Ok(self.inner.download_chunk(&attachment_id, index).await?)
+    }
+
+    pub async fn delete_attachment(&self, attachment_id: String) -> HarmonyResult<()> {
+        self.inner.delete_attachment(&attachment_id).await?;
+        Ok(())
+    }
+
+    pub async fn get_read_states(&self) -> HarmonyResult<Vec<ReadState>> {
+        let states = self
+            .inner
+            .get_channels()
+            .await?
+            .read_states
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        Ok(states)
+    }
+
+    pub async fn set_typing(&self, channel_id: String) -> HarmonyResult<()> {
+        self.inner.set_typing(&channel_id).await?;
+        Ok(())
+    }
+
+    pub async fn mark_read(
+        &self,
+        channel_id: String,
+        message_id: String,
+    ) -> HarmonyResult<ReadState> {
+        Ok(self.inner.mark_read(&channel_id, &message_id).await?.into())
+    }
+
     pub async fn create_invite(
         &self,
         channel_id: String,
@@ -126,10 +238,11 @@ impl HarmonyClient {
         &self,
         channel_id: String,
         preferred_region: Option<Region>,
+        name: Option<String>,
     ) -> HarmonyResult<StartCallResponse> {
         let response: StartCallResponse = self
             .inner
-            .start_call(&channel_id, preferred_region.map(Into::into))
+            .start_call(&channel_id, preferred_region.map(Into::into), name)
             .await?
             .into();
         Ok(response)
@@ -138,40 +251,53 @@ impl HarmonyClient {
     pub async fn create_call_token(
         &self,
         channel_id: String,
+        call_id: Option<String>,
         initial_muted: bool,
         initial_deafened: bool,
+        preferred_region: Option<Region>,
     ) -> HarmonyResult<CreateCallTokenResponse> {
         let response = self
             .inner
-            .create_call_token(&channel_id, initial_muted, initial_deafened)
+            .create_call_token(
+                &channel_id,
+                call_id.as_deref(),
+                initial_muted,
+                initial_deafened,
+                preferred_region.map(Into::into),
+            )
             .await?
             .into();
         Ok(response)
     }
 
-    pub async fn end_call(&self, channel_id: String) -> HarmonyResult<()> {
-        self.inner.end_call(&channel_id).await?;
+    pub async fn end_call(&self, channel_id: String, call_id: Option<String>) -> HarmonyResult<()> {
+        self.inner.end_call(&channel_id, call_id.as_deref()).await?;
         Ok(())
     }
 
     pub async fn update_voice_state(
         &self,
         channel_id: String,
+        call_id: Option<String>,
         muted: Option<bool>,
         deafened: Option<bool>,
     ) -> HarmonyResult<UpdateVoiceStateResponse> {
         let response = self
             .inner
-            .update_voice_state(&channel_id, muted, deafened)
+            .update_voice_state(&channel_id, call_id.as_deref(), muted, deafened)
             .await?
             .into();
         Ok(response)
     }
 
-    pub async fn get_call_members(&self, channel_id: String) -> HarmonyResult<Vec<CallMember>> {
+    pub async fn get_call_members(
+        &self,
+        channel_id: String,
+        call_id: Option<String>,
+    ) -> HarmonyResult<Vec<CallMember>> {
         let members: Vec<CallMember> = self
             .inner
-            .get_call_members(&channel_id)
+            .get_call_members(&channel_id, call_id.as_deref())
             .await?
             .into_iter()
             .map(Into::into)
@@ -179,6 +305,75 @@ impl HarmonyClient {
         Ok(members)
     }
 
+    pub async fn get_calls(&self, channel_id: String) -> HarmonyResult<Vec<CallRoom>> {
+        let calls: Vec<CallRoom> = self
+            .inner
+            .get_calls(&channel_id)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        Ok(calls)
+    }
+
+    pub async fn move_session(
+        &self,
+        channel_id: String,
+        session_id: String,
+        call_id: String,
+    ) -> HarmonyResult<()> {
+        self.inner
+            .move_session(&channel_id, &session_id, &call_id)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn decline_call(&self, channel_id: String) -> HarmonyResult<()> {
+        self.inner.decline_call(&channel_id).await?;
+        Ok(())
+    }
+
+    pub async fn get_call_history(
+        &self,
+        channel_id: String,
+        limit: Option<i64>,
+        before: Option<String>,
+    ) -> HarmonyResult<Vec<CallRecord>> {
+        let calls: Vec<CallRecord> = self
+            .inner
+            .get_call_history(&channel_id, limit, before)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        Ok(calls)
+    }
+
+    pub async fn restrict_call_session(
+        &self,
+        channel_id: String,
+        session_id: String,
+        restrictions: SessionRestrictions,
+    ) -> HarmonyResult<CallMember> {
+        let member = self
+            .inner
+            .restrict_call_session(&channel_id, &session_id, restrictions.into())
+            .await?
+            .into();
+        Ok(member)
+    }
+
+    pub async fn disconnect_call_session(
+        &self,
+        channel_id: String,
+        session_id: String,
+    ) -> HarmonyResult<()> {
+        self.inner
+            .disconnect_call_session(&channel_id, &session_id)
+            .await?;
+        Ok(())
+    }
+
     pub fn is_connected(&self) -> bool {
         self.inner.is_connected()
     }
@@ -229,6 +424,86 @@ impl HarmonyClient {
         Ok(())
     }
 
+    pub async fn kick_member(&self, channel_id: String, user_id: String) -> HarmonyResult<()> {
+        self.inner.kick_member(&channel_id, &user_id).await?;
+        Ok(())
+    }
+
+    pub async fn ban_member(&self, channel_id: String, user_id: String) -> HarmonyResult<()> {
+        self.inner.ban_member(&channel_id, &user_id).await?;
+        Ok(())
+    }
+
+    pub async fn unban_member(&self, channel_id: String, user_id: String) -> HarmonyResult<()> {
+        self.inner.unban_member(&channel_id, &user_id).await?;
+        Ok(())
+    }
+
+    pub async fn approve_member(&self, channel_id: String, user_id: String) -> HarmonyResult<()> {
+        self.inner.approve_member(&channel_id, &user_id).await?;
+        Ok(())
+    }
+
+    pub async fn reject_member(&self, channel_id: String, user_id: String) -> HarmonyResult<()> {
+        self.inner.reject_member(&channel_id, &user_id).await?;
+        Ok(())
+    }
+
+    pub async fn create_role(
+        &self,
+        channel_id: String,
+        name: String,
+        permissions: i64,
+    ) -> HarmonyResult<ChannelRole> {
+        Ok(self
+            .inner
+            .create_role(&channel_id, &name, permissions.into())
+            .await?
+            .into())
+    }
+
+    pub async fn edit_role(
+        &self,
+        channel_id: String,
+        role_id: String,
+        name: Option<String>,
+        permissions: Option<i64>,
+    ) -> HarmonyResult<ChannelRole> {
+        Ok(self
+            .inner
+            .edit_role(&channel_id, &role_id, name, permissions.map(Into::into))
+            .await?
+            .into())
+    }
+
+    pub async fn delete_role(&self, channel_id: String, role_id: String) -> HarmonyResult<()> {
+        self.inner.delete_role(&channel_id, &role_id).await?;
+        Ok(())
+    }
+
+    pub async fn set_member_roles(
+        &self,
+        channel_id: String,
+        user_id: String,
+        role_ids: Vec<String>,
+    ) -> HarmonyResult<()> {
+        self.inner
+            .set_member_roles(&channel_id, &user_id, role_ids)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_default_permissions(
+        &self,
+        channel_id: String,
+        permissions: i64,
+    ) -> HarmonyResult<()> {
+        self.inner
+            .set_default_permissions(&channel_id, permissions.into())
+            .await?;
+        Ok(())
+    }
+
     pub async fn edit_message(
         &self,
         message_id: String,
@@ -301,4 +576,16 @@ impl HarmonyClient {
     pub async fn unblock_contact(&self, user_id: String) -> HarmonyResult<ContactExtended> {
         Ok(self.inner.unblock_contact(&user_id).await?.into())
     }
+
+    pub async fn set_presence(
+        &self,
+        status: Option<Status>,
+        message: Option<String>,
+    ) -> HarmonyResult<Presence> {
+        Ok(self
+            .inner
+            .set_presence(status.map(Into::into), message)
+            .await?
+            .into())
+    }
 }
@@ -58,6 +58,9 @@ impl From<harmony_api::HarmonyError> for HarmonyBindingError {
             harmony_api::HarmonyError::Serialization(e) => HarmonyBindingError::Serialization {
                 reason: e.to_string(),
             },
+            harmony_api::HarmonyError::InvalidMessageBody => HarmonyBindingError::Serialization {
+                reason: "invalid message body header".to_string(),
+            },
             harmony_api::HarmonyError::Http(e) => HarmonyBindingError::Internal {
                 reason: e.to_string(),
             },
@@ -91,14 +94,16 @@ impl From<harmony_api::HarmonyError> for HarmonyBindingError {
             }
             error @ (harmony_api::HarmonyError::ContactNotFound
             | harmony_api::HarmonyError::RequesterPublicKeyUnavailable
-            | harmony_api::HarmonyError::UnexpectedRelationshipState) => {
-                HarmonyBindingError::Internal {
-                    reason: error.to_string(),
-                }
-            }
+            | harmony_api::HarmonyError::UnexpectedRelationshipState
+            | harmony_api::HarmonyError::ReactionNotFound) => HarmonyBindingError::Internal {
+                reason: error.to_string(),
+            },
             harmony_api::HarmonyError::Crypto(e) => HarmonyBindingError::Crypto {
                 reason: e.to_string(),
             },
+            harmony_api::HarmonyError::Mls(e) => HarmonyBindingError::Crypto {
+                reason: e.to_string(),
+            },
             harmony_api::HarmonyError::Core(e) => e.into(),
         }
     }
@@ -40,18 +40,44 @@ impl ChannelManager {
             .into())
     }
 
+    pub async fn create_mls_group_channel(&self, metadata: Vec<u8>) -> HarmonyResult<Channel> {
+        Ok(self
+            .inner
+            .create_mls_group_channel(&metadata)
+            .await?
+            .data()
+            .clone()
+            .into())
+    }
+
     pub async fn create_group_invite(&self, channel_id: String) -> HarmonyResult<String> {
         Ok(self.inner.create_group_invite(&channel_id).await?)
     }
 
+    pub async fn invite_contacts(
+        &self,
+        channel_id: String,
+        user_ids: Vec<String>,
+    ) -> HarmonyResult<String> {
+        Ok(self.inner.invite_contacts(&channel_id, &user_ids).await?)
+    }
+
+    pub async fn share_group_key(
+        &self,
+        channel_id: String,
+        user_ids: Vec<String>,
+    ) -> HarmonyResult<()> {
+        Ok(self.inner.share_group_key(&channel_id, &user_ids).await?)
+    }
+
     pub async fn join_group(
         &self,
         invite_code: String,
-        group_key: Vec<u8>,
+        group_key: Option<Vec<u8>>,
     ) -> HarmonyResult<String> {
         Ok(self
             .inner
-            .join_group(&invite_code, &group_key)
+            .join_group(&invite_code, group_key.as_deref())
             .await?
             .id()
             .to_string())
@@ -60,6 +86,19 @@ impl ChannelManager {
     pub async fn get_group_key(&self, channel_id: String) -> Option<Vec<u8>> {
         self.inner.get_group_key(&channel_id).await
     }
+
+    pub fn search(
+        &self,
+        query: String,
+        channel_id: Option<String>,
+        limit: u32,
+    ) -> Vec<SearchResult> {
+        self.inner
+            .search(&query, channel_id.as_deref(), limit as usize)
+            .into_iter()
+            .map(Into::into)
+            .collect()
+    }
 }
 
 #[derive(uniffi::Object)]
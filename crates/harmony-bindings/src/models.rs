@@ -1,3 +1,5 @@
+use crate::error::{HarmonyBindingError, HarmonyResult};
+
 #[derive(Clone, Debug, uniffi::Record)]
 pub struct UserProfile {
     pub id: String,
@@ -51,6 +53,18 @@ impl From<harmony_api::Status> for Status {
     }
 }
 
+impl From<Status> for harmony_api::Status {
+    fn from(status: Status) -> Self {
+        match status {
+            Status::Online => harmony_api::Status::Online,
+            Status::Idle => harmony_api::Status::Idle,
+            Status::Busy => harmony_api::Status::Busy,
+            Status::BusyNotify => harmony_api::Status::BusyNotify,
+            Status::Offline => harmony_api::Status::Offline,
+        }
+    }
+}
+
 #[derive(Clone, Debug, uniffi::Record)]
 pub struct Presence {
     pub status: Status,
@@ -66,6 +80,209 @@ impl From<harmony_api::Presence> for Presence {
     }
 }
 
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct ReadState {
+    pub channel_id: String,
+    pub last_read_id: Option<String>,
+    pub unread_count: u64,
+}
+
+impl From<harmony_api::ReadState> for ReadState {
+    fn from(state: harmony_api::ReadState) -> Self {
+        Self {
+            channel_id: state.channel_id,
+            last_read_id: state.last_read_id,
+            unread_count: state.unread_count,
+        }
+    }
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct Attachment {
+    pub id: String,
+    pub channel_id: String,
+    pub uploader_id: String,
+    pub size: u64,
+    pub chunk_count: u32,
+    pub complete: bool,
+    pub message_id: Option<String>,
+    pub expires_at: Option<i64>,
+}
+
+impl From<harmony_api::Attachment> for Attachment {
+    fn from(attachment: harmony_api::Attachment) -> Self {
+        Self {
+            id: attachment.id,
+            channel_id: attachment.channel_id,
+            uploader_id: attachment.uploader_id,
+            size: attachment.size,
+            chunk_count: attachment.chunk_count,
+            complete: attachment.complete,
+            message_id: attachment.message_id,
+            expires_at: attachment.expires_at,
+        }
+    }
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct AttachmentReference {
+    pub id: String,
+    pub key: Vec<u8>,
+    pub name: String,
+    pub mime_type: String,
+    pub size: u64,
+    pub chunk_count: u32,
+}
+
+impl From<harmony_api::AttachmentReference> for AttachmentReference {
+    fn from(reference: harmony_api::AttachmentReference) -> Self {
+        Self {
+            id: reference.id,
+            key: reference.key.to_vec(),
+            name: reference.name,
+            mime_type: reference.mime_type,
+            size: reference.size,
+            chunk_count: reference.chunk_count,
+        }
+    }
+}
+
+impl TryFrom<AttachmentReference> for harmony_api::AttachmentReference {
+    type Error = HarmonyBindingError;
+
+    fn try_from(reference: AttachmentReference) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: reference.id,
+            key: reference
+                .key
+                .try_into()
+                .map_err(|_| HarmonyBindingError::InvalidInput {
+                    reason: "expected 32-byte attachment key".into(),
+                })?,
+            name: reference.name,
+            mime_type: reference.mime_type,
+            size: reference.size,
+            chunk_count: reference.chunk_count,
+        })
+    }
+}
+
+#[derive(Clone, Debug, uniffi::Enum)]
+pub enum SystemMessage {
+    CallSummary {
+        started_at: i64,
+        duration: u64,
+        participant_ids: Vec<String>,
+    },
+    Unknown,
+}
+
+impl From<harmony_api::SystemMessage> for SystemMessage {
+    fn from(system: harmony_api::SystemMessage) -> Self {
+        match system {
+            harmony_api::SystemMessage::CallSummary {
+                started_at,
+                duration,
+                participant_ids,
+            } => SystemMessage::CallSummary {
+                started_at,
+                duration,
+                participant_ids,
+            },
+            harmony_api::SystemMessage::Unknown => SystemMessage::Unknown,
+        }
+    }
+}
+
+impl From<SystemMessage> for harmony_api::SystemMessage {
+    fn from(system: SystemMessage) -> Self {
+        match system {
+            SystemMessage::CallSummary {
+                started_at,
+                duration,
+                participant_ids,
+            } => harmony_api::SystemMessage::CallSummary {
+                started_at,
+                duration,
+                participant_ids,
+            },
+            SystemMessage::Unknown => harmony_api::SystemMessage::Unknown,
+        }
+    }
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct MessageBody {
+    pub text: String,
+    pub reply_to: Option<String>,
+    pub mentions: Vec<String>,
+    pub attachments: Vec<AttachmentReference>,
+    pub system: Option<SystemMessage>,
+}
+
+impl From<harmony_api::MessageBody> for MessageBody {
+    fn from(body: harmony_api::MessageBody) -> Self {
+        Self {
+            text: body.text,
+            reply_to: body.reply_to,
+            mentions: body.mentions,
+            attachments: body.attachments.into_iter().map(Into::into).collect(),
+            system: body.system.map(Into::into),
+        }
+    }
+}
+
+impl TryFrom<MessageBody> for harmony_api::MessageBody {
+    type Error = HarmonyBindingError;
+
+    fn try_from(body: MessageBody) -> Result<Self, Self::Error> {
+        Ok(Self {
+            text: body.text,
+            reply_to: body.reply_to,
+            mentions: body.mentions,
+            attachments: body
+                .attachments
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+            system: body.system.map(Into::into),
+        })
+    }
+}
+
+/// Encode a message body into the plaintext that gets encrypted and sent.
+#[uniffi::export]
+pub fn encode_message_body(body: MessageBody) -> HarmonyResult<Vec<u8>> {
+    let body: harmony_api::MessageBody = body.try_into()?;
+    Ok(body.to_bytes())
+}
+
+/// Decode decrypted message content. Plain UTF-8 from older clients comes
+/// back as a text-only body.
+#[uniffi::export]
+pub fn decode_message_body(content: Vec<u8>) -> HarmonyResult<MessageBody> {
+    Ok(harmony_api::MessageBody::from_bytes(&content)?.into())
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct SearchResult {
+    pub channel_id: String,
+    pub message_id: String,
+    pub author_id: String,
+    pub text: String,
+}
+
+impl From<harmony_api::SearchResult> for SearchResult {
+    fn from(result: harmony_api::SearchResult) -> Self {
+        Self {
+            channel_id: result.channel_id,
+            message_id: result.message_id,
+            author_id: result.author_id,
+            text: result.text,
+        }
+    }
+}
+
 #[derive(Clone, Debug, uniffi::Record)]
 pub struct HybridPublicKey {
     pub x25519: Vec<u8>,
@@ -204,6 +421,7 @@ impl From<harmony_api::ContactExtended> for ContactExtended {
 pub struct ChannelMember {
     pub id: String,
     pub role: ChannelMemberRole,
+    pub roles: Vec<String>,
 }
 
 impl From<harmony_api::ChannelMember> for ChannelMember {
@@ -211,6 +429,24 @@ impl From<harmony_api::ChannelMember> for ChannelMember {
         Self {
             id: member.id,
             role: member.role.into(),
+            roles: member.roles,
+        }
+    }
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct ChannelRole {
+    pub id: String,
+    pub name: String,
+    pub permissions: i64,
+}
+
+impl From<harmony_api::ChannelRole> for ChannelRole {
+    fn from(role: harmony_api::ChannelRole) -> Self {
+        Self {
+            id: role.id,
+            name: role.name,
+            permissions: role.permissions.to_i64(),
         }
     }
 }
@@ -254,6 +490,25 @@ impl From<EncryptionHint> for harmony_api::EncryptionHint {
     }
 }
 
+#[derive(Clone, Debug, uniffi::Enum)]
+pub enum PendingMessageKind {
+    Application,
+    KeyPackage,
+    Commit,
+    Welcome,
+}
+
+impl From<harmony_api::PendingMessageKind> for PendingMessageKind {
+    fn from(kind: harmony_api::PendingMessageKind) -> Self {
+        match kind {
+            harmony_api::PendingMessageKind::Application => PendingMessageKind::Application,
+            harmony_api::PendingMessageKind::KeyPackage => PendingMessageKind::KeyPackage,
+            harmony_api::PendingMessageKind::Commit => PendingMessageKind::Commit,
+            harmony_api::PendingMessageKind::Welcome => PendingMessageKind::Welcome,
+        }
+    }
+}
+
 #[derive(Clone, Debug, uniffi::Record)]
 pub struct AvatarUrl {
     pub id: String,
@@ -381,6 +636,9 @@ pub enum Channel {
         pending_members: Vec<String>,
         blacklist: Vec<String>,
         encryption_hint: EncryptionHint,
+        last_key_id: Option<String>,
+        roles: Vec<ChannelRole>,
+        default_permissions: i64,
     },
 }
 
@@ -405,6 +663,9 @@ impl From<harmony_api::ChannelData> for Channel {
                 pending_members,
                 blacklist,
                 encryption_hint,
+                last_key_id,
+                roles,
+                default_permissions,
             } => Channel::GroupChannel {
                 id,
                 metadata,
@@ -412,11 +673,38 @@ impl From<harmony_api::ChannelData> for Channel {
                 pending_members,
                 blacklist,
                 encryption_hint: encryption_hint.into(),
+                last_key_id,
+                roles: roles.into_iter().map(Into::into).collect(),
+                default_permissions: default_permissions.to_i64(),
             },
         }
     }
 }
 
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct Reaction {
+    pub user_id: String,
+    pub data: Vec<u8>,
+}
+
+impl From<harmony_api::Reaction> for Reaction {
+    fn from(reaction: harmony_api::Reaction) -> Self {
+        Self {
+            user_id: reaction.user_id,
+            data: reaction.data,
+        }
+    }
+}
+
+impl From<Reaction> for harmony_api::Reaction {
+    fn from(reaction: Reaction) -> Self {
+        Self {
+            user_id: reaction.user_id,
+            data: reaction.data,
+        }
+    }
+}
+
 #[derive(Clone, Debug, uniffi::Record)]
 pub struct Message {
     pub id: String,
@@ -425,6 +713,8 @@ pub struct Message {
     pub edited_at: Option<i64>,
     pub channel_id: String,
     pub key_id: Option<String>,
+    pub reactions: Vec<Reaction>,
+    pub parent_id: Option<String>,
 }
 
 impl From<harmony_api::Message> for Message {
@@ -436,6 +726,8 @@ impl From<harmony_api::Message> for Message {
             edited_at: message.edited_at,
             channel_id: message.channel_id,
             key_id: message.key_id,
+            reactions: message.reactions.into_iter().map(Into::into).collect(),
+            parent_id: message.parent_id,
         }
     }
 }
@@ -449,6 +741,8 @@ impl From<Message> for harmony_api::Message {
             edited_at: message.edited_at,
             channel_id: message.channel_id,
             key_id: message.key_id,
+            reactions: message.reactions.into_iter().map(Into::into).collect(),
+            parent_id: message.parent_id,
         }
     }
 }
@@ -683,6 +977,7 @@ pub struct CallMember {
     pub session_id: String,
     pub muted: bool,
     pub deafened: bool,
+    pub restrictions: SessionRestrictions,
 }
 
 impl From<harmony_api::CallMember> for CallMember {
@@ -692,6 +987,100 @@ impl From<harmony_api::CallMember> for CallMember {
             session_id: member.session_id,
             muted: member.muted,
             deafened: member.deafened,
+            restrictions: member.restrictions.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct CallRoom {
+    pub id: String,
+    pub name: Option<String>,
+    pub members: Vec<CallMember>,
+}
+
+impl From<harmony_api::CallRoom> for CallRoom {
+    fn from(room: harmony_api::CallRoom) -> Self {
+        Self {
+            id: room.id,
+            name: room.name,
+            members: room.members.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, uniffi::Record)]
+pub struct SessionRestrictions {
+    pub muted: bool,
+    pub deafened: bool,
+    pub video_disabled: bool,
+    pub screen_disabled: bool,
+}
+
+impl From<harmony_api::SessionRestrictions> for SessionRestrictions {
+    fn from(restrictions: harmony_api::SessionRestrictions) -> Self {
+        Self {
+            muted: restrictions.muted,
+            deafened: restrictions.deafened,
+            video_disabled: restrictions.video_disabled,
+            screen_disabled: restrictions.screen_disabled,
+        }
+    }
+}
+
+impl From<SessionRestrictions> for harmony_api::SessionRestrictions {
+    fn from(restrictions: SessionRestrictions) -> Self {
+        Self {
+            muted: restrictions.muted,
+            deafened: restrictions.deafened,
+            video_disabled: restrictions.video_disabled,
+            screen_disabled: restrictions.screen_disabled,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Enum)]
+pub enum RingOutcome {
+    Accepted,
+    Declined,
+    Missed,
+}
+
+impl From<harmony_api::RingOutcome> for RingOutcome {
+    fn from(outcome: harmony_api::RingOutcome) -> Self {
+        match outcome {
+            harmony_api::RingOutcome::Accepted => RingOutcome::Accepted,
+            harmony_api::RingOutcome::Declined => RingOutcome::Declined,
+            harmony_api::RingOutcome::Missed => RingOutcome::Missed,
+        }
+    }
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct CallRecord {
+    pub id: String,
+    pub channel_id: String,
+    pub initiator_id: String,
+    pub participant_ids: Vec<String>,
+    pub declined_ids: Vec<String>,
+    pub missed_ids: Vec<String>,
+    pub started_at: i64,
+    pub ended_at: Option<i64>,
+    pub duration: Option<u64>,
+}
+
+impl From<harmony_api::CallRecord> for CallRecord {
+    fn from(call: harmony_api::CallRecord) -> Self {
+        Self {
+            id: call.id,
+            channel_id: call.channel_id,
+            initiator_id: call.initiator_id,
+            participant_ids: call.participant_ids,
+            declined_ids: call.declined_ids,
+            missed_ids: call.missed_ids,
+            started_at: call.started_at,
+            ended_at: call.ended_at,
+            duration: call.duration,
         }
     }
 }
@@ -701,19 +1090,48 @@ pub enum Event {
     NewMessage {
         message: Message,
         channel_id: String,
+        // only set by the encrypted client, which can decrypt the message
+        body: Option<MessageBody>,
     },
     MessageEdited {
         message: Message,
         channel_id: String,
+        body: Option<MessageBody>,
     },
     MessageDeleted {
         message_id: String,
         channel_id: String,
     },
+    ReactionAdded {
+        message_id: String,
+        channel_id: String,
+        reaction: Reaction,
+    },
+    ReactionRemoved {
+        message_id: String,
+        channel_id: String,
+        reaction: Reaction,
+    },
+    PendingMessage {
+        message_id: String,
+        channel_id: String,
+        kind: PendingMessageKind,
+    },
     ContactStateChanged {
         user_id: String,
         state: RelationshipState,
     },
+    PresenceChanged {
+        user_id: String,
+        presence: Presence,
+    },
+    TypingStarted {
+        channel_id: String,
+        user_id: String,
+    },
+    ReadStateUpdated {
+        read_state: ReadState,
+    },
     ChannelUpdated {
         channel: Channel,
     },
@@ -734,7 +1152,9 @@ pub enum Event {
         attempt: u32,
         max_attempts: u32,
     },
-    Reconnected,
+    Reconnected {
+        resumed: bool,
+    },
     ReconnectionFailed {
         attempts: u32,
     },
@@ -759,6 +1179,32 @@ pub enum Event {
         call_id: String,
         server_address: String,
     },
+    CallStarted {
+        call_id: String,
+        channel_id: String,
+        initiator_id: String,
+        name: Option<String>,
+        ringing: Vec<String>,
+    },
+    CallEnded {
+        call_id: String,
+        channel_id: String,
+        duration: u64,
+    },
+    CallRingStopped {
+        call_id: String,
+        channel_id: String,
+        user_id: String,
+        outcome: RingOutcome,
+    },
+    CallSessionModerated {
+        call_id: String,
+        session_id: String,
+        user_id: String,
+        moderator_id: String,
+        restrictions: SessionRestrictions,
+        disconnected: bool,
+    },
     ContactAdded {
         outcome: AddContactOutcome,
     },
@@ -767,7 +1213,7 @@ pub enum Event {
 impl From<harmony_api::ClientEvent> for Event {
     fn from(event: harmony_api::ClientEvent) -> Self {
         match event {
-            harmony_api::ClientEvent::Event(e) => e.into(),
+            harmony_api::ClientEvent::Event(e) => (*e).into(),
             harmony_api::ClientEvent::Lifecycle(l) => l.into(),
         }
     }
@@ -785,7 +1231,7 @@ impl From<harmony_api::LifecycleEvent> for Event {
                 attempt,
                 max_attempts,
             },
-            harmony_api::LifecycleEvent::Reconnected => Event::Reconnected,
+            harmony_api::LifecycleEvent::Reconnected { resumed } => Event::Reconnected { resumed },
             harmony_api::LifecycleEvent::ReconnectionFailed { attempts } => {
                 Event::ReconnectionFailed { attempts }
             }
@@ -799,21 +1245,42 @@ impl From<harmony_api::Event> for Event {
             harmony_api::Event::NewMessage(e) => Event::NewMessage {
                 message: e.message.into(),
                 channel_id: e.channel_id,
+                body: None,
             },
             harmony_api::Event::MessageEdited(e) => Event::MessageEdited {
                 message: e.message.into(),
                 channel_id: e.channel_id,
+                body: None,
             },
             harmony_api::Event::MessageDeleted(e) => Event::MessageDeleted {
                 message_id: e.message_id,
                 channel_id: e.channel_id,
             },
+            harmony_api::Event::ReactionAdded(e) => Event::ReactionAdded {
+                message_id: e.message_id,
+                channel_id: e.channel_id,
+                reaction: e.reaction.into(),
+            },
+            harmony_api::Event::ReactionRemoved(e) => Event::ReactionRemoved {
+                message_id: e.message_id,
+                channel_id: e.channel_id,
+                reaction: e.reaction.into(),
+            },
+            harmony_api::Event::PendingMessage(e) => Event::PendingMessage {
+                message_id: e.message.id,
+                channel_id: e.message.channel_id,
+                kind: e.message.kind.into(),
+            },
             harmony_api::Event::ContactStateChanged { user_id, state } => {
                 Event::ContactStateChanged {
                     user_id,
                     state: state.into(),
                 }
             }
+            harmony_api::Event::PresenceChanged(e) => Event::PresenceChanged {
+                user_id: e.user_id,
+                presence: e.presence.into(),
+            },
             harmony_api::Event::ChannelUpdated(e) => Event::ChannelUpdated {
                 channel: e.channel.into(),
             },
@@ -849,6 +1316,39 @@ impl From<harmony_api::Event> for Event {
                 call_id: e.call_id,
                 server_address: e.server_address,
             },
+            harmony_api::Event::CallStarted(e) => Event::CallStarted {
+                call_id: e.call_id,
+                channel_id: e.channel_id,
+                initiator_id: e.initiator_id,
+                name: e.name,
+                ringing: e.ringing,
+            },
+            harmony_api::Event::CallEnded(e) => Event::CallEnded {
+                call_id: e.call_id,
+                channel_id: e.channel_id,
+                duration: e.duration,
+            },
+            harmony_api::Event::CallRingStopped(e) => Event::CallRingStopped {
+                call_id: e.call_id,
+                channel_id: e.channel_id,
+                user_id: e.user_id,
+                outcome: e.outcome.into(),
+            },
+            harmony_api::Event::CallSessionModerated(e) => Event::CallSessionModerated {
+                call_id: e.call_id,
+                session_id: e.session_id,
+                user_id: e.user_id,
+                moderator_id: e.moderator_id,
+                restrictions: e.restrictions.into(),
+                disconnected: e.disconnected,
+            },
+            harmony_api::Event::TypingStarted(e) => Event::TypingStarted {
+                channel_id: e.channel_id,
+                user_id: e.user_id,
+            },
+            harmony_api::Event::ReadStateUpdated(e) => Event::ReadStateUpdated {
+                read_state: e.read_state.into(),
+            },
         }
     }
 }
@@ -864,6 +1364,7 @@ impl From<harmony_api::EncryptedEvent> for Event {
             } => Event::NewMessage {
                 message: message.message.into(),
                 channel_id,
+                body: Some(message.body.into()),
             },
             E::MessageEdited {
                 channel_id,
@@ -871,6 +1372,7 @@ impl From<harmony_api::EncryptedEvent> for Event {
             } => Event::MessageEdited {
                 message: message.message.into(),
                 channel_id,
+                body: Some(message.body.into()),
             },
             E::MessageDeleted {
                 channel_id,
@@ -879,6 +1381,30 @@ impl From<harmony_api::EncryptedEvent> for Event {
                 message_id,
                 channel_id,
             },
+            E::ReactionAdded {
+                channel_id,
+                message_id,
+                reaction,
+            } => Event::ReactionAdded {
+                message_id,
+                channel_id,
+                reaction: Reaction {
+                    user_id: reaction.user_id,
+                    data: reaction.content,
+                },
+            },
+            E::ReactionRemoved {
+                channel_id,
+                message_id,
+                reaction,
+            } => Event::ReactionRemoved {
+                message_id,
+                channel_id,
+                reaction: Reaction {
+                    user_id: reaction.user_id,
+                    data: reaction.content,
+                },
+            },
             E::ChannelUpdated { channel } => Event::ChannelUpdated {
                 channel: channel.data().clone().into(),
             },
@@ -918,6 +1444,32 @@ impl From<harmony_api::EncryptedEvent> for Event {
                 call_id: e.call_id,
                 server_address: e.server_address,
             },
+            E::CallStarted(e) => Event::CallStarted {
+                call_id: e.call_id,
+                channel_id: e.channel_id,
+                initiator_id: e.initiator_id,
+                name: e.name,
+                ringing: e.ringing,
+            },
+            E::CallEnded(e) => Event::CallEnded {
+                call_id: e.call_id,
+                channel_id: e.channel_id,
+                duration: e.duration,
+            },
+            E::CallRingStopped(e) => Event::CallRingStopped {
+                call_id: e.call_id,
+                channel_id: e.channel_id,
+                user_id: e.user_id,
+                outcome: e.outcome.into(),
+            },
+            E::CallSessionModerated(e) => Event::CallSessionModerated {
+                call_id: e.call_id,
+                session_id: e.session_id,
+                user_id: e.user_id,
+                moderator_id: e.moderator_id,
+                restrictions: e.restrictions.into(),
+                disconnected: e.disconnected,
+            },
             E::ContactStateChanged { user_id, state } => Event::ContactStateChanged {
                 user_id,
                 state: state.into(),
@@ -925,6 +1477,17 @@ impl From<harmony_api::EncryptedEvent> for Event {
             E::ContactAdded(outcome) => Event::ContactAdded {
                 outcome: outcome.into(),
             },
+            E::PresenceChanged(e) => Event::PresenceChanged {
+                user_id: e.user_id,
+                presence: e.presence.into(),
+            },
+            E::TypingStarted(e) => Event::TypingStarted {
+                channel_id: e.channel_id,
+                user_id: e.user_id,
+            },
+            E::ReadStateUpdated(read_state) => Event::ReadStateUpdated {
+                read_state: read_state.into(),
+            },
         }
     }
 }
@@ -935,6 +1498,7 @@ pub struct ClientOptions {
     pub timeout_seconds: u64,
     pub auto_reconnect: bool,
     pub max_reconnect_attempts: u32,
+    pub device_id: Option<String>,
 }
 
 impl ClientOptions {
@@ -945,6 +1509,7 @@ impl ClientOptions {
             timeout_seconds: 30,
             auto_reconnect: true,
             max_reconnect_attempts: 5,
+            device_id: None,
         }
     }
 
@@ -968,13 +1533,26 @@ impl ClientOptions {
         options.max_reconnect_attempts = attempts;
         options
     }
+
+    /// Should be stored by the app and passed on every launch, so the device
+    /// restores its own MLS state.
+    #[uniffi::method]
+    pub fn with_device_id(&self, device_id: String) -> Self {
+        let mut options = self.clone();
+        options.device_id = Some(device_id);
+        options
+    }
 }
 
 impl From<ClientOptions> for harmony_api::ClientOptions {
     fn from(options: ClientOptions) -> Self {
-        harmony_api::ClientOptions::new(options.server_url)
+        let mut converted = harmony_api::ClientOptions::new(options.server_url)
             .with_timeout(std::time::Duration::from_secs(options.timeout_seconds))
             .with_auto_reconnect(options.auto_reconnect)
-            .with_max_reconnect_attempts(options.max_reconnect_attempts)
+            .with_max_reconnect_attempts(options.max_reconnect_attempts);
+        if let Some(device_id) = options.device_id {
+            converted = converted.with_device_id(device_id);
+        }
+        converted
     }
 }
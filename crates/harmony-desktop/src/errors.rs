@@ -28,6 +28,7 @@ impl From<HarmonyError> for RenderableError {
     fn from(error: HarmonyError) -> Self {
         match error {
             HarmonyError::Crypto(e) => RenderableError::CryptoError(e.to_string()),
+            HarmonyError::Mls(e) => RenderableError::CryptoError(e.to_string()),
             HarmonyError::NotConnected
             | HarmonyError::ConnectionLost
             | HarmonyError::Timeout
@@ -16,7 +16,7 @@ use std::{
 };
 
 use core_api::LoginMfa;
-use harmony_api::{Channel, EncryptedClient};
+use harmony_api::{Channel, EncryptedClient, MessageBody, SystemMessage};
 use iced::{
     Color, Element, Length, Task, Theme,
     widget::{container, mouse_area, stack, text},
@@ -110,7 +110,7 @@ pub struct ChatMessage {
 }
 
 impl ChatMessage {
-    pub fn new(msg: &harmony_api::Message, text: String) -> Self {
+    pub fn new(msg: &harmony_api::Message, body: &MessageBody) -> Self {
         let time = ulid::Ulid::from_string(&msg.id)
             .map(|u| {
                 u.datetime()
@@ -124,11 +124,39 @@ impl ChatMessage {
             author_id: msg.author_id.clone(),
             time,
             formatted_time: format_message_time(time),
-            content: MessageContent::Text(text),
+            content: MessageContent::from_body(body),
         }
     }
 }
 
+impl MessageContent {
+    fn from_body(body: &MessageBody) -> Self {
+        match &body.system {
+            Some(SystemMessage::CallSummary {
+                duration,
+                participant_ids,
+                ..
+            }) => MessageContent::CallCard {
+                channel: format!("{} participants", participant_ids.len()),
+                duration: format_call_duration(*duration),
+            },
+            Some(SystemMessage::Unknown) if body.text.is_empty() => {
+                MessageContent::Text("This message needs a newer version of Harmony".to_string())
+            }
+            _ => MessageContent::Text(body.text.clone()),
+        }
+    }
+}
+
+fn format_call_duration(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
 pub fn format_message_time(timestamp_millis: i64) -> String {
     use chrono::{DateTime, Local, Utc};
     DateTime::<Utc>::from_timestamp_millis(timestamp_millis)
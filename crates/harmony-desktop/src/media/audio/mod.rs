@@ -8,6 +8,7 @@ use std::sync::mpsc as sync_mpsc;
 use anyhow::{Context, Result};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{Stream, StreamConfig};
+use pulse_api::AudioLevel;
 use ringbuf::traits::{Consumer, Observer, Producer, RingBuffer, Split};
 use ringbuf::{HeapCons, HeapProd, HeapRb};
 use tokio::sync::mpsc;
@@ -20,6 +21,12 @@ const CHANNELS: u16 = 2;
 const FRAME_SIZE: usize = 960;
 const MAX_PACKET: usize = 4000;
 
+/// An encoded microphone frame, with the level senders put in its header.
+pub struct CapturedFrame {
+    pub data: Vec<u8>,
+    pub level: AudioLevel,
+}
+
 enum TrackCommand {
     Add {
         id: String,
@@ -65,7 +72,8 @@ pub struct AudioPipeline {
     track_cmd_tx: Option<sync_mpsc::Sender<TrackCommand>>,
 
     capture_stream: Option<Stream>,
-    capture_tx: Option<mpsc::UnboundedSender<Vec<u8>>>,
+    capture_tx: Option<mpsc::UnboundedSender<CapturedFrame>>,
+    capture_bitrate: Arc<AtomicU32>,
 }
 
 impl AudioPipeline {
@@ -77,6 +85,9 @@ impl AudioPipeline {
             track_cmd_tx: None,
             capture_stream: None,
             capture_tx: None,
+            capture_bitrate: Arc::new(AtomicU32::new(
+                pulse_api::BitrateConfig::opus().start_bitrate_bps,
+            )),
         })
     }
 
@@ -228,7 +239,7 @@ impl AudioPipeline {
         Ok(())
     }
 
-    pub fn start_capture(&mut self) -> Result<Option<mpsc::UnboundedReceiver<Vec<u8>>>> {
+    pub fn start_capture(&mut self) -> Result<Option<mpsc::UnboundedReceiver<CapturedFrame>>> {
         if self.capture_stream.is_some() {
             return Ok(None);
         }
@@ -255,12 +266,21 @@ impl AudioPipeline {
             opus::Application::Audio,
         )
         .context("opus encoder init")?;
+        let bitrate = Arc::clone(&self.capture_bitrate);
+        let mut encoder_bitrate = 0;
 
         let stream = device
             .build_input_stream(
                 config,
                 move |data: &[f32], _: &cpal::InputCallbackInfo| {
                     sample_buf.push_slice_overwrite(data);
+                    let target = bitrate.load(Ordering::Relaxed);
+                    if target != encoder_bitrate {
+                        encoder_bitrate = target;
+                        if let Err(e) = encoder.set_bitrate(opus::Bitrate::Bits(target as i32)) {
+                            tracing::warn!("opus set_bitrate: {e}");
+                        }
+                    }
                     let mut frame = [0f32; FRAME_SIZE * CHANNELS as usize];
                     while sample_buf.occupied_len() >= FRAME_SIZE * CHANNELS as usize {
                         sample_buf.pop_slice(&mut frame);
@@ -268,8 +288,11 @@ impl AudioPipeline {
                         match encoder.encode_float(&frame, &mut out) {
                             Ok(len) => {
                                 out.truncate(len);
-                                let packet = codec::prepend_codec_byte(codec::AUDIO_OPUS, &out);
-                                tx.send(packet).ok();
+                                tx.send(CapturedFrame {
+                                    data: codec::prepend_codec_byte(codec::AUDIO_OPUS, &out),
+                                    level: AudioLevel::from_samples(&frame),
+                                })
+                                .ok();
                             }
                             Err(e) => {
                                 tracing::warn!("opus encode error: {e}");
@@ -295,6 +318,11 @@ impl AudioPipeline {
         self.capture_tx = None;
     }
 
+    /// Retarget the microphone encoder, even while capturing.
+    pub fn set_capture_bitrate(&self, bitrate_bps: u32) {
+        self.capture_bitrate.store(bitrate_bps, Ordering::Relaxed);
+    }
+
     pub fn is_capturing(&self) -> bool {
         self.capture_stream.is_some()
     }
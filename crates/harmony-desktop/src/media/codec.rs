@@ -48,6 +48,7 @@ pub struct EncodedPacket {
     pub data: Vec<u8>,
     pub capture_ts_us: u64,
     pub keyframe: bool,
+    pub layer: u8, // simulcast layer, 0 being full quality
 }
 
 pub fn detect_keyframe(codec: u8, data: &[u8]) -> bool {
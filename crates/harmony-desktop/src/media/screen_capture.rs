@@ -1,14 +1,15 @@
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use arc_swap::ArcSwap;
+use pulse_api::BitrateDecision;
 use tokio::sync::mpsc;
 use wgpu_capture::{
-    CaptureFrame, CaptureTarget, Codec, EncodeConfig, EncodeOutput, EncodeSession, create_capturer,
-    create_encoder,
+    CaptureFrame, CaptureTarget, Codec, EncodeConfig, EncodeLayer, EncodeOutput, EncodeSession,
+    create_capturer, create_encoder,
 };
 
 use crate::media::{codec, video::Frame as DecodedFrame};
@@ -42,6 +43,18 @@ fn compute_encode_resolution(src_w: u32, src_h: u32, quality: ScreenQuality) ->
     (w & !1, h)
 }
 
+const MIN_LAYER_BITRATE_BPS: u32 = 150_000;
+
+/// Size and bitrate of simulcast layer `layer`, each layer halving the
+/// resolution of the one above it.
+fn layer_encode_params(width: u32, height: u32, bitrate_bps: u32, layer: u8) -> (u32, u32, u32) {
+    let w = (width >> layer).max(2) & !1;
+    let h = (height >> layer).max(2) & !1;
+    // a quarter of the pixels takes roughly a quarter of the bits
+    let bitrate = (bitrate_bps >> (2 * layer as u32)).max(MIN_LAYER_BITRATE_BPS);
+    (w, h, bitrate)
+}
+
 #[derive(Debug, Clone)]
 pub struct ScreenCaptureConfig {
     pub fps: u32,
@@ -49,6 +62,9 @@ pub struct ScreenCaptureConfig {
     pub quality: ScreenQuality,
     pub source_width: u32,
     pub source_height: u32,
+    /// Simulcast layers to encode, so viewers on poor connections can fall
+    /// back to a lower resolution.
+    pub layers: u8,
 }
 
 impl Default for ScreenCaptureConfig {
@@ -59,6 +75,7 @@ impl Default for ScreenCaptureConfig {
             quality: ScreenQuality::P1080,
             source_width: 1920,
             source_height: 1080,
+            layers: pulse_api::MAX_LAYERS,
         }
     }
 }
@@ -78,6 +95,30 @@ pub enum CaptureTargetList {
     Targets(Vec<CaptureTargetInfo>),
 }
 
+/// Steers a running screen encoder from outside its thread.
+#[derive(Debug, Default)]
+pub struct EncoderControl {
+    keyframe: AtomicBool,
+    bitrate_bps: AtomicU32, // 0 until the bitrate controller decides
+    max_fps: AtomicU32,     // 0 for no cap
+}
+
+impl EncoderControl {
+    pub fn request_keyframe(&self) {
+        self.keyframe.store(true, Ordering::Relaxed);
+    }
+
+    pub fn apply(&self, decision: &BitrateDecision) {
+        self.bitrate_bps
+            .store(decision.bitrate_bps, Ordering::Relaxed);
+        self.max_fps
+            .store(decision.max_fps.unwrap_or(0), Ordering::Relaxed);
+        if decision.keyframe {
+            self.request_keyframe();
+        }
+    }
+}
+
 pub struct ScreenCaptureSession {
     stop: Arc<AtomicBool>,
     handle: Option<thread::JoinHandle<()>>,
@@ -100,6 +141,7 @@ fn probe_encoder_codec() -> (Codec, u8) {
         bitrate_bps: 2_500_000,
         codec: Codec::H264,
         output: EncodeOutput::new(|_| {}),
+        layers: Vec::new(),
     };
     match create_encoder(probe_config) {
         Ok(encoder) => {
@@ -119,6 +161,7 @@ fn probe_encoder_codec() -> (Codec, u8) {
         bitrate_bps: 2_500_000,
         codec: Codec::AV1,
         output: EncodeOutput::new(|_| {}),
+        layers: Vec::new(),
     };
     match create_encoder(probe_config) {
         Ok(encoder) => {
@@ -201,7 +244,7 @@ pub fn start_screen_capture(
     mpsc::Receiver<codec::EncodedPacket>,
     Arc<ArcSwap<Option<CaptureFrame>>>,
     mpsc::UnboundedReceiver<()>,
-    Arc<AtomicBool>,
+    Arc<EncoderControl>,
 )> {
     let (tx, rx) = mpsc::channel(60);
     let (tick_tx, tick_rx) = mpsc::unbounded_channel();
@@ -209,8 +252,8 @@ pub fn start_screen_capture(
     let stop_thread = Arc::clone(&stop);
     let latest_frame = Arc::new(ArcSwap::from_pointee(None::<CaptureFrame>));
     let latest_frame_for_ret = Arc::clone(&latest_frame);
-    let keyframe_requested = Arc::new(AtomicBool::new(false));
-    let keyframe_requested_ret = Arc::clone(&keyframe_requested);
+    let control = Arc::new(EncoderControl::default());
+    let control_ret = Arc::clone(&control);
 
     let handle = thread::Builder::new()
         .name("screen-capture".into())
@@ -248,19 +291,15 @@ pub fn start_screen_capture(
             let (actual_codec, codec_byte) = probe_encoder_codec();
 
             let start_time = std::time::Instant::now();
-            let tx_for_callback = tx.clone();
-            let encoder_config = EncodeConfig {
-                width: enc_w,
-                height: enc_h,
-                fps: config.fps.max(1),
-                bitrate_bps: config.bitrate_kbps.max(250) * 1000,
-                codec: actual_codec,
-                output: EncodeOutput::new(move |encoded_data: Vec<u8>| {
+            let layer_output = |layer: u8| {
+                let tx_for_callback = tx.clone();
+                EncodeOutput::new(move |encoded_data: Vec<u8>| {
                     let packet = codec::EncodedPacket {
                         codec: codec_byte,
                         keyframe: codec::detect_keyframe(codec_byte, &encoded_data),
                         capture_ts_us: start_time.elapsed().as_micros() as u64,
                         data: encoded_data,
+                        layer,
                     };
                     match tx_for_callback.try_send(packet) {
                         Ok(()) => {}
@@ -269,7 +308,28 @@ pub fn start_screen_capture(
                         }
                         Err(mpsc::error::TrySendError::Closed(_)) => {}
                     }
-                }),
+                })
+            };
+            let bitrate_bps = config.bitrate_kbps.max(250) * 1000;
+            let encoder_config = EncodeConfig {
+                width: enc_w,
+                height: enc_h,
+                fps: config.fps.max(1),
+                bitrate_bps,
+                codec: actual_codec,
+                output: layer_output(0),
+                layers: (1..config.layers.max(1))
+                    .map(|layer| {
+                        let (width, height, bitrate_bps) =
+                            layer_encode_params(enc_w, enc_h, bitrate_bps, layer);
+                        EncodeLayer {
+                            width,
+                            height,
+                            bitrate_bps,
+                            output: layer_output(layer),
+                        }
+                    })
+                    .collect(),
             };
 
             let mut encoder: Box<dyn EncodeSession> = match create_encoder(encoder_config) {
@@ -281,16 +341,38 @@ pub fn start_screen_capture(
                 }
             };
 
+            let mut encoder_bitrate = bitrate_bps;
+            let mut last_submit: Option<Instant> = None;
             while !stop_thread.load(Ordering::Relaxed) {
-                if keyframe_requested.swap(false, Ordering::Relaxed)
+                if control.keyframe.swap(false, Ordering::Relaxed)
                     && let Err(e) = encoder.request_keyframe()
                 {
                     tracing::warn!("screen encoder request_keyframe: {e}");
                 }
+                let target = control.bitrate_bps.load(Ordering::Relaxed);
+                if target != 0 && target != encoder_bitrate {
+                    encoder_bitrate = target;
+                    if let Err(e) = encoder.set_bitrate(target) {
+                        tracing::warn!("screen encoder set_bitrate: {e}");
+                    }
+                }
                 match capturer.next_frame() {
                     Some(frame) => {
                         latest_frame.store(Arc::new(Some(frame.clone())));
                         tick_tx.send(()).ok();
+                        // under congestion, fewer sharper frames beat many
+                        // blurry ones
+                        let max_fps = control.max_fps.load(Ordering::Relaxed);
+                        let now = Instant::now();
+                        if max_fps > 0
+                            && max_fps < config.fps
+                            && last_submit.is_some_and(|at| {
+                                now.duration_since(at) < Duration::from_secs(1) / max_fps
+                            })
+                        {
+                            continue;
+                        }
+                        last_submit = Some(now);
                         if let Err(e) = encoder.submit_frame(&frame) {
                             tracing::warn!("screen encoder submit failed: {e}");
                             break;
@@ -314,6 +396,6 @@ pub fn start_screen_capture(
         rx,
         latest_frame_for_ret,
         tick_rx,
-        keyframe_requested_ret,
+        control_ret,
     ))
 }
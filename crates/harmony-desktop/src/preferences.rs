@@ -6,6 +6,7 @@ use std::{
 };
 
 use arc_swap::{ArcSwap, DefaultStrategy, Guard};
+use harmony_api::ClientOptions;
 use keyring_core::Entry;
 use serde::{Deserialize, Serialize};
 // user preferences
@@ -67,6 +68,9 @@ impl Locale {
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct Preferences {
     pub locale: Locale,
+    // identifies this installation to the server, see `device_options`
+    #[serde(default)]
+    pub device_id: Option<String>,
 }
 
 fn get_config_path() -> PathBuf {
@@ -111,6 +115,21 @@ impl Preferences {
             .store(Arc::new(self))
     }
 
+    /// Use the same device ID every time, so this installation restores its
+    /// own MLS state. The first ID the client generates is the one kept.
+    pub fn device_options(options: ClientOptions) -> ClientOptions {
+        let mut preferences = Preferences::get_clone();
+        if let Some(device_id) = &preferences.device_id {
+            return options.with_device_id(device_id.clone());
+        }
+        preferences.device_id = Some(options.device_id.clone());
+        if let Err(e) = preferences.save() {
+            tracing::warn!("failed to save device ID: {e}");
+        }
+        preferences.set();
+        options
+    }
+
     pub fn save(&self) -> Result<(), std::io::Error> {
         let config_path = get_config_path();
         if !config_path.exists() {
@@ -11,6 +11,7 @@ use iced::{
 use crate::{
     Message,
     errors::RenderableError,
+    preferences::Preferences,
     theme::{ACCENT_PURPLE, BG_APP, BG_LOGIN_INPUT, DM_SANS, SUBTLE_GREY, TEXT_WHITE},
     views::main::MainMessage,
     widgets::{button::ButtonExt, styles},
@@ -59,7 +60,7 @@ impl MfaView {
                     return Task::stream(stream! {
                         let result = async {
                             let session = Arc::new(mfa.code(&code).await?);
-                            let (client, stream) = EncryptedClient::connect(session.clone(), ClientOptions::new(backend_harmony)).await?;
+                            let (client, stream) = EncryptedClient::connect(session.clone(), Preferences::device_options(ClientOptions::new(backend_harmony))).await?;
                             let channels = client.channels().fetch_personal().await?;
                             Ok::<_, RenderableError>((client, channels, stream))
                         }.await;
@@ -105,7 +105,7 @@ impl LoginView {
                         match core_api::login(&backend_account, &email, &password).await? {
                             core_api::LoginResult::Success(session) => {
                                 let session = Arc::new(session);
-                                let (client, stream) = EncryptedClient::connect(session.clone(), ClientOptions::new(&backend_harmony)).await?;
+                                let (client, stream) = EncryptedClient::connect(session.clone(), Preferences::device_options(ClientOptions::new(&backend_harmony))).await?;
                                 let channels = client.channels().fetch_personal().await?;
                                 Ok::<_, RenderableError>(LoginFlow::Done(client, channels, stream))
                             }
@@ -1,22 +1,24 @@
 use std::{
     collections::HashMap,
     sync::{Arc, mpsc},
+    time::Instant,
 };
 
 use arc_swap::ArcSwap;
 use async_stream::stream;
 use iced::{Task, advanced::image::Handle as ImageHandle};
 use pulse_api::{
-    AvailableTrack, MediaHint, PulseClient, PulseClientOptions, PulseEvent, TrackHandle,
+    AvailableTrack, BitrateConfig, BitrateController, MediaHint, PulseClient, PulseClientOptions,
+    PulseEvent, TrackHandle,
 };
 use wgpu_capture::CaptureTarget;
 
-use harmony_api::{CallMember, EncryptedClient};
+use harmony_api::{CallMember, EncryptedClient, SessionRestrictions};
 
 use crate::{
     Message,
     errors::{RenderableError, RenderableResult},
-    media::screen_capture::{ScreenCaptureConfig, ScreenCaptureSession},
+    media::screen_capture::{EncoderControl, ScreenCaptureConfig, ScreenCaptureSession},
     media::video::{self, Frame as VideoFrame},
     media::{audio::AudioPipeline, codec},
     views::main::{MainMessage, fetch_users_task},
@@ -35,6 +37,7 @@ pub struct CallParticipant {
     pub user_id: String,
     pub session_id: String,
     pub tracks: CallTrackState,
+    pub speaking: bool,
 }
 
 impl From<CallMember> for CallParticipant {
@@ -43,10 +46,11 @@ impl From<CallMember> for CallParticipant {
             user_id: m.user_id,
             session_id: m.session_id,
             tracks: CallTrackState {
-                audio: !m.muted,
+                audio: !m.muted && !m.restrictions.muted,
                 video: false,
                 screen: false,
             },
+            speaking: false,
         }
     }
 }
@@ -115,7 +119,9 @@ pub struct CallSession {
     pub video_handles: HashMap<String, ImageHandle>,
     pub video_decode_tx: HashMap<String, mpsc::Sender<Vec<u8>>>,
     pub screen_view_track_id: Option<String>,
-    pub screen_keyframe_request: Option<Arc<std::sync::atomic::AtomicBool>>,
+    pub screen_encoder: Option<Arc<EncoderControl>>,
+    pub screen_bitrate: Option<BitrateController>,
+    pub mic_bitrate: Option<BitrateController>,
     pub available_screen_tracks: Vec<AvailableTrack>,
     pub screenshare_fullscreen: bool,
     pub screen_track_codec: Option<u8>,
@@ -139,7 +145,9 @@ impl CallSession {
             video_handles: HashMap::new(),
             video_decode_tx: HashMap::new(),
             screen_view_track_id: None,
-            screen_keyframe_request: None,
+            screen_encoder: None,
+            screen_bitrate: None,
+            mic_bitrate: None,
             available_screen_tracks: Vec::new(),
             screenshare_fullscreen: false,
             screen_track_codec: None,
@@ -174,17 +182,24 @@ impl CallSession {
                     p.tracks.audio = new_audio;
                     if !new_audio {
                         self.audio.stop_capture();
+                        self.mic_bitrate = None;
                     }
                     if let Some(conv_id) = self.channel_id.clone() {
                         let client = ctx.api.clone();
                         let pulse = self.pulse_client.clone();
+                        let call_id = self.call_id.clone();
                         let muted = !new_audio;
                         let mic_track = self.mic_track.take();
                         return Task::perform(
                             async move {
                                 client
                                     .client()
-                                    .update_voice_state(&conv_id, Some(muted), None)
+                                    .update_voice_state(
+                                        &conv_id,
+                                        call_id.as_deref(),
+                                        Some(muted),
+                                        None,
+                                    )
                                     .await
                                     .map_err(RenderableError::from)?;
                                 if let Some(pulse) = pulse {
@@ -250,7 +265,8 @@ impl CallSession {
                 if let Some(session) = self.screen_capture_session.take() {
                     session.stop();
                     self.screen_capture_preview = None;
-                    self.screen_keyframe_request = None;
+                    self.screen_encoder = None;
+                    self.screen_bitrate = None;
                     if let Some(p) = self.self_participant_mut(ctx.self_user_id) {
                         p.tracks.screen = false;
                     }
@@ -264,7 +280,13 @@ impl CallSession {
                 }
             }
             CallMessage::StartScreenCapture(target, config) => {
-                let (session, rx, frame_ref, tick_rx, keyframe_flag) =
+                let layers = config.layers;
+                let bitrate_config = BitrateConfig::video(
+                    config.bitrate_kbps.max(250) * 1000,
+                    config.fps,
+                    layers > 1,
+                );
+                let (session, rx, frame_ref, tick_rx, encoder) =
                     match crate::media::screen_capture::start_screen_capture(target, config) {
                         Ok(result) => result,
                         Err(e) => {
@@ -275,7 +297,8 @@ impl CallSession {
                     };
                 self.screen_capture_session = Some(session);
                 self.screen_capture_preview = Some(frame_ref);
-                self.screen_keyframe_request = Some(keyframe_flag.clone());
+                self.screen_encoder = Some(encoder);
+                self.screen_bitrate = Some(BitrateController::new(bitrate_config));
 
                 if let Some(p) = self.self_participant_mut(ctx.self_user_id) {
                     p.tracks.screen = true;
@@ -288,7 +311,7 @@ impl CallSession {
                         return;
                     };
                     let screen_track = match pulse
-                        .produce_track(pulse_api::MediaHint::ScreenVideo)
+                        .produce_simulcast_track(pulse_api::MediaHint::ScreenVideo, layers)
                         .await
                     {
                         Ok(handle) => handle,
@@ -309,8 +332,9 @@ impl CallSession {
                                 match packet {
                                     Some(p) => {
                                         let payload = codec::prepend_codec_byte(p.codec, &p.data);
-                                        if let Err(e) = pulse.send_media(
+                                        if let Err(e) = pulse.send_layer_media(
                                             &screen_track,
+                                            p.layer,
                                             p.capture_ts_us,
                                             p.keyframe,
                                             &payload,
@@ -337,7 +361,8 @@ impl CallSession {
                     session.stop();
                 }
                 self.screen_capture_preview = None;
-                self.screen_keyframe_request = None;
+                self.screen_encoder = None;
+                self.screen_bitrate = None;
                 let mut had_track_flag = false;
                 if let Some(p) = self.self_participant_mut(ctx.self_user_id) {
                     had_track_flag = p.tracks.screen;
@@ -460,20 +485,24 @@ impl CallSession {
             CallMessage::MicEnabled(handle) => {
                 self.mic_track = Some(handle.clone());
                 if self.pulse_client.is_some() {
+                    let bitrate_config = BitrateConfig::opus();
+                    self.audio
+                        .set_capture_bitrate(bitrate_config.start_bitrate_bps);
                     match self.audio.start_capture() {
                         Ok(Some(rx)) => {
+                            self.mic_bitrate = Some(BitrateController::new(bitrate_config));
                             let pulse = self.pulse_client.clone();
                             return Task::stream(stream! {
                                 let Some(pulse) = pulse else { return; };
                                 let mut rx = rx;
-                                while let Some(packet) = rx.recv().await {
-                                    if let Err(e) = pulse.send_media(
+                                while let Some(frame) = rx.recv().await {
+                                    if let Err(e) = pulse.send_audio(
                                         &handle,
                                         codec::now_micros(),
-                                        true,
-                                        &packet,
+                                        frame.level,
+                                        &frame.data,
                                     ) {
-                                        tracing::warn!("mic send_media: {e:#}");
+                                        tracing::warn!("mic send_audio: {e:#}");
                                     }
                                 }
                                 yield Message::Main(MainMessage::DismissError);
@@ -544,8 +573,8 @@ impl CallSession {
                 self.screenshare_fullscreen = !self.screenshare_fullscreen;
             }
             CallMessage::RequestScreenKeyframe => {
-                if let Some(ref flag) = self.screen_keyframe_request {
-                    flag.store(true, std::sync::atomic::Ordering::Relaxed);
+                if let Some(ref encoder) = self.screen_encoder {
+                    encoder.request_keyframe();
                 }
             }
         }
@@ -609,9 +638,49 @@ impl CallSession {
             PulseEvent::KeyFrameRequested(media_hint) => {
                 // we need to send an IDR
                 if matches!(media_hint, MediaHint::ScreenVideo)
-                    && let Some(ref flag) = self.screen_keyframe_request
+                    && let Some(ref encoder) = self.screen_encoder
                 {
-                    flag.store(true, std::sync::atomic::Ordering::Relaxed);
+                    encoder.request_keyframe();
+                }
+            }
+            PulseEvent::ReceiverReport {
+                media_hint,
+                session_id,
+                lost,
+                received,
+                jitter_ms,
+            } => {
+                let now = Instant::now();
+                match media_hint {
+                    MediaHint::ScreenVideo => {
+                        if let Some(ref mut controller) = self.screen_bitrate
+                            && let Some(decision) =
+                                controller.on_report(&session_id, lost, received, jitter_ms, now)
+                            && let Some(ref encoder) = self.screen_encoder
+                        {
+                            tracing::debug!("screen bitrate: {decision:?}");
+                            encoder.apply(&decision);
+                        }
+                    }
+                    MediaHint::Audio => {
+                        if let Some(ref mut controller) = self.mic_bitrate
+                            && let Some(decision) =
+                                controller.on_report(&session_id, lost, received, jitter_ms, now)
+                        {
+                            tracing::debug!("mic bitrate: {decision:?}");
+                            self.audio.set_capture_bitrate(decision.bitrate_bps);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            PulseEvent::RoundTrip(rtt) => {
+                let now = Instant::now();
+                for controller in [&mut self.screen_bitrate, &mut self.mic_bitrate]
+                    .into_iter()
+                    .flatten()
+                {
+                    controller.on_rtt(rtt, now);
                 }
             }
             PulseEvent::Error(e) => {
@@ -620,6 +689,17 @@ impl CallSession {
             PulseEvent::Reconnecting { attempt } => {
                 tracing::info!("Voice connection lost, reconnecting (attempt {attempt})");
             }
+            PulseEvent::Moved { call_id } => {
+                tracing::info!("Moved to call {call_id}");
+                self.call_id = Some(call_id);
+            }
+            PulseEvent::ActiveSpeakers(session_ids) => {
+                if let Some(ref mut call) = self.state {
+                    for p in call.participants.iter_mut() {
+                        p.speaking = session_ids.contains(&p.session_id);
+                    }
+                }
+            }
             _ => {}
         }
         Task::none()
@@ -648,6 +728,7 @@ impl CallSession {
                 video: false,
                 screen: sharing_screen,
             },
+            speaking: false,
         };
         if let Some(ref mut call) = self.state {
             if !call
@@ -700,6 +781,17 @@ impl CallSession {
         }
     }
 
+    pub fn on_session_restricted(
+        &mut self,
+        call_id: &str,
+        session_id: &str,
+        restrictions: SessionRestrictions,
+    ) {
+        if restrictions.muted {
+            self.on_voice_state_changed(call_id, session_id, true);
+        }
+    }
+
     pub fn on_call_migrated(&mut self, call_id: &str, api: &Arc<EncryptedClient>) -> Task<Message> {
         // reconnect to new server
         if self.call_id.as_deref() != Some(call_id) {
@@ -726,7 +818,9 @@ impl CallSession {
             session.stop();
         }
         self.screen_capture_preview = None;
-        self.screen_keyframe_request = None;
+        self.screen_encoder = None;
+        self.screen_bitrate = None;
+        self.mic_bitrate = None;
         self.clear_screen_view_state();
         self.available_screen_tracks.clear();
         self.video_frames.clear();
@@ -791,7 +885,7 @@ impl CallSession {
 }
 
 async fn fetch_call_state(api: &EncryptedClient, conv_id: &str) -> RenderableResult<CallState> {
-    let members = api.client().get_call_members(conv_id).await?;
+    let members = api.client().get_call_members(conv_id, None).await?;
     let ids: Vec<String> = members.iter().map(|m| m.user_id.clone()).collect();
     let _ = api.users().fetch_bulk(ids).await;
     Ok(CallState {
@@ -817,11 +911,11 @@ fn connect_call_task(
     start_first: bool,
 ) -> Task<Message> {
     Task::stream(stream! {
-        if start_first && let Err(e) = client.client().start_call(&conv_id, None).await {
+        if start_first && let Err(e) = client.client().start_call(&conv_id, None, None).await {
             yield err(e.into());
             return;
         }
-        let token_info = match client.client().create_call_token(&conv_id, true, false).await {
+        let token_info = match client.client().create_call_token(&conv_id, None, true, false, None).await {
             Ok(info) => info,
             Err(e) => {
                 yield err(e.into());
@@ -76,7 +76,13 @@ pub fn chat_frame(state: &MainView) -> Element<MainMessage> {
         ..Default::default()
     });
 
-    container(chat_box)
+    let mut frame = Column::new().spacing(4).width(Length::Fill);
+    if let Some(typing) = typing_line(state) {
+        frame = frame.push(text(typing).size(12).color(TEXT_MUTED).font(DM_SANS));
+    }
+    frame = frame.push(chat_box);
+
+    container(frame)
         .width(Length::Fill)
         .padding(Padding {
             top: 12.0,
@@ -91,6 +97,29 @@ pub fn chat_frame(state: &MainView) -> Element<MainMessage> {
         .into()
 }
 
+fn typing_line(state: &MainView) -> Option<String> {
+    let channel_id = state.current_conversation.as_ref()?;
+    let mut names: Vec<String> = state
+        .typing
+        .get(channel_id)?
+        .keys()
+        .map(|id| {
+            state
+                .api
+                .users()
+                .get(id)
+                .map_or("Someone".to_string(), |u| u.display_name().to_string())
+        })
+        .collect();
+    names.sort();
+    match names.as_slice() {
+        [] => None,
+        [one] => Some(format!("{one} is typing...")),
+        [one, two] => Some(format!("{one} and {two} are typing...")),
+        _ => Some("Several people are typing...".to_string()),
+    }
+}
+
 fn emoji_picker(state: &MainView) -> Element<MainMessage> {
     let search = container(
         text_input("Search emojis...", &state.emoji_search)
@@ -306,71 +306,78 @@ fn voice_in_call<'a>(state: &'a MainView, call: &'a CallState) -> Element<'a, Ma
         content.push(Space::new().height(Length::Fill).into());
     };
 
-    let participant_card =
-        |name: &str, avatar: Option<AvatarUrl>, audio: bool| -> Element<MainMessage> {
-            let avatar = container(image(state.default_avatar.clone()))
-                .width(64)
-                .height(64)
-                .style(move |_theme| container::Style {
-                    border: Border::default().rounded(6),
-                    ..Default::default()
-                });
-
-            let mic_indicator = text(if audio {
-                Icon::MicFilled.unicode()
-            } else {
-                Icon::MicOffRegular.unicode()
-            })
-            .size(14)
-            .color(if audio { TEXT_PRIMARY } else { TEXT_MUTED })
-            .font(FLUENT_ICONS);
-
-            let name_label = container(
-                row![
-                    text(name.to_string())
-                        .size(16)
-                        .color(TEXT_PRIMARY)
-                        .font(DM_SANS)
-                        .align_x(alignment::Horizontal::Center),
-                    mic_indicator,
-                ]
-                .spacing(4)
-                .align_y(alignment::Vertical::Center),
-            )
-            .width(Length::Fill)
-            .align_x(alignment::Horizontal::Center)
-            .padding(Padding::from([5, 15]))
-            .style(|_theme| container::Style {
-                background: Some(iced::Background::Color(BG_PARTICIPANT_LABEL)),
-                border: Border::default().rounded(5),
+    let participant_card = |name: &str,
+                            avatar: Option<AvatarUrl>,
+                            audio: bool,
+                            speaking: bool|
+     -> Element<MainMessage> {
+        let avatar = container(image(state.default_avatar.clone()))
+            .width(64)
+            .height(64)
+            .style(move |_theme| container::Style {
+                border: Border::default().rounded(6),
                 ..Default::default()
             });
 
-            container(
-                column![avatar, name_label]
-                    .spacing(16)
-                    .align_x(alignment::Horizontal::Center)
-                    .width(Length::Fill),
-            )
-            .width(200)
-            .padding(Padding::from([10, 13]))
-            .style(|_theme| container::Style {
-                background: Some(iced::Background::Color(BG_PARTICIPANT_CARD)),
-                border: Border::default().rounded(5),
-                shadow: Shadow {
-                    color: Color {
-                        r: 0.0,
-                        g: 0.0,
-                        b: 0.0,
-                        a: 0.25,
-                    },
-                    offset: Vector::new(0.0, 4.0),
-                    blur_radius: 4.0,
+        let mic_indicator = text(if audio {
+            Icon::MicFilled.unicode()
+        } else {
+            Icon::MicOffRegular.unicode()
+        })
+        .size(14)
+        .color(if audio { TEXT_PRIMARY } else { TEXT_MUTED })
+        .font(FLUENT_ICONS);
+
+        let name_label = container(
+            row![
+                text(name.to_string())
+                    .size(16)
+                    .color(TEXT_PRIMARY)
+                    .font(DM_SANS)
+                    .align_x(alignment::Horizontal::Center),
+                mic_indicator,
+            ]
+            .spacing(4)
+            .align_y(alignment::Vertical::Center),
+        )
+        .width(Length::Fill)
+        .align_x(alignment::Horizontal::Center)
+        .padding(Padding::from([5, 15]))
+        .style(|_theme| container::Style {
+            background: Some(iced::Background::Color(BG_PARTICIPANT_LABEL)),
+            border: Border::default().rounded(5),
+            ..Default::default()
+        });
+
+        container(
+            column![avatar, name_label]
+                .spacing(16)
+                .align_x(alignment::Horizontal::Center)
+                .width(Length::Fill),
+        )
+        .width(200)
+        .padding(Padding::from([10, 13]))
+        .style(move |_theme| container::Style {
+            background: Some(iced::Background::Color(BG_PARTICIPANT_CARD)),
+            border: Border {
+                color: ACCENT_PURPLE,
+                width: if speaking { 2.0 } else { 0.0 },
+                radius: 5.into(),
+            },
+            shadow: Shadow {
+                color: Color {
+                    r: 0.0,
+                    g: 0.0,
+                    b: 0.0,
+                    a: 0.25,
                 },
-                ..Default::default()
-            })
-            .into()
-        };
+                offset: Vector::new(0.0, 4.0),
+                blur_radius: 4.0,
+            },
+            ..Default::default()
+        })
+        .into()
+    };
 
     let mut participants_row_content = row![].spacing(10);
     for participant in call.participants.iter() {
@@ -385,6 +392,7 @@ fn voice_in_call<'a>(state: &'a MainView, call: &'a CallState) -> Element<'a, Ma
             &display_name,
             avatar_url,
             participant.tracks.audio,
+            participant.speaking,
         ));
     }
     let participants_row = container(participants_row_content)
@@ -78,11 +78,37 @@ pub fn chat_list(state: &MainView) -> Element<MainMessage> {
             ..DM_SANS
         });
 
+        let unread_count = if is_selected {
+            0
+        } else {
+            state
+                .api
+                .channels()
+                .read_state(id)
+                .map_or(0, |s| s.unread_count)
+        };
+        let unread_badge: Option<Element<MainMessage>> = (unread_count > 0).then(|| {
+            let label = if unread_count >= 100 {
+                "99+".to_string()
+            } else {
+                unread_count.to_string()
+            };
+            container(text(label).size(11).color(TEXT_WHITE).font(DM_SANS))
+                .padding(Padding::from([1, 6]))
+                .style(|_theme| container::Style {
+                    background: Some(iced::Background::Color(ACCENT_PURPLE)),
+                    border: Border::default().rounded(8),
+                    ..Default::default()
+                })
+                .into()
+        });
+
         let mut user_row_items = vec![channel_icon.into(), name.into()];
-        if let Some(indicator) = screenshare_indicator {
+        if screenshare_indicator.is_some() || unread_badge.is_some() {
             user_row_items.push(Space::new().width(Length::Fill).into());
-            user_row_items.push(indicator);
         }
+        user_row_items.extend(screenshare_indicator);
+        user_row_items.extend(unread_badge);
 
         let user_row = Row::from_vec(user_row_items)
             .spacing(12)
@@ -1,6 +1,10 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use harmony_api::{Channel, EncryptedClient, EncryptedEvent, LifecycleEvent, User};
+use harmony_api::{Channel, EncryptedClient, EncryptedEvent, LifecycleEvent, MessageBody, User};
 use iced::{
     Element, Length, Task,
     widget::{Space, button, column, container, image::Handle, row, text},
@@ -81,11 +85,12 @@ pub enum MainMessage {
     OpenPrivateChannel(String),
     PrivateChannelOpened(crate::errors::RenderableResult<Channel>),
     UsersFetched,
+    TypingExpired(String, String),
+    ChannelsRefreshed(HashMap<String, Channel>),
 }
 
-fn decode_content(bytes: Vec<u8>) -> String {
-    String::from_utf8_lossy(&bytes).into_owned()
-}
+/// How long someone is shown as typing after their last notification.
+const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
 
 pub struct MainView {
     active_tab: SidebarTab,
@@ -100,6 +105,8 @@ pub struct MainView {
     pub avatar_menu_open: bool,
 
     pub current_conversation_messages: Vec<ChatMessage>,
+    // channel ID -> user ID -> when they were last seen typing
+    pub typing: HashMap<String, HashMap<String, Instant>>,
 
     pub emoji_picker_open: bool,
     pub emoji_picker_category: emojis::Group,
@@ -129,6 +136,7 @@ impl MainView {
             chat_list_visible: true,
             avatar_menu_open: false,
             current_conversation_messages: Vec::new(),
+            typing: HashMap::new(),
             emoji_picker_open: false,
             emoji_picker_category: emojis::Group::SmileysAndEmotion,
             emoji_search: String::new(),
@@ -191,7 +199,7 @@ impl MainView {
                             .messages()
                             .await?
                             .into_iter()
-                            .map(|m| ChatMessage::new(&m.message, decode_content(m.content)))
+                            .map(|m| ChatMessage::new(&m.message, &m.body))
                             .collect();
                         Ok((i, messages))
                     },
@@ -204,7 +212,23 @@ impl MainView {
                 );
                 return Task::batch([msg_task, call_task]);
             }
-            MainMessage::ChatInputChanged(s) => self.chat_input = s,
+            MainMessage::ChatInputChanged(s) => {
+                self.chat_input = s;
+                if !self.chat_input.is_empty()
+                    && let Some(conv_id) = self.current_conversation.clone()
+                {
+                    let client = self.api.clone();
+                    return Task::perform(
+                        async move { client.channels().set_typing(&conv_id).await },
+                        |result| {
+                            if let Err(e) = result {
+                                tracing::warn!("failed to send typing notification: {e}");
+                            }
+                            Message::Main(MainMessage::Ignore)
+                        },
+                    );
+                }
+            }
             MainMessage::SearchInputChanged(s) => self.search_input = s,
             MainMessage::SendMessage => {
                 if !self.chat_input.is_empty()
@@ -217,8 +241,9 @@ impl MainView {
                     return Task::perform(
                         async move {
                             let channel = client.channels().fetch(&channel_id).await?;
-                            let msg = channel.send_message(content.as_bytes()).await?;
-                            Ok(ChatMessage::new(&msg, content))
+                            let body = MessageBody::text(content);
+                            let msg = channel.send_body(&body).await?;
+                            Ok(ChatMessage::new(&msg, &body))
                         },
                         move |result: crate::errors::RenderableResult<_>| match result {
                             Ok(chat_msg) => Message::Main(MainMessage::MessageSent(chat_msg)),
@@ -240,8 +265,9 @@ impl MainView {
                     return Task::perform(
                         async move {
                             let channel = client.channels().fetch(&channel_id).await?;
-                            let msg = channel.edit_message(&mid, new_content.as_bytes()).await?;
-                            Ok(ChatMessage::new(&msg, new_content))
+                            let body = MessageBody::text(new_content);
+                            let msg = channel.edit_body(&mid, &body).await?;
+                            Ok(ChatMessage::new(&msg, &body))
                         },
                         move |result: crate::errors::RenderableResult<_>| match result {
                             Ok(chat_msg) => Message::Main(MainMessage::MessageEdited(
@@ -325,12 +351,36 @@ impl MainView {
                     .collect();
                 missing.sort();
                 missing.dedup();
+                let mut tasks = vec![fetch_users_task(self.api.clone(), missing)];
                 if self.current_conversation.as_ref() == Some(&id) {
+                    if let Some(last) = messages.last() {
+                        tasks.push(mark_read_task(self.api.clone(), id, last.id.clone()));
+                    }
                     self.current_conversation_messages = messages;
                 }
-                return fetch_users_task(self.api.clone(), missing);
+                return Task::batch(tasks);
             }
             MainMessage::UsersFetched => {}
+            MainMessage::ChannelsRefreshed(channels) => {
+                self.current_channels = channels;
+                // reload the open conversation, which may have missed messages
+                if let Some(conv_id) = self.current_conversation.take() {
+                    return Task::done(Message::Main(MainMessage::ChatSelected(conv_id)));
+                }
+            }
+            MainMessage::TypingExpired(channel_id, user_id) => {
+                if let Some(users) = self.typing.get_mut(&channel_id) {
+                    if users
+                        .get(&user_id)
+                        .is_some_and(|since| since.elapsed() >= TYPING_TIMEOUT)
+                    {
+                        users.remove(&user_id);
+                    }
+                    if users.is_empty() {
+                        self.typing.remove(&channel_id);
+                    }
+                }
+            }
             MainMessage::ApiError(e) => {
                 self.error = Some(e);
             }
@@ -387,8 +437,18 @@ impl MainView {
             LifecycleEvent::Disconnected => {
                 self.error = Some(RenderableError::NetworkError);
             }
-            LifecycleEvent::Reconnected => {
+            LifecycleEvent::Reconnected { resumed } => {
                 self.error = None;
+                if !resumed {
+                    let client = self.api.clone();
+                    return Task::perform(
+                        async move { client.channels().fetch_personal().await },
+                        |result| match result {
+                            Ok(channels) => Message::Main(MainMessage::ChannelsRefreshed(channels)),
+                            Err(e) => Message::Main(MainMessage::ApiError(e.into())),
+                        },
+                    );
+                }
             }
             LifecycleEvent::ReconnectionFailed { .. } => {
                 self.error = Some(RenderableError::NetworkError);
@@ -405,22 +465,30 @@ impl MainView {
                 channel_id,
                 message,
             } => {
-                let chat_msg = ChatMessage::new(&message.message, decode_content(message.content));
+                let chat_msg = ChatMessage::new(&message.message, &message.body);
                 let author_id = chat_msg.author_id.clone();
+                let message_id = chat_msg.id.clone();
+                if let Some(users) = self.typing.get_mut(&channel_id) {
+                    users.remove(&author_id);
+                }
+                let mut tasks = Vec::new();
                 if self.current_conversation.as_ref() == Some(&channel_id) {
                     self.current_conversation_messages.push(chat_msg);
+                    if author_id != self.current_user_id {
+                        tasks.push(mark_read_task(self.api.clone(), channel_id, message_id));
+                    }
                 }
                 if self.api.users().get(&author_id).is_none() {
-                    return fetch_users_task(self.api.clone(), vec![author_id]);
+                    tasks.push(fetch_users_task(self.api.clone(), vec![author_id]));
                 }
+                return Task::batch(tasks);
             }
             EncryptedEvent::MessageEdited {
                 channel_id,
                 message,
             } => {
                 if self.current_conversation.as_ref() == Some(&channel_id) {
-                    let chat_msg =
-                        ChatMessage::new(&message.message, decode_content(message.content));
+                    let chat_msg = ChatMessage::new(&message.message, &message.body);
                     if let Some(m) = self
                         .current_conversation_messages
                         .iter_mut()
@@ -439,6 +507,9 @@ impl MainView {
                         .retain(|m| m.id != message_id);
                 }
             }
+            EncryptedEvent::ReactionAdded { .. } | EncryptedEvent::ReactionRemoved { .. } => {
+                // TODO: show reactions in the chat area
+            }
             EncryptedEvent::ChannelUpdated { channel } => {
                 let member_ids: Vec<String> = match channel.data() {
                     harmony_api::ChannelData::PrivateChannel {
@@ -490,9 +561,56 @@ impl MainView {
             EncryptedEvent::CallMigrated(e) => {
                 return self.call.on_call_migrated(&e.call_id, &self.api);
             }
+            EncryptedEvent::CallStarted(e) => {
+                if self.current_conversation.as_ref() == Some(&e.channel_id) {
+                    return call::load_call_state_task(self.api.clone(), e.channel_id);
+                }
+            }
+            EncryptedEvent::CallEnded(e) => {
+                if self.current_conversation.as_ref() == Some(&e.channel_id) {
+                    return call::load_call_state_task(self.api.clone(), e.channel_id);
+                }
+            }
+            EncryptedEvent::CallSessionModerated(e) => {
+                if e.user_id == self.current_user_id {
+                    // TODO: tell the user
+                    if e.disconnected {
+                        tracing::warn!("Disconnected from the call by {}", e.moderator_id);
+                    } else {
+                        tracing::warn!(
+                            "Restricted in the call by {}: {:?}",
+                            e.moderator_id,
+                            e.restrictions
+                        );
+                    }
+                }
+                self.call
+                    .on_session_restricted(&e.call_id, &e.session_id, e.restrictions);
+            }
+            EncryptedEvent::CallRingStopped(_) => {
+                // TODO: show incoming calls
+            }
             EncryptedEvent::ContactStateChanged { user_id, state } => {
                 return self.contacts.on_state_changed(user_id, &state, &self.api);
             }
+            EncryptedEvent::PresenceChanged(_) => {
+                // TODO: show presence in the people list
+            }
+            EncryptedEvent::TypingStarted(e) => {
+                self.typing
+                    .entry(e.channel_id.clone())
+                    .or_default()
+                    .insert(e.user_id.clone(), Instant::now());
+                return Task::perform(tokio::time::sleep(TYPING_TIMEOUT), move |_| {
+                    Message::Main(MainMessage::TypingExpired(
+                        e.channel_id.clone(),
+                        e.user_id.clone(),
+                    ))
+                });
+            }
+            EncryptedEvent::ReadStateUpdated(_) => {
+                // the chat list reads unread counts from the channel manager
+            }
             EncryptedEvent::ContactAdded(outcome) => {
                 return self.contacts.update(
                     ContactsMessage::Accepted(contacts::Contact::from_outcome(outcome)),
@@ -653,3 +771,19 @@ pub fn fetch_users_task(api: Arc<EncryptedClient>, user_ids: Vec<String>) -> Tas
         },
     )
 }
+
+fn mark_read_task(
+    api: Arc<EncryptedClient>,
+    channel_id: String,
+    message_id: String,
+) -> Task<Message> {
+    Task::perform(
+        async move { api.channels().mark_read(&channel_id, &message_id).await },
+        |result| {
+            if let Err(e) = result {
+                tracing::warn!("failed to mark channel read: {e}");
+            }
+            Message::Main(MainMessage::Ignore)
+        },
+    )
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+
+/// Size of every chunk of an attachment but the last, which holds the rest:
+/// 1 MiB of plaintext with the nonce and tag it is encrypted with.
+pub const CHUNK_SIZE: u64 = 1024 * 1024 + 24 + 16;
+
+/// An encrypted file uploaded in chunks. The server only sees ciphertext; the
+/// file key travels inside the message that references it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attachment {
+    pub id: String,
+    pub channel_id: String,
+    pub uploader_id: String,
+    // size of the encrypted file in bytes
+    pub size: u64,
+    pub chunk_count: u32,
+    pub complete: bool,
+    pub message_id: Option<String>,
+    // unix timestamp in milliseconds after which the file is deleted
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateAttachmentMethod {
+    pub channel_id: String,
+    pub size: u64,
+    pub chunk_count: u32,
+    // how long the file should be kept once sent, in seconds
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateAttachmentResponse {
+    pub attachment: Attachment,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadChunkMethod {
+    pub attachment_id: String,
+    pub index: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadChunkResponse {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FinishAttachmentMethod {
+    pub attachment_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FinishAttachmentResponse {
+    pub attachment: Attachment,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadChunkMethod {
+    pub attachment_id: String,
+    pub index: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadChunkResponse {
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteAttachmentMethod {
+    pub attachment_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteAttachmentResponse {}
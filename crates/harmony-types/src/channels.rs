@@ -1,4 +1,7 @@
 use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+use crate::{messages::ReadState, permissions::PermissionSet, users::Encapsulated};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
@@ -21,6 +24,18 @@ pub enum ChannelMemberRole {
 pub struct ChannelMember {
     pub id: String,
     pub role: ChannelMemberRole,
+    // IDs of the channel roles assigned to this member
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+/// A named set of permissions that managers can assign to group members.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelRole {
+    pub id: String,
+    pub name: String,
+    pub permissions: PermissionSet,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -39,6 +54,13 @@ pub enum Channel {
         pending_members: Vec<String>,
         blacklist: Vec<String>,
         encryption_hint: EncryptionHint,
+        // None until the group key is first rotated
+        last_key_id: Option<String>,
+        #[serde(default)]
+        roles: Vec<ChannelRole>,
+        // granted to every member on top of their roles
+        #[serde(default = "PermissionSet::member_default")]
+        default_permissions: PermissionSet,
     },
 }
 
@@ -71,6 +93,8 @@ pub struct GetChannelsMethod {}
 #[serde(rename_all = "camelCase")]
 pub struct GetChannelsResponse {
     pub channels: Vec<Channel>,
+    #[serde(default)]
+    pub read_states: Vec<ReadState>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -128,3 +152,186 @@ pub struct LeaveChannelMethod {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LeaveChannelResponse {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KickMemberMethod {
+    pub channel_id: String,
+    pub user_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KickMemberResponse {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BanMemberMethod {
+    pub channel_id: String,
+    pub user_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BanMemberResponse {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnbanMemberMethod {
+    pub channel_id: String,
+    pub user_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnbanMemberResponse {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApproveMemberMethod {
+    pub channel_id: String,
+    pub user_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApproveMemberResponse {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RejectMemberMethod {
+    pub channel_id: String,
+    pub user_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RejectMemberResponse {}
+
+/// A group key wrapped to one recipient's contact key.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupKeyShare {
+    pub recipient_id: String,
+    #[serde_as(as = "Box<[_; 1088]>")]
+    pub encapsulated: Encapsulated,
+    pub wrapped_key: Vec<u8>,
+}
+
+/// A group key share addressed to the requesting user.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WrappedGroupKey {
+    pub channel_id: String,
+    pub key_id: Option<String>,
+    pub sender_id: String,
+    #[serde_as(as = "Box<[_; 1088]>")]
+    pub encapsulated: Encapsulated,
+    pub wrapped_key: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareGroupKeyMethod {
+    pub channel_id: String,
+    pub key_id: Option<String>,
+    pub shares: Vec<GroupKeyShare>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareGroupKeyResponse {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetGroupKeysMethod {
+    pub channel_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetGroupKeysResponse {
+    pub keys: Vec<WrappedGroupKey>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RotateGroupKeyMethod {
+    pub channel_id: String,
+    pub previous_key_id: Option<String>,
+    pub key_id: String,
+    // re-encrypted with the new key
+    pub metadata: Vec<u8>,
+    pub shares: Vec<GroupKeyShare>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RotateGroupKeyResponse {
+    pub channel: Channel,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateRoleMethod {
+    pub channel_id: String,
+    pub name: String,
+    pub permissions: PermissionSet,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateRoleResponse {
+    pub role: ChannelRole,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EditRoleMethod {
+    pub channel_id: String,
+    pub role_id: String,
+    pub name: Option<String>,
+    pub permissions: Option<PermissionSet>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EditRoleResponse {
+    pub role: ChannelRole,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteRoleMethod {
+    pub channel_id: String,
+    pub role_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteRoleResponse {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetMemberRolesMethod {
+    pub channel_id: String,
+    pub user_id: String,
+    pub role_ids: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetMemberRolesResponse {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetDefaultPermissionsMethod {
+    pub channel_id: String,
+    pub permissions: PermissionSet,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetDefaultPermissionsResponse {}
@@ -37,6 +37,12 @@ pub enum Error {
     MessageTooLong,
     #[error("Message empty")]
     MessageEmpty,
+    #[error("Reaction limit reached")]
+    ReactionLimitReached,
+    #[error("Stale MLS epoch")]
+    StaleEpoch,
+    #[error("Stale group key")]
+    StaleGroupKey,
 
     // Space errors
     #[error("Name too long")]
@@ -72,6 +78,18 @@ pub enum Error {
     #[error("Invalid contact stage for current relationship state")]
     InvalidStage,
 
+    // Attachment errors
+    #[error("Attachment empty")]
+    AttachmentEmpty,
+    #[error("Attachment too large")]
+    AttachmentTooLarge,
+    #[error("Storage quota exceeded")]
+    QuotaExceeded,
+    #[error("Attachment incomplete")]
+    AttachmentIncomplete,
+    #[error("Storage error")]
+    StorageError,
+
     // Call errors
     #[error("Already exists")]
     AlreadyExists,
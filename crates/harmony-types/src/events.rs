@@ -1,6 +1,12 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{channels::Channel, messages::Message, users::RelationshipState};
+use crate::{
+    channels::Channel,
+    messages::{Message, Reaction, ReadState},
+    mls::PendingMessage,
+    users::{Presence, RelationshipState},
+    voice::{RingOutcome, SessionRestrictions},
+};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(tag = "type", content = "data", rename_all = "SCREAMING_SNAKE_CASE")]
@@ -9,12 +15,18 @@ pub enum Event {
     NewMessage(NewMessageEvent),
     MessageEdited(MessageEditedEvent),
     MessageDeleted(MessageDeletedEvent),
+    ReactionAdded(ReactionAddedEvent),
+    ReactionRemoved(ReactionRemovedEvent),
+    PendingMessage(PendingMessageEvent),
+    TypingStarted(TypingStartedEvent),
+    ReadStateUpdated(ReadStateUpdatedEvent),
     // Contacts
     #[serde(rename_all = "camelCase")]
     ContactStateChanged {
         user_id: String,
         state: RelationshipState,
     },
+    PresenceChanged(PresenceChangedEvent),
     // Channels
     ChannelUpdated(ChannelUpdatedEvent),
     ChannelDeleted(ChannelDeletedEvent),
@@ -25,6 +37,10 @@ pub enum Event {
     UserLeftCall(UserLeftCallEvent),
     UserVoiceStateChanged(UserVoiceStateChangedEvent),
     CallMigrated(CallMigratedEvent),
+    CallStarted(CallStartedEvent),
+    CallEnded(CallEndedEvent),
+    CallRingStopped(CallRingStoppedEvent),
+    CallSessionModerated(CallSessionModeratedEvent),
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -48,6 +64,50 @@ pub struct MessageDeletedEvent {
     pub channel_id: String,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionAddedEvent {
+    pub message_id: String,
+    pub channel_id: String,
+    pub reaction: Reaction,
+    // the key of the message, which the reaction is encrypted with too
+    #[serde(default)]
+    pub key_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionRemovedEvent {
+    pub message_id: String,
+    pub channel_id: String,
+    pub reaction: Reaction,
+    // the key of the message, which the reaction is encrypted with too
+    #[serde(default)]
+    pub key_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingMessageEvent {
+    pub message: PendingMessage,
+}
+
+/// Sent at most every few seconds per user and channel; clients drop the
+/// indicator when it is not repeated or when the user's message arrives.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypingStartedEvent {
+    pub channel_id: String,
+    pub user_id: String,
+}
+
+/// Sent to the user's own devices when one of them marks a channel read.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadStateUpdatedEvent {
+    pub read_state: ReadState,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ChannelUpdatedEvent {
@@ -74,6 +134,15 @@ pub struct MemberLeftEvent {
     pub user_id: String,
 }
 
+/// The presence of a contact as they present it, so a user that went invisible
+/// shows up as offline.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PresenceChangedEvent {
+    pub user_id: String,
+    pub presence: Presence,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UserJoinedCallEvent {
@@ -106,3 +175,49 @@ pub struct CallMigratedEvent {
     pub call_id: String,
     pub server_address: String,
 }
+
+/// Sent to every member of the channel. In private channels the call rings
+/// everyone in `ringing` until they answer or it times out.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallStartedEvent {
+    pub call_id: String,
+    pub channel_id: String,
+    pub initiator_id: String,
+    pub ringing: Vec<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallEndedEvent {
+    pub call_id: String,
+    pub channel_id: String,
+    /// Milliseconds
+    pub duration: u64,
+}
+
+/// Sent to every member of the channel, so the initiator learns the outcome
+/// and the user's other devices stop ringing.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallRingStoppedEvent {
+    pub call_id: String,
+    pub channel_id: String,
+    pub user_id: String,
+    pub outcome: RingOutcome,
+}
+
+/// Sent to every member of the call when a channel manager restricts a
+/// session or disconnects it, so its user knows why their media stopped.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallSessionModeratedEvent {
+    pub call_id: String,
+    pub session_id: String,
+    pub user_id: String,
+    pub moderator_id: String,
+    pub restrictions: SessionRestrictions,
+    pub disconnected: bool,
+}
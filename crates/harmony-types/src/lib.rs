@@ -1,7 +1,10 @@
+pub mod attachments;
 pub mod channels;
 pub mod errors;
 pub mod events;
 pub mod invites;
 pub mod messages;
+pub mod mls;
+pub mod permissions;
 pub mod users;
 pub mod voice;
@@ -1,5 +1,12 @@
 use serde::{Deserialize, Serialize};
 
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Reaction {
+    pub user_id: String,
+    pub data: Vec<u8>, // encrypted with the same key as the message
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Message {
@@ -12,6 +19,11 @@ pub struct Message {
     // since a new key is generated each time a user establishes
     // a relationship with another user
     pub key_id: Option<String>,
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+    // the message that started the thread this is a reply in
+    #[serde(default)]
+    pub parent_id: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -30,11 +42,35 @@ pub struct GetMessagesResponse {
     pub messages: Vec<Message>,
 }
 
+/// Replies in the thread started by `message_id`, paginated like
+/// [`GetMessagesMethod`]. The parent itself is not included.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetThreadMethod {
+    pub message_id: String,
+    pub limit: Option<i64>,
+    pub latest: Option<bool>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetThreadResponse {
+    pub messages: Vec<Message>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SendMessageMethod {
     pub channel_id: String,
     pub content: Vec<u8>,
+    // uploaded attachments referenced by the message
+    #[serde(default)]
+    pub attachment_ids: Vec<String>,
+    // reply in the thread of this message
+    #[serde(default)]
+    pub parent_id: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -65,3 +101,64 @@ pub struct DeleteMessageMethod {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DeleteMessageResponse {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddReactionMethod {
+    pub message_id: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddReactionResponse {
+    pub reaction: Reaction,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoveReactionMethod {
+    pub message_id: String,
+    // the exact encrypted payload of the reaction to remove
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoveReactionResponse {}
+
+/// Tell the other members of a channel that the user is typing. Nothing is
+/// stored; clients repeat this while the user keeps typing.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetTypingMethod {
+    pub channel_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetTypingResponse {}
+
+/// Where a user has read up to in a channel.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadState {
+    pub channel_id: String,
+    pub last_read_id: Option<String>,
+    pub unread_count: u64,
+}
+
+/// Mark everything up to and including `message_id` as read. Read state never
+/// moves backwards.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkReadMethod {
+    pub channel_id: String,
+    pub message_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkReadResponse {
+    pub read_state: ReadState,
+}
@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PendingMessageKind {
+    // an MLS application message (ciphertext of a chat message)
+    Application,
+    // a key package published by a pending member asking to be added
+    KeyPackage,
+    // a commit advancing the group to the next epoch
+    Commit,
+    // a welcome for members added by a commit, relayed alongside it
+    Welcome,
+}
+
+/// An MLS message held by the server until every recipient acknowledges it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingMessage {
+    pub id: String,
+    pub channel_id: String,
+    pub author_id: String,
+    pub kind: PendingMessageKind,
+    pub content: Vec<u8>,
+    // the epoch a commit was created in
+    pub epoch: Option<u64>,
+    // the thread an application message was sent in
+    #[serde(default)]
+    pub parent_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchPendingMethod {
+    pub channel_id: Option<String>,
+    pub limit: Option<i64>,
+    // skips messages this device already acknowledged
+    #[serde(default)]
+    pub device_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchPendingResponse {
+    pub messages: Vec<PendingMessage>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AckPendingMethod {
+    pub message_ids: Vec<String>,
+    // acknowledges for this device only, leaving the messages for the
+    // user's other devices until they expire
+    #[serde(default)]
+    pub device_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AckPendingResponse {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendHandshakeMethod {
+    pub channel_id: String,
+    pub kind: PendingMessageKind,
+    pub content: Vec<u8>,
+    // required for commits, must match the channel's current epoch
+    pub epoch: Option<u64>,
+    // users added by a commit, who receive `welcome`: pending members, or
+    // members with a new device
+    #[serde(default)]
+    pub added_member_ids: Vec<String>,
+    pub welcome: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendHandshakeResponse {
+    pub message: PendingMessage,
+}
@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[repr(i64)]
+pub enum Permission {
+    Administrator = 0x1,     // 1 << 0
+    CreateInvite = 0x4,      // 1 << 2
+    ManageMembers = 0x8,     // 1 << 3
+    KickMembers = 0x10,      // 1 << 4
+    BanMembers = 0x20,       // 1 << 5
+    ManageChannel = 0x40,    // 1 << 6
+    ManageRoles = 0x80,      // 1 << 7
+    ManageInvites = 0x100,   // 1 << 8
+    SendMessages = 0x800,    // 1 << 11
+    ManageMessages = 0x4000, // 1 << 14
+    AttachFiles = 0x8000,    // 1 << 15
+    UseReactions = 0x10000,  // 1 << 16
+    StartCalls = 0x20000,    // 1 << 17
+    JoinCalls = 0x40000,     // 1 << 18
+    ManageCalls = 0x80000,   // 1 << 19
+    Speak = 0x100000,        // 1 << 20
+    Video = 0x200000,        // 1 << 21
+    Screenshare = 0x400000,  // 1 << 22
+}
+
+impl Permission {
+    pub fn iter() -> impl Iterator<Item = Self> {
+        [
+            Permission::Administrator,
+            Permission::CreateInvite,
+            Permission::ManageMembers,
+            Permission::KickMembers,
+            Permission::BanMembers,
+            Permission::ManageChannel,
+            Permission::ManageRoles,
+            Permission::ManageInvites,
+            Permission::SendMessages,
+            Permission::ManageMessages,
+            Permission::AttachFiles,
+            Permission::UseReactions,
+            Permission::StartCalls,
+            Permission::JoinCalls,
+            Permission::ManageCalls,
+            Permission::Speak,
+            Permission::Video,
+            Permission::Screenshare,
+        ]
+        .iter()
+        .copied()
+    }
+}
+
+/// A bitset of [`Permission`]s, serialized as an `i64`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PermissionSet {
+    permissions: i64,
+}
+
+impl Serialize for PermissionSet {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_i64(self.permissions)
+    }
+}
+
+impl<'de> Deserialize<'de> for PermissionSet {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let permissions = i64::deserialize(deserializer)?;
+        Ok(PermissionSet { permissions })
+    }
+}
+
+impl PermissionSet {
+    pub fn new() -> Self {
+        Self { permissions: 0 }
+    }
+
+    pub fn all() -> Self {
+        Self {
+            permissions: i64::MAX,
+        }
+    }
+
+    /// What every group member can do unless the channel says otherwise.
+    pub fn member_default() -> Self {
+        Self::from_permissions(&[
+            Permission::CreateInvite,
+            Permission::SendMessages,
+            Permission::AttachFiles,
+            Permission::UseReactions,
+            Permission::StartCalls,
+            Permission::JoinCalls,
+            Permission::Speak,
+            Permission::Video,
+            Permission::Screenshare,
+        ])
+    }
+
+    /// Both participants of a private channel can do everything that does
+    /// not involve managing it.
+    pub fn private_channel() -> Self {
+        Self::from_permissions(&[
+            Permission::SendMessages,
+            Permission::AttachFiles,
+            Permission::UseReactions,
+            Permission::StartCalls,
+            Permission::JoinCalls,
+            Permission::Speak,
+            Permission::Video,
+            Permission::Screenshare,
+        ])
+    }
+
+    pub fn from_permissions(permissions: &[Permission]) -> Self {
+        let mut set = Self::new();
+        for permission in permissions {
+            set.add_permission(*permission);
+        }
+        set
+    }
+
+    pub fn to_i64(&self) -> i64 {
+        self.permissions
+    }
+
+    pub fn to_vec(&self) -> Vec<Permission> {
+        Permission::iter()
+            .filter(|p| self.has_permission(*p))
+            .collect()
+    }
+
+    /// Administrators implicitly hold every permission.
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.permissions & (permission as i64 | Permission::Administrator as i64) != 0
+    }
+
+    /// Whether every permission in `other` is also held by this set.
+    pub fn contains(&self, other: PermissionSet) -> bool {
+        self.has_permission(Permission::Administrator) || other.permissions & !self.permissions == 0
+    }
+
+    pub fn add_permission(&mut self, permission: Permission) {
+        self.permissions |= permission as i64;
+    }
+
+    pub fn remove_permission(&mut self, permission: Permission) {
+        self.permissions &= !(permission as i64);
+    }
+
+    pub fn combine(&mut self, other: PermissionSet) {
+        self.permissions |= other.permissions;
+    }
+}
+
+impl From<i64> for PermissionSet {
+    fn from(permissions: i64) -> Self {
+        Self { permissions }
+    }
+}
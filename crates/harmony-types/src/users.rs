@@ -190,7 +190,6 @@ pub struct GetContactsMethod {}
 pub struct GetContactsResponse {
     pub contacts: Vec<ContactExtended>,
 }
-// FIXME: implement these methods on server
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BlockContactMethod {
@@ -211,3 +210,17 @@ pub struct UnblockContactMethod {
 pub struct UnblockContactResponse {
     pub contact: ContactExtended,
 }
+
+/// Fields left out keep their current value.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetPresenceMethod {
+    pub status: Option<Status>,
+    pub message: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetPresenceResponse {
+    pub presence: Presence,
+}
@@ -8,14 +8,34 @@ pub struct CallMember {
     pub session_id: String,
     pub muted: bool,
     pub deafened: bool,
+    #[serde(default)]
+    pub restrictions: SessionRestrictions,
+}
+
+/// Restrictions a channel manager placed on a user in a call, on top of
+/// their own mute and deafen state.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SessionRestrictions {
+    pub muted: bool,
+    pub deafened: bool,
+    pub video_disabled: bool,
+    pub screen_disabled: bool,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateCallTokenMethod {
     pub id: String,
+    /// The room to join, the channel's unnamed call if missing.
+    #[serde(default)]
+    pub call_id: Option<String>,
     pub initial_muted: bool,
     pub initial_deafened: bool,
+    /// Where the user is. Far from the call's node, they are handed a node
+    /// near them that relays to it.
+    #[serde(default)]
+    pub preferred_region: Option<Region>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -32,6 +52,9 @@ pub struct CreateCallTokenResponse {
 pub struct StartCallMethod {
     pub id: String,
     pub preferred_region: Option<Region>,
+    /// Names a room in a group channel, which can have several at once.
+    #[serde(default)]
+    pub name: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -44,6 +67,8 @@ pub struct StartCallResponse {
 #[serde(rename_all = "camelCase")]
 pub struct EndCallMethod {
     pub id: String,
+    #[serde(default)]
+    pub call_id: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -54,6 +79,8 @@ pub struct EndCallResponse {}
 #[serde(rename_all = "camelCase")]
 pub struct UpdateVoiceStateMethod {
     pub id: String,
+    #[serde(default)]
+    pub call_id: Option<String>,
     pub muted: Option<bool>,
     pub deafened: Option<bool>,
 }
@@ -65,10 +92,73 @@ pub struct UpdateVoiceStateResponse {
     pub deafened: bool,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetCallsMethod {
+    pub id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetCallsResponse {
+    pub calls: Vec<CallRoom>,
+}
+
+/// A call going on in a channel. Group channels can have several rooms at
+/// once, told apart by name; the unnamed one is the channel's default call.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallRoom {
+    pub id: String,
+    pub name: Option<String>,
+    pub members: Vec<CallMember>,
+}
+
+/// Move one of your sessions to another room in the same channel. The
+/// session keeps its ID and reconnects to the room's node by itself.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveSessionMethod {
+    pub id: String,
+    pub session_id: String,
+    pub call_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveSessionResponse {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestrictCallSessionMethod {
+    pub id: String,
+    pub session_id: String,
+    pub restrictions: SessionRestrictions,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestrictCallSessionResponse {
+    pub member: CallMember,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DisconnectCallSessionMethod {
+    pub id: String,
+    pub session_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DisconnectCallSessionResponse {}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetCallMembersMethod {
     pub id: String,
+    #[serde(default)]
+    pub call_id: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -76,3 +166,53 @@ pub struct GetCallMembersMethod {
 pub struct GetCallMembersResponse {
     pub members: Vec<CallMember>,
 }
+
+/// How ringing stopped for one user.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum RingOutcome {
+    Accepted,
+    Declined,
+    /// Nobody answered in time, or the call ended first.
+    Missed,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeclineCallMethod {
+    pub id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeclineCallResponse {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetCallHistoryMethod {
+    pub id: String,
+    pub limit: Option<i64>,
+    pub before: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetCallHistoryResponse {
+    pub calls: Vec<CallRecord>,
+}
+
+/// A past or ongoing call. Times are Unix milliseconds; `ended_at` and
+/// `duration` are missing while the call is still going.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallRecord {
+    pub id: String,
+    pub channel_id: String,
+    pub initiator_id: String,
+    pub participant_ids: Vec<String>,
+    pub declined_ids: Vec<String>,
+    pub missed_ids: Vec<String>,
+    pub started_at: i64,
+    pub ended_at: Option<i64>,
+    pub duration: Option<u64>,
+}
@@ -9,13 +9,15 @@ use authentication::authenticate;
 use rapid::socket::RpcClients;
 use rapid::socket::RpcServer;
 use services::database;
+use services::events::EventReplay;
+use services::presence::PresenceListener;
 use services::rate_limiter::RedisRateLimiter;
 use services::redis;
 use services::voice;
 
 use tracing::info;
 
-use crate::services::environment::LISTEN_ADDRESS;
+use crate::services::environment::{LISTEN_ADDRESS, RPC_MAX_IN_FLIGHT};
 
 static RPC_CLIENTS: OnceLock<RpcClients> = OnceLock::new();
 
@@ -34,10 +36,16 @@ async fn main() {
     services::nats::connect().await;
     info!("Connected to NATS and created streams");
 
+    services::blobs::connect();
+    info!("Opened blob store");
+
     let listen_address = LISTEN_ADDRESS.to_owned();
     info!("Starting server at {listen_address}");
     let server = RpcServer::new(Box::new(|token| Box::pin(authenticate(token))))
         .rate_limiter(RedisRateLimiter)
+        .connection_listener(PresenceListener)
+        .resume_handler(EventReplay)
+        .max_in_flight(*RPC_MAX_IN_FLIGHT)
         // Channels
         .register("GET_CHANNEL", methods::channels::get_channel)
         .register("GET_CHANNELS", methods::channels::get_channels)
@@ -45,6 +53,22 @@ async fn main() {
         .register("EDIT_CHANNEL", methods::channels::edit_channel)
         .register("DELETE_CHANNEL", methods::channels::delete_channel)
         .register("LEAVE_CHANNEL", methods::channels::leave_channel)
+        .register("KICK_MEMBER", methods::channels::kick_member)
+        .register("BAN_MEMBER", methods::channels::ban_member)
+        .register("UNBAN_MEMBER", methods::channels::unban_member)
+        .register("APPROVE_MEMBER", methods::channels::approve_member)
+        .register("REJECT_MEMBER", methods::channels::reject_member)
+        .register("SHARE_GROUP_KEY", methods::channels::share_group_key)
+        .register("GET_GROUP_KEYS", methods::channels::get_group_keys)
+        .register("ROTATE_GROUP_KEY", methods::channels::rotate_group_key)
+        .register("CREATE_ROLE", methods::channels::create_role)
+        .register("EDIT_ROLE", methods::channels::edit_role)
+        .register("DELETE_ROLE", methods::channels::delete_role)
+        .register("SET_MEMBER_ROLES", methods::channels::set_member_roles)
+        .register(
+            "SET_DEFAULT_PERMISSIONS",
+            methods::channels::set_default_permissions,
+        )
         // Invites
         .register("CREATE_INVITE", methods::invites::create_invite)
         .register("DELETE_INVITE", methods::invites::delete_invite)
@@ -53,14 +77,32 @@ async fn main() {
         .register("ACCEPT_INVITE", methods::invites::accept_invite)
         // Messages
         .register("GET_MESSAGES", methods::messages::get_messages)
+        .register("GET_THREAD", methods::messages::get_thread)
         .register("SEND_MESSAGE", methods::messages::send_message)
         .register("EDIT_MESSAGE", methods::messages::edit_message)
         .register("DELETE_MESSAGE", methods::messages::delete_message)
+        .register("ADD_REACTION", methods::messages::add_reaction)
+        .register("REMOVE_REACTION", methods::messages::remove_reaction)
+        .register("SET_TYPING", methods::messages::set_typing)
+        .register("MARK_READ", methods::messages::mark_read)
+        // Attachments
+        .register("CREATE_ATTACHMENT", methods::attachments::create_attachment)
+        .register("UPLOAD_CHUNK", methods::attachments::upload_chunk)
+        .register("FINISH_ATTACHMENT", methods::attachments::finish_attachment)
+        .register("DOWNLOAD_CHUNK", methods::attachments::download_chunk)
+        .register("DELETE_ATTACHMENT", methods::attachments::delete_attachment)
+        // MLS
+        .register("FETCH_PENDING", methods::mls::fetch_pending)
+        .register("ACK_PENDING", methods::mls::ack_pending)
+        .register("SEND_HANDSHAKE", methods::mls::send_handshake)
         // Users
         .register("GET_CURRENT_USER", methods::users::get_current_user)
         .register("ADD_CONTACT", methods::users::add_contact)
         .register("REMOVE_CONTACT", methods::users::remove_contact)
         .register("GET_CONTACTS", methods::users::get_contacts)
+        .register("BLOCK_CONTACT", methods::users::block_contact)
+        .register("UNBLOCK_CONTACT", methods::users::unblock_contact)
+        .register("SET_PRESENCE", methods::users::set_presence)
         // Keys
         .register("SET_KEY_PACKAGE", methods::keys::set_key_package)
         .register("GET_USER", methods::keys::get_user)
@@ -70,13 +112,29 @@ async fn main() {
         .register("START_CALL", methods::voice::start_call)
         .register("END_CALL", methods::voice::end_call)
         .register("UPDATE_VOICE_STATE", methods::voice::update_voice_state)
-        .register("GET_CALL_MEMBERS", methods::voice::get_call_members);
+        .register("GET_CALL_MEMBERS", methods::voice::get_call_members)
+        .register("DECLINE_CALL", methods::voice::decline_call)
+        .register("GET_CALL_HISTORY", methods::voice::get_call_history)
+        .register("GET_CALLS", methods::voice::get_calls)
+        .register("MOVE_SESSION", methods::voice::move_session)
+        .register(
+            "RESTRICT_CALL_SESSION",
+            methods::voice::restrict_call_session,
+        )
+        .register(
+            "DISCONNECT_CALL_SESSION",
+            methods::voice::disconnect_call_session,
+        );
 
     RPC_CLIENTS
         .set(server.clients())
         .expect("Failed to set RPC clients");
     services::events::spawn_event_subscriber(server.clients());
     voice::spawn_voice_events();
+    services::ringing::spawn_ring_expiry();
+    services::presence::spawn_presence_keepalive();
+    services::blobs::spawn_attachment_expiry();
+    services::database::mls::spawn_commit_release();
 
     server.start(listen_address).await;
 }
@@ -0,0 +1,125 @@
+use harmony_types::attachments::{
+    CHUNK_SIZE, CreateAttachmentMethod, CreateAttachmentResponse, DeleteAttachmentMethod,
+    DeleteAttachmentResponse, DownloadChunkMethod, DownloadChunkResponse, FinishAttachmentMethod,
+    FinishAttachmentResponse, UploadChunkMethod, UploadChunkResponse,
+};
+use rapid::socket::{RpcResponder, RpcState, RpcValue};
+
+use crate::{
+    authentication::check_authenticated,
+    errors::Error,
+    services::{
+        database::{attachments::Attachment, channels::Channel},
+        environment::{ATTACHMENT_MAX_SIZE, ATTACHMENT_QUOTA},
+        permissions::Permission,
+    },
+};
+
+pub async fn create_attachment(
+    state: RpcState,
+    data: RpcValue<CreateAttachmentMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    if data.size == 0 || data.chunk_count == 0 {
+        return Err(Error::AttachmentEmpty);
+    }
+    if data.size > *ATTACHMENT_MAX_SIZE {
+        return Err(Error::AttachmentTooLarge);
+    }
+    // every chunk but the last one is full, so the count follows from the size
+    if data.size.div_ceil(CHUNK_SIZE) != data.chunk_count as u64 {
+        return Err(Error::InvalidTarget);
+    }
+    let channel = Channel::get(&data.channel_id).await?;
+    channel.check_permission(&user.id, Permission::AttachFiles)?;
+    user.check_not_blocked(&channel).await?;
+    let attachment = Attachment::create(
+        channel.id(),
+        &user.id,
+        data.size,
+        data.chunk_count,
+        data.expires_in,
+        *ATTACHMENT_QUOTA,
+    )
+    .await?;
+    Ok(RpcValue(CreateAttachmentResponse {
+        attachment: attachment.into(),
+    }))
+}
+
+pub async fn upload_chunk(state: RpcState, data: RpcValue<UploadChunkMethod>) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    if data.data.len() as u64 > CHUNK_SIZE {
+        return Err(Error::AttachmentTooLarge);
+    }
+    let attachment = Attachment::get(&data.attachment_id).await?;
+    if attachment.uploader_id != user.id {
+        return Err(Error::MissingPermission);
+    }
+    // sent attachments can no longer change
+    if attachment.message_id.is_some() || data.index >= attachment.chunk_count {
+        return Err(Error::InvalidTarget);
+    }
+    // chunks are fixed-size, so an upload can never outgrow the declared size
+    if data.data.len() as u64 != attachment.chunk_len(data.index) {
+        return Err(Error::InvalidTarget);
+    }
+    attachment.store_chunk(data.index, data.data).await?;
+    Ok::<_, Error>(RpcValue(UploadChunkResponse {}))
+}
+
+pub async fn finish_attachment(
+    state: RpcState,
+    data: RpcValue<FinishAttachmentMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let attachment = Attachment::get(&data.attachment_id).await?;
+    if attachment.uploader_id != user.id {
+        return Err(Error::MissingPermission);
+    }
+    if !attachment.is_complete() {
+        return Err(Error::AttachmentIncomplete);
+    }
+    Ok(RpcValue(FinishAttachmentResponse {
+        attachment: attachment.into(),
+    }))
+}
+
+pub async fn download_chunk(
+    state: RpcState,
+    data: RpcValue<DownloadChunkMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let attachment = Attachment::get(&data.attachment_id).await?;
+    let channel = Channel::get(&attachment.channel_id).await?;
+    if !channel.is_member(&user.id) {
+        return Err(Error::NotInChannel);
+    }
+    // until it is sent, only the uploader knows about the attachment
+    if attachment.message_id.is_none() && attachment.uploader_id != user.id {
+        return Err(Error::NotFound);
+    }
+    if !attachment.uploaded_chunks.contains(&data.index) {
+        return Err(Error::NotFound);
+    }
+    let data = attachment.load_chunk(data.index).await?;
+    Ok(RpcValue(DownloadChunkResponse { data }))
+}
+
+pub async fn delete_attachment(
+    state: RpcState,
+    data: RpcValue<DeleteAttachmentMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let attachment = Attachment::get(&data.attachment_id).await?;
+    if attachment.uploader_id != user.id {
+        return Err(Error::MissingPermission);
+    }
+    attachment.delete().await?;
+    Ok(RpcValue(DeleteAttachmentResponse {}))
+}
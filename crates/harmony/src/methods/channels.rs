@@ -1,27 +1,46 @@
+use futures_util::future::try_join_all;
 use harmony_types::channels::{
-    ChannelInformation, CreateChannelMethod, CreateChannelResponse, DeleteChannelMethod,
-    DeleteChannelResponse, EditChannelMethod, EditChannelResponse, EncryptionHint,
+    ApproveMemberMethod, ApproveMemberResponse, BanMemberMethod, BanMemberResponse,
+    ChannelInformation, CreateChannelMethod, CreateChannelResponse, CreateRoleMethod,
+    CreateRoleResponse, DeleteChannelMethod, DeleteChannelResponse, DeleteRoleMethod,
+    DeleteRoleResponse, EditChannelMethod, EditChannelResponse, EditRoleMethod, EditRoleResponse,
     GetChannelMethod, GetChannelResponse, GetChannelsMethod, GetChannelsResponse,
-    LeaveChannelMethod, LeaveChannelResponse,
+    GetGroupKeysMethod, GetGroupKeysResponse, KickMemberMethod, KickMemberResponse,
+    LeaveChannelMethod, LeaveChannelResponse, RejectMemberMethod, RejectMemberResponse,
+    RotateGroupKeyMethod, RotateGroupKeyResponse, SetDefaultPermissionsMethod,
+    SetDefaultPermissionsResponse, SetMemberRolesMethod, SetMemberRolesResponse,
+    ShareGroupKeyMethod, ShareGroupKeyResponse, UnbanMemberMethod, UnbanMemberResponse,
 };
+use harmony_types::users::RelationshipState;
 use rapid::socket::{RpcResponder, RpcState, RpcValue};
 
 use crate::{
     authentication::check_authenticated,
-    errors::Error,
-    methods::{ChannelDeletedEvent, ChannelUpdatedEvent, Event, MemberLeftEvent},
+    errors::{Error, Result},
+    methods::{
+        ChannelDeletedEvent, ChannelUpdatedEvent, Event, MemberJoinedEvent, MemberLeftEvent,
+    },
     services::database::{
         channels::{Channel, ChannelMemberRole},
+        group_keys::GroupKeyShare,
         messages::Message,
+        mls::{self, PendingMessage},
+        read_states::{self, ReadState},
+        users::User,
+    },
+    services::{
+        events,
+        permissions::{self, Permission},
+        voice::ActiveCall,
     },
-    services::events,
 };
 
 pub async fn get_channel(state: RpcState, data: RpcValue<GetChannelMethod>) -> impl RpcResponder {
     let data = data.into_inner();
     let user = check_authenticated(&state).await?;
     let channel = Channel::get(&data.id).await?;
-    if !channel.is_member(&user.id) {
+    // pending members of MLS channels need the channel to request to be added
+    if !channel.is_member(&user.id) && !channel.is_pending_member(&user.id) {
         return Err(Error::NotInChannel);
     }
     Ok(RpcValue(GetChannelResponse {
@@ -32,8 +51,15 @@ pub async fn get_channel(state: RpcState, data: RpcValue<GetChannelMethod>) -> i
 pub async fn get_channels(state: RpcState, _: RpcValue<GetChannelsMethod>) -> impl RpcResponder {
     let user = check_authenticated(&state).await?;
     let channels = user.get_channels().await?;
+    let mut markers = ReadState::for_user(&user.id).await?;
+    let read_states = try_join_all(channels.iter().map(|channel| {
+        let last_read_id = markers.remove(channel.id()).map(|m| m.last_read_id);
+        read_states::summarize(channel, &user.id, last_read_id)
+    }))
+    .await?;
     Ok::<_, Error>(RpcValue(GetChannelsResponse {
         channels: channels.into_iter().map(|c| c.into()).collect(),
+        read_states,
     }))
 }
 
@@ -51,6 +77,9 @@ pub async fn create_channel(
             }
             // check if the user's relationship with the target allows for creating a private channel
             let target = crate::services::database::users::User::get(&target_id).await?;
+            if user.has_blocked(&target.id) || target.has_blocked(&user.id) {
+                return Err(Error::Blocked);
+            }
             let Some(key_id) = user.can_dm(&target).await? else {
                 return Err(Error::InvalidTarget);
             };
@@ -60,14 +89,13 @@ pub async fn create_channel(
             metadata,
             encryption_hint,
         } => {
-            if let EncryptionHint::Mls = encryption_hint {
-                // FIXME: MLS implementation is quite a bit of work
-                // particularly, we need to track a key package for every device the user is logged in on
-                // each time a user logs in, a key package needs to be uploaded
-                // and then verified and approved by another device by generating commits
-                return Err(Error::Unimplemented);
+            let channel = Channel::create_group(user.id.clone(), metadata, encryption_hint).await?;
+            if channel.is_mls() {
+                // the creator's client holds the group at epoch 0; every later
+                // commit has to be sequenced through the server
+                mls::init_epoch(channel.id()).await?;
             }
-            Channel::create_group(user.id.clone(), metadata, encryption_hint).await?
+            channel
         }
     };
     Ok::<_, Error>(RpcValue(CreateChannelResponse {
@@ -79,9 +107,7 @@ pub async fn edit_channel(state: RpcState, data: RpcValue<EditChannelMethod>) ->
     let user = check_authenticated(&state).await?;
     let data = data.into_inner();
     let channel = Channel::get(&data.channel_id).await?;
-    if !channel.is_manager(&user.id) {
-        return Err(Error::MissingPermission);
-    }
+    channel.check_permission(&user.id, Permission::ManageChannel)?;
     let updated = channel.update_metadata(data.metadata).await?;
     let member_ids = updated.member_ids();
     events::publish(
@@ -91,7 +117,7 @@ pub async fn edit_channel(state: RpcState, data: RpcValue<EditChannelMethod>) ->
         }),
     )
     .await;
-    Ok(RpcValue(EditChannelResponse {
+    Ok::<_, Error>(RpcValue(EditChannelResponse {
         channel: updated.into(),
     }))
 }
@@ -103,9 +129,10 @@ pub async fn delete_channel(
     let user = check_authenticated(&state).await?;
     let data = data.into_inner();
     let channel = Channel::get(&data.channel_id).await?;
-    if !channel.is_manager(&user.id) {
+    if !matches!(channel, Channel::GroupChannel { .. }) {
         return Err(Error::MissingPermission);
     }
+    channel.check_permission(&user.id, Permission::Administrator)?;
     let member_ids = channel.member_ids();
     channel.delete().await?;
     // also delete all messages associated with the channel
@@ -144,6 +171,9 @@ pub async fn leave_channel(
                 return Err(Error::LastManager);
             }
             channel.remove_member(&user.id).await?;
+            PendingMessage::remove_recipient(&data.channel_id, &user.id).await?;
+            GroupKeyShare::delete_for(&data.channel_id, &user.id).await?;
+            ReadState::delete_for(&data.channel_id, &user.id).await?;
             if members.len() <= 1 {
                 channel.delete().await?;
             } else {
@@ -165,3 +195,394 @@ pub async fn leave_channel(
     }
     Ok(RpcValue(LeaveChannelResponse {}))
 }
+
+pub async fn kick_member(state: RpcState, data: RpcValue<KickMemberMethod>) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let channel = Channel::get(&data.channel_id).await?;
+    if !matches!(channel, Channel::GroupChannel { .. }) {
+        return Err(Error::InvalidMethod);
+    }
+    channel.check_permission(&user.id, Permission::KickMembers)?;
+    check_moderation_target(&user.id, &channel, &data.user_id)?;
+    if !channel.is_member(&data.user_id) {
+        return Err(Error::NotInChannel);
+    }
+    channel.remove_member(&data.user_id).await?;
+    member_removed(&channel, &data.user_id).await?;
+    Ok(RpcValue(KickMemberResponse {}))
+}
+
+pub async fn ban_member(state: RpcState, data: RpcValue<BanMemberMethod>) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let channel = Channel::get(&data.channel_id).await?;
+    if !matches!(channel, Channel::GroupChannel { .. }) {
+        return Err(Error::InvalidMethod);
+    }
+    channel.check_permission(&user.id, Permission::BanMembers)?;
+    check_moderation_target(&user.id, &channel, &data.user_id)?;
+    // users can be banned before they ever join
+    let was_present = channel.is_member(&data.user_id) || channel.is_pending_member(&data.user_id);
+    channel.ban_member(&data.user_id).await?;
+    if was_present {
+        member_removed(&channel, &data.user_id).await?;
+    }
+    Ok(RpcValue(BanMemberResponse {}))
+}
+
+pub async fn unban_member(state: RpcState, data: RpcValue<UnbanMemberMethod>) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let channel = Channel::get(&data.channel_id).await?;
+    channel.check_permission(&user.id, Permission::BanMembers)?;
+    if !channel.is_banned(&data.user_id) {
+        return Err(Error::InvalidTarget);
+    }
+    channel.unban_member(&data.user_id).await?;
+    Ok(RpcValue(UnbanMemberResponse {}))
+}
+
+pub async fn approve_member(
+    state: RpcState,
+    data: RpcValue<ApproveMemberMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let channel = Channel::get(&data.channel_id).await?;
+    channel.check_permission(&user.id, Permission::ManageMembers)?;
+    if !channel.is_pending_member(&data.user_id) {
+        return Err(Error::InvalidTarget);
+    }
+    // MLS members join through a commit carrying their welcome (SEND_HANDSHAKE)
+    if channel.is_mls() {
+        return Err(Error::InvalidMethod);
+    }
+    channel.promote_pending_member(&data.user_id).await?;
+    let mut notify = channel.member_ids();
+    notify.push(data.user_id.clone());
+    events::publish(
+        &notify,
+        Event::MemberJoined(MemberJoinedEvent {
+            channel_id: data.channel_id,
+            user_id: data.user_id,
+        }),
+    )
+    .await;
+    Ok(RpcValue(ApproveMemberResponse {}))
+}
+
+pub async fn reject_member(
+    state: RpcState,
+    data: RpcValue<RejectMemberMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let channel = Channel::get(&data.channel_id).await?;
+    channel.check_permission(&user.id, Permission::ManageMembers)?;
+    if !channel.is_pending_member(&data.user_id) {
+        return Err(Error::InvalidTarget);
+    }
+    channel.remove_pending_member(&data.user_id).await?;
+    member_removed(&channel, &data.user_id).await?;
+    Ok(RpcValue(RejectMemberResponse {}))
+}
+
+/// Members cannot moderate themselves, and only managers can moderate other
+/// managers.
+fn check_moderation_target(user_id: &str, channel: &Channel, target_id: &str) -> Result<()> {
+    if user_id == target_id {
+        return Err(Error::InvalidTarget);
+    }
+    if channel.is_manager(target_id) && !channel.is_manager(user_id) {
+        return Err(Error::MissingPermission);
+    }
+    Ok(())
+}
+
+/// Clean up after a user was removed from `channel` by someone else. The
+/// channel is the state from before the removal.
+async fn member_removed(channel: &Channel, user_id: &str) -> Result<()> {
+    PendingMessage::remove_recipient(channel.id(), user_id).await?;
+    GroupKeyShare::delete_for(channel.id(), user_id).await?;
+    ReadState::delete_for(channel.id(), user_id).await?;
+    for mut call in ActiveCall::list_in_channel(channel.id()).await? {
+        call.disconnect_user(user_id).await?;
+    }
+    // the removed user is told as well so their client can drop the channel
+    let mut notify: Vec<String> = channel
+        .member_ids()
+        .into_iter()
+        .filter(|id| id != user_id)
+        .collect();
+    notify.push(user_id.to_string());
+    events::publish(
+        &notify,
+        Event::MemberLeft(MemberLeftEvent {
+            channel_id: channel.id().to_string(),
+            user_id: user_id.to_string(),
+        }),
+    )
+    .await;
+    Ok(())
+}
+
+const MAX_GROUP_KEY_SHARES: usize = 100;
+
+/// Group keys may only be wrapped to established contacts of the sender, and
+/// only to users that are allowed into the channel.
+async fn check_group_key_recipients(
+    user: &User,
+    channel: &Channel,
+    recipient_ids: &[&String],
+    allow_invited: bool,
+) -> Result<()> {
+    let invited: Vec<String> = if allow_invited {
+        channel
+            .get_invites()
+            .await?
+            .into_iter()
+            .map(harmony_types::invites::Invite::from)
+            .filter(|i| i.is_valid())
+            .filter_map(|i| i.authorized_users)
+            .flatten()
+            .collect()
+    } else {
+        Vec::new()
+    };
+    for recipient_id in recipient_ids {
+        if **recipient_id == user.id {
+            return Err(Error::InvalidMethod);
+        }
+        let allowed = channel.is_member(recipient_id)
+            || channel.is_pending_member(recipient_id)
+            || invited.contains(recipient_id);
+        if !allowed {
+            return Err(Error::NotInChannel);
+        }
+        if !matches!(
+            user.relationship_with(recipient_id).await?,
+            Some(RelationshipState::Established { .. })
+        ) {
+            return Err(Error::MissingPermission);
+        }
+    }
+    Ok(())
+}
+
+pub async fn share_group_key(
+    state: RpcState,
+    data: RpcValue<ShareGroupKeyMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let channel = Channel::get(&data.channel_id).await?;
+    let Channel::GroupChannel { last_key_id, .. } = &channel else {
+        return Err(Error::InvalidMethod);
+    };
+    if !channel.is_member(&user.id) {
+        return Err(Error::NotInChannel);
+    }
+    // only the current key is handed out
+    if data.key_id != *last_key_id {
+        return Err(Error::StaleGroupKey);
+    }
+    if data.shares.len() > MAX_GROUP_KEY_SHARES {
+        return Err(Error::InvalidMethod);
+    }
+    let recipient_ids: Vec<&String> = data.shares.iter().map(|s| &s.recipient_id).collect();
+    check_group_key_recipients(&user, &channel, &recipient_ids, true).await?;
+    let shares: Vec<GroupKeyShare> = data
+        .shares
+        .into_iter()
+        .map(|share| GroupKeyShare::new(channel.id(), data.key_id.as_deref(), &user.id, share))
+        .collect();
+    GroupKeyShare::store(&shares).await?;
+    Ok(RpcValue(ShareGroupKeyResponse {}))
+}
+
+pub async fn get_group_keys(
+    state: RpcState,
+    data: RpcValue<GetGroupKeysMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let channel = Channel::get(&data.channel_id).await?;
+    if !channel.is_member(&user.id) && !channel.is_pending_member(&user.id) {
+        return Err(Error::NotInChannel);
+    }
+    let keys = GroupKeyShare::fetch(&data.channel_id, &user.id).await?;
+    Ok(RpcValue(GetGroupKeysResponse {
+        keys: keys.into_iter().map(|k| k.into()).collect(),
+    }))
+}
+
+pub async fn rotate_group_key(
+    state: RpcState,
+    data: RpcValue<RotateGroupKeyMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let channel = Channel::get(&data.channel_id).await?;
+    channel.check_permission(&user.id, Permission::ManageChannel)?;
+    if data.shares.len() > MAX_GROUP_KEY_SHARES {
+        return Err(Error::InvalidMethod);
+    }
+    let recipient_ids: Vec<&String> = data.shares.iter().map(|s| &s.recipient_id).collect();
+    check_group_key_recipients(&user, &channel, &recipient_ids, false).await?;
+    let updated = channel
+        .rotate_group_key(data.previous_key_id.as_deref(), &data.key_id, data.metadata)
+        .await?;
+    let shares: Vec<GroupKeyShare> = data
+        .shares
+        .into_iter()
+        .map(|share| GroupKeyShare::new(updated.id(), Some(&data.key_id), &user.id, share))
+        .collect();
+    GroupKeyShare::store(&shares).await?;
+    events::publish(
+        &updated.member_ids(),
+        Event::ChannelUpdated(ChannelUpdatedEvent {
+            channel: updated.clone().into(),
+        }),
+    )
+    .await;
+    Ok(RpcValue(RotateGroupKeyResponse {
+        channel: updated.into(),
+    }))
+}
+
+const MAX_ROLES: usize = 50;
+const MAX_ROLE_NAME_LENGTH: usize = 64;
+
+fn check_role_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(Error::NameEmpty);
+    }
+    if name.len() > MAX_ROLE_NAME_LENGTH {
+        return Err(Error::NameTooLong);
+    }
+    Ok(())
+}
+
+async fn publish_channel_updated(channel: &Channel) {
+    events::publish(
+        &channel.member_ids(),
+        Event::ChannelUpdated(ChannelUpdatedEvent {
+            channel: channel.clone().into(),
+        }),
+    )
+    .await;
+}
+
+pub async fn create_role(state: RpcState, data: RpcValue<CreateRoleMethod>) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    check_role_name(&data.name)?;
+    let channel = Channel::get(&data.channel_id).await?;
+    channel.check_permission(&user.id, Permission::ManageRoles)?;
+    if !permissions::can_grant(channel.permissions_for(&user.id), data.permissions) {
+        return Err(Error::MissingPermission);
+    }
+    if channel.role_count() >= MAX_ROLES {
+        return Err(Error::InvalidMethod);
+    }
+    let role = channel.create_role(data.name, data.permissions).await?;
+    publish_channel_updated(&Channel::get(&data.channel_id).await?).await;
+    Ok(RpcValue(CreateRoleResponse { role }))
+}
+
+pub async fn edit_role(state: RpcState, data: RpcValue<EditRoleMethod>) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    if data.name.is_none() && data.permissions.is_none() {
+        return Err(Error::InvalidMethod);
+    }
+    if let Some(name) = &data.name {
+        check_role_name(name)?;
+    }
+    let channel = Channel::get(&data.channel_id).await?;
+    channel.check_permission(&user.id, Permission::ManageRoles)?;
+    let actor = channel.permissions_for(&user.id);
+    let role = channel.get_role(&data.role_id).ok_or(Error::NotFound)?;
+    if !permissions::can_modify_role(actor, role) {
+        return Err(Error::MissingPermission);
+    }
+    if let Some(permissions) = data.permissions
+        && !permissions::can_grant(actor, permissions)
+    {
+        return Err(Error::MissingPermission);
+    }
+    let updated = channel
+        .edit_role(&data.role_id, data.name, data.permissions)
+        .await?;
+    let role = updated
+        .get_role(&data.role_id)
+        .cloned()
+        .ok_or(Error::NotFound)?;
+    publish_channel_updated(&updated).await;
+    Ok(RpcValue(EditRoleResponse { role }))
+}
+
+pub async fn delete_role(state: RpcState, data: RpcValue<DeleteRoleMethod>) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let channel = Channel::get(&data.channel_id).await?;
+    channel.check_permission(&user.id, Permission::ManageRoles)?;
+    let role = channel.get_role(&data.role_id).ok_or(Error::NotFound)?;
+    if !permissions::can_modify_role(channel.permissions_for(&user.id), role) {
+        return Err(Error::MissingPermission);
+    }
+    let updated = channel.delete_role(&data.role_id).await?;
+    publish_channel_updated(&updated).await;
+    Ok(RpcValue(DeleteRoleResponse {}))
+}
+
+pub async fn set_member_roles(
+    state: RpcState,
+    data: RpcValue<SetMemberRolesMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let mut data = data.into_inner();
+    let channel = Channel::get(&data.channel_id).await?;
+    channel.check_permission(&user.id, Permission::ManageRoles)?;
+    if !channel.is_member(&data.user_id) {
+        return Err(Error::NotInChannel);
+    }
+    data.role_ids.sort();
+    data.role_ids.dedup();
+    let actor = channel.permissions_for(&user.id);
+    let current = channel.member_roles(&data.user_id);
+    // every role being added or removed must be one the actor could manage
+    let changed = data
+        .role_ids
+        .iter()
+        .filter(|id| !current.contains(id))
+        .chain(current.iter().filter(|id| !data.role_ids.contains(id)));
+    for role_id in changed {
+        let role = channel.get_role(role_id).ok_or(Error::NotFound)?;
+        if !permissions::can_modify_role(actor, role) {
+            return Err(Error::MissingPermission);
+        }
+    }
+    let updated = channel
+        .set_member_roles(&data.user_id, data.role_ids)
+        .await?;
+    publish_channel_updated(&updated).await;
+    Ok(RpcValue(SetMemberRolesResponse {}))
+}
+
+pub async fn set_default_permissions(
+    state: RpcState,
+    data: RpcValue<SetDefaultPermissionsMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let channel = Channel::get(&data.channel_id).await?;
+    channel.check_permission(&user.id, Permission::ManageRoles)?;
+    if !permissions::can_grant(channel.permissions_for(&user.id), data.permissions) {
+        return Err(Error::MissingPermission);
+    }
+    let updated = channel.set_default_permissions(data.permissions).await?;
+    publish_channel_updated(&updated).await;
+    Ok(RpcValue(SetDefaultPermissionsResponse {}))
+}
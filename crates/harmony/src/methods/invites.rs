@@ -13,7 +13,7 @@ use crate::{
         channels::{Channel, EncryptionHint},
         invites::Invite,
     },
-    services::events,
+    services::{events, permissions::Permission},
 };
 
 pub async fn create_invite(
@@ -22,6 +22,11 @@ pub async fn create_invite(
 ) -> impl RpcResponder {
     let data = data.into_inner();
     let user = check_authenticated(&state).await?;
+    let channel = Channel::get(&data.channel_id).await?;
+    if !matches!(channel, Channel::GroupChannel { .. }) {
+        return Err(Error::InvalidMethod);
+    }
+    channel.check_permission(&user.id, Permission::CreateInvite)?;
     let invite = Invite::create(
         data.channel_id.clone(),
         user.id.clone(),
@@ -30,7 +35,7 @@ pub async fn create_invite(
         data.authorized_users.clone(),
     )
     .await?;
-    Ok::<_, Error>(RpcValue(CreateInviteResponse {
+    Ok(RpcValue(CreateInviteResponse {
         invite: invite.into(),
     }))
 }
@@ -43,7 +48,7 @@ pub async fn delete_invite(
     let user = check_authenticated(&state).await?;
     let invite = Invite::get(&data.id).await?;
     let channel = Channel::get(&invite.channel_id).await?;
-    if !channel.is_manager(&user.id) {
+    if invite.creator != user.id && !channel.has_permission(&user.id, Permission::ManageInvites) {
         Err(Error::MissingPermission)
     } else {
         invite.delete().await?;
@@ -54,9 +59,9 @@ pub async fn delete_invite(
 pub async fn get_invite(state: RpcState, data: RpcValue<GetInviteMethod>) -> impl RpcResponder {
     let data = data.into_inner();
     let user = check_authenticated(&state).await?;
-    let invite = Invite::get(&data.code).await?;
+    let invite = Invite::get_by_code(&data.code).await?;
     let channel = Channel::get(&invite.channel_id).await?;
-    //ban?
+    let banned = channel.is_banned(&user.id);
     let Channel::GroupChannel {
         id: channel_id,
         metadata,
@@ -71,9 +76,10 @@ pub async fn get_invite(state: RpcState, data: RpcValue<GetInviteMethod>) -> imp
             channel_id,
             metadata: metadata.clone(),
             inviter_id: invite.creator,
-            authorized: invite
-                .authorized_users
-                .is_none_or(|users| users.contains(&user.id)),
+            authorized: !banned
+                && invite
+                    .authorized_users
+                    .is_none_or(|users| users.contains(&user.id)),
             member_count: members.len() as i32,
         },
     }))
@@ -83,11 +89,9 @@ pub async fn get_invites(state: RpcState, data: RpcValue<GetInvitesMethod>) -> i
     let data = data.into_inner();
     let user = check_authenticated(&state).await?;
     let channel = Channel::get(&data.channel_id).await?;
-    if !channel.is_manager(&user.id) {
-        return Err(Error::MissingPermission);
-    }
+    channel.check_permission(&user.id, Permission::ManageInvites)?;
     let invites = channel.get_invites().await?;
-    Ok(RpcValue(GetInvitesResponse {
+    Ok::<_, Error>(RpcValue(GetInvitesResponse {
         invites: invites.into_iter().map(|i| i.into()).collect(),
     }))
 }
@@ -98,13 +102,16 @@ pub async fn accept_invite(
 ) -> impl RpcResponder {
     let data = data.into_inner();
     let user = check_authenticated(&state).await?;
-    let invite = Invite::get(&data.code).await?;
+    let invite = Invite::get_by_code(&data.code).await?;
     if invite
         .authorized_users
         .as_ref()
         .is_none_or(|users| users.contains(&user.id))
     {
         let channel = Channel::get(&invite.channel_id).await?;
+        if channel.is_banned(&user.id) {
+            return Err(Error::Blocked);
+        }
         let pending = if let Channel::GroupChannel {
             encryption_hint: EncryptionHint::Mls,
             ..
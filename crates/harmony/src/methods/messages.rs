@@ -1,21 +1,39 @@
 use harmony_types::messages::{
-    DeleteMessageMethod, DeleteMessageResponse, EditMessageMethod, EditMessageResponse,
-    GetMessagesMethod, GetMessagesResponse, SendMessageMethod, SendMessageResponse,
+    AddReactionMethod, AddReactionResponse, DeleteMessageMethod, DeleteMessageResponse,
+    EditMessageMethod, EditMessageResponse, GetMessagesMethod, GetMessagesResponse,
+    GetThreadMethod, GetThreadResponse, MarkReadMethod, MarkReadResponse, RemoveReactionMethod,
+    RemoveReactionResponse, SendMessageMethod, SendMessageResponse, SetTypingMethod,
+    SetTypingResponse,
 };
 use rapid::socket::{RpcResponder, RpcState, RpcValue};
+use ulid::Ulid;
 
 use crate::{
     authentication::check_authenticated,
     errors::Error,
-    methods::{Event, MessageDeletedEvent, MessageEditedEvent, NewMessageEvent},
+    methods::{
+        Event, MessageDeletedEvent, MessageEditedEvent, NewMessageEvent, ReactionAddedEvent,
+        ReactionRemovedEvent, ReadStateUpdatedEvent, TypingStartedEvent,
+    },
     services::database::{
-        channels::{Channel, EncryptionHint},
+        attachments::Attachment,
+        channels::Channel,
         messages::Message,
+        mls::{PendingMessage, PendingMessageKind},
+        read_states::{self, ReadState},
         users::User,
     },
-    services::events,
+    services::{
+        environment::PENDING_MESSAGE_TTL, events, permissions::Permission, redis::start_typing,
+    },
 };
 
+const MAX_REACTION_SIZE: usize = 1024;
+const MAX_REACTIONS_PER_USER: usize = 20;
+const MAX_ATTACHMENTS_PER_MESSAGE: usize = 10;
+// clients repeat SET_TYPING while the user types; at most one event is sent per window
+const TYPING_THROTTLE_SECONDS: u64 = 3;
+
 pub async fn get_messages(state: RpcState, data: RpcValue<GetMessagesMethod>) -> impl RpcResponder {
     let user = check_authenticated(&state).await?;
     let data = data.into_inner();
@@ -23,12 +41,18 @@ pub async fn get_messages(state: RpcState, data: RpcValue<GetMessagesMethod>) ->
     if !channel.is_member(&user.id) {
         return Err(Error::NotInChannel);
     }
-    if let Channel::GroupChannel {
-        encryption_hint: EncryptionHint::Mls,
-        ..
-    } = &channel
-    {
-        return Err(Error::Unimplemented);
+    if channel.is_mls() {
+        // MLS history is not kept on the server, only what the user has yet to acknowledge
+        let messages =
+            PendingMessage::fetch(&user.id, None, Some(channel.id()), data.limit.unwrap_or(50))
+                .await?;
+        return Ok(RpcValue(GetMessagesResponse {
+            messages: messages
+                .into_iter()
+                .filter(|m| m.kind == PendingMessageKind::Application)
+                .map(|m| Message::from(m).into())
+                .collect(),
+        }));
     }
     let messages = channel
         .get_messages(
@@ -43,6 +67,23 @@ pub async fn get_messages(state: RpcState, data: RpcValue<GetMessagesMethod>) ->
     }))
 }
 
+pub async fn get_thread(state: RpcState, data: RpcValue<GetThreadMethod>) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    // MLS messages are never stored, so their threads only exist on clients
+    let parent = Message::get(&data.message_id).await?;
+    let channel = Channel::get(&parent.channel_id).await?;
+    if !channel.is_member(&user.id) {
+        return Err(Error::NotInChannel);
+    }
+    let messages = parent
+        .get_thread(data.limit, data.latest, data.before, data.after)
+        .await?;
+    Ok(RpcValue(GetThreadResponse {
+        messages: messages.into_iter().map(|m| m.into()).collect(),
+    }))
+}
+
 pub async fn send_message(state: RpcState, data: RpcValue<SendMessageMethod>) -> impl RpcResponder {
     let user = check_authenticated(&state).await?;
     let data = data.into_inner();
@@ -53,9 +94,8 @@ pub async fn send_message(state: RpcState, data: RpcValue<SendMessageMethod>) ->
         return Err(Error::MessageEmpty);
     }
     let channel = Channel::get(&data.channel_id).await?;
-    if !channel.is_member(&user.id) {
-        return Err(Error::NotInChannel);
-    }
+    channel.check_permission(&user.id, Permission::SendMessages)?;
+    user.check_not_blocked(&channel).await?;
     if let Channel::PrivateChannel {
         initiator_id,
         target_id,
@@ -71,19 +111,51 @@ pub async fn send_message(state: RpcState, data: RpcValue<SendMessageMethod>) ->
             return Err(Error::InvalidTarget);
         }
     }
-    let is_mls = matches!(
-        &channel,
-        Channel::GroupChannel {
-            encryption_hint: EncryptionHint::Mls,
-            ..
+    if let Some(parent_id) = &data.parent_id {
+        if channel.is_mls() {
+            // the parent is not on the server; only check that it could be a message ID
+            if Ulid::from_string(parent_id).is_err() {
+                return Err(Error::InvalidTarget);
+            }
+        } else {
+            // threads are one level deep, so replies cannot start threads of their own
+            let parent = Message::get(parent_id).await?;
+            if parent.channel_id != channel.id() || parent.parent_id.is_some() {
+                return Err(Error::InvalidTarget);
+            }
         }
-    );
-
-    let message = if is_mls {
-        Message::ephemeral(&data.channel_id, &user.id, &data.content).await?
+    }
+    if data.attachment_ids.len() > MAX_ATTACHMENTS_PER_MESSAGE {
+        return Err(Error::InvalidTarget);
+    }
+    let mut attachments = Vec::with_capacity(data.attachment_ids.len());
+    if !data.attachment_ids.is_empty() {
+        channel.check_permission(&user.id, Permission::AttachFiles)?;
+    }
+    for attachment_id in &data.attachment_ids {
+        let attachment = Attachment::get(attachment_id).await?;
+        if attachment.uploader_id != user.id
+            || attachment.channel_id != channel.id()
+            || attachment.message_id.is_some()
+        {
+            return Err(Error::InvalidTarget);
+        }
+        if !attachment.is_complete() {
+            return Err(Error::AttachmentIncomplete);
+        }
+        attachments.push(attachment);
+    }
+    let message = if channel.is_mls() {
+        Message::ephemeral(&channel, &user.id, &data.content, data.parent_id.as_deref()).await?
     } else {
-        Message::create(&channel, &user.id, &data.content).await?
+        Message::create(&channel, &user.id, &data.content, data.parent_id.as_deref()).await?
     };
+    // MLS messages are gone once every member fetched them, so their files
+    // cannot outlive the mailbox
+    let max_lifetime = channel.is_mls().then_some(*PENDING_MESSAGE_TTL);
+    for attachment in attachments {
+        attachment.attach(&message.id, max_lifetime).await?;
+    }
 
     let member_ids = channel.member_ids();
     events::publish(
@@ -138,11 +210,11 @@ pub async fn delete_message(
     let message = Message::get(&data.message_id).await?;
     let channel = Channel::get(&message.channel_id).await?;
     let is_author = message.author_id == user.id;
-    let is_manager = channel.is_manager(&user.id);
-    if !is_author && !is_manager {
+    if !is_author && !channel.has_permission(&user.id, Permission::ManageMessages) {
         return Err(Error::MissingPermission);
     }
     let deleted = message.delete().await?;
+    Attachment::delete_for_message(&deleted.id).await?;
     let member_ids = channel.member_ids();
     events::publish(
         &member_ids,
@@ -154,3 +226,107 @@ pub async fn delete_message(
     .await;
     Ok(RpcValue(DeleteMessageResponse {}))
 }
+
+pub async fn add_reaction(state: RpcState, data: RpcValue<AddReactionMethod>) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    if data.data.len() > MAX_REACTION_SIZE {
+        return Err(Error::MessageTooLong);
+    }
+    if data.data.is_empty() {
+        return Err(Error::MessageEmpty);
+    }
+    let message = Message::get(&data.message_id).await?;
+    let channel = Channel::get(&message.channel_id).await?;
+    channel.check_permission(&user.id, Permission::UseReactions)?;
+    user.check_not_blocked(&channel).await?;
+    if message.reaction_count(&user.id) >= MAX_REACTIONS_PER_USER {
+        return Err(Error::ReactionLimitReached);
+    }
+    let reaction = message.add_reaction(&user.id, data.data).await?;
+    let member_ids = channel.member_ids();
+    events::publish(
+        &member_ids,
+        Event::ReactionAdded(ReactionAddedEvent {
+            message_id: message.id.clone(),
+            channel_id: message.channel_id.clone(),
+            reaction: reaction.clone(),
+            key_id: message.key_id.clone(),
+        }),
+    )
+    .await;
+    Ok(RpcValue(AddReactionResponse { reaction }))
+}
+
+pub async fn remove_reaction(
+    state: RpcState,
+    data: RpcValue<RemoveReactionMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let message = Message::get(&data.message_id).await?;
+    let channel = Channel::get(&message.channel_id).await?;
+    if !channel.is_member(&user.id) {
+        return Err(Error::NotInChannel);
+    }
+    // users can only remove their own reactions
+    let reaction = message.remove_reaction(&user.id, data.data).await?;
+    let member_ids = channel.member_ids();
+    events::publish(
+        &member_ids,
+        Event::ReactionRemoved(ReactionRemovedEvent {
+            message_id: message.id.clone(),
+            channel_id: message.channel_id.clone(),
+            reaction,
+            key_id: message.key_id.clone(),
+        }),
+    )
+    .await;
+    Ok::<_, Error>(RpcValue(RemoveReactionResponse {}))
+}
+
+pub async fn set_typing(state: RpcState, data: RpcValue<SetTypingMethod>) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    // checked before throttling, so only members can create throttle keys
+    let channel = Channel::get(&data.channel_id).await?;
+    channel.check_permission(&user.id, Permission::SendMessages)?;
+    if start_typing(channel.id(), &user.id, TYPING_THROTTLE_SECONDS).await? {
+        let recipients: Vec<String> = channel
+            .member_ids()
+            .into_iter()
+            .filter(|id| *id != user.id)
+            .collect();
+        events::publish(
+            &recipients,
+            Event::TypingStarted(TypingStartedEvent {
+                channel_id: data.channel_id,
+                user_id: user.id,
+            }),
+        )
+        .await;
+    }
+    Ok::<_, Error>(RpcValue(SetTypingResponse {}))
+}
+
+pub async fn mark_read(state: RpcState, data: RpcValue<MarkReadMethod>) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    if Ulid::from_string(&data.message_id).is_err() {
+        return Err(Error::InvalidTarget);
+    }
+    let channel = Channel::get(&data.channel_id).await?;
+    if !channel.is_member(&user.id) {
+        return Err(Error::NotInChannel);
+    }
+    let marker = ReadState::mark(&user.id, channel.id(), &data.message_id).await?;
+    let read_state = read_states::summarize(&channel, &user.id, Some(marker.last_read_id)).await?;
+    events::publish_one(
+        &user.id,
+        Event::ReadStateUpdated(ReadStateUpdatedEvent {
+            read_state: read_state.clone(),
+        }),
+    )
+    .await;
+    Ok(RpcValue(MarkReadResponse { read_state }))
+}
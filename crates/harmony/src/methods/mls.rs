@@ -0,0 +1,199 @@
+use std::time::Duration;
+
+use harmony_types::mls::{
+    AckPendingMethod, AckPendingResponse, FetchPendingMethod, FetchPendingResponse,
+    SendHandshakeMethod, SendHandshakeResponse,
+};
+use rapid::socket::{RpcResponder, RpcState, RpcValue};
+use tokio::time;
+
+use crate::{
+    authentication::check_authenticated,
+    errors::Error,
+    methods::{Event, MemberJoinedEvent, PendingMessageEvent},
+    services::database::{
+        channels::Channel,
+        mls::{self, PendingMessage, PendingMessageKind},
+    },
+    services::events,
+};
+
+const MAX_FETCH_PENDING: i64 = 100;
+const MAX_ACK_PENDING: usize = 100;
+const MAX_HANDSHAKE_SIZE: usize = 262144;
+const RELEASE_ATTEMPTS: u32 = 3;
+const RELEASE_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+pub async fn fetch_pending(
+    state: RpcState,
+    data: RpcValue<FetchPendingMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let limit = data
+        .limit
+        .unwrap_or(MAX_FETCH_PENDING)
+        .clamp(1, MAX_FETCH_PENDING);
+    let messages = PendingMessage::fetch(
+        &user.id,
+        data.device_id.as_deref(),
+        data.channel_id.as_deref(),
+        limit,
+    )
+    .await?;
+    Ok::<_, Error>(RpcValue(FetchPendingResponse {
+        messages: messages.into_iter().map(|m| m.into()).collect(),
+    }))
+}
+
+pub async fn ack_pending(state: RpcState, data: RpcValue<AckPendingMethod>) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    if data.message_ids.len() > MAX_ACK_PENDING {
+        return Err(Error::InvalidMethod);
+    }
+    if !data.message_ids.is_empty() {
+        PendingMessage::acknowledge(&user.id, data.device_id.as_deref(), &data.message_ids).await?;
+    }
+    Ok(RpcValue(AckPendingResponse {}))
+}
+
+pub async fn send_handshake(
+    state: RpcState,
+    data: RpcValue<SendHandshakeMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    if data.content.len() > MAX_HANDSHAKE_SIZE {
+        return Err(Error::MessageTooLong);
+    }
+    if data.content.is_empty() {
+        return Err(Error::MessageEmpty);
+    }
+    let channel = Channel::get(&data.channel_id).await?;
+    if !channel.is_mls() {
+        return Err(Error::InvalidMethod);
+    }
+    let recipients: Vec<String> = match data.kind {
+        PendingMessageKind::KeyPackage => {
+            // pending members and members on a new device ask to be added,
+            // and only managers can commit them
+            if !channel.is_pending_member(&user.id) && !channel.is_member(&user.id) {
+                return Err(Error::InvalidTarget);
+            }
+            channel
+                .member_ids()
+                .into_iter()
+                .filter(|id| channel.is_manager(id))
+                .collect()
+        }
+        PendingMessageKind::Commit => {
+            if !channel.is_member(&user.id) {
+                return Err(Error::NotInChannel);
+            }
+            if !data.added_member_ids.is_empty() {
+                if !channel.is_manager(&user.id) {
+                    return Err(Error::MissingPermission);
+                }
+                if data
+                    .added_member_ids
+                    .iter()
+                    .any(|id| !channel.is_pending_member(id) && !channel.is_member(id))
+                {
+                    return Err(Error::InvalidTarget);
+                }
+                match &data.welcome {
+                    Some(welcome) if welcome.len() <= MAX_HANDSHAKE_SIZE => {}
+                    Some(_) => return Err(Error::MessageTooLong),
+                    None => return Err(Error::InvalidMethod),
+                }
+            }
+            // including the author, whose other devices are in the group too
+            channel.member_ids()
+        }
+        // application messages go through SEND_MESSAGE, welcomes ride along with commits
+        PendingMessageKind::Application | PendingMessageKind::Welcome => {
+            return Err(Error::InvalidMethod);
+        }
+    };
+    let mut messages = vec![PendingMessage::new(
+        channel.id(),
+        &user.id,
+        data.kind,
+        &data.content,
+        data.epoch,
+        None,
+        recipients,
+    )];
+    if let (PendingMessageKind::Commit, Some(welcome)) = (data.kind, &data.welcome)
+        && !data.added_member_ids.is_empty()
+    {
+        messages.push(PendingMessage::new(
+            channel.id(),
+            &user.id,
+            PendingMessageKind::Welcome,
+            welcome,
+            data.epoch,
+            None,
+            data.added_member_ids.clone(),
+        ));
+    }
+    if data.kind == PendingMessageKind::Commit {
+        let epoch = data.epoch.ok_or(Error::InvalidMethod)?;
+        // store the commit first, so an accepted epoch always has its commit
+        // behind it, and only deliver it once the epoch change is accepted
+        PendingMessage::create_held(&mut messages).await?;
+        if let Err(e) = mls::advance_epoch(channel.id(), epoch, &messages).await {
+            // left behind, they would only expire unseen
+            if let Err(discard_error) = PendingMessage::discard(&messages).await {
+                tracing::warn!("Failed to discard rejected commit: {:?}", discard_error);
+            }
+            return Err(e);
+        }
+        // the accepted epoch remembers the commit, so if every attempt fails
+        // it is still released later on
+        let mut attempt = 1;
+        while let Err(e) = PendingMessage::release(channel.id(), &mut messages).await {
+            if attempt == RELEASE_ATTEMPTS {
+                tracing::error!("Failed to release accepted commit: {:?}", e);
+                return Err(e);
+            }
+            attempt += 1;
+            time::sleep(RELEASE_RETRY_DELAY).await;
+        }
+    } else {
+        messages[0].insert().await?;
+    }
+    for message in &messages {
+        events::publish(
+            &message.recipients,
+            Event::PendingMessage(PendingMessageEvent {
+                message: message.clone().into(),
+            }),
+        )
+        .await;
+    }
+    if data.kind == PendingMessageKind::Commit && !data.added_member_ids.is_empty() {
+        // the commit is the manager's approval of the pending members
+        let mut notify = channel.member_ids();
+        notify.extend(data.added_member_ids.iter().cloned());
+        for added_id in data
+            .added_member_ids
+            .iter()
+            .filter(|id| channel.is_pending_member(id))
+        {
+            channel.promote_pending_member(added_id).await?;
+            events::publish(
+                &notify,
+                Event::MemberJoined(MemberJoinedEvent {
+                    channel_id: channel.id().to_string(),
+                    user_id: added_id.clone(),
+                }),
+            )
+            .await;
+        }
+    }
+    Ok(RpcValue(SendHandshakeResponse {
+        message: messages.swap_remove(0).into(),
+    }))
+}
@@ -1,20 +1,35 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 pub use harmony_types::events::{
-    CallMigratedEvent, ChannelDeletedEvent, ChannelUpdatedEvent, Event, MemberJoinedEvent,
-    MemberLeftEvent, MessageDeletedEvent, MessageEditedEvent, NewMessageEvent, UserJoinedCallEvent,
-    UserLeftCallEvent, UserVoiceStateChangedEvent,
+    CallEndedEvent, CallMigratedEvent, CallRingStoppedEvent, CallSessionModeratedEvent,
+    CallStartedEvent, ChannelDeletedEvent, ChannelUpdatedEvent, Event, MemberJoinedEvent,
+    MemberLeftEvent, MessageDeletedEvent, MessageEditedEvent, NewMessageEvent, PendingMessageEvent,
+    PresenceChangedEvent, ReactionAddedEvent, ReactionRemovedEvent, ReadStateUpdatedEvent,
+    TypingStartedEvent, UserJoinedCallEvent, UserLeftCallEvent, UserVoiceStateChangedEvent,
 };
 use rapid::socket::RpcClients;
 
+pub mod attachments;
 pub mod channels;
 pub mod invites;
 pub mod keys;
 pub mod messages;
+pub mod mls;
 pub mod users;
 pub mod voice;
 
-pub fn emit_to_ids(clients: RpcClients, user_ids: &[String], event: Event) {
+pub fn emit_to_ids(
+    clients: RpcClients,
+    user_ids: &[String],
+    sequences: &HashMap<String, u64>,
+    event: Event,
+) {
+    if !sequences.is_empty() {
+        clients.emit_sequenced(event, |client| {
+            client.user_id().and_then(|uid| sequences.get(uid).copied())
+        });
+        return;
+    }
     let id_set: HashSet<&str> = user_ids.iter().map(|s| s.as_str()).collect();
     clients.emit_by(event, |client| {
         client.user_id().is_some_and(|uid| id_set.contains(uid))
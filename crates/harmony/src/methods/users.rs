@@ -1,15 +1,17 @@
 use harmony_types::users::{
-    AddContactMethod, AddContactResponse, CurrentUserResponse, GetContactsMethod,
-    GetContactsResponse, GetCurrentUserMethod, RemoveContactMethod, RemoveContactResponse,
+    AddContactMethod, AddContactResponse, BlockContactMethod, BlockContactResponse,
+    CurrentUserResponse, GetContactsMethod, GetContactsResponse, GetCurrentUserMethod,
+    RemoveContactMethod, RemoveContactResponse, SetPresenceMethod, SetPresenceResponse,
+    UnblockContactMethod, UnblockContactResponse, UserProfile,
 };
 use rapid::socket::{RpcResponder, RpcState, RpcValue};
 
 use crate::{
     authentication::check_authenticated,
     errors::Error,
-    methods::Event,
-    services::database::users::{RelationshipState, User},
-    services::events,
+    methods::{Event, PresenceChangedEvent},
+    services::database::users::{ContactExtended, RelationshipState, User},
+    services::{events, presence},
 };
 
 pub async fn add_contact(state: RpcState, data: RpcValue<AddContactMethod>) -> impl RpcResponder {
@@ -49,6 +51,67 @@ pub async fn remove_contact(
     Ok::<_, Error>(RpcValue(RemoveContactResponse {}))
 }
 
+pub async fn block_contact(
+    state: RpcState,
+    data: RpcValue<BlockContactMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let other_state = user.block_contact(&data.id).await?;
+    events::publish_one(
+        &user.id,
+        Event::ContactStateChanged {
+            user_id: data.id.clone(),
+            state: RelationshipState::Blocked,
+        },
+    )
+    .await;
+    events::publish_one(
+        &data.id,
+        Event::ContactStateChanged {
+            user_id: user.id.clone(),
+            state: other_state,
+        },
+    )
+    .await;
+    Ok::<_, Error>(RpcValue(BlockContactResponse {}))
+}
+
+pub async fn unblock_contact(
+    state: RpcState,
+    data: RpcValue<UnblockContactMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let (target, other_state) = user.unblock_contact(&data.id).await?;
+    events::publish_one(
+        &user.id,
+        Event::ContactStateChanged {
+            user_id: target.id.clone(),
+            state: RelationshipState::None,
+        },
+    )
+    .await;
+    events::publish_one(
+        &target.id,
+        Event::ContactStateChanged {
+            user_id: user.id.clone(),
+            state: other_state,
+        },
+    )
+    .await;
+    Ok::<_, Error>(RpcValue(UnblockContactResponse {
+        contact: ContactExtended {
+            id: target.id.clone(),
+            state: RelationshipState::None,
+            user: UserProfile {
+                id: target.id,
+                presence: None,
+            },
+        },
+    }))
+}
+
 pub async fn get_contacts(
     state: RpcState,
     _data: RpcValue<GetContactsMethod>,
@@ -79,3 +142,31 @@ pub async fn get_current_user(
         presence: user.presence.clone(),
     }))
 }
+
+const MAX_PRESENCE_MESSAGE_LENGTH: usize = 128;
+
+pub async fn set_presence(state: RpcState, data: RpcValue<SetPresenceMethod>) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    if data
+        .message
+        .as_ref()
+        .is_some_and(|m| m.chars().count() > MAX_PRESENCE_MESSAGE_LENGTH)
+    {
+        return Err(Error::MessageTooLong);
+    }
+    let user = user.set_presence(data.status, data.message).await?;
+    presence::publish_presence(&user).await?;
+    // other devices see what was chosen, not what contacts are shown
+    events::publish_one(
+        &user.id,
+        Event::PresenceChanged(PresenceChangedEvent {
+            user_id: user.id.clone(),
+            presence: user.presence.clone(),
+        }),
+    )
+    .await;
+    Ok(RpcValue(SetPresenceResponse {
+        presence: user.presence,
+    }))
+}
@@ -1,17 +1,25 @@
 use harmony_types::voice::{
-    CallMember, CreateCallTokenMethod, CreateCallTokenResponse, EndCallMethod, EndCallResponse,
-    GetCallMembersMethod, GetCallMembersResponse, StartCallMethod, StartCallResponse,
-    UpdateVoiceStateMethod, UpdateVoiceStateResponse,
+    CallMember, CallRoom, CreateCallTokenMethod, CreateCallTokenResponse, DeclineCallMethod,
+    DeclineCallResponse, DisconnectCallSessionMethod, DisconnectCallSessionResponse, EndCallMethod,
+    EndCallResponse, GetCallHistoryMethod, GetCallHistoryResponse, GetCallMembersMethod,
+    GetCallMembersResponse, GetCallsMethod, GetCallsResponse, MoveSessionMethod,
+    MoveSessionResponse, RestrictCallSessionMethod, RestrictCallSessionResponse, RingOutcome,
+    StartCallMethod, StartCallResponse, UpdateVoiceStateMethod, UpdateVoiceStateResponse,
 };
 use rapid::socket::{RpcResponder, RpcState, RpcValue};
+use std::collections::HashSet;
 
 use crate::authentication::check_authenticated;
-use crate::errors::Error;
-use crate::methods::{Event, UserVoiceStateChangedEvent};
+use crate::errors::{Error, Result};
+use crate::methods::{
+    CallSessionModeratedEvent, CallStartedEvent, Event, UserVoiceStateChangedEvent,
+};
+use crate::services::database::calls::Call;
 use crate::services::database::channels::Channel;
+use crate::services::permissions::Permission;
 use crate::services::redis::INSTANCE_ID;
 use crate::services::voice::ActiveCall;
-use crate::services::{events, nats};
+use crate::services::{events, nats, ringing};
 use common::nats::subject_node;
 use common::{NodeEvent, NodeEventKind};
 
@@ -19,20 +27,30 @@ pub async fn create_call_token(
     state: RpcState,
     data: RpcValue<CreateCallTokenMethod>,
 ) -> impl RpcResponder {
-    let user = check_authenticated(&state).await?; // TODO: check rate limit, permissions req'd
+    let user = check_authenticated(&state).await?; // TODO: check rate limit
     let data = data.into_inner();
-    let (call, channel) =
-        tokio::try_join!(ActiveCall::get_in_channel(&data.id), Channel::get(&data.id))?;
+    let (call, channel) = tokio::try_join!(
+        ActiveCall::find(&data.id, data.call_id.as_ref()),
+        Channel::get(&data.id)
+    )?;
     let Some(mut call) = call else {
         return Err(Error::NotFound);
     };
     if !user.in_channel(&channel).await? {
         return Err(Error::NotFound);
     }
-    let server_address = call.server_address.clone();
-    let (id, token) = call
-        .create_token(&user.id, data.initial_muted, data.initial_deafened)
+    channel.check_permission(&user.id, Permission::JoinCalls)?;
+    user.check_not_blocked(&channel).await?;
+    let (id, token, server_address) = call
+        .create_token(
+            &user.id,
+            data.initial_muted,
+            data.initial_deafened,
+            channel.permissions_for(&user.id),
+            data.preferred_region,
+        )
         .await?;
+    ringing::stop(&call.id, &channel, &user.id, RingOutcome::Accepted).await?;
     Ok(RpcValue(CreateCallTokenResponse {
         id,
         token,
@@ -44,18 +62,59 @@ pub async fn create_call_token(
 pub async fn start_call(state: RpcState, data: RpcValue<StartCallMethod>) -> impl RpcResponder {
     let user = check_authenticated(&state).await?;
     let data = data.into_inner();
-    if ActiveCall::get_in_channel(&data.id).await?.is_some() {
-        return Err(Error::AlreadyExists);
-    };
-    let channel = Channel::get(&data.id).await?;
+    let name = data.name.as_deref().map(check_room_name).transpose()?;
+    let (calls, channel) = tokio::try_join!(
+        ActiveCall::list_in_channel(&data.id),
+        Channel::get(&data.id)
+    )?;
     if !user.in_channel(&channel).await? {
         return Err(Error::NotFound);
     }
-    let call = ActiveCall::create(&data.id, &user.id, data.preferred_region).await?;
+    if name.is_some() && !matches!(channel, Channel::GroupChannel { .. }) {
+        return Err(Error::InvalidTarget);
+    }
+    if calls.iter().any(|c| c.name == name) {
+        return Err(Error::AlreadyExists);
+    }
+    if calls.len() >= MAX_ROOMS_PER_CHANNEL {
+        return Err(Error::CallLimitReached);
+    }
+    channel.check_permission(&user.id, Permission::StartCalls)?;
+    user.check_not_blocked(&channel).await?;
+    let call = ActiveCall::create(&data.id, &user.id, data.preferred_region, name).await?;
+    let ringing = ringing::start(&call.id, &channel, &user.id).await?;
+    events::publish(
+        &channel.member_ids(),
+        Event::CallStarted(CallStartedEvent {
+            call_id: call.id.clone(),
+            channel_id: channel.id().to_string(),
+            initiator_id: user.id.clone(),
+            name: call.name.clone(),
+            ringing,
+        }),
+    )
+    .await;
     Ok::<_, Error>(RpcValue(StartCallResponse { id: call.id }))
 }
 
-pub async fn end_call(state: RpcState, data: RpcValue<EndCallMethod>) -> impl RpcResponder {
+const MAX_ROOMS_PER_CHANNEL: usize = 10;
+const MAX_ROOM_NAME_LENGTH: usize = 32;
+
+fn check_room_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(Error::NameEmpty);
+    }
+    if name.chars().count() > MAX_ROOM_NAME_LENGTH {
+        return Err(Error::NameTooLong);
+    }
+    Ok(name.to_string())
+}
+
+/// Turn down a call that is ringing. In a private channel that leaves
+/// nobody to talk to, so the call ends unless the user is already in it on
+/// another device.
+pub async fn decline_call(state: RpcState, data: RpcValue<DeclineCallMethod>) -> impl RpcResponder {
     let user = check_authenticated(&state).await?;
     let data = data.into_inner();
     let (call, channel) =
@@ -66,9 +125,54 @@ pub async fn end_call(state: RpcState, data: RpcValue<EndCallMethod>) -> impl Rp
     if !user.in_channel(&channel).await? {
         return Err(Error::NotFound);
     }
-    if !channel.is_manager(&user.id) {
-        return Err(Error::MissingPermission);
+    let declined = ringing::stop(&call.id, &channel, &user.id, RingOutcome::Declined).await?;
+    if declined
+        && matches!(channel, Channel::PrivateChannel { .. })
+        && !call.members.iter().any(|s| s.user_id == user.id)
+    {
+        call.end().await?;
+    }
+    Ok(RpcValue(DeclineCallResponse {}))
+}
+
+pub async fn get_call_history(
+    state: RpcState,
+    data: RpcValue<GetCallHistoryMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let (active, channel) = tokio::try_join!(
+        ActiveCall::list_in_channel(&data.id),
+        Channel::get(&data.id)
+    )?;
+    if !user.in_channel(&channel).await? {
+        return Err(Error::NotFound);
+    }
+    let limit = data.limit.unwrap_or(50).clamp(1, 100);
+    let calls = Call::history(channel.id(), limit, data.before).await?;
+    let ongoing: HashSet<String> = active.into_iter().map(|call| call.id).collect();
+    Ok(RpcValue(GetCallHistoryResponse {
+        calls: calls
+            .iter()
+            .map(|c| c.to_record(ongoing.contains(&c.id)))
+            .collect(),
+    }))
+}
+
+pub async fn end_call(state: RpcState, data: RpcValue<EndCallMethod>) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let (call, channel) = tokio::try_join!(
+        ActiveCall::find(&data.id, data.call_id.as_ref()),
+        Channel::get(&data.id)
+    )?;
+    let Some(call) = call else {
+        return Err(Error::NotFound);
+    };
+    if !user.in_channel(&channel).await? {
+        return Err(Error::NotFound);
     }
+    channel.check_permission(&user.id, Permission::ManageCalls)?;
     call.end().await?;
     Ok(RpcValue(EndCallResponse {}))
 }
@@ -79,51 +183,38 @@ pub async fn update_voice_state(
 ) -> impl RpcResponder {
     let user = check_authenticated(&state).await?;
     let data = data.into_inner();
-    let (call, channel) =
-        tokio::try_join!(ActiveCall::get_in_channel(&data.id), Channel::get(&data.id))?;
-    let Some(mut call) = call else {
+    let (call, channel) = tokio::try_join!(
+        ActiveCall::find(&data.id, data.call_id.as_ref()),
+        Channel::get(&data.id)
+    )?;
+    let Some(call) = call else {
         return Err(Error::NotFound);
     };
     if !user.in_channel(&channel).await? {
         return Err(Error::NotFound);
     }
+    if data.muted == Some(false) && !channel.has_permission(&user.id, Permission::Speak) {
+        return Err(Error::MissingPermission);
+    }
 
-    let member_index = call
-        .members
-        .iter()
-        .position(|s| s.user_id == user.id)
-        .ok_or(Error::NotFound)?;
-
-    let mut session = call.members[member_index].clone();
-    let muted_changed = data
-        .muted
-        .is_some_and(|new_muted| new_muted != session.muted);
-    let deafened_changed = data
-        .deafened
-        .is_some_and(|new_deafened| new_deafened != session.deafened);
-    if !muted_changed && !deafened_changed {
+    let (call, (session, changed)) = ActiveCall::modify(&call.id, |call| {
+        call.set_voice_state(&user.id, data.muted, data.deafened)
+    })
+    .await?;
+    if !changed {
         return Ok(RpcValue(UpdateVoiceStateResponse {
             muted: session.muted,
             deafened: session.deafened,
         }));
     }
 
-    if let Some(new_muted) = data.muted {
-        session.muted = new_muted;
-    }
-    if let Some(new_deafened) = data.deafened {
-        session.deafened = new_deafened;
-    }
-
-    call.members[member_index] = session.clone();
-    call.update().await?;
-
     let event = NodeEvent {
         id: INSTANCE_ID.clone(),
         event: NodeEventKind::UserStateChange {
             id: session.id.clone(),
-            muted: session.muted,
-            deafened: session.deafened,
+            // unmuting doesn't lift a server mute
+            muted: session.muted || session.restrictions.muted,
+            deafened: session.deafened || session.restrictions.deafened,
         },
     };
     nats::publish_node_event(subject_node(&call.assigned_node), &event).await;
@@ -147,6 +238,122 @@ pub async fn update_voice_state(
     }))
 }
 
+/// Server-mute, server-deafen or take video and screensharing away from the
+/// user behind one session. The restrictions replace whatever the user had
+/// before and hold for all of their sessions in the call, including any they
+/// open by rejoining.
+pub async fn restrict_call_session(
+    state: RpcState,
+    data: RpcValue<RestrictCallSessionMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let (call, channel) = tokio::try_join!(
+        ActiveCall::with_session(&data.id, &data.session_id),
+        Channel::get(&data.id)
+    )?;
+    let Some(call) = call else {
+        return Err(Error::NotFound);
+    };
+    if !user.in_channel(&channel).await? {
+        return Err(Error::NotFound);
+    }
+    channel.check_permission(&user.id, Permission::ManageCalls)?;
+
+    let (call, sessions) = ActiveCall::modify(&call.id, |call| {
+        let user_id = call
+            .members
+            .iter()
+            .find(|s| s.id == data.session_id)
+            .map(|s| s.user_id.clone())
+            .ok_or(Error::NotFound)?;
+        Ok(call.restrict(&user_id, data.restrictions))
+    })
+    .await?;
+
+    let member_user_ids: Vec<String> = call.members.iter().map(|s| s.user_id.clone()).collect();
+    for session in &sessions {
+        let event = NodeEvent {
+            id: INSTANCE_ID.clone(),
+            event: NodeEventKind::UserGrantsChange {
+                id: session.id.clone(),
+                grants: session.grants(channel.permissions_for(&session.user_id)),
+            },
+        };
+        nats::publish_node_event(subject_node(&call.assigned_node), &event).await;
+
+        events::publish(
+            &member_user_ids,
+            Event::CallSessionModerated(CallSessionModeratedEvent {
+                call_id: call.id.clone(),
+                session_id: session.id.clone(),
+                user_id: session.user_id.clone(),
+                moderator_id: user.id.clone(),
+                restrictions: session.restrictions,
+                disconnected: false,
+            }),
+        )
+        .await;
+    }
+
+    let Some(session) = sessions.iter().find(|s| s.id == data.session_id) else {
+        return Err(Error::NotFound);
+    };
+    Ok(RpcValue(RestrictCallSessionResponse {
+        member: session.to_member(),
+    }))
+}
+
+/// Disconnect one session from the call. The user may rejoin unless they
+/// lost the permission to.
+pub async fn disconnect_call_session(
+    state: RpcState,
+    data: RpcValue<DisconnectCallSessionMethod>,
+) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let (call, channel) = tokio::try_join!(
+        ActiveCall::with_session(&data.id, &data.session_id),
+        Channel::get(&data.id)
+    )?;
+    let Some(call) = call else {
+        return Err(Error::NotFound);
+    };
+    if !user.in_channel(&channel).await? {
+        return Err(Error::NotFound);
+    }
+    channel.check_permission(&user.id, Permission::ManageCalls)?;
+    let Some(session) = call.members.iter().find(|s| s.id == data.session_id) else {
+        return Err(Error::NotFound);
+    };
+
+    // tell everyone why before the node reports the session leaving
+    let member_user_ids: Vec<String> = call.members.iter().map(|s| s.user_id.clone()).collect();
+    events::publish(
+        &member_user_ids,
+        Event::CallSessionModerated(CallSessionModeratedEvent {
+            call_id: call.id.clone(),
+            session_id: session.id.clone(),
+            user_id: session.user_id.clone(),
+            moderator_id: user.id.clone(),
+            restrictions: session.restrictions,
+            disconnected: true,
+        }),
+    )
+    .await;
+
+    let event = NodeEvent {
+        id: INSTANCE_ID.clone(),
+        event: NodeEventKind::UserDisconnect {
+            id: session.id.clone(),
+            call_id: call.id.clone(),
+        },
+    };
+    nats::publish_node_event(subject_node(&call.assigned_node), &event).await;
+
+    Ok(RpcValue(DisconnectCallSessionResponse {}))
+}
+
 pub async fn get_call_members(
     state: RpcState,
     data: RpcValue<GetCallMembersMethod>,
@@ -154,8 +361,10 @@ pub async fn get_call_members(
     let user = check_authenticated(&state).await?;
     let data = data.into_inner();
 
-    let (call, channel) =
-        tokio::try_join!(ActiveCall::get_in_channel(&data.id), Channel::get(&data.id))?;
+    let (call, channel) = tokio::try_join!(
+        ActiveCall::find(&data.id, data.call_id.as_ref()),
+        Channel::get(&data.id)
+    )?;
     let Some(call) = call else {
         return Err(Error::NotFound);
     };
@@ -164,16 +373,82 @@ pub async fn get_call_members(
         return Err(Error::NotFound);
     }
 
-    let members: Vec<CallMember> = call
-        .members
-        .iter()
-        .map(|session| CallMember {
-            user_id: session.user_id.clone(),
-            session_id: session.id.clone(),
-            muted: session.muted,
-            deafened: session.deafened,
+    let members: Vec<CallMember> = call.members.iter().map(|s| s.to_member()).collect();
+
+    Ok(RpcValue(GetCallMembersResponse { members }))
+}
+
+pub async fn get_calls(state: RpcState, data: RpcValue<GetCallsMethod>) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let (calls, channel) = tokio::try_join!(
+        ActiveCall::list_in_channel(&data.id),
+        Channel::get(&data.id)
+    )?;
+    if !user.in_channel(&channel).await? {
+        return Err(Error::NotFound);
+    }
+    let calls: Vec<CallRoom> = calls
+        .into_iter()
+        .map(|call| CallRoom {
+            members: call.members.iter().map(|s| s.to_member()).collect(),
+            id: call.id,
+            name: call.name,
         })
         .collect();
+    Ok(RpcValue(GetCallsResponse { calls }))
+}
 
-    Ok(RpcValue(GetCallMembersResponse { members }))
+/// Move one of the user's sessions to another room in the channel. The node
+/// hosting the session has it reconnect to the room's node with a new token.
+pub async fn move_session(state: RpcState, data: RpcValue<MoveSessionMethod>) -> impl RpcResponder {
+    let user = check_authenticated(&state).await?;
+    let data = data.into_inner();
+    let (call, target, channel) = tokio::try_join!(
+        ActiveCall::with_session(&data.id, &data.session_id),
+        ActiveCall::find(&data.id, Some(&data.call_id)),
+        Channel::get(&data.id)
+    )?;
+    let (Some(mut call), Some(mut target)) = (call, target) else {
+        return Err(Error::NotFound);
+    };
+    if !user.in_channel(&channel).await? {
+        return Err(Error::NotFound);
+    }
+    if !call
+        .members
+        .iter()
+        .any(|s| s.id == data.session_id && s.user_id == user.id)
+    {
+        return Err(Error::NotFound);
+    }
+    if call.id == target.id {
+        return Ok(RpcValue(MoveSessionResponse {}));
+    }
+    channel.check_permission(&user.id, Permission::JoinCalls)?;
+    let node = call.assigned_node.clone();
+    let token = call
+        .move_session(
+            &data.session_id,
+            &mut target,
+            channel.permissions_for(&user.id),
+        )
+        .await?;
+
+    let event = NodeEvent {
+        id: INSTANCE_ID.clone(),
+        event: NodeEventKind::SessionMoved {
+            id: data.session_id.clone(),
+            call_id: target.id.clone(),
+            target_token: token,
+            target_server: if target.assigned_node == node {
+                String::new()
+            } else {
+                target.server_address.clone()
+            },
+        },
+    };
+    nats::publish_node_event(subject_node(&node), &event).await;
+
+    Ok(RpcValue(MoveSessionResponse {}))
 }
@@ -0,0 +1,60 @@
+use std::{io::ErrorKind, path::PathBuf};
+
+use async_trait::async_trait;
+use tokio::fs;
+use ulid::Ulid;
+
+use super::{BlobStore, storage_error};
+use crate::errors::{Error, Result};
+
+/// Keeps blobs as files below a root directory.
+pub struct FilesystemStore {
+    root: PathBuf,
+}
+
+impl FilesystemStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        // keys are generated by the server and never contain ".."
+        self.root.join(key)
+    }
+}
+
+#[async_trait]
+impl BlobStore for FilesystemStore {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await.map_err(storage_error)?;
+        }
+        // write to the side first so that readers never see a partial blob
+        let temporary = path.with_extension(format!("{}.tmp", Ulid::new()));
+        fs::write(&temporary, data).await.map_err(storage_error)?;
+        fs::rename(&temporary, &path).await.map_err(storage_error)
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        match fs::read(self.path(key)).await {
+            Ok(data) => Ok(data),
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(Error::NotFound),
+            Err(e) => Err(storage_error(e)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let path = self.path(key);
+        match fs::remove_file(&path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(storage_error(e)),
+        }
+        // drop the directory of an attachment along with its last chunk
+        if let Some(parent) = path.parent() {
+            fs::remove_dir(parent).await.ok();
+        }
+        Ok(())
+    }
+}
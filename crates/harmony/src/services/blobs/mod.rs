@@ -0,0 +1,68 @@
+use std::{fmt::Debug, sync::OnceLock, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{task, time};
+use tracing::error;
+
+use crate::{
+    errors::{Error, Result},
+    services::{
+        database::attachments::Attachment,
+        environment::{BLOB_STORE, BLOB_STORE_BUCKET, BLOB_STORE_PATH},
+    },
+};
+
+pub mod filesystem;
+pub mod s3;
+
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Storage for opaque blobs, such as the encrypted chunks of attachments.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()>;
+
+    /// Fails with [`Error::NotFound`] if nothing is stored under `key`.
+    async fn get(&self, key: &str) -> Result<Vec<u8>>;
+
+    /// Deleting a blob that does not exist is not an error.
+    async fn delete(&self, key: &str) -> Result<()>;
+}
+
+static STORE: OnceLock<Box<dyn BlobStore>> = OnceLock::new();
+
+pub fn connect() {
+    let store: Box<dyn BlobStore> = match BLOB_STORE.as_str() {
+        "filesystem" => Box::new(filesystem::FilesystemStore::new(&*BLOB_STORE_PATH)),
+        "s3" => Box::new(s3::S3Store::new(&BLOB_STORE_BUCKET)),
+        other => panic!("Unknown blob store: {}", other),
+    };
+    if STORE.set(store).is_err() {
+        panic!("Failed to set blob store");
+    }
+}
+
+pub fn get_store() -> &'static dyn BlobStore {
+    STORE.get().expect("Failed to get blob store").as_ref()
+}
+
+pub fn chunk_key(attachment_id: &str, index: u32) -> String {
+    format!("attachments/{}/{}", attachment_id, index)
+}
+
+fn storage_error(error: impl Debug) -> Error {
+    error!("Blob store error: {:?}", error);
+    Error::StorageError
+}
+
+/// Periodically delete attachments that expired or were never sent.
+pub fn spawn_attachment_expiry() {
+    task::spawn(async move {
+        loop {
+            if let Err(e) = Attachment::delete_expired().await {
+                error!("Failed to delete expired attachments: {:?}", e);
+            }
+            time::sleep(EXPIRY_INTERVAL).await;
+        }
+    });
+}
@@ -0,0 +1,53 @@
+use async_trait::async_trait;
+use object_store::{
+    ObjectStore, PutPayload,
+    aws::{AmazonS3, AmazonS3Builder},
+    path::Path,
+};
+
+use super::{BlobStore, storage_error};
+use crate::errors::{Error, Result};
+
+/// Keeps blobs in an S3-compatible bucket. Credentials, region and endpoint
+/// are read from the `AWS_*` environment variables.
+pub struct S3Store {
+    bucket: AmazonS3,
+}
+
+impl S3Store {
+    pub fn new(bucket: &str) -> Self {
+        let bucket = AmazonS3Builder::from_env()
+            .with_bucket_name(bucket)
+            .build()
+            .expect("Failed to configure S3 blob store");
+        Self { bucket }
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3Store {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        self.bucket
+            .put(&Path::from(key), PutPayload::from(data))
+            .await
+            .map_err(storage_error)?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let result = match self.bucket.get(&Path::from(key)).await {
+            Ok(result) => result,
+            Err(object_store::Error::NotFound { .. }) => return Err(Error::NotFound),
+            Err(e) => return Err(storage_error(e)),
+        };
+        let bytes = result.bytes().await.map_err(storage_error)?;
+        Ok(bytes.to_vec())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match self.bucket.delete(&Path::from(key)).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(e) => Err(storage_error(e)),
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use std::time::{Duration, SystemTime};
+
+use futures_util::StreamExt;
+use harmony_types::attachments::CHUNK_SIZE;
+use mongodb::{
+    IndexModel,
+    bson::{self, doc},
+    options::IndexOptions,
+};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::{
+    errors::{Error, Result},
+    services::{
+        blobs::{chunk_key, get_store},
+        environment::ATTACHMENT_UPLOAD_TTL,
+    },
+};
+
+/// An encrypted file and the chunks of it uploaded so far. Until it is sent
+/// with a message, an attachment expires after `ATTACHMENT_UPLOAD_TTL`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attachment {
+    pub(crate) id: String,
+    pub(crate) channel_id: String,
+    pub(crate) uploader_id: String,
+    pub(crate) size: u64,
+    pub(crate) chunk_count: u32,
+    pub(crate) uploaded_chunks: Vec<u32>,
+    // bytes of the uploaded chunks, never more than `size`
+    #[serde(default)]
+    pub(crate) stored_bytes: u64,
+    pub(crate) message_id: Option<String>,
+    // how long to keep the file once it is sent, if not for as long as the message
+    pub(crate) expires_in: Option<u64>,
+    pub(crate) expires_at: Option<bson::DateTime>,
+}
+
+/// Bytes reserved by a user's attachments, counting uploads that were not
+/// sent or finished yet at their full size. Kept apart from the attachments
+/// so the quota can be checked and reserved in one update.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AttachmentUsage {
+    user_id: String,
+    bytes: i64,
+}
+
+pub async fn create_indexes() -> Result<()> {
+    super::get_database()
+        .collection::<AttachmentUsage>("attachment_usage")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "userId": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+    let attachments = super::get_database().collection::<Attachment>("attachments");
+    attachments
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "id": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+    attachments
+        .create_index(IndexModel::builder().keys(doc! { "messageId": 1 }).build())
+        .await?;
+    attachments
+        .create_index(IndexModel::builder().keys(doc! { "channelId": 1 }).build())
+        .await?;
+    attachments
+        .create_index(IndexModel::builder().keys(doc! { "expiresAt": 1 }).build())
+        .await?;
+    attachments
+        .create_index(IndexModel::builder().keys(doc! { "uploaderId": 1 }).build())
+        .await?;
+    Ok(())
+}
+
+fn expires_after(seconds: u64) -> bson::DateTime {
+    bson::DateTime::from_system_time(SystemTime::now() + Duration::from_secs(seconds))
+}
+
+/// Reserve `size` bytes of a user's quota, failing if that would take them
+/// over `quota`.
+async fn reserve(uploader_id: &str, size: u64, quota: u64) -> Result<()> {
+    let Some(limit) = quota.checked_sub(size) else {
+        return Err(Error::QuotaExceeded);
+    };
+    let usage = super::get_database().collection::<AttachmentUsage>("attachment_usage");
+    usage
+        .update_one(
+            doc! { "userId": uploader_id },
+            doc! { "$setOnInsert": { "bytes": 0i64 } },
+        )
+        .upsert(true)
+        .await?;
+    let result = usage
+        .update_one(
+            doc! { "userId": uploader_id, "bytes": { "$lte": limit as i64 } },
+            doc! { "$inc": { "bytes": size as i64 } },
+        )
+        .await?;
+    if result.matched_count == 0 {
+        return Err(Error::QuotaExceeded);
+    }
+    Ok(())
+}
+
+/// Give back bytes reserved with [`reserve`].
+async fn release(uploader_id: &str, size: u64) -> Result<()> {
+    super::get_database()
+        .collection::<AttachmentUsage>("attachment_usage")
+        .update_one(
+            doc! { "userId": uploader_id },
+            doc! { "$inc": { "bytes": -(size as i64) } },
+        )
+        .await?;
+    Ok(())
+}
+
+impl Attachment {
+    /// Create an attachment, reserving its size against the uploader's
+    /// `quota` until it is deleted.
+    pub async fn create(
+        channel_id: &str,
+        uploader_id: &str,
+        size: u64,
+        chunk_count: u32,
+        expires_in: Option<u64>,
+        quota: u64,
+    ) -> Result<Attachment> {
+        reserve(uploader_id, size, quota).await?;
+        let attachment = Attachment {
+            id: Ulid::new().to_string(),
+            channel_id: channel_id.to_string(),
+            uploader_id: uploader_id.to_string(),
+            size,
+            chunk_count,
+            uploaded_chunks: Vec::new(),
+            stored_bytes: 0,
+            message_id: None,
+            expires_in,
+            expires_at: Some(expires_after(*ATTACHMENT_UPLOAD_TTL)),
+        };
+        let inserted = super::get_database()
+            .collection::<Attachment>("attachments")
+            .insert_one(attachment.clone())
+            .await;
+        if let Err(e) = inserted {
+            release(uploader_id, size).await?;
+            return Err(e.into());
+        }
+        Ok(attachment)
+    }
+
+    pub async fn get(id: &str) -> Result<Attachment> {
+        super::get_database()
+            .collection::<Attachment>("attachments")
+            .find_one(doc! { "id": id })
+            .await?
+            .ok_or(Error::NotFound)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.uploaded_chunks.len() == self.chunk_count as usize
+    }
+
+    /// Length of the chunk at `index`: full, except for the last one.
+    pub fn chunk_len(&self, index: u32) -> u64 {
+        if index + 1 < self.chunk_count {
+            CHUNK_SIZE
+        } else {
+            self.size - (self.chunk_count as u64 - 1) * CHUNK_SIZE
+        }
+    }
+
+    /// Store a chunk and count its bytes, unless it replaces one that was
+    /// already uploaded, which has the same length. Fails if the attachment
+    /// would end up holding more than its declared size.
+    pub async fn store_chunk(&self, index: u32, data: Vec<u8>) -> Result<()> {
+        let len = data.len() as u64;
+        if len != self.chunk_len(index) {
+            return Err(Error::InvalidTarget);
+        }
+        get_store().put(&chunk_key(&self.id, index), data).await?;
+        let collection = super::get_database().collection::<Attachment>("attachments");
+        let result = collection
+            .update_one(
+                doc! {
+                    "id": &self.id,
+                    "uploadedChunks": { "$ne": index },
+                    "storedBytes": { "$lte": (self.size - len) as i64 },
+                },
+                doc! {
+                    "$addToSet": { "uploadedChunks": index },
+                    "$inc": { "storedBytes": len as i64 },
+                },
+            )
+            .await?;
+        if result.matched_count == 0 {
+            // either an overwrite, which changes nothing, or too much data
+            let overwrite = collection
+                .count_documents(doc! { "id": &self.id, "uploadedChunks": index })
+                .await?;
+            if overwrite == 0 {
+                return Err(Error::AttachmentTooLarge);
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn load_chunk(&self, index: u32) -> Result<Vec<u8>> {
+        get_store().get(&chunk_key(&self.id, index)).await
+    }
+
+    /// Tie the attachment to a message so it is deleted along with it. Fails
+    /// if it was already sent with another message.
+    pub async fn attach(&self, message_id: &str, max_lifetime: Option<u64>) -> Result<()> {
+        let lifetime = match (self.expires_in, max_lifetime) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let expires_at = lifetime.map(expires_after);
+        let result = super::get_database()
+            .collection::<Attachment>("attachments")
+            .update_one(
+                doc! { "id": &self.id, "messageId": null },
+                doc! { "$set": { "messageId": message_id, "expiresAt": expires_at } },
+            )
+            .await?;
+        if result.matched_count == 0 {
+            return Err(Error::InvalidTarget);
+        }
+        Ok(())
+    }
+
+    /// Delete the stored chunks, then the attachment itself.
+    pub async fn delete(&self) -> Result<()> {
+        for index in &self.uploaded_chunks {
+            get_store().delete(&chunk_key(&self.id, *index)).await?;
+        }
+        let result = super::get_database()
+            .collection::<Attachment>("attachments")
+            .delete_one(doc! { "id": &self.id })
+            .await?;
+        // whoever deleted it gives back the space
+        if result.deleted_count > 0 {
+            release(&self.uploader_id, self.size).await?;
+        }
+        Ok(())
+    }
+
+    async fn delete_matching(filter: bson::Document) -> Result<()> {
+        let attachments: Vec<_> = super::get_database()
+            .collection::<Attachment>("attachments")
+            .find(filter)
+            .await?
+            .collect()
+            .await;
+        for attachment in attachments {
+            attachment?.delete().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn delete_for_message(message_id: &str) -> Result<()> {
+        Self::delete_matching(doc! { "messageId": message_id }).await
+    }
+
+    pub async fn delete_in(channel_id: &str) -> Result<()> {
+        Self::delete_matching(doc! { "channelId": channel_id }).await
+    }
+
+    pub async fn delete_expired() -> Result<()> {
+        Self::delete_matching(doc! { "expiresAt": { "$lt": bson::DateTime::now() } }).await
+    }
+}
+
+impl From<Attachment> for harmony_types::attachments::Attachment {
+    fn from(a: Attachment) -> Self {
+        harmony_types::attachments::Attachment {
+            complete: a.is_complete(),
+            id: a.id,
+            channel_id: a.channel_id,
+            uploader_id: a.uploader_id,
+            size: a.size,
+            chunk_count: a.chunk_count,
+            message_id: a.message_id,
+            expires_at: a.expires_at.map(|t| t.timestamp_millis()),
+        }
+    }
+}
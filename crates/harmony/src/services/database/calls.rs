@@ -1,5 +1,12 @@
-use mongodb::bson::doc;
+use futures_util::StreamExt;
+use harmony_types::voice::{CallRecord, RingOutcome};
+use mongodb::{
+    IndexModel,
+    bson::doc,
+    options::{FindOptions, IndexOptions},
+};
 use serde::{Deserialize, Serialize};
+use ulid::Ulid;
 
 use crate::errors::Result;
 
@@ -11,6 +18,30 @@ pub struct Call {
     pub joined_members: Vec<String>,
     pub ended_at: i64, // last check: this will be useful if the server goes down
     pub initiator: String, // user id of who started the call
+    #[serde(default)]
+    pub declined: Vec<String>,
+    #[serde(default)]
+    pub missed: Vec<String>,
+}
+
+pub async fn create_indexes() -> Result<()> {
+    let calls = super::get_database().collection::<Call>("calls");
+    calls
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "id": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+    calls
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "channel_id": 1, "id": -1 })
+                .build(),
+        )
+        .await?;
+    Ok(())
 }
 
 impl Call {
@@ -45,4 +76,74 @@ impl Call {
             .await?;
         Ok(())
     }
+
+    /// Note how ringing stopped for a user who didn't pick up.
+    pub async fn record_ring_outcome(id: &str, user_id: &str, outcome: RingOutcome) -> Result<()> {
+        let field = match outcome {
+            RingOutcome::Accepted => return Ok(()),
+            RingOutcome::Declined => "declined",
+            RingOutcome::Missed => "missed",
+        };
+        super::get_database()
+            .collection::<Call>("calls")
+            .update_one(doc! { "id": id }, doc! { "$addToSet": { field: user_id } })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get(id: &str) -> Result<Option<Call>> {
+        let database = super::get_database();
+        let call = database
+            .collection::<Call>("calls")
+            .find_one(doc! { "id": id })
+            .await?;
+        Ok(call)
+    }
+
+    /// Calls in a channel, newest first.
+    pub async fn history(
+        channel_id: &str,
+        limit: i64,
+        before: Option<String>,
+    ) -> Result<Vec<Call>> {
+        let mut query = doc! { "channel_id": channel_id };
+        if let Some(before) = before {
+            query.insert("id", doc! { "$lt": before });
+        }
+        let options = FindOptions::builder()
+            .sort(doc! { "id": -1 })
+            .limit(limit)
+            .build();
+        let calls: Vec<_> = super::get_database()
+            .collection::<Call>("calls")
+            .find(query)
+            .with_options(options)
+            .await?
+            .collect()
+            .await;
+        calls.into_iter().map(|c| c.map_err(|e| e.into())).collect()
+    }
+
+    pub fn started_at(&self) -> i64 {
+        Ulid::from_string(&self.id)
+            .map(|id| id.timestamp_ms() as i64)
+            .unwrap_or(self.ended_at)
+    }
+
+    /// The call as clients see it; `ongoing` leaves out when it ended.
+    pub fn to_record(&self, ongoing: bool) -> CallRecord {
+        let started_at = self.started_at();
+        let ended_at = (!ongoing).then_some(self.ended_at);
+        CallRecord {
+            id: self.id.clone(),
+            channel_id: self.channel_id.clone(),
+            initiator_id: self.initiator.clone(),
+            participant_ids: self.joined_members.clone(),
+            declined_ids: self.declined.clone(),
+            missed_ids: self.missed.clone(),
+            started_at,
+            ended_at,
+            duration: ended_at.map(|ended_at| ended_at.saturating_sub(started_at).max(0) as u64),
+        }
+    }
 }
@@ -1,6 +1,6 @@
 use futures_util::StreamExt;
 use mongodb::{
-    bson::{Binary, doc, spec::BinarySubtype},
+    bson::{self, Binary, Document, doc, spec::BinarySubtype},
     options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
 };
 use serde::{Deserialize, Serialize};
@@ -8,9 +8,13 @@ use ulid::Ulid;
 
 use crate::errors::{Error, Result};
 
-use super::{invites::Invite, messages::Message};
+use super::{
+    attachments::Attachment, group_keys::GroupKeyShare, invites::Invite, messages::Message,
+    mls::PendingMessage, read_states::ReadState,
+};
 
-pub use harmony_types::channels::{ChannelMember, ChannelMemberRole, EncryptionHint};
+pub use harmony_types::channels::{ChannelMember, ChannelMemberRole, ChannelRole, EncryptionHint};
+use harmony_types::permissions::PermissionSet;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE", tag = "type")]
@@ -28,6 +32,11 @@ pub enum Channel {
         pending_members: Vec<String>,
         blacklist: Vec<String>,
         encryption_hint: EncryptionHint,
+        last_key_id: Option<String>,
+        #[serde(default)]
+        roles: Vec<ChannelRole>,
+        #[serde(default = "PermissionSet::member_default")]
+        default_permissions: PermissionSet,
     },
 }
 
@@ -91,7 +100,7 @@ impl Channel {
             Channel::GroupChannel { id, .. } => {
                 let database = super::get_database();
                 let query = doc! {
-                    "channel_id": &id,
+                    "channelId": &id,
                 };
                 let invites: std::result::Result<Vec<Invite>, _> = database
                     .collection::<Invite>("invites")
@@ -145,10 +154,14 @@ impl Channel {
             members: vec![ChannelMember {
                 id: initiator_id,
                 role: ChannelMemberRole::Manager,
+                roles: vec![],
             }],
             pending_members: vec![],
             blacklist: vec![],
             encryption_hint,
+            last_key_id: None,
+            roles: vec![],
+            default_permissions: PermissionSet::member_default(),
         };
         database
             .collection::<Channel>("channels")
@@ -205,6 +218,32 @@ impl Channel {
         }
     }
 
+    pub fn is_pending_member(&self, user_id: &str) -> bool {
+        match self {
+            Channel::GroupChannel {
+                pending_members, ..
+            } => pending_members.iter().any(|m| m == user_id),
+            _ => false,
+        }
+    }
+
+    pub fn is_banned(&self, user_id: &str) -> bool {
+        match self {
+            Channel::GroupChannel { blacklist, .. } => blacklist.iter().any(|b| b == user_id),
+            _ => false,
+        }
+    }
+
+    pub fn is_mls(&self) -> bool {
+        matches!(
+            self,
+            Channel::GroupChannel {
+                encryption_hint: EncryptionHint::Mls,
+                ..
+            }
+        )
+    }
+
     pub fn member_ids(&self) -> Vec<String> {
         match self {
             Channel::PrivateChannel {
@@ -249,7 +288,7 @@ impl Channel {
                 doc! { "id": id },
                 doc! {
                     "$addToSet": {
-                        "pendingMembers": user_id
+                        "pending_members": user_id
                     }
                 },
             )
@@ -267,7 +306,7 @@ impl Channel {
             .update_one(
                 doc! { "id": id },
                 doc! {
-                    "$pull": { "pendingMembers": user_id },
+                    "$pull": { "pending_members": user_id },
                     "$push": {
                         "members": {
                             "id": user_id,
@@ -299,6 +338,63 @@ impl Channel {
         Ok(())
     }
 
+    pub async fn remove_pending_member(&self, user_id: &str) -> Result<()> {
+        let Channel::GroupChannel { id, .. } = self else {
+            return Err(Error::MissingPermission);
+        };
+        let database = super::get_database();
+        database
+            .collection::<Channel>("channels")
+            .update_one(
+                doc! { "id": id },
+                doc! {
+                    "$pull": { "pending_members": user_id }
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Remove a user from the members and pending members and keep them from
+    /// joining again.
+    pub async fn ban_member(&self, user_id: &str) -> Result<()> {
+        let Channel::GroupChannel { id, .. } = self else {
+            return Err(Error::MissingPermission);
+        };
+        let database = super::get_database();
+        database
+            .collection::<Channel>("channels")
+            .update_one(
+                doc! { "id": id },
+                doc! {
+                    "$pull": {
+                        "members": { "id": user_id },
+                        "pending_members": user_id,
+                    },
+                    "$addToSet": { "blacklist": user_id }
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn unban_member(&self, user_id: &str) -> Result<()> {
+        let Channel::GroupChannel { id, .. } = self else {
+            return Err(Error::MissingPermission);
+        };
+        let database = super::get_database();
+        database
+            .collection::<Channel>("channels")
+            .update_one(
+                doc! { "id": id },
+                doc! {
+                    "$pull": { "blacklist": user_id }
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
     pub async fn update_metadata(&self, metadata: Vec<u8>) -> Result<Channel> {
         let Channel::GroupChannel { id, .. } = self else {
             return Err(Error::MissingPermission);
@@ -322,6 +418,148 @@ impl Channel {
         Ok(updated)
     }
 
+    pub fn get_role(&self, role_id: &str) -> Option<&ChannelRole> {
+        match self {
+            Channel::GroupChannel { roles, .. } => roles.iter().find(|r| r.id == role_id),
+            _ => None,
+        }
+    }
+
+    pub fn role_count(&self) -> usize {
+        match self {
+            Channel::GroupChannel { roles, .. } => roles.len(),
+            _ => 0,
+        }
+    }
+
+    /// Roles currently assigned to a group member.
+    pub fn member_roles(&self, user_id: &str) -> Vec<String> {
+        match self {
+            Channel::GroupChannel { members, .. } => members
+                .iter()
+                .find(|m| m.id == user_id)
+                .map(|m| m.roles.clone())
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+
+    async fn update_group(&self, filter: Document, update: Document) -> Result<Channel> {
+        let Channel::GroupChannel { id, .. } = self else {
+            return Err(Error::MissingPermission);
+        };
+        let mut filter = filter;
+        filter.insert("id", id);
+        let database = super::get_database();
+        database
+            .collection::<Channel>("channels")
+            .find_one_and_update(filter, update)
+            .with_options(
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await?
+            .ok_or(Error::NotFound)
+    }
+
+    pub async fn create_role(
+        &self,
+        name: String,
+        permissions: PermissionSet,
+    ) -> Result<ChannelRole> {
+        let role = ChannelRole {
+            id: Ulid::new().to_string(),
+            name,
+            permissions,
+        };
+        self.update_group(
+            doc! {},
+            doc! { "$push": { "roles": bson::to_bson(&role)? } },
+        )
+        .await?;
+        Ok(role)
+    }
+
+    pub async fn edit_role(
+        &self,
+        role_id: &str,
+        name: Option<String>,
+        permissions: Option<PermissionSet>,
+    ) -> Result<Channel> {
+        let mut set = Document::new();
+        if let Some(name) = name {
+            set.insert("roles.$.name", name);
+        }
+        if let Some(permissions) = permissions {
+            set.insert("roles.$.permissions", permissions.to_i64());
+        }
+        self.update_group(doc! { "roles.id": role_id }, doc! { "$set": set })
+            .await
+    }
+
+    /// Delete a role and unassign it from every member.
+    pub async fn delete_role(&self, role_id: &str) -> Result<Channel> {
+        self.update_group(
+            doc! {},
+            doc! {
+                "$pull": {
+                    "roles": { "id": role_id },
+                    "members.$[].roles": role_id,
+                }
+            },
+        )
+        .await
+    }
+
+    pub async fn set_member_roles(&self, user_id: &str, role_ids: Vec<String>) -> Result<Channel> {
+        self.update_group(
+            doc! { "members.id": user_id },
+            doc! { "$set": { "members.$.roles": role_ids } },
+        )
+        .await
+    }
+
+    pub async fn set_default_permissions(&self, permissions: PermissionSet) -> Result<Channel> {
+        self.update_group(
+            doc! {},
+            doc! { "$set": { "default_permissions": permissions.to_i64() } },
+        )
+        .await
+    }
+
+    /// Replace the group key, failing with `StaleGroupKey` if another member
+    /// rotated it first.
+    pub async fn rotate_group_key(
+        &self,
+        previous_key_id: Option<&str>,
+        key_id: &str,
+        metadata: Vec<u8>,
+    ) -> Result<Channel> {
+        let Channel::GroupChannel { id, .. } = self else {
+            return Err(Error::MissingPermission);
+        };
+        let database = super::get_database();
+        database
+            .collection::<Channel>("channels")
+            .find_one_and_update(
+                doc! { "id": id, "last_key_id": previous_key_id },
+                doc! {
+                    "$set": {
+                        "last_key_id": key_id,
+                        "metadata": Binary { subtype: BinarySubtype::Generic, bytes: metadata },
+                    }
+                },
+            )
+            .with_options(
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await?
+            .ok_or(Error::StaleGroupKey)
+    }
+
     pub async fn delete(&self) -> Result<()> {
         let id = self.id();
         let database = super::get_database();
@@ -337,6 +575,10 @@ impl Channel {
             .collection::<Invite>("invites")
             .delete_many(doc! { "channelId": id })
             .await?;
+        PendingMessage::delete_in(id).await?;
+        GroupKeyShare::delete_in(id).await?;
+        ReadState::delete_in(id).await?;
+        Attachment::delete_in(id).await?;
         Ok(())
     }
 
@@ -373,6 +615,9 @@ impl From<Channel> for harmony_types::channels::Channel {
                 pending_members,
                 blacklist,
                 encryption_hint,
+                last_key_id,
+                roles,
+                default_permissions,
             } => harmony_types::channels::Channel::GroupChannel {
                 id,
                 metadata,
@@ -382,6 +627,9 @@ impl From<Channel> for harmony_types::channels::Channel {
                 pending_members,
                 blacklist,
                 encryption_hint,
+                last_key_id,
+                roles,
+                default_permissions,
             },
         }
     }
@@ -0,0 +1,113 @@
+use futures_util::StreamExt;
+use mongodb::{IndexModel, bson::doc, options::IndexOptions};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+use crate::errors::Result;
+
+pub use harmony_types::users::Encapsulated;
+
+/// A group key wrapped by one member to another user's contact key. The server
+/// only relays these and cannot unwrap them.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupKeyShare {
+    pub(crate) channel_id: String,
+    pub(crate) key_id: Option<String>,
+    pub(crate) sender_id: String,
+    pub(crate) recipient_id: String,
+    #[serde_as(as = "Box<[_; 1088]>")]
+    pub(crate) encapsulated: Encapsulated,
+    pub(crate) wrapped_key: Vec<u8>,
+}
+
+pub async fn create_indexes() -> Result<()> {
+    super::get_database()
+        .collection::<GroupKeyShare>("group_keys")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "channelId": 1, "recipientId": 1, "keyId": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+    Ok(())
+}
+
+impl GroupKeyShare {
+    pub fn new(
+        channel_id: &str,
+        key_id: Option<&str>,
+        sender_id: &str,
+        share: harmony_types::channels::GroupKeyShare,
+    ) -> Self {
+        GroupKeyShare {
+            channel_id: channel_id.to_string(),
+            key_id: key_id.map(str::to_string),
+            sender_id: sender_id.to_string(),
+            recipient_id: share.recipient_id,
+            encapsulated: share.encapsulated,
+            wrapped_key: share.wrapped_key,
+        }
+    }
+
+    /// Store the shares, replacing any earlier share of the same key for the
+    /// same recipient.
+    pub async fn store(shares: &[GroupKeyShare]) -> Result<()> {
+        let collection = super::get_database().collection::<GroupKeyShare>("group_keys");
+        for share in shares {
+            collection
+                .replace_one(
+                    doc! {
+                        "channelId": &share.channel_id,
+                        "recipientId": &share.recipient_id,
+                        "keyId": share.key_id.as_deref(),
+                    },
+                    share,
+                )
+                .upsert(true)
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn fetch(channel_id: &str, recipient_id: &str) -> Result<Vec<GroupKeyShare>> {
+        let shares: Vec<_> = super::get_database()
+            .collection::<GroupKeyShare>("group_keys")
+            .find(doc! { "channelId": channel_id, "recipientId": recipient_id })
+            .await?
+            .collect()
+            .await;
+        Ok(shares.into_iter().collect::<std::result::Result<_, _>>()?)
+    }
+
+    /// Drop every share addressed to a user that left the channel.
+    pub async fn delete_for(channel_id: &str, recipient_id: &str) -> Result<()> {
+        super::get_database()
+            .collection::<GroupKeyShare>("group_keys")
+            .delete_many(doc! { "channelId": channel_id, "recipientId": recipient_id })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_in(channel_id: &str) -> Result<()> {
+        super::get_database()
+            .collection::<GroupKeyShare>("group_keys")
+            .delete_many(doc! { "channelId": channel_id })
+            .await?;
+        Ok(())
+    }
+}
+
+impl From<GroupKeyShare> for harmony_types::channels::WrappedGroupKey {
+    fn from(share: GroupKeyShare) -> Self {
+        harmony_types::channels::WrappedGroupKey {
+            channel_id: share.channel_id,
+            key_id: share.key_id,
+            sender_id: share.sender_id,
+            encapsulated: share.encapsulated,
+            wrapped_key: share.wrapped_key,
+        }
+    }
+}
@@ -57,6 +57,19 @@ impl Invite {
             None => Err(Error::NotFound),
         }
     }
+    pub async fn get_by_code(code: &str) -> Result<Invite> {
+        let database = super::get_database();
+        let invite = database
+            .collection::<Invite>("invites")
+            .find_one(doc! {
+                "code": code,
+            })
+            .await?;
+        match invite {
+            Some(invite) => Ok(invite),
+            None => Err(Error::NotFound),
+        }
+    }
     pub async fn delete(&self) -> Result<bool> {
         let database = super::get_database();
         let result = database
@@ -1,18 +1,24 @@
-use mongodb::bson::{Binary, doc, spec::BinarySubtype};
+use futures_util::StreamExt;
+use mongodb::{
+    IndexModel,
+    bson::{self, Binary, doc, spec::BinarySubtype},
+    options::FindOptions,
+};
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 
 use crate::{
     errors::{Error, Result},
-    services::database::channels::Channel,
+    services::database::{
+        channels::Channel,
+        mls::{PendingMessage, PendingMessageKind},
+    },
 };
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Reaction {
-    pub user_id: String,
-    pub data: Vec<u8>,
-}
+pub use harmony_types::messages::Reaction;
+
+/// Unread counts stop here; clients show anything above as "99+".
+const MAX_UNREAD_COUNT: u64 = 100;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -24,6 +30,20 @@ pub struct Message {
     pub(crate) edited_at: Option<i64>,
     pub(crate) key_id: Option<String>,
     pub(crate) channel_id: String,
+    #[serde(default)]
+    pub(crate) parent_id: Option<String>,
+}
+
+pub async fn create_indexes() -> Result<()> {
+    super::get_database()
+        .collection::<Message>("messages")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "parentId": 1, "id": 1 })
+                .build(),
+        )
+        .await?;
+    Ok(())
 }
 
 impl Message {
@@ -39,20 +59,71 @@ impl Message {
         }
     }
 
+    /// MLS messages are not persisted; the ciphertext is held in the pending
+    /// mailbox for every other member until they acknowledge it.
     pub async fn ephemeral(
-        _channel_id: &str,
-        _author_id: &str,
-        _content: &[u8],
+        channel: &Channel,
+        author_id: &str,
+        content: &[u8],
+        parent_id: Option<&str>,
     ) -> Result<Message> {
-        //  TODO: MLS messages are ephemeral, but they should still be stored
-        // for any offline users for a short period of time
-        todo!()
+        // the author's other devices need it too
+        let recipients = channel.member_ids();
+        let pending = PendingMessage::create(
+            channel.id(),
+            author_id,
+            PendingMessageKind::Application,
+            content,
+            None,
+            parent_id,
+            recipients,
+        )
+        .await?;
+        Ok(pending.into())
+    }
+
+    /// Messages by other members sent after `last_read_id`. MLS messages are
+    /// only counted while they wait in the user's mailbox.
+    pub async fn count_unread(
+        channel: &Channel,
+        user_id: &str,
+        last_read_id: Option<&str>,
+    ) -> Result<u64> {
+        let mut filter = doc! {
+            "channelId": channel.id(),
+            "authorId": { "$ne": user_id },
+        };
+        if let Some(last_read_id) = last_read_id {
+            filter.insert("id", doc! { "$gt": last_read_id });
+        }
+        let database = super::get_database();
+        let count = if channel.is_mls() {
+            filter.insert("kind", bson::to_bson(&PendingMessageKind::Application)?);
+            filter.insert("recipients", user_id);
+            database
+                .collection::<PendingMessage>("pending_messages")
+                .count_documents(filter)
+                .limit(MAX_UNREAD_COUNT)
+                .await?
+        } else {
+            database
+                .collection::<Message>("messages")
+                .count_documents(filter)
+                .limit(MAX_UNREAD_COUNT)
+                .await?
+        };
+        Ok(count)
     }
 
-    pub async fn create(channel: &Channel, author_id: &str, content: &[u8]) -> Result<Message> {
+    pub async fn create(
+        channel: &Channel,
+        author_id: &str,
+        content: &[u8],
+        parent_id: Option<&str>,
+    ) -> Result<Message> {
         let key_id = match channel {
             Channel::PrivateChannel { last_key_id, .. } => Some(last_key_id.clone()),
-            Channel::GroupChannel { .. } => None,
+            Channel::GroupChannel { last_key_id, .. } => last_key_id.clone(),
         };
         let message = Message {
             id: Ulid::new().to_string(),
@@ -62,6 +133,7 @@ impl Message {
             channel_id: channel.id().to_string(),
             reactions: Vec::new(),
             key_id,
+            parent_id: parent_id.map(str::to_string),
         };
         let database = super::get_database();
         database
@@ -70,6 +142,42 @@ impl Message {
             .await?;
         Ok(message)
     }
+    /// Replies in the thread started by this message, paginated like
+    /// [`Channel::get_messages`].
+    pub async fn get_thread(
+        &self,
+        limit: Option<i64>,
+        latest: Option<bool>,
+        before: Option<String>,
+        after: Option<String>,
+    ) -> Result<Vec<Message>> {
+        let database = super::get_database();
+        let mut query = doc! { "parentId": &self.id };
+        if let Some(before) = before {
+            query.insert("id", doc! { "$lt": before });
+        }
+        if let Some(after) = after {
+            query.insert("id", doc! { "$gt": after });
+        }
+        let options = FindOptions::builder()
+            .sort(doc! {
+                "id": if latest.unwrap_or(false) { -1 } else { 1 }
+            })
+            .limit(limit.unwrap_or(50))
+            .build();
+        let messages: Vec<_> = database
+            .collection::<Message>("messages")
+            .find(query)
+            .with_options(options)
+            .await?
+            .collect()
+            .await;
+        messages
+            .into_iter()
+            .map(|m| m.map_err(|e| e.into()))
+            .collect()
+    }
+
     pub async fn edit(&self, content: Vec<u8>) -> Result<Message> {
         let database = super::get_database();
         let message = database
@@ -88,6 +196,51 @@ impl Message {
         }
     }
 
+    pub async fn add_reaction(&self, user_id: &str, data: Vec<u8>) -> Result<Reaction> {
+        let reaction = Reaction {
+            user_id: user_id.to_string(),
+            data,
+        };
+        let database = super::get_database();
+        let result = database
+            .collection::<Message>("messages")
+            .update_one(
+                doc! { "id": &self.id },
+                doc! { "$push": { "reactions": bson::to_bson(&reaction)? } },
+            )
+            .await?;
+        if result.matched_count == 0 {
+            return Err(Error::NotFound);
+        }
+        Ok(reaction)
+    }
+
+    pub async fn remove_reaction(&self, user_id: &str, data: Vec<u8>) -> Result<Reaction> {
+        let reaction = Reaction {
+            user_id: user_id.to_string(),
+            data,
+        };
+        let database = super::get_database();
+        let result = database
+            .collection::<Message>("messages")
+            .update_one(
+                doc! { "id": &self.id },
+                doc! { "$pull": { "reactions": bson::to_bson(&reaction)? } },
+            )
+            .await?;
+        if result.modified_count == 0 {
+            return Err(Error::NotFound);
+        }
+        Ok(reaction)
+    }
+
+    pub fn reaction_count(&self, user_id: &str) -> usize {
+        self.reactions
+            .iter()
+            .filter(|r| r.user_id == user_id)
+            .count()
+    }
+
     pub async fn delete(&self) -> Result<Message> {
         let database = super::get_database();
         let message = database
@@ -104,7 +257,7 @@ impl Message {
         let database = super::get_database();
         database
             .collection::<Message>("messages")
-            .delete_many(doc! { "channel_id": channel_id })
+            .delete_many(doc! { "channelId": channel_id })
             .await?;
         Ok(())
     }
@@ -119,6 +272,23 @@ impl From<Message> for harmony_types::messages::Message {
             edited_at: m.edited_at,
             channel_id: m.channel_id,
             key_id: m.key_id,
+            reactions: m.reactions,
+            parent_id: m.parent_id,
+        }
+    }
+}
+
+impl From<PendingMessage> for Message {
+    fn from(m: PendingMessage) -> Self {
+        Message {
+            id: m.id,
+            content: m.content,
+            reactions: Vec::new(),
+            author_id: m.author_id,
+            edited_at: None,
+            key_id: None,
+            channel_id: m.channel_id,
+            parent_id: m.parent_id,
         }
     }
 }
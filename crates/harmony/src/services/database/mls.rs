@@ -0,0 +1,468 @@
+use std::time::{Duration, SystemTime};
+
+use futures_util::StreamExt;
+use mongodb::{
+    IndexModel,
+    bson::{self, doc},
+    options::{FindOptions, IndexOptions, UpdateOptions},
+};
+use serde::{Deserialize, Serialize};
+use tokio::{task, time};
+use ulid::Ulid;
+
+use crate::{
+    errors::{Error, Result},
+    services::environment::PENDING_MESSAGE_TTL,
+};
+
+const RELEASE_INTERVAL: Duration = Duration::from_secs(60);
+
+pub use harmony_types::mls::PendingMessageKind;
+
+/// Ciphertext for an MLS group channel, held until every recipient has
+/// acknowledged it or it expires. Devices that identify themselves only
+/// acknowledge for themselves, so the user's other devices still get the
+/// message until it expires.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingMessage {
+    pub(crate) id: String,
+    pub(crate) channel_id: String,
+    pub(crate) author_id: String,
+    pub(crate) kind: PendingMessageKind,
+    pub(crate) content: Vec<u8>,
+    pub(crate) epoch: Option<u64>,
+    #[serde(default)]
+    pub(crate) parent_id: Option<String>,
+    // users that have not yet acknowledged this message
+    pub(crate) recipients: Vec<String>,
+    // "{user id}:{device id}" of devices that acknowledged it
+    #[serde(default)]
+    pub(crate) acknowledged: Vec<String>,
+    // devices of each recipient known when the message was stored that have
+    // not acknowledged it yet
+    #[serde(default)]
+    pub(crate) awaiting: Vec<AwaitingDevices>,
+    pub(crate) expires_at: bson::DateTime,
+    // stored ahead of an epoch change and hidden until the change is accepted
+    #[serde(default)]
+    pub(crate) held: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AwaitingDevices {
+    pub(crate) user_id: String,
+    pub(crate) devices: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MlsEpoch {
+    channel_id: String,
+    epoch: i64,
+    // held messages of accepted commits that were not released yet
+    #[serde(default)]
+    releasing: Vec<String>,
+}
+
+/// A device that fetched or acknowledged messages, so that messages can be
+/// dropped once every device of their recipients has them. Forgotten once
+/// unseen for as long as messages are held.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MlsDevice {
+    user_id: String,
+    device_id: String,
+    seen_at: bson::DateTime,
+}
+
+pub async fn create_indexes() -> Result<()> {
+    let database = super::get_database();
+    let pending = database.collection::<PendingMessage>("pending_messages");
+    pending
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "expiresAt": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(Duration::from_secs(0))
+                        .build(),
+                )
+                .build(),
+        )
+        .await?;
+    pending
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "recipients": 1, "channelId": 1, "id": 1 })
+                .build(),
+        )
+        .await?;
+    database
+        .collection::<MlsEpoch>("mls_epochs")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "channelId": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+    let devices = database.collection::<MlsDevice>("mls_devices");
+    devices
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "userId": 1, "deviceId": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+    devices
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "seenAt": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(Duration::from_secs(*PENDING_MESSAGE_TTL))
+                        .build(),
+                )
+                .build(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Remember that a device of `user_id` is in use.
+async fn touch_device(user_id: &str, device_id: &str) -> Result<()> {
+    super::get_database()
+        .collection::<MlsDevice>("mls_devices")
+        .update_one(
+            doc! { "userId": user_id, "deviceId": device_id },
+            doc! { "$set": { "seenAt": bson::DateTime::now() } },
+        )
+        .upsert(true)
+        .await?;
+    Ok(())
+}
+
+/// The known devices of each of `user_ids`.
+async fn devices_of(user_ids: &[String]) -> Result<Vec<AwaitingDevices>> {
+    let devices: Vec<_> = super::get_database()
+        .collection::<MlsDevice>("mls_devices")
+        .find(doc! { "userId": { "$in": user_ids } })
+        .await?
+        .collect()
+        .await;
+    let mut awaiting: Vec<AwaitingDevices> = Vec::new();
+    for device in devices {
+        let device = device?;
+        match awaiting.iter_mut().find(|a| a.user_id == device.user_id) {
+            Some(entry) => entry.devices.push(device.device_id),
+            None => awaiting.push(AwaitingDevices {
+                user_id: device.user_id,
+                devices: vec![device.device_id],
+            }),
+        }
+    }
+    Ok(awaiting)
+}
+
+impl PendingMessage {
+    pub async fn create(
+        channel_id: &str,
+        author_id: &str,
+        kind: PendingMessageKind,
+        content: &[u8],
+        epoch: Option<u64>,
+        parent_id: Option<&str>,
+        recipients: Vec<String>,
+    ) -> Result<PendingMessage> {
+        let mut message = PendingMessage::new(
+            channel_id, author_id, kind, content, epoch, parent_id, recipients,
+        );
+        message.insert().await?;
+        Ok(message)
+    }
+
+    /// Store messages that only make sense once an epoch change is accepted,
+    /// such as its commit and welcome. They stay hidden from their recipients
+    /// until [`release`](Self::release)d.
+    pub async fn create_held(messages: &mut [PendingMessage]) -> Result<()> {
+        for message in messages.iter_mut() {
+            message.held = true;
+            message.awaiting = devices_of(&message.recipients).await?;
+        }
+        let database = super::get_database();
+        database
+            .collection::<PendingMessage>("pending_messages")
+            .insert_many(messages.iter())
+            .await?;
+        Ok(())
+    }
+
+    /// Deliver held messages once their epoch change has been accepted.
+    /// Releasing the same messages again changes nothing.
+    pub async fn release(channel_id: &str, messages: &mut [PendingMessage]) -> Result<()> {
+        let ids: Vec<String> = messages.iter().map(|m| m.id.clone()).collect();
+        release_ids(channel_id, &ids).await?;
+        for message in messages.iter_mut() {
+            message.held = false;
+        }
+        Ok(())
+    }
+
+    /// Drop held messages whose epoch change was rejected.
+    pub async fn discard(messages: &[PendingMessage]) -> Result<()> {
+        let ids: Vec<&str> = messages.iter().map(|m| m.id.as_str()).collect();
+        let database = super::get_database();
+        database
+            .collection::<PendingMessage>("pending_messages")
+            .delete_many(doc! { "id": { "$in": ids }, "held": true })
+            .await?;
+        Ok(())
+    }
+
+    pub fn new(
+        channel_id: &str,
+        author_id: &str,
+        kind: PendingMessageKind,
+        content: &[u8],
+        epoch: Option<u64>,
+        parent_id: Option<&str>,
+        recipients: Vec<String>,
+    ) -> PendingMessage {
+        let expires_at = SystemTime::now() + Duration::from_secs(*PENDING_MESSAGE_TTL);
+        PendingMessage {
+            id: Ulid::new().to_string(),
+            channel_id: channel_id.to_string(),
+            author_id: author_id.to_string(),
+            kind,
+            content: content.to_vec(),
+            epoch,
+            parent_id: parent_id.map(str::to_string),
+            recipients,
+            acknowledged: Vec::new(),
+            awaiting: Vec::new(),
+            expires_at: bson::DateTime::from_system_time(expires_at),
+            held: false,
+        }
+    }
+
+    pub async fn insert(&mut self) -> Result<()> {
+        self.awaiting = devices_of(&self.recipients).await?;
+        let database = super::get_database();
+        database
+            .collection::<PendingMessage>("pending_messages")
+            .insert_one(self)
+            .await?;
+        Ok(())
+    }
+
+    /// Messages still awaiting acknowledgement from `user_id`, or from one
+    /// of their devices, oldest first.
+    pub async fn fetch(
+        user_id: &str,
+        device_id: Option<&str>,
+        channel_id: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<PendingMessage>> {
+        if let Some(device_id) = device_id {
+            touch_device(user_id, device_id).await?;
+        }
+        let database = super::get_database();
+        let mut query = doc! { "recipients": user_id, "held": { "$ne": true } };
+        if let Some(channel_id) = channel_id {
+            query.insert("channelId", channel_id);
+        }
+        if let Some(device_id) = device_id {
+            query.insert(
+                "acknowledged",
+                doc! { "$ne": device_key(user_id, device_id) },
+            );
+        }
+        let options = FindOptions::builder()
+            .sort(doc! { "id": 1 })
+            .limit(limit)
+            .build();
+        let messages: Vec<_> = database
+            .collection::<PendingMessage>("pending_messages")
+            .find(query)
+            .with_options(options)
+            .await?
+            .collect()
+            .await;
+        messages
+            .into_iter()
+            .map(|m| m.map_err(|e| e.into()))
+            .collect()
+    }
+
+    /// Remove `user_id` from the recipients of `ids`, dropping any message
+    /// that no longer has anyone waiting for it. With a `device_id`, the
+    /// messages are marked as seen by that device, and the user is only
+    /// removed once none of their devices is still waiting.
+    pub async fn acknowledge(user_id: &str, device_id: Option<&str>, ids: &[String]) -> Result<()> {
+        let database = super::get_database();
+        let collection = database.collection::<PendingMessage>("pending_messages");
+        let mut filter = doc! { "id": { "$in": ids }, "recipients": user_id };
+        if let Some(device_id) = device_id {
+            touch_device(user_id, device_id).await?;
+            collection
+                .update_many(
+                    filter.clone(),
+                    doc! {
+                        "$addToSet": { "acknowledged": device_key(user_id, device_id) },
+                        "$pull": { "awaiting.$[entry].devices": device_id },
+                    },
+                )
+                .with_options(
+                    UpdateOptions::builder()
+                        .array_filters(vec![doc! { "entry.userId": user_id }])
+                        .build(),
+                )
+                .await?;
+            filter.insert(
+                "awaiting",
+                doc! { "$not": { "$elemMatch": { "userId": user_id, "devices": { "$ne": [] } } } },
+            );
+        }
+        collection
+            .update_many(filter, doc! { "$pull": { "recipients": user_id } })
+            .await?;
+        collection
+            .delete_many(doc! { "id": { "$in": ids }, "recipients": { "$size": 0 } })
+            .await?;
+        Ok(())
+    }
+
+    /// Stop holding messages in a channel for a user who is no longer in it.
+    pub async fn remove_recipient(channel_id: &str, user_id: &str) -> Result<()> {
+        let database = super::get_database();
+        let collection = database.collection::<PendingMessage>("pending_messages");
+        collection
+            .update_many(
+                doc! { "channelId": channel_id, "recipients": user_id },
+                doc! { "$pull": { "recipients": user_id, "awaiting": { "userId": user_id } } },
+            )
+            .await?;
+        collection
+            .delete_many(doc! { "channelId": channel_id, "recipients": { "$size": 0 } })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_in(channel_id: &str) -> Result<()> {
+        let database = super::get_database();
+        database
+            .collection::<PendingMessage>("pending_messages")
+            .delete_many(doc! { "channelId": channel_id })
+            .await?;
+        database
+            .collection::<MlsEpoch>("mls_epochs")
+            .delete_one(doc! { "channelId": channel_id })
+            .await?;
+        Ok(())
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+fn device_key(user_id: &str, device_id: &str) -> String {
+    format!("{user_id}:{device_id}")
+}
+
+/// Record the initial epoch of a freshly created MLS group.
+pub async fn init_epoch(channel_id: &str) -> Result<()> {
+    let database = super::get_database();
+    database
+        .collection::<MlsEpoch>("mls_epochs")
+        .insert_one(MlsEpoch {
+            channel_id: channel_id.to_string(),
+            epoch: 0,
+            releasing: Vec::new(),
+        })
+        .await?;
+    Ok(())
+}
+
+/// Advance the group epoch if it still matches `expected`, so only one of
+/// several concurrent commits on the same epoch is accepted. The commit's
+/// `held` messages are recorded along with the new epoch, so that
+/// [`release_stalled`] delivers them should releasing them fail.
+pub async fn advance_epoch(channel_id: &str, expected: u64, held: &[PendingMessage]) -> Result<()> {
+    let ids: Vec<&str> = held.iter().map(|m| m.id.as_str()).collect();
+    let database = super::get_database();
+    let result = database
+        .collection::<MlsEpoch>("mls_epochs")
+        .update_one(
+            doc! { "channelId": channel_id, "epoch": expected as i64 },
+            doc! { "$inc": { "epoch": 1 }, "$push": { "releasing": { "$each": ids } } },
+        )
+        .await?;
+    if result.matched_count == 0 {
+        return Err(Error::StaleEpoch);
+    }
+    Ok(())
+}
+
+async fn release_ids(channel_id: &str, ids: &[String]) -> Result<()> {
+    let database = super::get_database();
+    database
+        .collection::<PendingMessage>("pending_messages")
+        .update_many(
+            doc! { "id": { "$in": ids } },
+            doc! { "$set": { "held": false } },
+        )
+        .await?;
+    database
+        .collection::<MlsEpoch>("mls_epochs")
+        .update_one(
+            doc! { "channelId": channel_id },
+            doc! { "$pull": { "releasing": { "$in": ids } } },
+        )
+        .await?;
+    Ok(())
+}
+
+/// Deliver the held messages of accepted commits that were never released.
+pub async fn release_stalled() -> Result<()> {
+    let epochs: Vec<_> = super::get_database()
+        .collection::<MlsEpoch>("mls_epochs")
+        .find(doc! { "releasing.0": { "$exists": true } })
+        .await?
+        .collect()
+        .await;
+    for epoch in epochs {
+        let epoch = epoch?;
+        release_ids(&epoch.channel_id, &epoch.releasing).await?;
+    }
+    Ok(())
+}
+
+impl From<PendingMessage> for harmony_types::mls::PendingMessage {
+    fn from(m: PendingMessage) -> Self {
+        harmony_types::mls::PendingMessage {
+            id: m.id,
+            channel_id: m.channel_id,
+            author_id: m.author_id,
+            kind: m.kind,
+            content: m.content,
+            epoch: m.epoch,
+            parent_id: m.parent_id,
+        }
+    }
+}
+
+/// Periodically deliver commits whose release failed after they were accepted.
+pub fn spawn_commit_release() {
+    task::spawn(async move {
+        loop {
+            if let Err(e) = release_stalled().await {
+                tracing::error!("Failed to release stalled commits: {:?}", e);
+            }
+            time::sleep(RELEASE_INTERVAL).await;
+        }
+    });
+}
@@ -1,7 +1,11 @@
+pub mod attachments;
 pub mod calls;
 pub mod channels;
+pub mod group_keys;
 pub mod invites;
 pub mod messages;
+pub mod mls;
+pub mod read_states;
 pub mod users;
 
 use std::sync::OnceLock;
@@ -17,6 +21,24 @@ pub async fn connect() {
         .await
         .expect("Failed to connect to MongoDB");
     DATABASE.set(client).expect("Failed to set MongoDB client");
+    mls::create_indexes()
+        .await
+        .expect("Failed to create MLS mailbox indexes");
+    group_keys::create_indexes()
+        .await
+        .expect("Failed to create group key indexes");
+    read_states::create_indexes()
+        .await
+        .expect("Failed to create read state indexes");
+    attachments::create_indexes()
+        .await
+        .expect("Failed to create attachment indexes");
+    messages::create_indexes()
+        .await
+        .expect("Failed to create message indexes");
+    calls::create_indexes()
+        .await
+        .expect("Failed to create call indexes");
 }
 
 pub fn get_connection() -> &'static Client {
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use futures_util::StreamExt;
+use mongodb::{
+    IndexModel,
+    bson::doc,
+    options::{FindOneAndUpdateOptions, IndexOptions, ReturnDocument},
+};
+use serde::{Deserialize, Serialize};
+
+use super::{channels::Channel, messages::Message};
+use crate::errors::{Error, Result};
+
+/// The last message a user has read in a channel. Message IDs are ULIDs, so
+/// they order the same way the messages were sent.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadState {
+    pub(crate) user_id: String,
+    pub(crate) channel_id: String,
+    pub(crate) last_read_id: String,
+}
+
+pub async fn create_indexes() -> Result<()> {
+    super::get_database()
+        .collection::<ReadState>("read_states")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "userId": 1, "channelId": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+    Ok(())
+}
+
+impl ReadState {
+    /// Move the read marker forward to `message_id`; an older ID leaves it
+    /// where it is.
+    pub async fn mark(user_id: &str, channel_id: &str, message_id: &str) -> Result<ReadState> {
+        super::get_database()
+            .collection::<ReadState>("read_states")
+            .find_one_and_update(
+                doc! { "userId": user_id, "channelId": channel_id },
+                doc! { "$max": { "lastReadId": message_id } },
+            )
+            .with_options(
+                FindOneAndUpdateOptions::builder()
+                    .upsert(true)
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await?
+            .ok_or(Error::NotFound)
+    }
+
+    /// Every read marker of a user, by channel ID.
+    pub async fn for_user(user_id: &str) -> Result<HashMap<String, ReadState>> {
+        let states: Vec<_> = super::get_database()
+            .collection::<ReadState>("read_states")
+            .find(doc! { "userId": user_id })
+            .await?
+            .collect()
+            .await;
+        states
+            .into_iter()
+            .map(|s| s.map(|s| (s.channel_id.clone(), s)).map_err(Into::into))
+            .collect()
+    }
+
+    pub async fn delete_for(channel_id: &str, user_id: &str) -> Result<()> {
+        super::get_database()
+            .collection::<ReadState>("read_states")
+            .delete_one(doc! { "channelId": channel_id, "userId": user_id })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_in(channel_id: &str) -> Result<()> {
+        super::get_database()
+            .collection::<ReadState>("read_states")
+            .delete_many(doc! { "channelId": channel_id })
+            .await?;
+        Ok(())
+    }
+}
+
+/// The read state of a user in a channel as clients see it.
+pub async fn summarize(
+    channel: &Channel,
+    user_id: &str,
+    last_read_id: Option<String>,
+) -> Result<harmony_types::messages::ReadState> {
+    let unread_count = Message::count_unread(channel, user_id, last_read_id.as_deref()).await?;
+    Ok(harmony_types::messages::ReadState {
+        channel_id: channel.id().to_string(),
+        last_read_id,
+        unread_count,
+    })
+}
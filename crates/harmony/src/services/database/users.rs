@@ -4,7 +4,7 @@ use futures_util::{StreamExt, TryStreamExt, future::try_join_all};
 use harmony_types::users::{AddContactStage, UserProfile};
 use mongodb::{
     bson::{self, doc},
-    options::UpdateOptions,
+    options::{FindOneAndUpdateOptions, ReturnDocument, UpdateOptions},
 };
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
@@ -57,6 +57,130 @@ pub struct User {
     pub presence: Presence,
 }
 
+/// States a contact request leaves the requester and the target in, given
+/// what the requester had with the target before.
+fn request_states(
+    existing: Option<&RelationshipState>,
+    blocked_by_target: bool,
+    public_key: UnifiedPublicKey,
+) -> Result<(RelationshipState, RelationshipState)> {
+    if blocked_by_target {
+        return Err(Error::Blocked);
+    }
+    match existing {
+        Some(RelationshipState::Established { .. }) => return Err(Error::AlreadyEstablished),
+        Some(RelationshipState::Blocked) => return Err(Error::Blocked),
+        Some(RelationshipState::Requested { .. })
+        | Some(RelationshipState::PendingKeyExchange { .. }) => {
+            return Err(Error::AlreadyRequested);
+        }
+        Some(RelationshipState::None) | None => {} // allow re-request
+    }
+    Ok((
+        RelationshipState::Requested { public_key: None },
+        RelationshipState::Requested {
+            public_key: Some(public_key),
+        },
+    ))
+}
+
+/// States accepting a request leaves the acceptor and the requester in.
+fn accept_states(
+    state: &RelationshipState,
+    blocked_by_requester: bool,
+    public_key: UnifiedPublicKey,
+    encapsulated: Encapsulated,
+) -> Result<(RelationshipState, RelationshipState)> {
+    if !matches!(
+        state,
+        RelationshipState::Requested {
+            public_key: Some(_)
+        }
+    ) {
+        return Err(Error::InvalidStage);
+    }
+    if blocked_by_requester {
+        return Err(Error::Blocked);
+    }
+    Ok((
+        RelationshipState::PendingKeyExchange {
+            public_key: None,
+            encapsulated: None,
+        },
+        RelationshipState::PendingKeyExchange {
+            public_key: Some(public_key),
+            encapsulated: Some(encapsulated),
+        },
+    ))
+}
+
+/// States finalizing the key exchange leaves the requester and the acceptor in.
+fn finalize_states(
+    state: &RelationshipState,
+    blocked_by_acceptor: bool,
+    public_key: UnifiedPublicKey,
+    encapsulated: Encapsulated,
+    key_id: String,
+) -> Result<(RelationshipState, RelationshipState)> {
+    let RelationshipState::PendingKeyExchange {
+        public_key: Some(peer_pk),
+        encapsulated: Some(their_ct),
+    } = state
+    else {
+        return Err(Error::InvalidStage);
+    };
+    if blocked_by_acceptor {
+        return Err(Error::Blocked);
+    }
+    Ok((
+        RelationshipState::Established {
+            public_key: peer_pk.clone(),
+            encapsulated: their_ct.clone(),
+            key_id: key_id.clone(),
+        },
+        RelationshipState::Established {
+            public_key,
+            encapsulated,
+            key_id,
+        },
+    ))
+}
+
+/// State blocking someone leaves them in. Whatever they had with us is
+/// dropped, unless they have blocked us too.
+fn block_state(
+    existing: Option<&RelationshipState>,
+    blocked_by_target: bool,
+) -> Result<RelationshipState> {
+    if matches!(existing, Some(RelationshipState::Blocked)) {
+        return Err(Error::Blocked);
+    }
+    Ok(if blocked_by_target {
+        RelationshipState::Blocked
+    } else {
+        RelationshipState::None
+    })
+}
+
+/// States unblocking someone leaves us and them in. Neither side keeps a
+/// relationship, unless they have blocked us too.
+fn unblock_states(
+    existing: Option<&RelationshipState>,
+    blocked_by_target: bool,
+) -> Result<(RelationshipState, RelationshipState)> {
+    if !matches!(existing, Some(RelationshipState::Blocked)) {
+        return Err(Error::InvalidStage);
+    }
+    Ok((
+        RelationshipState::None,
+        if blocked_by_target {
+            RelationshipState::Blocked
+        } else {
+            RelationshipState::None
+        },
+    ))
+}
+
 impl User {
     pub async fn in_channel(&self, channel: &Channel) -> Result<bool> {
         match channel {
@@ -112,23 +236,11 @@ impl User {
                 let target = User::get(&id).await?;
                 let contact_id = &target.id;
                 let existing = self.contacts.iter().find(|a| &a.id == contact_id);
-                if let Some(existing) = existing {
-                    match &existing.state {
-                        RelationshipState::Established { .. } => {
-                            return Err(Error::AlreadyEstablished);
-                        }
-                        RelationshipState::Blocked => return Err(Error::Blocked),
-                        RelationshipState::Requested { .. } => return Err(Error::AlreadyRequested),
-                        RelationshipState::PendingKeyExchange { .. } => {
-                            return Err(Error::AlreadyRequested);
-                        }
-                        RelationshipState::None => {} // allow re-request
-                    }
-                }
-                let self_state = RelationshipState::Requested { public_key: None };
-                let target_state = RelationshipState::Requested {
-                    public_key: Some(public_key),
-                };
+                let (self_state, target_state) = request_states(
+                    existing.map(|c| &c.state),
+                    target.has_blocked(&self.id),
+                    public_key,
+                )?;
 
                 if existing.is_some() {
                     users
@@ -193,21 +305,13 @@ impl User {
                     .iter()
                     .find(|a| a.id == user_id)
                     .ok_or(Error::NotFound)?;
-                match &contact.state {
-                    RelationshipState::Requested {
-                        public_key: Some(_),
-                    } => {}
-                    _ => return Err(Error::InvalidStage),
-                };
-
-                let self_state = RelationshipState::PendingKeyExchange {
-                    public_key: None,
-                    encapsulated: None,
-                };
-                let requester_state = RelationshipState::PendingKeyExchange {
-                    public_key: Some(public_key),
-                    encapsulated: Some(encapsulated),
-                };
+                let requester = User::get(&user_id).await?;
+                let (self_state, requester_state) = accept_states(
+                    &contact.state,
+                    requester.has_blocked(&self.id),
+                    public_key,
+                    encapsulated,
+                )?;
 
                 users
                     .update_one(
@@ -253,25 +357,15 @@ impl User {
                     .iter()
                     .find(|a| a.id == user_id)
                     .ok_or(Error::NotFound)?;
-                let (peer_pk, their_ct) = match &contact.state {
-                    RelationshipState::PendingKeyExchange {
-                        public_key: Some(pk),
-                        encapsulated: Some(ct),
-                    } => (pk.clone(), **ct),
-                    _ => return Err(Error::InvalidStage),
-                };
+                let other = User::get(&user_id).await?;
                 let key_id = Ulid::new().to_string();
-
-                let self_state = RelationshipState::Established {
-                    public_key: peer_pk,
-                    encapsulated: Box::new(their_ct),
-                    key_id: key_id.clone(),
-                };
-                let acceptor_state = RelationshipState::Established {
+                let (self_state, acceptor_state) = finalize_states(
+                    &contact.state,
+                    other.has_blocked(&self.id),
                     public_key,
                     encapsulated,
-                    key_id: key_id.clone(),
-                };
+                    key_id.clone(),
+                )?;
 
                 users
                     .update_one(
@@ -307,9 +401,7 @@ impl User {
                 Ok(AddContactResult {
                     profile: UserProfile {
                         id: user_id.clone(),
-                        presence: Some(
-                            get_presentable_presence(&User::get(&user_id).await?).await?,
-                        ),
+                        presence: Some(get_presentable_presence(&other).await?),
                     },
                     self_state,
                     other_id: user_id.clone(),
@@ -347,6 +439,104 @@ impl User {
         }
     }
 
+    /// Block a user. Any relationship with them, including a pending key
+    /// exchange, is dropped on both sides. Returns the state the blocked user
+    /// now has for us.
+    pub async fn block_contact(&self, contact_id: &String) -> Result<RelationshipState> {
+        if contact_id == &self.id {
+            return Err(Error::InvalidTarget);
+        }
+        let users = super::get_database().collection::<User>("users");
+        let target = User::get(contact_id).await?;
+        let existing = self.contacts.iter().find(|c| &c.id == contact_id);
+        let target_state = block_state(existing.map(|c| &c.state), target.has_blocked(&self.id))?;
+        match existing {
+            Some(_) => {
+                users
+                    .update_one(
+                        doc! { "id": &self.id },
+                        doc! { "$set": { "contacts.$[contact].state": bson::to_bson(&RelationshipState::Blocked)? } },
+                    )
+                    .with_options(Some(
+                        UpdateOptions::builder()
+                            .array_filters(vec![doc! { "contact.id": contact_id }])
+                            .build(),
+                    ))
+                    .await?;
+            }
+            None => {
+                users
+                    .update_one(
+                        doc! { "id": &self.id },
+                        doc! { "$push": { "contacts": bson::to_bson(&Contact { id: contact_id.clone(), state: RelationshipState::Blocked })? } },
+                    )
+                    .await?;
+            }
+        }
+
+        if target_state == RelationshipState::None {
+            users
+                .update_one(
+                    doc! { "id": contact_id },
+                    doc! { "$pull": { "contacts": { "id": &self.id } } },
+                )
+                .await?;
+        }
+        Ok(target_state)
+    }
+
+    /// Unblock a contact, returning them and the state they are left in.
+    pub async fn unblock_contact(&self, contact_id: &String) -> Result<(User, RelationshipState)> {
+        let users = super::get_database().collection::<User>("users");
+        let target = User::get(contact_id).await?;
+        let existing = self.contacts.iter().find(|c| &c.id == contact_id);
+        let (_, target_state) =
+            unblock_states(existing.map(|c| &c.state), target.has_blocked(&self.id))?;
+        users
+            .update_one(
+                doc! { "id": &self.id },
+                doc! { "$pull": { "contacts": { "id": contact_id } } },
+            )
+            .await?;
+        Ok((target, target_state))
+    }
+
+    pub fn has_blocked(&self, other_id: &str) -> bool {
+        self.contacts
+            .iter()
+            .any(|c| c.id == other_id && matches!(c.state, RelationshipState::Blocked))
+    }
+
+    /// Fails with `Error::Blocked` if the channel is private and either side
+    /// has blocked the other.
+    pub async fn check_not_blocked(&self, channel: &Channel) -> Result<()> {
+        let Channel::PrivateChannel {
+            initiator_id,
+            target_id,
+            ..
+        } = channel
+        else {
+            return Ok(());
+        };
+        let other_id = if initiator_id == &self.id {
+            target_id
+        } else {
+            initiator_id
+        };
+        if self.has_blocked(other_id) || User::get(other_id).await?.has_blocked(&self.id) {
+            return Err(Error::Blocked);
+        }
+        Ok(())
+    }
+
+    pub fn established_contact_ids(&self) -> Vec<String> {
+        self.contacts
+            .iter()
+            .filter(|c| matches!(c.state, RelationshipState::Established { .. }))
+            .map(|c| c.id.clone())
+            .collect()
+    }
+
     pub async fn get_established_contacts(&self) -> Result<Vec<User>> {
         let established_ids: Vec<&str> = self
             .contacts
@@ -514,6 +704,36 @@ impl User {
         Ok(new_generation)
     }
 
+    /// Update the status and custom message the user chose. Whether they are
+    /// online is tracked separately, see `services::presence`.
+    pub async fn set_presence(
+        &self,
+        status: Option<Status>,
+        message: Option<String>,
+    ) -> Result<User> {
+        let mut update = doc! {};
+        if let Some(status) = status {
+            update.insert("presence.status", bson::to_bson(&status)?);
+        }
+        if let Some(message) = message {
+            update.insert("presence.message", message);
+        }
+        if update.is_empty() {
+            return Ok(self.clone());
+        }
+        let users = super::get_database().collection::<User>("users");
+        let updated = users
+            .find_one_and_update(doc! { "id": &self.id }, doc! { "$set": update })
+            .with_options(
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await?
+            .ok_or(Error::NotFound)?;
+        Ok(updated)
+    }
+
     pub async fn can_dm(&self, other: &User) -> Result<Option<String>> {
         let contact = self.contacts.iter().find(|c| c.id == other.id);
         if let Some(contact) = contact {
@@ -526,3 +746,225 @@ impl User {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use harmony_types::users::HybridPublicKey;
+
+    use super::*;
+
+    fn key(seed: u8) -> UnifiedPublicKey {
+        UnifiedPublicKey {
+            hybrid: HybridPublicKey {
+                x25519: [seed; 32],
+                mlkem: Box::new([seed; 1184]),
+            },
+            ed25519: [seed; 32],
+        }
+    }
+
+    fn ciphertext(seed: u8) -> Encapsulated {
+        Box::new([seed; 1088])
+    }
+
+    #[test]
+    fn full_key_exchange() {
+        // alice requests bob
+        let (alice, bob) = request_states(None, false, key(1)).unwrap();
+        assert_eq!(alice, RelationshipState::Requested { public_key: None });
+        assert_eq!(
+            bob,
+            RelationshipState::Requested {
+                public_key: Some(key(1))
+            }
+        );
+
+        // bob accepts with their key and an encapsulation to alice
+        let (bob, alice) = accept_states(&bob, false, key(2), ciphertext(2)).unwrap();
+        assert_eq!(
+            bob,
+            RelationshipState::PendingKeyExchange {
+                public_key: None,
+                encapsulated: None,
+            }
+        );
+        assert_eq!(
+            alice,
+            RelationshipState::PendingKeyExchange {
+                public_key: Some(key(2)),
+                encapsulated: Some(ciphertext(2)),
+            }
+        );
+
+        // alice finalizes with her encapsulation to bob
+        let (alice, bob) =
+            finalize_states(&alice, false, key(1), ciphertext(1), "key".to_string()).unwrap();
+        assert_eq!(
+            alice,
+            RelationshipState::Established {
+                public_key: key(2),
+                encapsulated: ciphertext(2),
+                key_id: "key".to_string(),
+            }
+        );
+        assert_eq!(
+            bob,
+            RelationshipState::Established {
+                public_key: key(1),
+                encapsulated: ciphertext(1),
+                key_id: "key".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn request_rejected_while_related() {
+        let requested = RelationshipState::Requested { public_key: None };
+        let pending = RelationshipState::PendingKeyExchange {
+            public_key: None,
+            encapsulated: None,
+        };
+        let established = RelationshipState::Established {
+            public_key: key(2),
+            encapsulated: ciphertext(2),
+            key_id: "key".to_string(),
+        };
+        assert!(matches!(
+            request_states(Some(&requested), false, key(1)),
+            Err(Error::AlreadyRequested)
+        ));
+        assert!(matches!(
+            request_states(Some(&pending), false, key(1)),
+            Err(Error::AlreadyRequested)
+        ));
+        assert!(matches!(
+            request_states(Some(&established), false, key(1)),
+            Err(Error::AlreadyEstablished)
+        ));
+    }
+
+    #[test]
+    fn request_allowed_after_removal() {
+        assert!(request_states(Some(&RelationshipState::None), false, key(1)).is_ok());
+    }
+
+    #[test]
+    fn request_rejected_when_blocked() {
+        assert!(matches!(
+            request_states(Some(&RelationshipState::Blocked), false, key(1)),
+            Err(Error::Blocked)
+        ));
+        assert!(matches!(
+            request_states(None, true, key(1)),
+            Err(Error::Blocked)
+        ));
+    }
+
+    #[test]
+    fn stages_out_of_order() {
+        // the requester can't accept their own request
+        let own_request = RelationshipState::Requested { public_key: None };
+        assert!(matches!(
+            accept_states(&own_request, false, key(1), ciphertext(1)),
+            Err(Error::InvalidStage)
+        ));
+        // the acceptor can't finalize
+        let accepted = RelationshipState::PendingKeyExchange {
+            public_key: None,
+            encapsulated: None,
+        };
+        assert!(matches!(
+            finalize_states(&accepted, false, key(2), ciphertext(2), "key".to_string()),
+            Err(Error::InvalidStage)
+        ));
+        // nor can a request be finalized before it is accepted
+        let request = RelationshipState::Requested {
+            public_key: Some(key(1)),
+        };
+        assert!(matches!(
+            finalize_states(&request, false, key(2), ciphertext(2), "key".to_string()),
+            Err(Error::InvalidStage)
+        ));
+    }
+
+    #[test]
+    fn pending_exchange_stops_once_blocked() {
+        let request = RelationshipState::Requested {
+            public_key: Some(key(1)),
+        };
+        assert!(matches!(
+            accept_states(&request, true, key(2), ciphertext(2)),
+            Err(Error::Blocked)
+        ));
+        let accepted = RelationshipState::PendingKeyExchange {
+            public_key: Some(key(2)),
+            encapsulated: Some(ciphertext(2)),
+        };
+        assert!(matches!(
+            finalize_states(&accepted, true, key(1), ciphertext(1), "key".to_string()),
+            Err(Error::Blocked)
+        ));
+    }
+
+    #[test]
+    fn blocking_drops_the_other_side() {
+        assert_eq!(block_state(None, false).unwrap(), RelationshipState::None);
+        let established = RelationshipState::Established {
+            public_key: key(2),
+            encapsulated: ciphertext(2),
+            key_id: "key".to_string(),
+        };
+        assert_eq!(
+            block_state(Some(&established), false).unwrap(),
+            RelationshipState::None
+        );
+    }
+
+    #[test]
+    fn mutual_block_keeps_their_side_blocked() {
+        assert_eq!(block_state(None, true).unwrap(), RelationshipState::Blocked);
+    }
+
+    #[test]
+    fn block_twice() {
+        assert!(matches!(
+            block_state(Some(&RelationshipState::Blocked), false),
+            Err(Error::Blocked)
+        ));
+    }
+
+    #[test]
+    fn request_block_unblock() {
+        // alice requests bob, then blocks him
+        let (alice, bob) = request_states(None, false, key(1)).unwrap();
+        assert!(matches!(bob, RelationshipState::Requested { .. }));
+        let bob = block_state(Some(&alice), false).unwrap();
+        assert_eq!(bob, RelationshipState::None);
+        let alice = RelationshipState::Blocked;
+        assert!(matches!(
+            request_states(Some(&alice), false, key(1)),
+            Err(Error::Blocked)
+        ));
+
+        // unblocking leaves neither side related, so alice may request again
+        let (alice, bob) = unblock_states(Some(&alice), false).unwrap();
+        assert_eq!(alice, RelationshipState::None);
+        assert_eq!(bob, RelationshipState::None);
+        assert!(request_states(Some(&alice), false, key(1)).is_ok());
+        assert!(matches!(
+            unblock_states(Some(&alice), false),
+            Err(Error::InvalidStage)
+        ));
+    }
+
+    #[test]
+    fn unblock_keeps_their_block() {
+        let (alice, bob) = unblock_states(Some(&RelationshipState::Blocked), true).unwrap();
+        assert_eq!(alice, RelationshipState::None);
+        assert_eq!(bob, RelationshipState::Blocked);
+        assert!(matches!(
+            request_states(Some(&alice), true, key(1)),
+            Err(Error::Blocked)
+        ));
+    }
+}
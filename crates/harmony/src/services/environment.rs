@@ -12,4 +12,41 @@ lazy_static! {
     pub static ref NATS_URL: String = env::var("NATS_URL").expect("NATS_URL must be set");
     pub static ref AS_URI: String = env::var("AS_URI").expect("AS_URI must be set");
     pub static ref AS_TOKEN: String = env::var("AS_TOKEN").expect("AS_TOKEN must be set");
+    pub static ref RPC_MAX_IN_FLIGHT: usize = env::var("RPC_MAX_IN_FLIGHT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(32);
+    pub static ref PENDING_MESSAGE_TTL: u64 = env::var("PENDING_MESSAGE_TTL")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(7 * 24 * 60 * 60);
+    // "filesystem" or "s3"; S3 is configured through the usual AWS_* variables
+    pub static ref BLOB_STORE: String =
+        env::var("BLOB_STORE").unwrap_or_else(|_| "filesystem".to_string());
+    pub static ref BLOB_STORE_PATH: String =
+        env::var("BLOB_STORE_PATH").unwrap_or_else(|_| "data/blobs".to_string());
+    pub static ref BLOB_STORE_BUCKET: String =
+        env::var("BLOB_STORE_BUCKET").unwrap_or_else(|_| "harmony".to_string());
+    pub static ref ATTACHMENT_MAX_SIZE: u64 = env::var("ATTACHMENT_MAX_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(100 * 1024 * 1024);
+    pub static ref ATTACHMENT_QUOTA: u64 = env::var("ATTACHMENT_QUOTA")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(2 * 1024 * 1024 * 1024);
+    // nodes above this CPU utilisation (0.0 to 1.0) get no new calls
+    pub static ref NODE_MAX_CPU: f32 = env::var("NODE_MAX_CPU")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0.85);
+    // nodes sending more than this many bits per second get no new calls
+    pub static ref NODE_MAX_EGRESS_BPS: Option<u64> = env::var("NODE_MAX_EGRESS_BPS")
+        .ok()
+        .and_then(|v| v.parse().ok());
+    // how long an upload may stay unsent before it is deleted, in seconds
+    pub static ref ATTACHMENT_UPLOAD_TTL: u64 = env::var("ATTACHMENT_UPLOAD_TTL")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(24 * 60 * 60);
 }
@@ -1,15 +1,24 @@
 use std::time::Duration;
 
 use async_nats::jetstream::consumer::{DeliverPolicy, pull};
+use async_trait::async_trait;
 use futures_util::StreamExt;
-use rapid::socket::RpcClients;
+use rapid::{resume::ResumeHandler, socket::RpcClients};
+use serde_cbor_2::Value;
 use tokio::{task, time};
 use tracing::{error, warn};
 
 use common::nats::{EventEnvelope, SUBJECT_EVENTS_DISPATCH};
 
 use crate::methods::{Event, emit_to_ids};
-use crate::services::{nats, redis::INSTANCE_ID};
+use crate::services::{
+    nats,
+    redis::{INSTANCE_ID, event_cursor, missed_events, record_event},
+};
+
+/// How long the events of a user are kept for replay after their latest one.
+const EVENT_RETENTION_SECONDS: u64 = 5 * 60;
+const MAX_RETAINED_EVENTS: u64 = 1000;
 
 pub async fn publish(recipients: &[String], event: Event) {
     let payload = match serde_cbor_2::to_vec(&event) {
@@ -19,11 +28,25 @@ pub async fn publish(recipients: &[String], event: Event) {
             return;
         }
     };
+    // without a sequence number the event is still delivered, but cannot be
+    // replayed to those that miss it
+    let sequences = record_event(
+        recipients,
+        &payload,
+        EVENT_RETENTION_SECONDS,
+        MAX_RETAINED_EVENTS,
+    )
+    .await
+    .unwrap_or_else(|e| {
+        error!("Failed to record event for replay: {:?}", e);
+        Default::default()
+    });
     let id = ulid::Ulid::new().to_string();
     let envelope = EventEnvelope {
         id: id.clone(),
         recipients: recipients.to_vec(),
         payload,
+        sequences,
     };
     let body = match serde_cbor_2::to_vec(&envelope) {
         Ok(body) => body,
@@ -41,7 +64,7 @@ pub async fn publish(recipients: &[String], event: Event) {
             e
         );
         if let Some(clients) = crate::RPC_CLIENTS.get() {
-            emit_to_ids(clients.clone(), recipients, event);
+            emit_to_ids(clients.clone(), recipients, &envelope.sequences, event);
         }
     }
 }
@@ -92,7 +115,45 @@ async fn run_subscriber(clients: &RpcClients) -> Result<(), async_nats::Error> {
                 continue;
             }
         };
-        emit_to_ids(clients.clone(), &envelope.recipients, event);
+        emit_to_ids(
+            clients.clone(),
+            &envelope.recipients,
+            &envelope.sequences,
+            event,
+        );
     }
     Ok(())
 }
+
+/// Replays the events a user missed from the log kept in Redis.
+pub struct EventReplay;
+
+#[async_trait]
+impl ResumeHandler for EventReplay {
+    async fn cursor(&self, user_id: &str) -> u64 {
+        event_cursor(user_id).await.unwrap_or_else(|e| {
+            error!("Failed to get event cursor of {}: {:?}", user_id, e);
+            0
+        })
+    }
+
+    async fn replay(&self, user_id: &str, cursor: u64) -> Option<Vec<(u64, Value)>> {
+        let events = match missed_events(user_id, cursor).await {
+            Ok(events) => events?,
+            Err(e) => {
+                error!("Failed to get missed events of {}: {:?}", user_id, e);
+                return None;
+            }
+        };
+        events
+            .into_iter()
+            .map(|(seq, payload)| match serde_cbor_2::from_slice(&payload) {
+                Ok(event) => Some((seq, event)),
+                Err(e) => {
+                    warn!("Failed to deserialize retained event: {:?}", e);
+                    None
+                }
+            })
+            .collect()
+    }
+}
@@ -1,10 +1,13 @@
+pub mod blobs;
 pub mod database;
 pub mod environment;
 pub mod events;
 pub mod nats;
 pub mod permissions;
+pub mod presence;
 pub mod rate_limiter;
 pub mod redis;
+pub mod ringing;
 pub mod utilities;
 pub mod voice;
 // pub mod logger;
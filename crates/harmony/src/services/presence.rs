@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use rapid::connection::ConnectionListener;
+use redis::AsyncCommands;
+use tokio::{task, time};
+use tracing::error;
+
+use crate::{
+    errors::Result,
+    methods::{Event, PresenceChangedEvent},
+    services::{
+        database::users::{Status, User, get_presentable_presence},
+        events,
+    },
+};
+
+use super::redis::{
+    INSTANCE_ID, add_user_connection, get_connection, instance_connections, remove_user_connection,
+};
+
+const INSTANCE_TTL: u64 = 30;
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    // client ID -> user ID of every identified connection to this instance
+    static ref LOCAL_CONNECTIONS: DashMap<String, String> = DashMap::new();
+}
+
+/// Tracks which users are connected to this instance. The connections of
+/// every instance are kept in Redis so that a user with several devices is
+/// only reported offline once the last of them is gone.
+pub struct PresenceListener;
+
+#[async_trait]
+impl ConnectionListener for PresenceListener {
+    async fn on_connect(&self, client_id: &str, user_id: &str) {
+        LOCAL_CONNECTIONS.insert(client_id.to_string(), user_id.to_string());
+        match add_user_connection(user_id, client_id).await {
+            Ok(true) => connection_changed(user_id).await,
+            Ok(false) => {}
+            Err(e) => error!("Failed to record connection of {}: {:?}", user_id, e),
+        }
+    }
+
+    async fn on_disconnect(&self, client_id: &str, user_id: &str) {
+        LOCAL_CONNECTIONS.remove(client_id);
+        match remove_user_connection(user_id, &INSTANCE_ID, client_id).await {
+            Ok(true) => connection_changed(user_id).await,
+            Ok(false) => {}
+            Err(e) => error!("Failed to remove connection of {}: {:?}", user_id, e),
+        }
+    }
+}
+
+/// Tell the established contacts of a user how they now present themselves.
+pub async fn publish_presence(user: &User) -> Result<()> {
+    let presence = get_presentable_presence(user).await?;
+    events::publish(
+        &user.established_contact_ids(),
+        Event::PresenceChanged(PresenceChangedEvent {
+            user_id: user.id.clone(),
+            presence,
+        }),
+    )
+    .await;
+    Ok(())
+}
+
+async fn connection_changed(user_id: &str) {
+    let result = match User::get(user_id).await {
+        // invisible users look offline either way
+        Ok(user) if matches!(user.presence.status, Status::Offline) => Ok(()),
+        Ok(user) => publish_presence(&user).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        error!("Failed to publish presence of {}: {:?}", user_id, e);
+    }
+}
+
+/// Keep this instance marked alive, and clean up after instances that
+/// stopped without closing their connections.
+pub fn spawn_presence_keepalive() {
+    task::spawn(async move {
+        loop {
+            if let Err(e) = keepalive().await {
+                error!("Presence keepalive failed: {:?}", e);
+            }
+            time::sleep(KEEPALIVE_INTERVAL).await;
+        }
+    });
+}
+
+async fn keepalive() -> Result<()> {
+    let mut redis = get_connection().await;
+    redis
+        .set_ex::<_, _, ()>(
+            format!("instance:{}:alive", *INSTANCE_ID),
+            true,
+            INSTANCE_TTL,
+        )
+        .await?;
+    let added: u64 = redis.sadd("presence:instances", &*INSTANCE_ID).await?;
+    if added == 1 {
+        // either we just started, or we were unreachable for long enough
+        // that another instance swept our connections
+        let connections: Vec<(String, String)> = LOCAL_CONNECTIONS
+            .iter()
+            .map(|c| (c.key().clone(), c.value().clone()))
+            .collect();
+        for (client_id, user_id) in connections {
+            if add_user_connection(&user_id, &client_id).await? {
+                connection_changed(&user_id).await;
+            }
+        }
+    }
+
+    let instances: Vec<String> = redis.smembers("presence:instances").await?;
+    for instance in instances.iter().filter(|i| **i != *INSTANCE_ID) {
+        let alive: bool = redis.exists(format!("instance:{}:alive", instance)).await?;
+        if alive {
+            continue;
+        }
+        // only the instance that removes it from the set cleans up
+        let removed: u64 = redis.srem("presence:instances", instance).await?;
+        if removed == 1 {
+            sweep_instance(instance).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn sweep_instance(instance_id: &str) -> Result<()> {
+    let mut redis = get_connection().await;
+    let connections: Vec<String> = redis.smembers(instance_connections(instance_id)).await?;
+    for connection in connections {
+        let Some((user_id, client_id)) = connection.rsplit_once(':') else {
+            continue;
+        };
+        if remove_user_connection(user_id, instance_id, client_id).await? {
+            connection_changed(user_id).await;
+        }
+    }
+    redis
+        .del::<_, ()>(instance_connections(instance_id))
+        .await?;
+    Ok(())
+}
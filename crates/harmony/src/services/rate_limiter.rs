@@ -25,19 +25,49 @@ const WRITE_METHODS: &[&str] = &[
     "CREATE_INVITE",
     "EDIT_MESSAGE",
     "EDIT_CHANNEL",
+    "ADD_REACTION",
+    "SEND_HANDSHAKE",
+    "SHARE_GROUP_KEY",
+    "ROTATE_GROUP_KEY",
+    "CREATE_ROLE",
+    "EDIT_ROLE",
+    "KICK_MEMBER",
+    "SET_PRESENCE",
+    "BAN_MEMBER",
+    "CREATE_ATTACHMENT",
 ];
 
+// chunk transfers are counted separately so large files do not use up the global limit
+const TRANSFER_METHODS: &[&str] = &["UPLOAD_CHUNK", "DOWNLOAD_CHUNK"];
+
 const GLOBAL_INTERVAL: Duration = Duration::from_secs(60);
 const GLOBAL_MAX_REQUESTS: u64 = 120;
 
 const WRITE_INTERVAL: Duration = Duration::from_secs(60);
 const WRITE_MAX_REQUESTS: u64 = 30;
 
+const TRANSFER_INTERVAL: Duration = Duration::from_secs(60);
+const TRANSFER_MAX_REQUESTS: u64 = 600;
+
 pub struct RedisRateLimiter;
 
 #[async_trait]
 impl RateLimiter for RedisRateLimiter {
     async fn check_rate_limit(&self, user_id: &str, method: &str) -> bool {
+        if TRANSFER_METHODS.contains(&method) {
+            let transfer_key = format!("rl:{}:transfer", user_id);
+            if !check_and_record(&transfer_key, TRANSFER_INTERVAL, TRANSFER_MAX_REQUESTS).await {
+                rate_limited_counter().add(
+                    1,
+                    &[
+                        KeyValue::new("kind", "transfer"),
+                        KeyValue::new("method", method.to_string()),
+                    ],
+                );
+                return false;
+            }
+            return true;
+        }
         let global_key = format!("rl:{}:global", user_id);
         if !check_and_record(&global_key, GLOBAL_INTERVAL, GLOBAL_MAX_REQUESTS).await {
             rate_limited_counter().add(
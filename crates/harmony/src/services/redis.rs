@@ -1,9 +1,13 @@
 use std::{
+    collections::HashMap,
     sync::{LazyLock, OnceLock},
     time::Duration,
 };
 
-use redis::{AsyncCommands, AsyncConnectionConfig, Client, aio::MultiplexedConnection};
+use redis::{
+    AsyncCommands, AsyncConnectionConfig, Client, ExistenceCheck, Script, SetExpiry, SetOptions,
+    aio::MultiplexedConnection, pipe,
+};
 use ulid::Ulid;
 
 use super::environment::REDIS_URI;
@@ -11,6 +15,38 @@ use super::environment::REDIS_URI;
 static REDIS: OnceLock<Client> = OnceLock::new();
 pub static INSTANCE_ID: LazyLock<String> = LazyLock::new(|| Ulid::new().to_string());
 
+// KEYS: sequence and log key of every recipient
+// ARGV: event, retention in seconds, maximum events per log
+static RECORD_EVENT: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        local sequences = {}
+        for i = 1, #KEYS, 2 do
+            local seq = redis.call('INCR', KEYS[i])
+            redis.call('EXPIRE', KEYS[i], ARGV[2])
+            redis.call('ZADD', KEYS[i + 1], seq, seq .. ':' .. ARGV[1])
+            redis.call('ZREMRANGEBYRANK', KEYS[i + 1], 0, -(tonumber(ARGV[3]) + 1))
+            redis.call('EXPIRE', KEYS[i + 1], ARGV[2])
+            sequences[#sequences + 1] = seq
+        end
+        return sequences
+        ",
+    )
+});
+
+// KEYS: claim
+// ARGV: instance ID
+static RELEASE_CLAIM: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        if redis.call('GET', KEYS[1]) == ARGV[1] then
+            return redis.call('DEL', KEYS[1])
+        end
+        return 0
+        ",
+    )
+});
+
 pub fn connect() {
     let client = Client::open(&**REDIS_URI).expect("Failed to connect");
     REDIS.set(client).expect("Failed to set client");
@@ -21,26 +57,215 @@ pub fn get_client() -> &'static Client {
 }
 
 pub async fn get_connection() -> MultiplexedConnection {
+    try_get_connection()
+        .await
+        .expect("Failed to get connection")
+}
+
+/// Like [`get_connection`], for background tasks that should outlive Redis
+/// being unreachable.
+pub async fn try_get_connection() -> redis::RedisResult<MultiplexedConnection> {
     get_client()
         .get_multiplexed_async_connection_with_config(
             &AsyncConnectionConfig::default().set_response_timeout(Some(Duration::from_secs(10))),
         )
         .await
-        .expect("Failed to get connection")
 }
 
-pub async fn set_user_online(user_id: &str) -> redis::RedisResult<()> {
+fn user_connections(user_id: &str) -> String {
+    format!("user:{}:connections", user_id)
+}
+
+pub fn instance_connections(instance_id: &str) -> String {
+    format!("instance:{}:connections", instance_id)
+}
+
+/// Record a connection of `user_id` to this instance. Returns whether it is
+/// the user's only connection, i.e. whether they just came online.
+pub async fn add_user_connection(user_id: &str, client_id: &str) -> redis::RedisResult<bool> {
     let mut conn = get_connection().await;
-    conn.set_ex(format!("user:{}:online", user_id), true, 60)
-        .await
+    let (added, count): (u64, u64) = pipe()
+        .atomic()
+        .sadd(
+            user_connections(user_id),
+            format!("{}:{}", *INSTANCE_ID, client_id),
+        )
+        .scard(user_connections(user_id))
+        .sadd(
+            instance_connections(&INSTANCE_ID),
+            format!("{}:{}", user_id, client_id),
+        )
+        .ignore()
+        .query_async(&mut conn)
+        .await?;
+    Ok(added == 1 && count == 1)
 }
 
-pub async fn set_user_offline(user_id: &str) -> redis::RedisResult<()> {
+/// Forget a connection of `user_id` to `instance_id`. Returns whether it was
+/// the user's last connection, i.e. whether they just went offline.
+pub async fn remove_user_connection(
+    user_id: &str,
+    instance_id: &str,
+    client_id: &str,
+) -> redis::RedisResult<bool> {
     let mut conn = get_connection().await;
-    conn.del(format!("user:{}:online", user_id)).await
+    let (removed, count): (u64, u64) = pipe()
+        .atomic()
+        .srem(
+            user_connections(user_id),
+            format!("{}:{}", instance_id, client_id),
+        )
+        .scard(user_connections(user_id))
+        .srem(
+            instance_connections(instance_id),
+            format!("{}:{}", user_id, client_id),
+        )
+        .ignore()
+        .query_async(&mut conn)
+        .await?;
+    Ok(removed == 1 && count == 0)
 }
 
+/// A user is online while any of their devices is connected to any instance.
 pub async fn is_user_online(user_id: &str) -> redis::RedisResult<bool> {
     let mut conn = get_connection().await;
-    conn.exists(format!("user:{}:online", user_id)).await
+    conn.exists(user_connections(user_id)).await
+}
+
+/// Mark a user as typing in a channel for `seconds`. Returns whether they were
+/// not marked already, i.e. whether the other members should be told.
+pub async fn start_typing(
+    channel_id: &str,
+    user_id: &str,
+    seconds: u64,
+) -> redis::RedisResult<bool> {
+    let mut conn = get_connection().await;
+    let set: Option<String> = conn
+        .set_options(
+            format!("typing:{}:{}", channel_id, user_id),
+            true,
+            SetOptions::default()
+                .conditional_set(ExistenceCheck::NX)
+                .with_expiration(SetExpiry::EX(seconds)),
+        )
+        .await?;
+    Ok(set.is_some())
+}
+
+/// Claim the migration of a draining voice node's calls. Every instance sees
+/// the node start draining; only the one that gets the claim acts on it.
+pub async fn claim_node_drain(node_id: &str, seconds: u64) -> redis::RedisResult<bool> {
+    claim(format!("node:{}:draining", node_id), seconds).await
+}
+
+/// Give up the claim on draining a node once the drain finished or was
+/// cancelled, so that leftover calls can be picked up again.
+pub async fn release_node_drain(node_id: &str) -> redis::RedisResult<()> {
+    release(format!("node:{}:draining", node_id)).await
+}
+
+/// Claim the reconciliation of a voice node's inventory, which every
+/// instance receives.
+pub async fn claim_node_reconcile(node_id: &str, seconds: u64) -> redis::RedisResult<bool> {
+    claim(format!("node:{}:reconciling", node_id), seconds).await
+}
+
+async fn claim(key: String, seconds: u64) -> redis::RedisResult<bool> {
+    let mut conn = get_connection().await;
+    let set: Option<String> = conn
+        .set_options(
+            key,
+            &*INSTANCE_ID,
+            SetOptions::default()
+                .conditional_set(ExistenceCheck::NX)
+                .with_expiration(SetExpiry::EX(seconds)),
+        )
+        .await?;
+    Ok(set.is_some())
+}
+
+/// Delete a claim, unless it expired and was taken by another instance.
+async fn release(key: String) -> redis::RedisResult<()> {
+    let mut conn = get_connection().await;
+    RELEASE_CLAIM
+        .key(key)
+        .arg(&*INSTANCE_ID)
+        .invoke_async::<i64>(&mut conn)
+        .await?;
+    Ok(())
+}
+
+fn event_sequence(user_id: &str) -> String {
+    format!("user:{}:events:seq", user_id)
+}
+
+fn event_log(user_id: &str) -> String {
+    format!("user:{}:events", user_id)
+}
+
+/// Give an event the next sequence number of each recipient and keep it for
+/// replay. A log keeps at most `max_events`, and is dropped along with the
+/// sequence once no event was recorded for `retention_seconds`.
+pub async fn record_event(
+    recipients: &[String],
+    event: &[u8],
+    retention_seconds: u64,
+    max_events: u64,
+) -> redis::RedisResult<HashMap<String, u64>> {
+    if recipients.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let mut conn = get_connection().await;
+    let mut invocation = RECORD_EVENT.prepare_invoke();
+    for recipient in recipients {
+        invocation
+            .key(event_sequence(recipient))
+            .key(event_log(recipient));
+    }
+    let sequences: Vec<u64> = invocation
+        .arg(event)
+        .arg(retention_seconds)
+        .arg(max_events)
+        .invoke_async(&mut conn)
+        .await?;
+    Ok(recipients.iter().cloned().zip(sequences).collect())
+}
+
+/// The sequence number of the latest event of a user.
+pub async fn event_cursor(user_id: &str) -> redis::RedisResult<u64> {
+    let mut conn = get_connection().await;
+    let latest: Option<u64> = conn.get(event_sequence(user_id)).await?;
+    Ok(latest.unwrap_or(0))
+}
+
+/// The events of a user after `after`, oldest first, or `None` if any of
+/// them is no longer kept.
+pub async fn missed_events(
+    user_id: &str,
+    after: u64,
+) -> redis::RedisResult<Option<Vec<(u64, Vec<u8>)>>> {
+    let mut conn = get_connection().await;
+    let (latest, entries): (Option<u64>, Vec<Vec<u8>>) = pipe()
+        .atomic()
+        .get(event_sequence(user_id))
+        .zrangebyscore(event_log(user_id), format!("({}", after), "+inf")
+        .query_async(&mut conn)
+        .await?;
+    // the sequence restarts once it expires
+    let latest = latest.unwrap_or(0);
+    if after > latest {
+        return Ok(None);
+    }
+    let events: Vec<(u64, Vec<u8>)> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let split = entry.iter().position(|b| *b == b':')?;
+            let seq = std::str::from_utf8(&entry[..split]).ok()?.parse().ok()?;
+            Some((seq, entry[split + 1..].to_vec()))
+        })
+        .collect();
+    if events.len() as u64 != latest - after {
+        return Ok(None);
+    }
+    Ok(Some(events))
 }
@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use harmony_types::voice::RingOutcome;
+use redis::{AsyncCommands, aio::MultiplexedConnection, pipe};
+use tokio::{task, time};
+
+use crate::{
+    errors::Result,
+    methods::{CallRingStoppedEvent, Event},
+    services::{
+        database::{calls::Call, channels::Channel},
+        events,
+    },
+};
+
+use super::{
+    redis::{get_connection, try_get_connection},
+    voice::ActiveCall,
+};
+
+/// How long a call rings before it counts as missed.
+const RING_TIMEOUT_MS: i64 = 30000;
+/// How often rings are checked for timing out.
+const RING_EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+/// Most rings timed out in one go.
+const RING_EXPIRY_BATCH: isize = 100;
+/// Longest wait before retrying after Redis failed.
+const MAX_EXPIRY_BACKOFF: Duration = Duration::from_secs(30);
+
+fn ringing_key(call_id: &str) -> String {
+    format!("call:{}:ringing", call_id)
+}
+
+/// Ring everyone but the initiator when a call starts in a private channel.
+/// Returns the users being rung.
+pub async fn start(call_id: &str, channel: &Channel, initiator_id: &str) -> Result<Vec<String>> {
+    let Channel::PrivateChannel { .. } = channel else {
+        return Ok(vec![]);
+    };
+    let ringing: Vec<String> = channel
+        .member_ids()
+        .into_iter()
+        .filter(|id| id != initiator_id)
+        .collect();
+    if ringing.is_empty() {
+        return Ok(ringing);
+    }
+    let deadline = chrono::Utc::now().timestamp_millis() + RING_TIMEOUT_MS;
+    let mut redis = get_connection().await;
+    pipe()
+        .atomic()
+        .sadd(ringing_key(call_id), &ringing)
+        .ignore()
+        .zadd("voice:ringing", call_id, deadline)
+        .ignore()
+        .query_async::<()>(&mut redis)
+        .await?;
+    Ok(ringing)
+}
+
+/// Stop ringing one user. Returns whether they were still being rung; only
+/// then is the outcome recorded and announced.
+pub async fn stop(
+    call_id: &str,
+    channel: &Channel,
+    user_id: &str,
+    outcome: RingOutcome,
+) -> Result<bool> {
+    let mut redis = get_connection().await;
+    let removed: u64 = redis.srem(ringing_key(call_id), user_id).await?;
+    if removed == 0 {
+        return Ok(false);
+    }
+    announce(call_id, channel, user_id, outcome).await?;
+    Ok(true)
+}
+
+/// Everyone still being rung missed the call, e.g. because the ring timed
+/// out or the call ended before they answered.
+pub async fn stop_all(call_id: &str, channel: Option<&Channel>) -> Result<()> {
+    let mut redis = get_connection().await;
+    let (missed,): (Vec<String>,) = pipe()
+        .atomic()
+        .smembers(ringing_key(call_id))
+        .del(ringing_key(call_id))
+        .ignore()
+        .zrem("voice:ringing", call_id)
+        .ignore()
+        .query_async(&mut redis)
+        .await?;
+    for user_id in missed {
+        match channel {
+            Some(channel) => announce(call_id, channel, &user_id, RingOutcome::Missed).await?,
+            None => Call::record_ring_outcome(call_id, &user_id, RingOutcome::Missed).await?,
+        }
+    }
+    Ok(())
+}
+
+async fn announce(
+    call_id: &str,
+    channel: &Channel,
+    user_id: &str,
+    outcome: RingOutcome,
+) -> Result<()> {
+    Call::record_ring_outcome(call_id, user_id, outcome).await?;
+    events::publish(
+        &channel.member_ids(),
+        Event::CallRingStopped(CallRingStoppedEvent {
+            call_id: call_id.to_string(),
+            channel_id: channel.id().to_string(),
+            user_id: user_id.to_string(),
+            outcome,
+        }),
+    )
+    .await;
+    Ok(())
+}
+
+/// Time out rings nobody answered. If Redis fails, the connection is
+/// replaced and retries back off.
+pub fn spawn_ring_expiry() {
+    task::spawn(async move {
+        let mut redis: Option<MultiplexedConnection> = None;
+        let mut backoff = RING_EXPIRY_INTERVAL;
+        loop {
+            let result = match redis.as_mut() {
+                Some(conn) => expire_rings(conn).await,
+                None => match try_get_connection().await {
+                    Ok(conn) => expire_rings(redis.insert(conn)).await,
+                    Err(e) => Err(e.into()),
+                },
+            };
+            match result {
+                Ok(()) => {
+                    backoff = RING_EXPIRY_INTERVAL;
+                    time::sleep(RING_EXPIRY_INTERVAL).await;
+                }
+                Err(e) => {
+                    tracing::error!("Ring expiry failed: {:?}; retrying in {:?}", e, backoff);
+                    redis = None;
+                    time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_EXPIRY_BACKOFF);
+                }
+            }
+        }
+    });
+}
+
+/// Time out every ring that is due. Each is claimed by removing it, so only
+/// one instance times it out.
+async fn expire_rings(redis: &mut MultiplexedConnection) -> Result<()> {
+    loop {
+        let time = chrono::Utc::now().timestamp_millis();
+        let due: Vec<(String, i64)> = redis
+            .zrangebyscore_limit_withscores("voice:ringing", "-inf", time, 0, RING_EXPIRY_BATCH)
+            .await?;
+        let done = due.len() < RING_EXPIRY_BATCH as usize;
+        for (call_id, deadline) in due {
+            let claimed: u64 = redis.zrem("voice:ringing", &call_id).await?;
+            if claimed == 0 {
+                continue;
+            }
+            let channel = match ActiveCall::get(&call_id).await {
+                Ok(Some(call)) => Channel::get(&call.channel_id).await.ok(),
+                _ => None,
+            };
+            if let Err(e) = stop_all(&call_id, channel.as_ref()).await {
+                tracing::error!("Failed to time out ringing for call {}: {:?}", call_id, e);
+                // try again next time
+                redis
+                    .zadd::<_, _, _, ()>("voice:ringing", &call_id, deadline)
+                    .await?;
+            }
+        }
+        if done {
+            return Ok(());
+        }
+    }
+}
@@ -1,38 +1,63 @@
-use std::time::Duration;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::LazyLock,
+    time::Duration,
+};
 
 use async_nats::jetstream::consumer::{AckPolicy, pull};
 use common::nats::{STREAM_VOICE_LIFECYCLE, SUBJECT_NODES_ALL, subject_node};
-use common::{NodeDescription, NodeEvent, NodeEventKind, SessionData};
+use common::{
+    CallInventory, HomeNode, NodeDescription, NodeEvent, NodeEventKind, NodeLoad, SessionData,
+    SessionGrants,
+};
 use dashmap::DashMap;
 use futures_util::StreamExt;
+use harmony_types::voice::{CallMember, SessionRestrictions};
 use lazy_static::lazy_static;
 use pulse_types::Region;
-use redis::{AsyncCommands, FromRedisValue, ToRedisArgs, ToSingleRedisArg};
+use rand::RngExt;
+use redis::{AsyncCommands, FromRedisValue, Script, ToRedisArgs, ToSingleRedisArg, pipe};
 use serde::{Deserialize, Serialize};
 use tokio::{task, time};
 use tracing::info;
 
 use crate::{
     errors::{Error, Result},
-    methods::{CallMigratedEvent, Event, UserJoinedCallEvent, UserLeftCallEvent},
-    services::{events, nats, utilities::generate_token},
+    methods::{
+        CallEndedEvent, CallMigratedEvent, CallStartedEvent, Event, UserJoinedCallEvent,
+        UserLeftCallEvent,
+    },
+    services::{events, nats, ringing, utilities::generate_token},
 };
 
 use super::{
-    database::calls::Call,
-    redis::{INSTANCE_ID, get_connection},
+    database::{calls::Call, channels::Channel},
+    environment::{NODE_MAX_CPU, NODE_MAX_EGRESS_BPS},
+    permissions::{Permission, PermissionSet},
+    redis::{
+        INSTANCE_ID, claim_node_drain, claim_node_reconcile, get_connection, release_node_drain,
+    },
 };
 
 lazy_static! {
     pub static ref AVAILABLE_NODES: DashMap<String, Node> = DashMap::new();
 }
 
+/// Nodes that haven't pinged for this long are considered down.
+const NODE_TIMEOUT_MS: i64 = 10000;
+
+/// How much closer a node has to be than the call's own to relay a user
+/// through it. Nearby nodes aren't worth the extra hop.
+const MIN_RELAY_SAVING_KM: f64 = 1500.0;
+
 #[derive(Clone, Debug)]
 pub struct Node {
     pub id: String,
     pub region: Region,
     pub server_address: String,
     pub last_ping: i64,
+    pub load: NodeLoad,
+    pub draining: bool,
 }
 
 impl Node {
@@ -43,8 +68,83 @@ impl Node {
             region: description.region,
             server_address: description.server_address,
             last_ping: time,
+            load: NodeLoad::default(),
+            draining: description.draining,
         }
     }
+
+    pub fn accepts_calls(&self, time: i64) -> bool {
+        !self.draining && self.last_ping + NODE_TIMEOUT_MS >= time
+    }
+
+    /// Whether the node is too busy to take on more calls, however close.
+    fn overloaded(&self) -> bool {
+        self.load.cpu > *NODE_MAX_CPU
+            || NODE_MAX_EGRESS_BPS.is_some_and(|max| self.load.egress_bps > max)
+    }
+
+    /// Rough cost of placing another call here. CPU dominates; calls,
+    /// sessions and egress separate nodes that are otherwise idle.
+    fn cost(&self) -> f64 {
+        self.load.cpu as f64
+            + self.load.calls as f64 / 100.0
+            + self.load.sessions as f64 / 1000.0
+            + self.load.egress_bps as f64 / 1e10
+    }
+}
+
+/// Pick a node for a new or relocated call: the least loaded healthy node in
+/// the preferred region, else in the nearest region that has one. Overloaded
+/// nodes are skipped, unless every node is, in which case the least loaded
+/// one anywhere is used.
+///
+/// The call is counted against the chosen node until its next ping reports
+/// real figures, so a burst of calls doesn't all land on the same node.
+pub fn select_node(preferred_region: Option<Region>, exclude: Option<&str>) -> Option<Node> {
+    let time = chrono::Utc::now().timestamp_millis();
+    let distance = |node: &Node| {
+        preferred_region
+            .map(|region| region.distance_km(&node.region))
+            .unwrap_or_default()
+    };
+    let candidates: Vec<Node> = AVAILABLE_NODES
+        .iter()
+        .filter(|n| n.accepts_calls(time) && Some(n.id.as_str()) != exclude)
+        .map(|n| n.value().clone())
+        .collect();
+    let node = if candidates.iter().any(|n| !n.overloaded()) {
+        candidates
+            .into_iter()
+            .filter(|n| !n.overloaded())
+            .min_by(|a, b| {
+                distance(a)
+                    .total_cmp(&distance(b))
+                    .then(a.cost().total_cmp(&b.cost()))
+            })?
+    } else {
+        candidates
+            .into_iter()
+            .min_by(|a, b| a.cost().total_cmp(&b.cost()))?
+    };
+    if let Some(mut entry) = AVAILABLE_NODES.get_mut(&node.id) {
+        entry.load.calls += 1;
+    }
+    Some(node)
+}
+
+/// Pick a node for a new room in a channel: the node its other rooms are on,
+/// so sessions move between them without switching nodes, unless that node
+/// can't take more calls.
+fn select_room_node(rooms: &[ActiveCall], preferred_region: Option<Region>) -> Option<Node> {
+    let time = chrono::Utc::now().timestamp_millis();
+    let shared = rooms.iter().find_map(|room| {
+        let mut node = AVAILABLE_NODES
+            .get_mut(&room.assigned_node)
+            .filter(|n| n.accepts_calls(time) && !n.overloaded())?;
+        node.load.calls += 1;
+        Some(node.clone())
+    });
+    shared.or_else(|| select_node(preferred_region, None))
 }
 
 pub fn spawn_voice_events() {
@@ -82,23 +182,45 @@ pub fn spawn_voice_events() {
                     event: NodeEventKind::Description(description),
                     ..
                 } => {
-                    let node: Node = Node::new(id, description);
-                    if AVAILABLE_NODES.contains_key(&node.id) {
+                    if let Some(mut node) = AVAILABLE_NODES.get_mut(&id) {
+                        // re-announced, e.g. after the node started draining
+                        let started_draining = description.draining && !node.draining;
+                        node.draining = description.draining;
+                        let region = node.region;
+                        drop(node);
+                        if started_draining {
+                            task::spawn(drain_node(id, region));
+                        }
                         continue;
                     }
+                    let node: Node = Node::new(id, description);
                     let i = node.id.clone();
                     AVAILABLE_NODES.insert(node.id.clone(), node);
                     info!("Node {} connected", i);
                 }
                 NodeEvent {
                     id,
-                    event: NodeEventKind::Ping,
+                    event: NodeEventKind::Ping { load, draining },
                 } => {
                     let node = AVAILABLE_NODES.get_mut(&id);
                     if let Some(mut node) = node {
                         node.last_ping = chrono::Utc::now().timestamp_millis();
+                        node.load = load;
+                        node.draining = draining;
+                        let region = node.region;
+                        drop(node);
+                        // repeated until every call is off the node
+                        if draining {
+                            task::spawn(drain_node(id, region));
+                        }
                     }
                 }
+                NodeEvent {
+                    id,
+                    event: NodeEventKind::Inventory { calls },
+                } => {
+                    task::spawn(reconcile_node(id, calls));
+                }
                 NodeEvent {
                     id,
                     event: NodeEventKind::Disconnect,
@@ -129,7 +251,7 @@ pub fn spawn_voice_events() {
             let time = chrono::Utc::now().timestamp_millis();
             let mut dead_nodes: Vec<(String, Region)> = Vec::new();
             AVAILABLE_NODES.retain(|id, node| {
-                if node.last_ping + 10000 < time {
+                if node.last_ping + NODE_TIMEOUT_MS < time {
                     info!("Node {} timed out", id);
                     dead_nodes.push((id.clone(), node.region));
                     false // Remove node
@@ -164,8 +286,10 @@ pub fn spawn_voice_events() {
                     time::sleep(Duration::from_millis((call_id.1 - time) as u64)).await;
                     continue;
                 }
+                // someone may join between reading the call and ending it
                 if let Ok(Some(call)) = ActiveCall::get(&call_id.0).await
-                    && let Err(e) = call.end().await
+                    && call.members.is_empty()
+                    && let Err(e) = call.end_if_unchanged().await
                 {
                     tracing::error!("Failed to end call {}: {:?}", call_id.0, e);
                     // don't leak in Redis
@@ -215,7 +339,7 @@ async fn run_lifecycle_worker() -> std::result::Result<(), async_nats::Error> {
             NodeEventKind::UserDisconnect {
                 ref id,
                 ref call_id,
-            } => process_user_disconnect(id, call_id).await,
+            } => process_user_disconnect(&payload.id, id, call_id).await,
             _ => Ok(()),
         };
 
@@ -231,9 +355,17 @@ async fn run_lifecycle_worker() -> std::result::Result<(), async_nats::Error> {
     Ok(())
 }
 
-async fn process_user_disconnect(session_id: &str, call_id: &str) -> Result<()> {
-    if let Ok(Some(mut call)) = ActiveCall::get(&call_id.to_string()).await {
-        if let Err(e) = call.leave_user(&session_id.to_string()).await {
+async fn process_user_disconnect(node_id: &str, session_id: &str, call_id: &str) -> Result<()> {
+    if let Ok(Some(mut call)) = ActiveCall::get(call_id).await {
+        if call.assigned_node != node_id {
+            // the session was moved off that node and has reconnected elsewhere
+            info!(
+                "Ignoring disconnect of {} from node {}: call {} has moved",
+                session_id, node_id, call_id
+            );
+            return Ok(());
+        }
+        if let Err(e) = call.leave_user(session_id).await {
             tracing::error!(
                 "Failed to remove user {} from call {}: {:?}",
                 session_id,
@@ -243,6 +375,14 @@ async fn process_user_disconnect(session_id: &str, call_id: &str) -> Result<()>
             return Err(e);
         } else {
             info!("User {} disconnected from call {}", session_id, call_id);
+            if call
+                .pending_sessions
+                .iter()
+                .any(|s| s.id == session_id && s.migrating)
+            {
+                // rerouted away from its relay; the others never see it leave
+                return Ok(());
+            }
 
             let member_user_ids: Vec<String> = call
                 .members
@@ -265,26 +405,26 @@ async fn process_user_disconnect(session_id: &str, call_id: &str) -> Result<()>
 
 async fn process_user_connect(session_id: &str, call_id: &str) -> Result<()> {
     info!("User {} connected to call {}", session_id, call_id);
-    if let Ok(call) = ActiveCall::get(&call_id.to_string()).await {
+    if let Ok(call) = ActiveCall::get(call_id).await {
         if let Some(mut call) = call {
-            call.empty_since = None;
-            // remove item from pending sessions
-            let session: Vec<CallSession> = call
-                .pending_sessions
-                .extract_if(.., |s| s.id == session_id)
-                .collect();
-            let Some(session) = session.first() else {
-                tracing::warn!(
-                    "Session {} not found in pending sessions for call {}",
-                    session_id,
-                    call_id
-                );
-                return Err(Error::NotFound);
+            let session = match call.admit(session_id).await {
+                Ok(session) => session,
+                Err(Error::NotFound) => {
+                    tracing::warn!(
+                        "Session {} not found in pending sessions for call {}",
+                        session_id,
+                        call_id
+                    );
+                    return Err(Error::NotFound);
+                }
+                Err(e) => {
+                    tracing::error!("Failed to update call {} in redis: {:?}", call_id, e);
+                    return Err(e);
+                }
             };
-            call.members.push(session.clone());
-            if let Err(e) = call.update().await {
-                tracing::error!("Failed to update call {} in redis: {:?}", call_id, e);
-                return Err(e);
+            if session.migrating {
+                // the others never saw this session leave
+                return Ok(());
             }
             let member_ids: Vec<String> = call
                 .members
@@ -304,8 +444,9 @@ async fn process_user_connect(session_id: &str, call_id: &str) -> Result<()> {
             )
             .await;
         } else {
-            // TODO:!! this is most likely due to the call expiring while user is connecting
-            // this should RESTORE the call into memory
+            // most likely the call expired while the user was connecting; the
+            // node's next inventory restores it, or ends it if it was ended
+            // on purpose
             tracing::warn!(
                 "Call {} not found when user {} tried to connect",
                 call_id,
@@ -349,24 +490,20 @@ pub async fn handle_node_down(node_id: String, region: Option<Region>) {
             .collect();
 
         // pick an alternative node to move users
-        let target = region
-            .as_ref()
-            .and_then(|r| {
-                AVAILABLE_NODES
-                    .iter()
-                    .find(|n| n.region == *r && n.id != node_id)
-                    .map(|n| (n.id.clone(), n.server_address.clone()))
-            })
-            .or_else(|| {
-                AVAILABLE_NODES
-                    .iter()
-                    .find(|n| n.id != node_id)
-                    .map(|n| (n.id.clone(), n.server_address.clone()))
-            });
+        let target = select_node(region, Some(&node_id));
 
         match target {
-            Some((target_id, target_addr)) => {
-                if let Err(e) = call.migrate_to(&node_id, &target_id, &target_addr).await {
+            Some(Node {
+                id: target_id,
+                server_address: target_addr,
+                ..
+            }) => {
+                let migrated = call.migrate_to(&node_id, &target_id, &target_addr).await;
+                if let Ok(None) = migrated {
+                    // another instance got to it first
+                    continue;
+                }
+                if let Err(e) = migrated {
                     tracing::error!(
                         "Failed to migrate call {} to node {}: {:?}; ending it instead",
                         call_id,
@@ -409,6 +546,344 @@ pub async fn handle_node_down(node_id: String, region: Option<Region>) {
     let _: std::result::Result<(), _> = redis.del::<_, ()>(&index_key).await;
 }
 
+/// How long an instance holds the claim on draining a node.
+const DRAIN_CLAIM_SECONDS: u64 = 300;
+
+/// Move every call off a node that is draining, and every session it relays
+/// onto another route. Each connected session is told to reconnect under its
+/// current session ID, so clients keep their MLS identity and nobody sees
+/// anyone leave.
+///
+/// Runs again on every ping of the node until no calls or relayed sessions
+/// are left, so whatever could not be moved is retried. Stops early if the node is no
+/// longer draining.
+pub async fn drain_node(node_id: String, region: Region) {
+    match claim_node_drain(&node_id, DRAIN_CLAIM_SECONDS).await {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            tracing::error!("Failed to claim drain of node {}: {:?}", node_id, e);
+            return;
+        }
+    }
+    let mut redis = get_connection().await;
+    let index_key = format!("node:{}:calls", node_id);
+    let call_ids: Vec<String> = match redis.smembers(&index_key).await {
+        Ok(call_ids) => call_ids,
+        Err(e) => {
+            tracing::error!("Failed to list calls of node {}: {:?}", node_id, e);
+            Vec::new()
+        }
+    };
+    if !call_ids.is_empty() {
+        info!(
+            "Node {} draining: moving {} call(s)",
+            node_id,
+            call_ids.len()
+        );
+    }
+
+    for call_id in call_ids {
+        if !AVAILABLE_NODES.get(&node_id).is_some_and(|n| n.draining) {
+            info!("Node {} stopped draining", node_id);
+            break;
+        }
+        let Ok(Some(mut call)) = ActiveCall::get(&call_id).await else {
+            let _: std::result::Result<(), _> = redis.srem::<_, _, ()>(&index_key, &call_id).await;
+            continue;
+        };
+        let Some(target) = select_node(Some(region), Some(&node_id)) else {
+            tracing::warn!(
+                "No node to move call {} to; it stays on draining node {}",
+                call_id,
+                node_id
+            );
+            continue;
+        };
+        if let Err(e) = migrate_call(&mut call, &node_id, &target).await {
+            tracing::error!(
+                "Failed to move call {} from node {} to node {}: {:?}",
+                call_id,
+                node_id,
+                target.id,
+                e
+            );
+            continue;
+        }
+        info!(
+            "Moved call {} from draining node {} to node {}",
+            call_id, node_id, target.id
+        );
+    }
+
+    reroute_relayed(&node_id, region).await;
+
+    if let Err(e) = release_node_drain(&node_id).await {
+        tracing::error!("Failed to release drain of node {}: {:?}", node_id, e);
+    }
+}
+
+/// Have every session a draining node relays reconnect elsewhere. Calls are
+/// dropped from the node's index once none of their sessions use it.
+async fn reroute_relayed(node_id: &str, region: Region) {
+    let mut redis = get_connection().await;
+    let index_key = relayed_key(node_id);
+    let call_ids: Vec<String> = match redis.smembers(&index_key).await {
+        Ok(call_ids) => call_ids,
+        Err(e) => {
+            tracing::error!("Failed to list calls relayed by node {}: {:?}", node_id, e);
+            return;
+        }
+    };
+
+    for call_id in call_ids {
+        if !AVAILABLE_NODES.get(node_id).is_some_and(|n| n.draining) {
+            return;
+        }
+        let mut call = match ActiveCall::get(&call_id).await {
+            Ok(Some(call)) if call.relays_through(node_id) => call,
+            Ok(_) => {
+                let _: std::result::Result<(), _> =
+                    redis.srem::<_, _, ()>(&index_key, &call_id).await;
+                continue;
+            }
+            Err(e) => {
+                tracing::error!("Failed to check call {}: {:?}", call_id, e);
+                continue;
+            }
+        };
+        if let Err(e) = reroute_call(&mut call, node_id, region).await {
+            tracing::error!(
+                "Failed to reroute call {} off relay {}: {:?}",
+                call_id,
+                node_id,
+                e
+            );
+        }
+    }
+}
+
+async fn reroute_call(call: &mut ActiveCall, relay: &str, region: Region) -> Result<()> {
+    let channel = Channel::get(&call.channel_id).await?;
+    let rerouted = call
+        .reroute(relay, region, |user_id| channel.permissions_for(user_id))
+        .await?;
+    for (session, server_address, token) in rerouted {
+        // the call's node passes it on through the relay
+        nats::publish_node_event(
+            subject_node(&call.assigned_node),
+            &NodeEvent {
+                id: INSTANCE_ID.clone(),
+                event: NodeEventKind::UserMoved {
+                    id: session.id,
+                    target_server: server_address,
+                    target_token: token,
+                },
+            },
+        )
+        .await;
+    }
+    Ok(())
+}
+
+async fn migrate_call(call: &mut ActiveCall, old_node: &str, target: &Node) -> Result<()> {
+    let channel = Channel::get(&call.channel_id).await?;
+    let Some(sessions) = call
+        .migrate_to(old_node, &target.id, &target.server_address)
+        .await?
+    else {
+        return Ok(());
+    };
+    let moved = call
+        .readmit(sessions, |user_id| channel.permissions_for(user_id))
+        .await?;
+    for (session, token) in moved {
+        nats::publish_node_event(
+            subject_node(old_node),
+            &NodeEvent {
+                id: INSTANCE_ID.clone(),
+                event: NodeEventKind::UserMoved {
+                    id: session.id,
+                    target_server: target.server_address.clone(),
+                    target_token: token,
+                },
+            },
+        )
+        .await;
+    }
+    Ok(())
+}
+
+/// How long an instance holds the claim on reconciling a node's inventory,
+/// a little less than the interval nodes report it at.
+const RECONCILE_CLAIM_SECONDS: u64 = 20;
+
+/// Check what a node says it hosts against what is stored. Calls the node
+/// hosts that are gone from Redis are restored with the sessions connected
+/// to them, or ended on the node if they were ended on purpose or the channel
+/// has moved on to another call. Calls the node's index lists but that are
+/// gone or have moved elsewhere are dropped from the index.
+pub async fn reconcile_node(node_id: String, calls: Vec<CallInventory>) {
+    match claim_node_reconcile(&node_id, RECONCILE_CLAIM_SECONDS).await {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            tracing::error!(
+                "Failed to claim reconciliation of node {}: {:?}",
+                node_id,
+                e
+            );
+            return;
+        }
+    }
+    let Some(server_address) = AVAILABLE_NODES
+        .get(&node_id)
+        .map(|n| n.server_address.clone())
+    else {
+        return;
+    };
+    let mut redis = get_connection().await;
+    let index_key = format!("node:{}:calls", node_id);
+
+    let hosted: HashSet<String> = calls.iter().map(|c| c.call_id.clone()).collect();
+    for inventory in calls {
+        let call_id = inventory.call_id.clone();
+        let result = match ActiveCall::get(&call_id).await {
+            Ok(Some(call)) if call.assigned_node == node_id => redis
+                .sadd::<_, _, ()>(&index_key, &call_id)
+                .await
+                .map_err(Error::from),
+            // moved away; its sessions follow it or are disconnected
+            Ok(Some(_)) => Ok(()),
+            Ok(None) => restore_call(&node_id, &server_address, inventory).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::error!(
+                "Failed to reconcile call {} on node {}: {:?}",
+                call_id,
+                node_id,
+                e
+            );
+        }
+    }
+
+    let indexed: Vec<String> = redis.smembers(&index_key).await.unwrap_or_default();
+    for call_id in indexed.into_iter().filter(|id| !hosted.contains(id)) {
+        match ActiveCall::get(&call_id).await {
+            Ok(Some(call)) if call.assigned_node == node_id => {}
+            Ok(Some(_)) => {
+                let _: std::result::Result<(), _> =
+                    redis.srem::<_, _, ()>(&index_key, &call_id).await;
+            }
+            Ok(None) => {
+                let _: std::result::Result<(), _> = pipe()
+                    .atomic()
+                    .srem(&index_key, &call_id)
+                    .ignore()
+                    .zrem("voice:empty-calls", &call_id)
+                    .ignore()
+                    .query_async::<()>(&mut redis)
+                    .await;
+                info!("Dropped stale call {} from node {}", call_id, node_id);
+            }
+            Err(e) => {
+                tracing::error!("Failed to check call {}: {:?}", call_id, e);
+            }
+        }
+    }
+}
+
+async fn restore_call(node_id: &str, server_address: &str, inventory: CallInventory) -> Result<()> {
+    let mut redis = get_connection().await;
+    let call_id = inventory.call_id;
+    let ended: bool = redis.exists(ended_key(&call_id)).await?;
+    let stored = match Call::get(&call_id).await? {
+        Some(stored) if !ended => stored,
+        _ => {
+            info!("Ending call {} left behind on node {}", call_id, node_id);
+            end_on_node(node_id, &call_id).await;
+            return Ok(());
+        }
+    };
+    let members: Vec<CallSession> = inventory
+        .sessions
+        .into_iter()
+        // connected through a node that doesn't report users yet
+        .filter(|s| !s.user_id.is_empty())
+        .map(|s| CallSession {
+            id: s.id,
+            user_id: s.user_id,
+            call_id: call_id.clone(),
+            muted: !s.grants.can_speak,
+            deafened: !s.grants.can_listen,
+            migrating: false,
+            restrictions: SessionRestrictions::default(),
+            relay: None,
+        })
+        .collect();
+    if members.is_empty() {
+        end_on_node(node_id, &call_id).await;
+        return Ok(());
+    }
+    let call = ActiveCall {
+        id: call_id.clone(),
+        name: stored.name,
+        members,
+        channel_id: stored.channel_id,
+        assigned_node: node_id.to_string(),
+        server_address: server_address.to_string(),
+        empty_since: None,
+        pending_sessions: vec![],
+        restricted: HashMap::new(),
+        revision: 0,
+    };
+    if !call.insert(None).await? {
+        info!(
+            "Ending call {} on node {}: its room in channel {} has another call",
+            call_id, node_id, call.channel_id
+        );
+        end_on_node(node_id, &call_id).await;
+        return Ok(());
+    }
+    call.record_members().await?;
+    info!(
+        "Restored call {} on node {} with {} session(s)",
+        call_id,
+        node_id,
+        call.members.len()
+    );
+
+    // everyone was told the call ended
+    if let Ok(channel) = Channel::get(&call.channel_id).await {
+        events::publish(
+            &channel.member_ids(),
+            Event::CallStarted(CallStartedEvent {
+                call_id: call.id.clone(),
+                channel_id: call.channel_id.clone(),
+                initiator_id: stored.initiator,
+                name: call.name.clone(),
+                ringing: vec![],
+            }),
+        )
+        .await;
+    }
+    Ok(())
+}
+
+async fn end_on_node(node_id: &str, call_id: &str) {
+    nats::publish_node_event(
+        subject_node(node_id),
+        &NodeEvent {
+            id: INSTANCE_ID.clone(),
+            event: NodeEventKind::CallEnded {
+                call_id: call_id.to_string(),
+            },
+        },
+    )
+    .await;
+}
+
 async fn emit_call_ended(
     call_id: &str,
     affected_users: &[String],
@@ -436,6 +911,13 @@ pub struct ActiveCall {
     pub server_address: String,
     pub empty_since: Option<i64>,
     pub pending_sessions: Vec<CallSession>,
+    /// Restrictions managers placed on users in this call, by user ID. They
+    /// outlast the user's sessions, so rejoining doesn't lift them.
+    #[serde(default)]
+    pub restricted: HashMap<String, SessionRestrictions>,
+    /// Revision the call was read at, see [`ActiveCall::modify`].
+    #[serde(skip)]
+    pub revision: u64,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -445,6 +927,44 @@ pub struct CallSession {
     pub call_id: String,
     pub muted: bool,
     pub deafened: bool,
+    /// Set while the session reconnects to the node its call was moved to.
+    #[serde(default)]
+    pub migrating: bool,
+    /// The user's entry in [`ActiveCall::restricted`].
+    #[serde(default)]
+    pub restrictions: SessionRestrictions,
+    /// The node the session connects through, if it is relayed.
+    #[serde(default)]
+    pub relay: Option<String>,
+}
+
+impl CallSession {
+    /// What the node lets this session do: the user's permissions in the
+    /// channel, narrowed by their own mute and deafen state and by whatever
+    /// a manager restricted.
+    pub fn grants(&self, permissions: PermissionSet) -> SessionGrants {
+        let restrictions = self.restrictions;
+        SessionGrants {
+            can_listen: !self.deafened && !restrictions.deafened,
+            can_speak: !self.muted
+                && !restrictions.muted
+                && permissions.has_permission(Permission::Speak),
+            can_video: !restrictions.video_disabled
+                && permissions.has_permission(Permission::Video),
+            can_screen: !restrictions.screen_disabled
+                && permissions.has_permission(Permission::Screenshare),
+        }
+    }
+
+    pub fn to_member(&self) -> CallMember {
+        CallMember {
+            user_id: self.user_id.clone(),
+            session_id: self.id.clone(),
+            muted: self.muted,
+            deafened: self.deafened,
+            restrictions: self.restrictions,
+        }
+    }
 }
 
 impl FromRedisValue for ActiveCall {
@@ -475,71 +995,184 @@ impl ToRedisArgs for ActiveCall {
     }
 }
 
+// Every write of a call bumps `call:{id}:rev`; a write only goes through if
+// the revision is still the one the call was read at.
+
+// A channel's rooms are a hash of room name to call ID, the unnamed room
+// under the empty name.
+
+// KEYS: channel rooms, call, revision, empty calls, node calls
+// ARGV: call id, call, empty since (empty if in use), room name
+static CREATE_CALL: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        local existing = redis.call('HGET', KEYS[1], ARGV[4])
+        if existing and redis.call('EXISTS', 'call:' .. existing) == 1 then
+            return 0
+        end
+        redis.call('HSET', KEYS[1], ARGV[4], ARGV[1])
+        redis.call('SET', KEYS[2], ARGV[2])
+        redis.call('SET', KEYS[3], 0)
+        if ARGV[3] ~= '' then
+            redis.call('ZADD', KEYS[4], ARGV[3], ARGV[1])
+        end
+        redis.call('SADD', KEYS[5], ARGV[1])
+        return 1
+        ",
+    )
+});
+
+// KEYS: call, revision, empty calls
+// ARGV: expected revision, call, call id, empty since (empty if in use)
+// Returns the new revision, -1 if the call is gone or -2 on a conflict.
+static UPDATE_CALL: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        if redis.call('EXISTS', KEYS[1]) == 0 then
+            return -1
+        end
+        if tonumber(redis.call('GET', KEYS[2]) or '0') ~= tonumber(ARGV[1]) then
+            return -2
+        end
+        redis.call('SET', KEYS[1], ARGV[2])
+        if ARGV[4] == '' then
+            redis.call('ZREM', KEYS[3], ARGV[3])
+        else
+            redis.call('ZADD', KEYS[3], ARGV[4], ARGV[3])
+        end
+        return redis.call('INCR', KEYS[2])
+        ",
+    )
+});
+
+// KEYS: channel rooms, call, revision, empty calls, node calls, ended marker
+// ARGV: call id, expected revision (empty to end regardless), seconds to keep
+// the ended marker (empty for none), room name
+static END_CALL: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        if ARGV[2] ~= '' and tonumber(redis.call('GET', KEYS[3]) or '0') ~= tonumber(ARGV[2]) then
+            return 0
+        end
+        if redis.call('HGET', KEYS[1], ARGV[4]) == ARGV[1] then
+            redis.call('HDEL', KEYS[1], ARGV[4])
+        end
+        redis.call('DEL', KEYS[2], KEYS[3])
+        redis.call('ZREM', KEYS[4], ARGV[1])
+        redis.call('SREM', KEYS[5], ARGV[1])
+        if ARGV[3] ~= '' then
+            redis.call('SET', KEYS[6], 1, 'EX', ARGV[3])
+        end
+        return 1
+        ",
+    )
+});
+
+/// How long a call that was ended on purpose is kept from being restored:
+/// long enough for its last tokens to expire and its node to report it.
+const ENDED_MARKER_SECONDS: u64 = 120;
+
+/// Attempts at a read-modify-write of a call before giving up.
+const MAX_CALL_WRITE_ATTEMPTS: u32 = 8;
+
+fn rooms_key(channel_id: &str) -> String {
+    format!("call:channel:{}:rooms", channel_id)
+}
+
+fn call_key(id: &str) -> String {
+    format!("call:{}", id)
+}
+
+fn revision_key(id: &str) -> String {
+    format!("call:{}:rev", id)
+}
+
+fn ended_key(id: &str) -> String {
+    format!("call:{}:ended", id)
+}
+
+fn relayed_key(node_id: &str) -> String {
+    format!("node:{}:relayed", node_id)
+}
+
 impl ActiveCall {
     pub async fn create(
-        channel: &String,
+        channel: &str,
         initiator: &str,
         preferred_region: Option<Region>,
+        name: Option<String>,
     ) -> Result<ActiveCall> {
-        let mut redis = get_connection().await;
-        let call = Self::get_in_channel(channel).await?;
-        if call.is_some() {
+        let rooms = Self::list_in_channel(channel).await?;
+        if rooms.iter().any(|room| room.name == name) {
             return Err(Error::AlreadyExists);
         }
         // assign node
-        let (assigned_node, server_address) = if let Some(region) = preferred_region
-            && let Some(node) = AVAILABLE_NODES.iter().find(|n| n.region == region)
-        {
-            (node.id.clone(), node.server_address.clone())
-        } else {
-            // fallback to any node
-            let node = AVAILABLE_NODES.iter().next();
-            if let Some(node) = node {
-                (node.id.clone(), node.server_address.clone())
-            } else {
-                return Err(Error::NoVoiceNodesAvailable);
-            }
+        let Some(Node {
+            id: assigned_node,
+            server_address,
+            ..
+        }) = select_room_node(&rooms, preferred_region)
+        else {
+            return Err(Error::NoVoiceNodesAvailable);
         };
         let time = chrono::Utc::now().timestamp_millis();
         let call = ActiveCall {
             id: ulid::Ulid::new().to_string(),
-            name: None,
+            name: name.clone(),
             members: vec![],
-            channel_id: channel.clone(),
+            channel_id: channel.to_string(),
             assigned_node,
             server_address,
             empty_since: Some(time),
             pending_sessions: vec![],
+            restricted: HashMap::new(),
+            revision: 0,
         };
-        redis
-            .set::<String, ActiveCall, ()>(format!("call:{}", call.id), call.clone())
-            .await
-            .unwrap();
-        redis
-            .set::<String, String, ()>(format!("call:channel:{}", channel), call.id.clone())
-            .await
-            .unwrap();
-        redis
-            .zadd::<_, _, _, ()>("voice:empty-calls", &call.id, time)
-            .await?;
-        redis
-            .sadd::<_, _, ()>(format!("node:{}:calls", call.assigned_node), &call.id)
-            .await?;
+        // someone may have opened the room since we looked
+        if !call.insert(Some(time)).await? {
+            return Err(Error::AlreadyExists);
+        }
         let stored_call = Call {
-            channel_id: channel.clone(),
+            channel_id: channel.to_string(),
             id: call.id.clone(),
             joined_members: vec![],
-            name: None,
+            name,
             ended_at: time,
             initiator: initiator.to_owned(),
+            declined: vec![],
+            missed: vec![],
         };
         stored_call.create().await?;
         Ok(call)
     }
 
-    pub async fn get_in_channel(channel: &String) -> Result<Option<ActiveCall>> {
+    /// Store a new call unless its room is taken by another. Returns whether
+    /// it was stored.
+    async fn insert(&self, empty_since: Option<i64>) -> Result<bool> {
         let mut redis = get_connection().await;
-        let id: Option<String> = redis.get(format!("call:channel:{}", channel)).await?;
+        let inserted: bool = CREATE_CALL
+            .key(rooms_key(&self.channel_id))
+            .key(call_key(&self.id))
+            .key(revision_key(&self.id))
+            .key("voice:empty-calls")
+            .key(format!("node:{}:calls", self.assigned_node))
+            .arg(&self.id)
+            .arg(self)
+            .arg(empty_since.map(|t| t.to_string()).unwrap_or_default())
+            .arg(self.name.as_deref().unwrap_or_default())
+            .invoke_async(&mut redis)
+            .await?;
+        Ok(inserted)
+    }
+
+    /// The channel's unnamed call, the only one private channels have.
+    pub async fn get_in_channel(channel: &str) -> Result<Option<ActiveCall>> {
+        Self::get_room(channel, "").await
+    }
+
+    pub async fn get_room(channel: &str, name: &str) -> Result<Option<ActiveCall>> {
+        let mut redis = get_connection().await;
+        let id: Option<String> = redis.hget(rooms_key(channel), name).await?;
         if let Some(id) = id {
             Ok(Self::get(&id).await?)
         } else {
@@ -547,137 +1180,637 @@ impl ActiveCall {
         }
     }
 
-    pub async fn get(id: &String) -> Result<Option<ActiveCall>> {
+    /// Every call going on in a channel.
+    pub async fn list_in_channel(channel: &str) -> Result<Vec<ActiveCall>> {
         let mut redis = get_connection().await;
-        let call: Option<ActiveCall> = redis.get(format!("call:{}", id)).await?;
-        Ok(call)
+        let ids: Vec<String> = redis.hvals(rooms_key(channel)).await?;
+        let mut calls = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(call) = Self::get(&id).await? {
+                calls.push(call);
+            }
+        }
+        Ok(calls)
     }
 
-    pub async fn update(&self) -> Result<()> {
+    /// The call with `id` if given, else the unnamed call, as long as it is
+    /// in `channel`.
+    pub async fn find(channel: &String, id: Option<&String>) -> Result<Option<ActiveCall>> {
+        let Some(id) = id else {
+            return Self::get_in_channel(channel).await;
+        };
+        Ok(Self::get(id)
+            .await?
+            .filter(|call| call.channel_id == *channel))
+    }
+
+    /// The call in `channel` that `session_id` is connected to.
+    pub async fn with_session(channel: &str, session_id: &str) -> Result<Option<ActiveCall>> {
+        Ok(Self::list_in_channel(channel)
+            .await?
+            .into_iter()
+            .find(|call| call.members.iter().any(|s| s.id == session_id)))
+    }
+
+    pub async fn get(id: &str) -> Result<Option<ActiveCall>> {
         let mut redis = get_connection().await;
-        redis
-            .set::<String, ActiveCall, ()>(format!("call:{}", self.id), self.clone())
+        let (call, revision): (Option<ActiveCall>, Option<u64>) = pipe()
+            .atomic()
+            .get(call_key(id))
+            .get(revision_key(id))
+            .query_async(&mut redis)
             .await?;
-        if !self.members.is_empty() {
-            let _: () = redis.zrem("voice:empty-calls", &self.id).await?;
+        Ok(call.map(|call| ActiveCall {
+            revision: revision.unwrap_or_default(),
+            ..call
+        }))
+    }
+
+    /// Apply `change` to the latest state of a call and store the result,
+    /// starting over from a fresh read whenever another instance stored the
+    /// call in between. `change` may run several times and should only touch
+    /// the call; an error from it aborts without writing anything.
+    pub async fn modify<T>(
+        id: &String,
+        change: impl FnMut(&mut ActiveCall) -> Result<T>,
+    ) -> Result<(ActiveCall, T)> {
+        let (call, output) = Self::write(id, change).await?;
+        call.record_members().await?;
+        Ok((call, output))
+    }
+
+    /// The Redis side of [`ActiveCall::modify`], leaving the stored call's
+    /// participant list alone.
+    async fn write<T>(
+        id: &String,
+        mut change: impl FnMut(&mut ActiveCall) -> Result<T>,
+    ) -> Result<(ActiveCall, T)> {
+        let mut redis = get_connection().await;
+        for attempt in 1..=MAX_CALL_WRITE_ATTEMPTS {
+            let mut call = Self::get(id).await?.ok_or(Error::NotFound)?;
+            let output = change(&mut call)?;
+            let empty_since = match call.empty_since {
+                Some(time) if call.members.is_empty() => time.to_string(),
+                _ => String::new(),
+            };
+            let revision: i64 = UPDATE_CALL
+                .key(call_key(id))
+                .key(revision_key(id))
+                .key("voice:empty-calls")
+                .arg(call.revision)
+                .arg(&call)
+                .arg(id)
+                .arg(empty_since)
+                .invoke_async(&mut redis)
+                .await?;
+            match revision {
+                -1 => return Err(Error::NotFound),
+                -2 => {
+                    // back off a little so contending instances spread out
+                    let delay = rand::rng().random_range(0..=5 * attempt as u64);
+                    time::sleep(Duration::from_millis(delay)).await;
+                }
+                revision => {
+                    call.revision = revision as u64;
+                    return Ok((call, output));
+                }
+            }
         }
+        tracing::error!(
+            "Gave up writing call {} after {} conflicting attempts",
+            id,
+            MAX_CALL_WRITE_ATTEMPTS
+        );
+        Err(Error::InternalError)
+    }
 
+    /// Keep the stored call's participant list up to date.
+    async fn record_members(&self) -> Result<()> {
         let member_ids: Vec<String> = self
             .members
             .iter()
             .map(|session| session.user_id.clone())
             .collect();
-        if let Err(e) = Call::update(&self.id.to_string(), member_ids.clone()).await {
+        if let Err(e) = Call::update(&self.id.to_string(), member_ids).await {
             tracing::error!("Failed to update call {} in database: {:?}", self.id, e);
             return Err(e);
         }
         Ok(())
     }
 
-    pub async fn create_token(
+    /// What managers restricted `user_id` to in this call.
+    pub fn restrictions_for(&self, user_id: &str) -> SessionRestrictions {
+        self.restricted.get(user_id).copied().unwrap_or_default()
+    }
+
+    /// Restrict a user in this call, replacing what they had before. Applies
+    /// to each of their sessions and to any they open later. Returns their
+    /// connected sessions.
+    pub fn restrict(
         &mut self,
         user_id: &str,
-        initial_muted: bool,
-        initial_deafened: bool,
-    ) -> Result<(String, String)> {
-        let session_id = ulid::Ulid::new().to_string();
-        self.pending_sessions.push(CallSession {
-            id: session_id.clone(),
+        restrictions: SessionRestrictions,
+    ) -> Vec<CallSession> {
+        if restrictions == SessionRestrictions::default() {
+            self.restricted.remove(user_id);
+        } else {
+            self.restricted.insert(user_id.to_string(), restrictions);
+        }
+        for session in self
+            .members
+            .iter_mut()
+            .chain(self.pending_sessions.iter_mut())
+            .filter(|s| s.user_id == user_id)
+        {
+            session.restrictions = restrictions;
+        }
+        self.members
+            .iter()
+            .filter(|s| s.user_id == user_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Add a session that is about to connect to the pending sessions. It
+    /// starts out with whatever managers restricted the user to.
+    fn expect_session(
+        &mut self,
+        id: &str,
+        user_id: &str,
+        muted: bool,
+        deafened: bool,
+        relay: Option<String>,
+    ) -> CallSession {
+        let session = CallSession {
+            id: id.to_string(),
             user_id: user_id.to_string(),
             call_id: self.id.clone(),
-            muted: initial_muted,
-            deafened: initial_deafened,
+            muted,
+            deafened,
+            migrating: false,
+            restrictions: self.restrictions_for(user_id),
+            relay,
+        };
+        self.pending_sessions.push(session.clone());
+        session
+    }
+
+    /// Move a pending session to the members once it connected.
+    fn admit_pending(&mut self, session_id: &str) -> Result<CallSession> {
+        let index = self
+            .pending_sessions
+            .iter()
+            .position(|s| s.id == session_id)
+            .ok_or(Error::NotFound)?;
+        let session = self.pending_sessions.remove(index);
+        // a rerouted session takes the place of its old connection
+        self.members.retain(|s| s.id != session.id);
+        self.members.push(CallSession {
+            migrating: false,
+            ..session.clone()
         });
-        self.update().await?;
+        self.empty_since = None;
+        Ok(session)
+    }
+
+    /// Change the mute and deafen state of `user_id`'s session. Returns the
+    /// session and whether anything changed.
+    pub fn set_voice_state(
+        &mut self,
+        user_id: &str,
+        muted: Option<bool>,
+        deafened: Option<bool>,
+    ) -> Result<(CallSession, bool)> {
+        let session = self
+            .members
+            .iter_mut()
+            .find(|s| s.user_id == user_id)
+            .ok_or(Error::NotFound)?;
+        // a manager has to lift these first
+        if (muted == Some(false) && session.restrictions.muted)
+            || (deafened == Some(false) && session.restrictions.deafened)
+        {
+            return Err(Error::MissingPermission);
+        }
+        let muted_changed = muted.is_some_and(|new_muted| new_muted != session.muted);
+        let deafened_changed =
+            deafened.is_some_and(|new_deafened| new_deafened != session.deafened);
+        if let Some(new_muted) = muted {
+            session.muted = new_muted;
+        }
+        if let Some(new_deafened) = deafened {
+            session.deafened = new_deafened;
+        }
+        Ok((session.clone(), muted_changed || deafened_changed))
+    }
+
+    /// Drop a connected session, noting when the call became empty. Returns
+    /// the node the session was relayed through.
+    fn remove_session(&mut self, session_id: &str, time: i64) -> Option<String> {
+        let relay = self
+            .members
+            .iter()
+            .find(|x| x.id == session_id)
+            .and_then(|x| x.relay.clone());
+        self.members.retain(|x| x.id != session_id);
+        if self.members.is_empty() && self.empty_since.is_none() {
+            self.empty_since = Some(time);
+        }
+        relay
+    }
+
+    /// Expect a new session and issue its token. It connects through the
+    /// node nearest `region` if that is much closer than the call's own.
+    /// Returns the session ID, the token and the address to connect to.
+    pub async fn create_token(
+        &mut self,
+        user_id: &str,
+        initial_muted: bool,
+        initial_deafened: bool,
+        permissions: PermissionSet,
+        region: Option<Region>,
+    ) -> Result<(String, String, String)> {
+        let id = ulid::Ulid::new().to_string();
+        let relay = region.and_then(|region| self.relay_node(region));
+        let session;
+        (*self, session) = Self::modify(&self.id, |call| {
+            Ok(call.expect_session(
+                &id,
+                user_id,
+                initial_muted,
+                initial_deafened,
+                relay.as_ref().map(|node| node.id.clone()),
+            ))
+        })
+        .await?;
+        if let Some(relay) = &relay {
+            self.index_relayed(&relay.id).await?;
+        }
+
+        let token = self
+            .issue_token(&session, permissions, relay.as_ref())
+            .await?;
+        let server_address = relay
+            .map(|node| node.server_address)
+            .unwrap_or_else(|| self.server_address.clone());
+        Ok((session.id, token, server_address))
+    }
+
+    /// The node to relay a user in `region` through: the nearest healthy one
+    /// that isn't overloaded, as long as it saves enough distance over the
+    /// call's node.
+    fn relay_node(&self, region: Region) -> Option<Node> {
+        let home_region = AVAILABLE_NODES.get(&self.assigned_node)?.region;
+        let time = chrono::Utc::now().timestamp_millis();
+        let node = AVAILABLE_NODES
+            .iter()
+            .filter(|n| n.accepts_calls(time) && !n.overloaded() && n.id != self.assigned_node)
+            .map(|n| n.value().clone())
+            .min_by(|a, b| {
+                region
+                    .distance_km(&a.region)
+                    .total_cmp(&region.distance_km(&b.region))
+                    .then(a.cost().total_cmp(&b.cost()))
+            })?;
+        let saved = region.distance_km(&home_region) - region.distance_km(&node.region);
+        (saved >= MIN_RELAY_SAVING_KM).then_some(node)
+    }
+
+    /// Let sessions that were connected before a migration rejoin on the new
+    /// node under their current IDs. Returns each session with its token.
+    pub async fn readmit(
+        &mut self,
+        previous: Vec<CallSession>,
+        permissions: impl Fn(&str) -> PermissionSet,
+    ) -> Result<Vec<(CallSession, String)>> {
+        if previous.is_empty() {
+            return Ok(vec![]);
+        }
+        let sessions;
+        (*self, sessions) = Self::modify(&self.id, |call| {
+            let sessions: Vec<CallSession> = previous
+                .iter()
+                .map(|session| CallSession {
+                    restrictions: call.restrictions_for(&session.user_id),
+                    relay: None,
+                    ..session.clone()
+                })
+                .collect();
+            for session in &sessions {
+                call.pending_sessions.push(CallSession {
+                    migrating: true,
+                    ..session.clone()
+                });
+            }
+            Ok(sessions)
+        })
+        .await?;
+
+        let mut tokens = Vec::with_capacity(sessions.len());
+        for session in sessions {
+            let token = self
+                .issue_token(&session, permissions(&session.user_id), None)
+                .await?;
+            tokens.push((session, token));
+        }
+        Ok(tokens)
+    }
 
+    async fn issue_token(
+        &self,
+        session: &CallSession,
+        permissions: PermissionSet,
+        relay: Option<&Node>,
+    ) -> Result<String> {
         let token = generate_token();
+        let grants = session.grants(permissions);
         let mut redis = get_connection().await;
         redis
             .set_ex::<String, SessionData, ()>(
                 format!("session:{}", token),
                 SessionData {
                     call_id: self.id.clone(),
-                    session_id: session_id.clone(),
-                    assigned_server: self.assigned_node.clone(),
-
-                    can_listen: !initial_deafened,
-                    can_speak: !initial_muted,
-                    // TODO:!! support limiting video/screen
-                    can_screen: true,
-                    can_video: true,
+                    session_id: session.id.clone(),
+                    user_id: session.user_id.clone(),
+                    assigned_server: relay
+                        .map(|node| node.id.clone())
+                        .unwrap_or_else(|| self.assigned_node.clone()),
+
+                    can_listen: grants.can_listen,
+                    can_speak: grants.can_speak,
+                    can_screen: grants.can_screen,
+                    can_video: grants.can_video,
+                    home: relay.map(|_| HomeNode {
+                        id: self.assigned_node.clone(),
+                        server_address: self.server_address.clone(),
+                    }),
                 },
                 60,
             )
             .await?;
-        Ok((session_id, token))
+        Ok(token)
     }
 
+    /// Reassign the call from `old_node` to another node. Nobody is connected
+    /// to it there yet, so the members are dropped and returned; unused
+    /// tokens for the old node are abandoned. Returns `None` if the call had
+    /// already left `old_node`, e.g. because another instance moved it.
     pub async fn migrate_to(
         &mut self,
         old_node: &str,
         node_id: &str,
         server_address: &str,
-    ) -> Result<()> {
-        let mut redis = get_connection().await;
-        self.assigned_node = node_id.to_string();
-        self.server_address = server_address.to_string();
-        self.members.clear();
-        self.pending_sessions.clear();
+    ) -> Result<Option<Vec<CallSession>>> {
         let time = chrono::Utc::now().timestamp_millis();
-        self.empty_since = Some(time);
+        let (call, members) = Self::modify(&self.id, |call| {
+            if call.assigned_node != old_node {
+                return Ok(None);
+            }
+            call.assigned_node = node_id.to_string();
+            call.server_address = server_address.to_string();
+            call.pending_sessions.clear();
+            call.empty_since = Some(time);
+            Ok(Some(std::mem::take(&mut call.members)))
+        })
+        .await?;
+        *self = call;
+        if members.is_none() {
+            return Ok(None);
+        }
 
-        redis
-            .set::<String, ActiveCall, ()>(format!("call:{}", self.id), self.clone())
-            .await?;
-        redis
-            .zadd::<_, _, _, ()>("voice:empty-calls", &self.id, time)
-            .await?;
+        let mut redis = get_connection().await;
         redis
             .srem::<_, _, ()>(format!("node:{}:calls", old_node), &self.id)
             .await?;
         redis
             .sadd::<_, _, ()>(format!("node:{}:calls", node_id), &self.id)
             .await?;
-        Ok(())
+        Ok(members)
     }
 
-    pub async fn leave_user(&mut self, session_id: &String) -> Result<()> {
-        self.members.retain(|x| x.id != *session_id);
-        if self.members.is_empty() {
-            let time = chrono::Utc::now().timestamp_millis();
-            self.empty_since = Some(time);
+    /// Move a session that connected to the node from pending to members.
+    pub async fn admit(&mut self, session_id: &str) -> Result<CallSession> {
+        let session;
+        (*self, session) = Self::modify(&self.id, |call| call.admit_pending(session_id)).await?;
+        Ok(session)
+    }
+
+    /// Move a connected session from this call to another. It is expected in
+    /// `target` before it leaves this call, so it is always in one of them;
+    /// the node still reports it leaving once it reconnects. Returns the
+    /// session's token for `target`.
+    pub async fn move_session(
+        &mut self,
+        session_id: &str,
+        target: &mut ActiveCall,
+        permissions: PermissionSet,
+    ) -> Result<String> {
+        let session = self
+            .members
+            .iter()
+            .find(|s| s.id == session_id)
+            .cloned()
+            .ok_or(Error::NotFound)?;
+        let moved;
+        (*target, moved) = Self::modify(&target.id, |call| {
+            // restrictions follow the user into the other room
+            if session.restrictions != SessionRestrictions::default() {
+                call.restricted
+                    .insert(session.user_id.clone(), session.restrictions);
+            }
+            let moved = CallSession {
+                call_id: call.id.clone(),
+                restrictions: call.restrictions_for(&session.user_id),
+                relay: None,
+                ..session.clone()
+            };
+            call.pending_sessions.push(moved.clone());
+            Ok(moved)
+        })
+        .await?;
+
+        let time = chrono::Utc::now().timestamp_millis();
+        let left = Self::modify(&self.id, |call| {
+            call.remove_session(session_id, time);
+            Ok(())
+        })
+        .await;
+        match left {
+            Ok((call, _)) => *self = call,
+            Err(e) => {
+                let _ = Self::modify(&target.id, |call| {
+                    call.pending_sessions.retain(|s| s.id != session_id);
+                    Ok(())
+                })
+                .await;
+                return Err(e);
+            }
+        }
+
+        target.issue_token(&moved, permissions, None).await
+    }
+
+    pub async fn leave_user(&mut self, session_id: &str) -> Result<()> {
+        let time = chrono::Utc::now().timestamp_millis();
+        let relay;
+        (*self, relay) =
+            Self::modify(&self.id, |call| Ok(call.remove_session(session_id, time))).await?;
+        if let Some(relay) = relay
+            && !self.relays_through(&relay)
+        {
             let mut redis = get_connection().await;
             redis
-                .zadd::<_, _, _, ()>("voice:empty-calls", &self.id, time)
+                .srem::<_, _, ()>(relayed_key(&relay), &self.id)
                 .await?;
         }
-        self.update().await?;
-
         Ok(())
     }
 
-    pub async fn end(&self) -> Result<()> {
+    /// Whether any session of the call connects through `relay`.
+    fn relays_through(&self, relay: &str) -> bool {
+        self.members
+            .iter()
+            .chain(&self.pending_sessions)
+            .any(|s| s.relay.as_deref() == Some(relay))
+    }
+
+    /// Note that the call has sessions relayed by `relay`, so they can be
+    /// rerouted when it drains.
+    async fn index_relayed(&self, relay: &str) -> Result<()> {
         let mut redis = get_connection().await;
+        redis.sadd::<_, _, ()>(relayed_key(relay), &self.id).await?;
+        Ok(())
+    }
 
-        redis
-            .del::<std::string::String, ()>(format!("call:channel:{}", self.channel_id))
-            .await?;
-        redis
-            .del::<std::string::String, ()>(format!("call:{}", self.id))
-            .await?;
-        redis
-            .zrem::<_, _, ()>("voice:empty-calls", &self.id)
+    /// Have the sessions relayed by a draining node reconnect through another
+    /// node near it, or straight to the call's node, under their current IDs.
+    /// Returns each session with the address to connect to and its token.
+    pub async fn reroute(
+        &mut self,
+        old_relay: &str,
+        region: Region,
+        permissions: impl Fn(&str) -> PermissionSet,
+    ) -> Result<Vec<(CallSession, String, String)>> {
+        let relay = self.relay_node(region).filter(|node| node.id != old_relay);
+        let sessions;
+        (*self, sessions) = Self::modify(&self.id, |call| {
+            let sessions: Vec<CallSession> = call
+                .members
+                .iter()
+                .filter(|s| s.relay.as_deref() == Some(old_relay))
+                // already on their way
+                .filter(|s| !call.pending_sessions.iter().any(|p| p.id == s.id))
+                .map(|s| CallSession {
+                    migrating: true,
+                    restrictions: call.restrictions_for(&s.user_id),
+                    relay: relay.as_ref().map(|node| node.id.clone()),
+                    ..s.clone()
+                })
+                .collect();
+            call.pending_sessions.extend(sessions.iter().cloned());
+            Ok(sessions)
+        })
+        .await?;
+        if sessions.is_empty() {
+            return Ok(vec![]);
+        }
+        if let Some(relay) = &relay {
+            self.index_relayed(&relay.id).await?;
+        }
+
+        let server_address = relay
+            .as_ref()
+            .map(|node| node.server_address.clone())
+            .unwrap_or_else(|| self.server_address.clone());
+        let mut tokens = Vec::with_capacity(sessions.len());
+        for session in sessions {
+            let token = self
+                .issue_token(&session, permissions(&session.user_id), relay.as_ref())
+                .await?;
+            tokens.push((session, server_address.clone(), token));
+        }
+        Ok(tokens)
+    }
+
+    /// Drop a user from the call: their sessions are disconnected by the
+    /// node, which reports back as usual, and unused tokens are revoked.
+    pub async fn disconnect_user(&mut self, user_id: &str) -> Result<()> {
+        if self.pending_sessions.iter().any(|s| s.user_id == user_id) {
+            (*self, _) = Self::modify(&self.id, |call| {
+                call.pending_sessions.retain(|s| s.user_id != user_id);
+                Ok(())
+            })
             .await?;
-        redis
-            .srem::<_, _, ()>(format!("node:{}:calls", self.assigned_node), &self.id)
+        }
+        for session in self.members.iter().filter(|s| s.user_id == user_id) {
+            nats::publish_node_event(
+                subject_node(&self.assigned_node),
+                &NodeEvent {
+                    id: INSTANCE_ID.clone(),
+                    event: NodeEventKind::UserDisconnect {
+                        id: session.id.clone(),
+                        call_id: self.id.clone(),
+                    },
+                },
+            )
+            .await;
+        }
+        Ok(())
+    }
+
+    /// End the call for good: it is not restored if its node still reports it.
+    pub async fn end(&self) -> Result<()> {
+        self.end_call(None, Some(ENDED_MARKER_SECONDS)).await?;
+        Ok(())
+    }
+
+    /// End the call unless it changed since it was read. Returns whether it
+    /// was ended.
+    pub async fn end_if_unchanged(&self) -> Result<bool> {
+        self.end_call(Some(self.revision), None).await
+    }
+
+    async fn end_call(&self, revision: Option<u64>, ended_marker: Option<u64>) -> Result<bool> {
+        let mut redis = get_connection().await;
+        let ended: bool = END_CALL
+            .key(rooms_key(&self.channel_id))
+            .key(call_key(&self.id))
+            .key(revision_key(&self.id))
+            .key("voice:empty-calls")
+            .key(format!("node:{}:calls", self.assigned_node))
+            .key(ended_key(&self.id))
+            .arg(&self.id)
+            .arg(revision.map(|r| r.to_string()).unwrap_or_default())
+            .arg(ended_marker.map(|s| s.to_string()).unwrap_or_default())
+            .arg(self.name.as_deref().unwrap_or_default())
+            .invoke_async(&mut redis)
             .await?;
+        if !ended {
+            return Ok(false);
+        }
 
-        let member_ids: Vec<String> = self
-            .members
-            .iter()
-            .map(|session| session.user_id.clone())
-            .collect();
-        Call::update(&self.id, member_ids).await?;
+        self.record_members().await?;
+
+        let channel = Channel::get(&self.channel_id).await.ok();
+        if let Err(e) = ringing::stop_all(&self.id, channel.as_ref()).await {
+            tracing::error!("Failed to stop ringing for call {}: {:?}", self.id, e);
+        }
+        if let Some(channel) = channel {
+            let started_at = ulid::Ulid::from_string(&self.id)
+                .map(|id| id.timestamp_ms())
+                .unwrap_or_default();
+            let duration =
+                (chrono::Utc::now().timestamp_millis() as u64).saturating_sub(started_at);
+            events::publish(
+                &channel.member_ids(),
+                Event::CallEnded(CallEndedEvent {
+                    call_id: self.id.clone(),
+                    channel_id: self.channel_id.clone(),
+                    duration,
+                }),
+            )
+            .await;
+        }
 
         nats::publish_node_event(
             subject_node(&self.assigned_node),
@@ -692,6 +1825,287 @@ impl ActiveCall {
 
         info!("Call {} ended", self.id);
 
-        Ok(())
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! Call state transitions, and a concurrency harness for storing them
+    //! that runs against a disposable Redis:
+    //! `REDIS_URI=redis://127.0.0.1 cargo test -p harmony -- --ignored`
+
+    use std::sync::{
+        Arc, Once,
+        atomic::{AtomicU64, Ordering},
+    };
+
+    use super::*;
+
+    const USERS: usize = 8;
+    const ROUNDS: usize = 20;
+    const MUTE_TOGGLES: usize = 3;
+
+    /// Connect to the Redis in `REDIS_URI`, or return false if there is none.
+    fn connect() -> bool {
+        static CONNECT: Once = Once::new();
+        if std::env::var("REDIS_URI").is_err() {
+            return false;
+        }
+        CONNECT.call_once(crate::services::redis::connect);
+        true
+    }
+
+    fn new_call(channel_id: &str) -> ActiveCall {
+        ActiveCall {
+            id: ulid::Ulid::new().to_string(),
+            name: None,
+            members: vec![],
+            channel_id: channel_id.to_string(),
+            assigned_node: format!("test-{}", channel_id),
+            server_address: String::new(),
+            empty_since: Some(chrono::Utc::now().timestamp_millis()),
+            pending_sessions: vec![],
+            restricted: HashMap::new(),
+            revision: 0,
+        }
+    }
+
+    async fn remove(call: &ActiveCall) {
+        let mut redis = get_connection().await;
+        let _: () = pipe()
+            .del(rooms_key(&call.channel_id))
+            .del(call_key(&call.id))
+            .del(revision_key(&call.id))
+            .del(format!("node:{}:calls", call.assigned_node))
+            .zrem("voice:empty-calls", &call.id)
+            .query_async(&mut redis)
+            .await
+            .unwrap();
+    }
+
+    /// Write to the call until it goes through, counting every stored write.
+    async fn write_until_stored<T>(
+        id: &String,
+        writes: &AtomicU64,
+        mut change: impl FnMut(&mut ActiveCall) -> Result<T>,
+    ) -> T {
+        loop {
+            match ActiveCall::write(id, &mut change).await {
+                Ok((_, output)) => {
+                    writes.fetch_add(1, Ordering::Relaxed);
+                    return output;
+                }
+                Err(Error::InternalError) => continue,
+                Err(e) => panic!("writing call {} failed: {:?}", id, e),
+            }
+        }
+    }
+
+    /// One user joining, muting and leaving over and over, staying connected
+    /// after the last round. Returns whether they ended up muted.
+    async fn churn(call_id: String, user_id: String, writes: Arc<AtomicU64>) -> bool {
+        let mut muted = false;
+        for round in 0..ROUNDS {
+            let session_id = ulid::Ulid::new().to_string();
+            write_until_stored(&call_id, &writes, |call| {
+                Ok(call.expect_session(&session_id, &user_id, muted, false, None))
+            })
+            .await;
+            write_until_stored(&call_id, &writes, |call| call.admit_pending(&session_id)).await;
+            for _ in 0..MUTE_TOGGLES {
+                muted = !muted;
+                write_until_stored(&call_id, &writes, |call| {
+                    call.set_voice_state(&user_id, Some(muted), None)
+                })
+                .await;
+            }
+            if round + 1 < ROUNDS {
+                let time = chrono::Utc::now().timestamp_millis();
+                write_until_stored(&call_id, &writes, |call| {
+                    Ok(call.remove_session(&session_id, time))
+                })
+                .await;
+            }
+        }
+        muted
+    }
+
+    fn restricted(muted: bool, deafened: bool) -> SessionRestrictions {
+        SessionRestrictions {
+            muted,
+            deafened,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn join_admits_pending_session() {
+        let mut call = new_call("channel");
+        call.restricted
+            .insert("user".to_string(), restricted(true, false));
+        let pending = call.expect_session("session", "user", false, true, None);
+        assert_eq!(pending.call_id, call.id);
+        assert_eq!(pending.restrictions, restricted(true, false));
+        assert!(call.members.is_empty());
+        assert!(call.empty_since.is_some());
+
+        let admitted = call.admit_pending("session").unwrap();
+        assert_eq!(admitted.id, "session");
+        assert!(call.pending_sessions.is_empty());
+        assert_eq!(call.members.len(), 1);
+        assert!(call.members[0].deafened);
+        assert!(call.empty_since.is_none());
+        assert!(matches!(
+            call.admit_pending("session"),
+            Err(Error::NotFound)
+        ));
+    }
+
+    #[test]
+    fn rerouted_session_replaces_its_connection() {
+        let mut call = new_call("channel");
+        call.expect_session("session", "user", false, false, None);
+        call.admit_pending("session").unwrap();
+        call.expect_session("session", "user", false, false, Some("relay".to_string()));
+        call.pending_sessions[0].migrating = true;
+
+        let admitted = call.admit_pending("session").unwrap();
+        assert!(admitted.migrating);
+        assert_eq!(call.members.len(), 1);
+        assert!(!call.members[0].migrating);
+        assert_eq!(call.members[0].relay.as_deref(), Some("relay"));
+    }
+
+    #[test]
+    fn voice_state_reports_changes() {
+        let mut call = new_call("channel");
+        call.expect_session("session", "user", false, false, None);
+        call.admit_pending("session").unwrap();
+
+        let (session, changed) = call.set_voice_state("user", Some(true), None).unwrap();
+        assert!(changed && session.muted && !session.deafened);
+        let (_, changed) = call
+            .set_voice_state("user", Some(true), Some(false))
+            .unwrap();
+        assert!(!changed);
+        let (session, changed) = call.set_voice_state("user", None, Some(true)).unwrap();
+        assert!(changed && session.muted && session.deafened);
+        assert!(call.members[0].deafened);
+        assert!(matches!(
+            call.set_voice_state("someone-else", Some(true), None),
+            Err(Error::NotFound)
+        ));
+    }
+
+    #[test]
+    fn voice_state_keeps_restrictions() {
+        let mut call = new_call("channel");
+        call.expect_session("session", "user", true, true, None);
+        call.admit_pending("session").unwrap();
+        call.restrict("user", restricted(true, true));
+
+        assert!(matches!(
+            call.set_voice_state("user", Some(false), None),
+            Err(Error::MissingPermission)
+        ));
+        assert!(matches!(
+            call.set_voice_state("user", None, Some(false)),
+            Err(Error::MissingPermission)
+        ));
+        assert!(call.members[0].muted && call.members[0].deafened);
+
+        call.restrict("user", SessionRestrictions::default());
+        let (session, changed) = call.set_voice_state("user", Some(false), None).unwrap();
+        assert!(changed && !session.muted);
+    }
+
+    #[test]
+    fn leaving_marks_call_empty_once() {
+        let mut call = new_call("channel");
+        for (session_id, relay) in [("first", Some("relay".to_string())), ("second", None)] {
+            call.expect_session(session_id, session_id, false, false, relay);
+            call.admit_pending(session_id).unwrap();
+        }
+
+        assert_eq!(call.remove_session("first", 10).as_deref(), Some("relay"));
+        assert!(call.empty_since.is_none());
+        assert_eq!(call.remove_session("second", 20), None);
+        assert_eq!(call.empty_since, Some(20));
+        assert_eq!(call.remove_session("second", 30), None);
+        assert_eq!(call.empty_since, Some(20));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore = "needs a Redis server in REDIS_URI"]
+    async fn concurrent_join_leave_mute_keeps_every_change() {
+        if !connect() {
+            return;
+        }
+        let call = new_call(&ulid::Ulid::new().to_string());
+        assert!(call.insert(call.empty_since).await.unwrap());
+
+        let writes = Arc::new(AtomicU64::new(0));
+        let tasks: Vec<_> = (0..USERS)
+            .map(|i| {
+                let user_id = format!("user-{}", i);
+                let task = task::spawn(churn(call.id.clone(), user_id.clone(), writes.clone()));
+                (user_id, task)
+            })
+            .collect();
+        let mut expected = Vec::with_capacity(USERS);
+        for (user_id, task) in tasks {
+            expected.push((user_id, task.await.unwrap()));
+        }
+
+        let stored = ActiveCall::get(&call.id).await.unwrap().unwrap();
+        remove(&call).await;
+        assert_eq!(stored.revision, writes.load(Ordering::Relaxed));
+        assert!(stored.pending_sessions.is_empty());
+        assert!(stored.empty_since.is_none());
+        assert_eq!(stored.members.len(), USERS);
+        for (user_id, muted) in expected {
+            let sessions: Vec<_> = stored
+                .members
+                .iter()
+                .filter(|s| s.user_id == user_id)
+                .collect();
+            assert_eq!(sessions.len(), 1, "{} should have one session", user_id);
+            assert_eq!(sessions[0].muted, muted, "{} lost a mute", user_id);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore = "needs a Redis server in REDIS_URI"]
+    async fn concurrent_starts_open_one_call_per_room() {
+        if !connect() {
+            return;
+        }
+        let channel_id = ulid::Ulid::new().to_string();
+        let tasks: Vec<_> = (0..USERS)
+            .map(|_| {
+                let call = new_call(&channel_id);
+                task::spawn(async move {
+                    let inserted = call.insert(call.empty_since).await.unwrap();
+                    (call, inserted)
+                })
+            })
+            .collect();
+        let mut started = Vec::new();
+        let mut calls = Vec::new();
+        for task in tasks {
+            let (call, inserted) = task.await.unwrap();
+            if inserted {
+                started.push(call.id.clone());
+            }
+            calls.push(call);
+        }
+
+        let open = ActiveCall::get_in_channel(&channel_id).await.unwrap();
+        for call in &calls {
+            remove(call).await;
+        }
+        assert_eq!(started.len(), 1);
+        assert_eq!(open.map(|call| call.id), started.pop());
     }
 }
@@ -6,11 +6,13 @@ use moq_native::moq_net::{
     self, BroadcastProducer, GroupProducer, Origin, OriginProducer, Track, TrackProducer,
 };
 use pulse_types::{
-    AvailableTrack, ControlC2S, ControlS2C, MediaHint, priority_for_hint, track_name_for_hint,
+    AudioLevel, AvailableTrack, ControlC2S, ControlS2C, MAX_LAYERS, MEDIA_HEADER_VERSION,
+    MediaHint, layer_track_name, priority_for_hint, supports_layers, track_name_for_hint,
     track_names,
 };
 use tokio::sync::{Mutex, mpsc, oneshot};
 
+use crate::congestion::ReceptionStats;
 use crate::error::PulseError;
 use crate::events::PulseEvent;
 use crate::mls::{MlsClient, MlsError, MlsIdentity};
@@ -41,12 +43,18 @@ pub struct MediaFrame {
 #[derive(Clone, Debug)]
 pub struct TrackHandle {
     media_hint: MediaHint,
+    layers: u8,
 }
 
 impl TrackHandle {
     pub fn media_hint(&self) -> &MediaHint {
         &self.media_hint
     }
+
+    /// Number of simulcast layers the track is published in.
+    pub fn layers(&self) -> u8 {
+        self.layers
+    }
 }
 
 const MAX_GROUP_BACKLOG: u64 = 3;
@@ -54,6 +62,8 @@ const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
 const MAX_RECONNECT_ATTEMPTS: u32 = 5;
 const ERROR_EVENT_INTERVAL: Duration = Duration::from_secs(5);
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+const PING_INTERVAL: Duration = Duration::from_secs(2);
 
 /// Handle to a connected Pulse session.
 #[derive(Clone)]
@@ -63,10 +73,19 @@ pub struct PulseClient {
     session_id: String,
 }
 
+struct OutgoingFrame {
+    layer: u8,
+    capture_ts_us: u64,
+    keyframe: bool,
+    audio_level: Option<AudioLevel>,
+    data: Vec<u8>,
+}
+
 enum ClientCommand {
     SendCtl(ControlC2S),
     StartProduce {
         media_hint: MediaHint,
+        layers: u8,
         reply: oneshot::Sender<Result<TrackHandle, PulseError>>,
     },
     StopProduce {
@@ -75,12 +94,11 @@ enum ClientCommand {
     },
     WriteMedia {
         media_hint: MediaHint,
-        capture_ts_us: u64,
-        keyframe: bool,
-        data: Vec<u8>,
+        frame: OutgoingFrame,
     },
     StartConsume {
         track: AvailableTrack,
+        layer: u8,
         sink: mpsc::UnboundedSender<MediaFrame>,
     },
     StopConsume {
@@ -126,9 +144,31 @@ impl PulseClient {
     ///
     /// At most one track per [`MediaHint`] can be produced per session.
     pub async fn produce_track(&self, media_hint: MediaHint) -> Result<TrackHandle, PulseError> {
+        self.produce_simulcast_track(media_hint, 1).await
+    }
+
+    /// Start producing a video track in up to [`MAX_LAYERS`] simulcast
+    /// layers, each its own MoQ track, so receivers can pick the quality they
+    /// can handle. Write each layer with [`PulseClient::send_layer_media`].
+    ///
+    /// Audio tracks are always produced in a single layer.
+    pub async fn produce_simulcast_track(
+        &self,
+        media_hint: MediaHint,
+        layers: u8,
+    ) -> Result<TrackHandle, PulseError> {
+        let layers = if supports_layers(&media_hint) {
+            layers.clamp(1, MAX_LAYERS)
+        } else {
+            1
+        };
         let (reply, reply_rx) = oneshot::channel();
         self.command_tx
-            .send(ClientCommand::StartProduce { media_hint, reply })
+            .send(ClientCommand::StartProduce {
+                media_hint,
+                layers,
+                reply,
+            })
             .map_err(|_| PulseError::Disconnected)?;
         tokio::time::timeout(REQUEST_TIMEOUT, reply_rx)
             .await
@@ -161,13 +201,30 @@ impl PulseClient {
         &self,
         track: &AvailableTrack,
     ) -> Result<mpsc::UnboundedReceiver<MediaFrame>, PulseError> {
+        self.consume_track_layer(track, 0).await
+    }
+
+    /// Subscribe to a remote track starting at the given simulcast layer,
+    /// 0 being the best. The stream switches layers on its own whenever the
+    /// server reports a [`PulseEvent::LayerSelected`].
+    pub async fn consume_track_layer(
+        &self,
+        track: &AvailableTrack,
+        layer: u8,
+    ) -> Result<mpsc::UnboundedReceiver<MediaFrame>, PulseError> {
+        let layer = layer.min(track.layers.saturating_sub(1));
         let (sink, rx) = mpsc::unbounded_channel();
         self.command_tx
             .send(ClientCommand::StartConsume {
                 track: track.clone(),
+                layer,
                 sink,
             })
             .map_err(|_| PulseError::Disconnected)?;
+        // start the server's layer adaptation from the one we picked
+        if track.layers > 1 {
+            self.select_layer(&track.id, layer)?;
+        }
 
         if matches!(track.media_hint, MediaHint::Video | MediaHint::ScreenVideo) {
             self.request_keyframe(&track.id).ok();
@@ -190,12 +247,57 @@ impl PulseClient {
         keyframe: bool,
         data: &[u8],
     ) -> Result<(), PulseError> {
-        self.command_tx
-            .send(ClientCommand::WriteMedia {
-                media_hint: handle.media_hint.clone(),
+        self.send_layer_media(handle, 0, capture_ts_us, keyframe, data)
+    }
+
+    /// Write an encoded access unit for one simulcast layer of a track.
+    pub fn send_layer_media(
+        &self,
+        handle: &TrackHandle,
+        layer: u8,
+        capture_ts_us: u64,
+        keyframe: bool,
+        data: &[u8],
+    ) -> Result<(), PulseError> {
+        self.queue_media(
+            handle,
+            OutgoingFrame {
+                layer,
                 capture_ts_us,
                 keyframe,
+                audio_level: None,
                 data: data.to_vec(),
+            },
+        )
+    }
+
+    /// Write an encoded microphone frame along with its audio level, which
+    /// Pulse reads from the frame header to detect who is speaking. Every
+    /// audio frame is a keyframe.
+    pub fn send_audio(
+        &self,
+        handle: &TrackHandle,
+        capture_ts_us: u64,
+        audio_level: AudioLevel,
+        data: &[u8],
+    ) -> Result<(), PulseError> {
+        self.queue_media(
+            handle,
+            OutgoingFrame {
+                layer: 0,
+                capture_ts_us,
+                keyframe: true,
+                audio_level: Some(audio_level),
+                data: data.to_vec(),
+            },
+        )
+    }
+
+    fn queue_media(&self, handle: &TrackHandle, frame: OutgoingFrame) -> Result<(), PulseError> {
+        self.command_tx
+            .send(ClientCommand::WriteMedia {
+                media_hint: handle.media_hint.clone(),
+                frame,
             })
             .map_err(|_| PulseError::Disconnected)
     }
@@ -209,7 +311,20 @@ impl PulseClient {
             .map_err(|_| PulseError::Disconnected)
     }
 
-    /// Send a periodic receiver report so the producer can adapt. Fire-and-forget.
+    /// Ask to receive `layer` of a simulcast track, 0 being the best. The
+    /// server confirms with [`PulseEvent::LayerSelected`] and may step down
+    /// from it while our receiver reports show congestion. Fire-and-forget.
+    pub fn select_layer(&self, track_id: &str, layer: u8) -> Result<(), PulseError> {
+        self.command_tx
+            .send(ClientCommand::SendCtl(ControlC2S::SelectLayer {
+                track_id: track_id.to_string(),
+                layer,
+            }))
+            .map_err(|_| PulseError::Disconnected)
+    }
+
+    /// Send a periodic receiver report so the producer can adapt, and so the
+    /// server can pick a simulcast layer for us. Fire-and-forget.
     pub fn send_receiver_report(
         &self,
         track_id: &str,
@@ -243,11 +358,24 @@ impl PulseClient {
 
 struct MediaProducer {
     _broadcast: BroadcastProducer,
+    layers: Vec<LayerProducer>, // best first
+    media_hint: MediaHint,
+    server_track_id: Option<String>,
+}
+
+/// One simulcast layer of a produced track.
+struct LayerProducer {
+    name: String,
     track: TrackProducer,
     group: Option<GroupProducer>,
     next_group_seq: u64,
-    media_hint: MediaHint,
-    server_track_id: Option<String>,
+}
+
+struct MediaConsumer {
+    track: AvailableTrack,
+    layer: u8,
+    sink: mpsc::UnboundedSender<MediaFrame>,
+    task: tokio::task::JoinHandle<()>,
 }
 
 enum PendingKind {
@@ -273,9 +401,14 @@ struct SessionCtx {
     s2c_rx: mpsc::UnboundedReceiver<ControlS2C>,
     buffered: Vec<ControlS2C>,
     producers: HashMap<&'static str, MediaProducer>, // track name -> producer
-    consumers: HashMap<String, tokio::task::JoinHandle<()>>, // global track id -> task
+    consumers: HashMap<String, MediaConsumer>,       // global track id -> consumer
     pending: HashMap<u64, PendingRequest>,
     last_crypto_error: Option<Instant>,
+    // consumers send their receiver reports through here
+    feedback_tx: mpsc::UnboundedSender<ControlC2S>,
+    feedback_rx: mpsc::UnboundedReceiver<ControlC2S>,
+    // pings carry their send time relative to this
+    ping_epoch: Instant,
 }
 
 /// State that survives reconnects.
@@ -283,7 +416,7 @@ struct Shared {
     options: PulseClientOptions,
     mls: Arc<Mutex<MlsClient>>,
     event_tx: mpsc::UnboundedSender<PulseEvent>,
-    active_hints: Vec<MediaHint>,
+    active_hints: Vec<(MediaHint, u8)>, // with their simulcast layers
     next_request_id: u64,
 }
 
@@ -296,6 +429,13 @@ enum SessionEnd {
     /// The connection was lost, optionally with a migration target
     /// `(server_url, token)` to reconnect to.
     Lost { redirect: Option<(String, String)> },
+    /// The server moved us to another call, hosted at `server_url` if it
+    /// isn't on the same node.
+    Moved {
+        call_id: String,
+        token: String,
+        server_url: Option<String>,
+    },
 }
 
 async fn supervisor(
@@ -341,11 +481,12 @@ async fn supervisor(
                 return;
             }
             SessionEnd::Lost { redirect } => {
+                let migrated = redirect.is_some();
                 if let Some((server_url, token)) = redirect {
                     shared.options.server_url = server_url;
                     shared.options.session_token = token;
                 }
-                let Some(new_ctx) = reconnect(&shared).await else {
+                let Some(new_ctx) = resume(&shared, migrated).await else {
                     shared
                         .event_tx
                         .send(PulseEvent::Disconnected {
@@ -355,14 +496,72 @@ async fn supervisor(
                     return;
                 };
                 ctx = new_ctx;
-                for hint in shared.active_hints.clone() {
-                    if let Err(e) = start_producer(&mut ctx, &mut shared, hint, None) {
-                        shared.event_tx.send(PulseEvent::Error(e)).ok();
+            }
+            SessionEnd::Moved {
+                call_id,
+                token,
+                server_url,
+            } => {
+                // our credential names the call, so join the new call's group
+                // with a fresh one
+                let mls = match MlsClient::new(
+                    &shared.options.session_id,
+                    &call_id,
+                    shared.options.identity.clone(),
+                ) {
+                    Ok(mls) => mls,
+                    Err(e) => {
+                        emit_mls_error(&shared, e);
+                        shared
+                            .event_tx
+                            .send(PulseEvent::Disconnected {
+                                reason: "failed to join the new call".to_string(),
+                            })
+                            .ok();
+                        return;
                     }
+                };
+                *shared.mls.lock().await = mls;
+                shared.options.call_id = call_id.clone();
+                shared.options.session_token = token;
+                if let Some(server_url) = server_url {
+                    shared.options.server_url = server_url;
                 }
+                shared.event_tx.send(PulseEvent::Moved { call_id }).ok();
+                let Some(new_ctx) = resume(&shared, true).await else {
+                    shared
+                        .event_tx
+                        .send(PulseEvent::Disconnected {
+                            reason: "reconnect attempts exhausted".to_string(),
+                        })
+                        .ok();
+                    return;
+                };
+                ctx = new_ctx;
+            }
+        }
+        for (hint, layers) in shared.active_hints.clone() {
+            if let Err(e) = start_producer(&mut ctx, &mut shared, hint, layers, None) {
+                shared.event_tx.send(PulseEvent::Error(e)).ok();
+            }
+        }
+    }
+}
+
+/// Pick the session back up after it ended. When the server moved us to
+/// another node, the target is expected to be up, so connect straight away
+/// without reporting a reconnect; only fall back to retries if that fails.
+async fn resume(shared: &Shared, migrated: bool) -> Option<SessionCtx> {
+    if migrated {
+        match establish(&shared.options, &shared.mls).await {
+            Ok((ctx, id, tracks)) => {
+                announce_connected(&shared.event_tx, id, tracks);
+                return Some(ctx);
             }
+            Err(e) => tracing::warn!("Failed to connect to migration target: {e}"),
         }
     }
+    reconnect(shared).await
 }
 
 async fn reconnect(shared: &Shared) -> Option<SessionCtx> {
@@ -406,8 +605,8 @@ fn announce_connected(
 }
 
 fn teardown(ctx: &mut SessionCtx) {
-    for (_, handle) in ctx.consumers.drain() {
-        handle.abort();
+    for (_, consumer) in ctx.consumers.drain() {
+        consumer.task.abort();
     }
     for (_, pending) in ctx.pending.drain() {
         match pending.kind {
@@ -466,7 +665,13 @@ async fn establish(
     let mut ctl_track = ctl_broadcast
         .create_track(Track::new(track_names::CTL_C2S))
         .map_err(|e| PulseError::Transport(Arc::new(e)))?;
-    write_ctl_frame(&mut ctl_track, &ControlC2S::Join { key_package })?;
+    write_ctl_frame(
+        &mut ctl_track,
+        &ControlC2S::Join {
+            key_package,
+            media_header_version: MEDIA_HEADER_VERSION,
+        },
+    )?;
 
     let (s2c_tx, mut s2c_rx) = mpsc::unbounded_channel();
     tokio::spawn(read_s2c(
@@ -499,6 +704,7 @@ async fn establish(
     .map_err(|_| PulseError::Timeout(CONNECT_TIMEOUT, "Connected"))??;
 
     let (id, available_tracks) = connected;
+    let (feedback_tx, feedback_rx) = mpsc::unbounded_channel();
     Ok((
         SessionCtx {
             origin,
@@ -511,6 +717,9 @@ async fn establish(
             consumers: HashMap::new(),
             pending: HashMap::new(),
             last_crypto_error: None,
+            feedback_tx,
+            feedback_rx,
+            ping_epoch: Instant::now(),
         },
         id,
         available_tracks,
@@ -530,6 +739,8 @@ async fn run_session(
 
     let mut sweep = tokio::time::interval(Duration::from_secs(1));
     sweep.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    ping.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
 
     loop {
         tokio::select! {
@@ -537,6 +748,21 @@ async fn run_session(
                 sweep_expired_requests(ctx, shared);
             }
 
+            _ = ping.tick() => {
+                let ping = ControlC2S::Ping {
+                    sent_us: ctx.ping_epoch.elapsed().as_micros() as u64,
+                };
+                if let Err(e) = write_ctl_frame(&mut ctx.ctl_track, &ping) {
+                    tracing::warn!("Failed to write ping: {e}");
+                }
+            }
+
+            Some(report) = ctx.feedback_rx.recv() => {
+                if let Err(e) = write_ctl_frame(&mut ctx.ctl_track, &report) {
+                    tracing::warn!("Failed to write receiver report: {e}");
+                }
+            }
+
             msg = ctx.s2c_rx.recv() => {
                 let Some(msg) = msg else {
                     tracing::warn!("s2c control reader ended; treating as connection loss");
@@ -558,27 +784,29 @@ async fn run_session(
                             tracing::warn!("Failed to write control frame: {e}");
                         }
                     }
-                    ClientCommand::StartProduce { media_hint, reply } => {
-                        if let Err(e) = start_producer(ctx, shared, media_hint, Some(reply)) {
+                    ClientCommand::StartProduce { media_hint, layers, reply } => {
+                        let reply = Some(reply);
+                        if let Err(e) = start_producer(ctx, shared, media_hint, layers, reply) {
                             shared.event_tx.send(PulseEvent::Error(e)).ok();
                         }
                     }
                     ClientCommand::StopProduce { media_hint, reply } => {
                         stop_producer(ctx, shared, media_hint, reply);
                     }
-                    ClientCommand::WriteMedia { media_hint, capture_ts_us, keyframe, data } => {
-                        write_media(ctx, shared, media_hint, capture_ts_us, keyframe, data).await;
+                    ClientCommand::WriteMedia { media_hint, frame } => {
+                        write_media(ctx, shared, media_hint, frame).await;
                     }
-                    ClientCommand::StartConsume { track, sink } => {
+                    ClientCommand::StartConsume { track, layer, sink } => {
                         let id = track.id.clone();
-                        let handle = spawn_consumer(ctx, shared, track, sink);
-                        if let Some(prev) = ctx.consumers.insert(id, handle) {
-                            prev.abort();
+                        let task = spawn_consumer(ctx, shared, &track, layer, sink.clone());
+                        let consumer = MediaConsumer { track, layer, sink, task };
+                        if let Some(prev) = ctx.consumers.insert(id, consumer) {
+                            prev.task.abort();
                         }
                     }
                     ClientCommand::StopConsume { id } => {
-                        if let Some(handle) = ctx.consumers.remove(&id) {
-                            handle.abort();
+                        if let Some(consumer) = ctx.consumers.remove(&id) {
+                            consumer.task.abort();
                         }
                     }
                     ClientCommand::Shutdown => {
@@ -598,6 +826,7 @@ fn start_producer(
     ctx: &mut SessionCtx,
     shared: &mut Shared,
     media_hint: MediaHint,
+    layers: u8,
     reply: Option<oneshot::Sender<Result<TrackHandle, PulseError>>>,
 ) -> Result<(), PulseError> {
     let track_name = track_name_for_hint(&media_hint);
@@ -614,16 +843,30 @@ fn start_producer(
             .origin
             .create_broadcast(&path)
             .ok_or_else(|| PulseError::BroadcastCreation(path.clone()))?;
-        let track = broadcast
-            .create_track(Track::new(track_name).with_priority(priority_for_hint(&media_hint)))
-            .map_err(|e| PulseError::Transport(Arc::new(e)))?;
+        // every layer is a track of the same broadcast, so consumers only
+        // receive the one they subscribe to
+        let layers = (0..layers)
+            .map(|layer| {
+                let name = layer_track_name(&media_hint, layer);
+                let track = broadcast
+                    .create_track(
+                        Track::new(name.as_str()).with_priority(priority_for_hint(&media_hint)),
+                    )
+                    .map_err(|e| PulseError::Transport(Arc::new(e)))?;
+                Ok(LayerProducer {
+                    name,
+                    track,
+                    group: None,
+                    next_group_seq: 0,
+                })
+            })
+            .collect::<Result<Vec<_>, PulseError>>()?;
+        let layer_count = layers.len() as u8;
         ctx.producers.insert(
             track_name,
             MediaProducer {
                 _broadcast: broadcast,
-                track,
-                group: None,
-                next_group_seq: 0,
+                layers,
                 media_hint: media_hint.clone(),
                 server_track_id: None,
             },
@@ -636,18 +879,19 @@ fn start_producer(
             &ControlC2S::StartProduce {
                 request_id,
                 media_hint: media_hint.clone(),
+                layers: layer_count,
             },
         ) {
             ctx.producers.remove(track_name);
             return Err(e);
         }
-        Ok(request_id)
+        Ok((request_id, layer_count))
     })();
 
     match result {
-        Ok(request_id) => {
-            if !shared.active_hints.contains(&media_hint) {
-                shared.active_hints.push(media_hint.clone());
+        Ok((request_id, layers)) => {
+            if !shared.active_hints.iter().any(|(h, _)| h == &media_hint) {
+                shared.active_hints.push((media_hint.clone(), layers));
             }
             ctx.pending.insert(
                 request_id,
@@ -680,7 +924,7 @@ fn stop_producer(
         reply.send(Err(PulseError::NotProducing(media_hint))).ok();
         return;
     }
-    shared.active_hints.retain(|h| h != &media_hint);
+    shared.active_hints.retain(|(h, _)| h != &media_hint);
 
     let request_id = shared.next_request_id;
     shared.next_request_id += 1;
@@ -718,7 +962,7 @@ fn sweep_expired_requests(ctx: &mut SessionCtx, shared: &mut Shared) {
         match req.kind {
             PendingKind::StartProduce { media_hint, reply } => {
                 ctx.producers.remove(track_name_for_hint(&media_hint));
-                shared.active_hints.retain(|h| h != &media_hint);
+                shared.active_hints.retain(|(h, _)| h != &media_hint);
                 if let Some(reply) = reply {
                     reply
                         .send(Err(PulseError::Timeout(REQUEST_TIMEOUT, "ProduceStarted")))
@@ -738,15 +982,25 @@ async fn write_media(
     ctx: &mut SessionCtx,
     shared: &Shared,
     media_hint: MediaHint,
-    capture_ts_us: u64,
-    keyframe: bool,
-    data: Vec<u8>,
+    frame: OutgoingFrame,
 ) {
+    let OutgoingFrame {
+        layer,
+        capture_ts_us,
+        keyframe,
+        audio_level,
+        data,
+    } = frame;
     let track_name = track_name_for_hint(&media_hint);
-    if !ctx.producers.contains_key(track_name) {
-        tracing::warn!("Write to unknown producer {track_name}");
+    let Some(layer_name) = ctx
+        .producers
+        .get(track_name)
+        .and_then(|p| p.layers.get(layer as usize))
+        .map(|l| l.name.clone())
+    else {
+        tracing::warn!("Write to unknown producer {track_name} layer {layer}");
         return;
-    }
+    };
 
     let payload = {
         let mut mls = shared.mls.lock().await;
@@ -755,7 +1009,7 @@ async fn write_media(
             emit_crypto_error(ctx, shared, PulseError::Crypto(MlsError::NoActiveEpoch));
             return;
         }
-        match mls.seal_media(track_name, capture_ts_us, &data) {
+        match mls.seal_media(&layer_name, capture_ts_us, audio_level, &data) {
             Ok(p) => p,
             Err(e) => {
                 drop(mls);
@@ -765,7 +1019,11 @@ async fn write_media(
         }
     };
 
-    let Some(producer) = ctx.producers.get_mut(track_name) else {
+    let Some(producer) = ctx
+        .producers
+        .get_mut(track_name)
+        .and_then(|p| p.layers.get_mut(layer as usize))
+    else {
         return;
     };
 
@@ -807,18 +1065,22 @@ fn emit_crypto_error(ctx: &mut SessionCtx, shared: &Shared, error: PulseError) {
 fn spawn_consumer(
     ctx: &SessionCtx,
     shared: &Shared,
-    track: AvailableTrack,
+    track: &AvailableTrack,
+    layer: u8,
     sink: mpsc::UnboundedSender<MediaFrame>,
 ) -> tokio::task::JoinHandle<()> {
     let origin = ctx.origin.clone();
     let mls = shared.mls.clone();
     let event_tx = shared.event_tx.clone();
     let call_id = shared.options.call_id.clone();
-    let track_name = track_name_for_hint(&track.media_hint).to_string();
+    let broadcast_name = track_name_for_hint(&track.media_hint);
+    let track_name = layer_track_name(&track.media_hint, layer);
     let producer_session = track.session_id.clone();
+    let track_id = track.id.clone();
+    let feedback_tx = ctx.feedback_tx.clone();
 
     tokio::spawn(async move {
-        let path = format!("calls/{call_id}/{producer_session}/{track_name}");
+        let path = format!("calls/{call_id}/{producer_session}/{broadcast_name}");
         let sub_origin = origin.consume();
         let Some(bc) = sub_origin.announced_broadcast(&path).await else {
             tracing::warn!("Remote track {path} never announced");
@@ -834,6 +1096,8 @@ fn spawn_consumer(
 
         let mut last_group_seq: u64 = 0;
         let mut last_error_emit: Option<Instant> = None;
+        let started = Instant::now();
+        let mut stats = ReceptionStats::new(started);
         loop {
             // if we fall behind, discard and jump ahead
             if let Some(latest) = tc.latest()
@@ -863,6 +1127,21 @@ fn spawn_consumer(
                         };
                         match opened {
                             Ok((header, data)) => {
+                                let now = Instant::now();
+                                let arrival_us = now.duration_since(started).as_micros() as u64;
+                                stats.on_frame(header.sequence, header.capture_ts_us, arrival_us);
+                                if let Some((lost, received, jitter_ms)) =
+                                    stats.take_report(REPORT_INTERVAL, now)
+                                {
+                                    feedback_tx
+                                        .send(ControlC2S::ReceiverReport {
+                                            track_id: track_id.clone(),
+                                            lost,
+                                            received,
+                                            jitter_ms,
+                                        })
+                                        .ok();
+                                }
                                 if sink
                                     .send(MediaFrame {
                                         capture_ts_us: header.capture_ts_us,
@@ -910,11 +1189,13 @@ async fn handle_server_message(
         } => match ctx.pending.remove(&request_id).map(|r| r.kind) {
             Some(PendingKind::StartProduce { media_hint, reply }) => {
                 let track_name = track_name_for_hint(&media_hint);
+                let mut layers = 1;
                 if let Some(producer) = ctx.producers.get_mut(track_name) {
                     producer.server_track_id = Some(track_id);
+                    layers = producer.layers.len() as u8;
                 }
                 if let Some(reply) = reply {
-                    reply.send(Ok(TrackHandle { media_hint })).ok();
+                    reply.send(Ok(TrackHandle { media_hint, layers })).ok();
                 }
             }
             _ => tracing::warn!("ProduceStarted for unknown request {request_id}"),
@@ -931,7 +1212,7 @@ async fn handle_server_message(
             match ctx.pending.remove(&request_id).map(|r| r.kind) {
                 Some(PendingKind::StartProduce { media_hint, reply }) => {
                     ctx.producers.remove(track_name_for_hint(&media_hint));
-                    shared.active_hints.retain(|h| h != &media_hint);
+                    shared.active_hints.retain(|(h, _)| h != &media_hint);
                     match reply {
                         Some(reply) => {
                             reply.send(Err(PulseError::Rejected(reason))).ok();
@@ -955,17 +1236,47 @@ async fn handle_server_message(
             shared.event_tx.send(PulseEvent::TrackAvailable(track)).ok();
         }
         ControlS2C::TrackUnavailable { id } => {
-            if let Some(handle) = ctx.consumers.remove(&id) {
-                handle.abort();
+            if let Some(consumer) = ctx.consumers.remove(&id) {
+                consumer.task.abort();
             }
             shared.event_tx.send(PulseEvent::TrackUnavailable(id)).ok();
         }
+        ControlS2C::ProduceRevoked {
+            track_id,
+            media_hint,
+        } => {
+            let track_name = track_name_for_hint(&media_hint);
+            let current = ctx
+                .producers
+                .get(track_name)
+                .is_some_and(|p| p.server_track_id.as_deref() == Some(track_id.as_str()));
+            if current {
+                ctx.producers.remove(track_name);
+                // not restarted on reconnect either
+                shared.active_hints.retain(|(h, _)| h != &media_hint);
+                shared
+                    .event_tx
+                    .send(PulseEvent::ProduceRevoked(media_hint))
+                    .ok();
+            }
+        }
         ControlS2C::Connected {
             id,
             available_tracks,
         } => {
             announce_connected(&shared.event_tx, id, available_tracks);
         }
+        ControlS2C::Moved {
+            call_id,
+            token,
+            server_url,
+        } => {
+            return Some(SessionEnd::Moved {
+                call_id,
+                token,
+                server_url,
+            });
+        }
         ControlS2C::Disconnected { reconnect } => {
             return Some(match reconnect {
                 Some(redirect) => SessionEnd::Lost {
@@ -1079,6 +1390,7 @@ async fn handle_server_message(
         }
         ControlS2C::ReceiverReport {
             track_id,
+            session_id,
             lost,
             received,
             jitter_ms,
@@ -1094,6 +1406,7 @@ async fn handle_server_message(
                         .event_tx
                         .send(PulseEvent::ReceiverReport {
                             media_hint,
+                            session_id,
                             lost,
                             received,
                             jitter_ms,
@@ -1103,10 +1416,51 @@ async fn handle_server_message(
                 None => tracing::debug!(track_id, "ReceiverReport for unknown track"),
             }
         }
+        ControlS2C::Pong { sent_us } => {
+            let sent = Duration::from_micros(sent_us);
+            if let Some(rtt) = ctx.ping_epoch.elapsed().checked_sub(sent) {
+                shared.event_tx.send(PulseEvent::RoundTrip(rtt)).ok();
+            }
+        }
+        ControlS2C::ActiveSpeakers { session_ids } => {
+            shared
+                .event_tx
+                .send(PulseEvent::ActiveSpeakers(session_ids))
+                .ok();
+        }
+        ControlS2C::MediaHeaderVersion { version } => {
+            shared.mls.lock().await.set_media_header_version(version);
+        }
+        ControlS2C::LayerSelected { track_id, layer } => {
+            switch_layer(ctx, shared, &track_id, layer);
+            shared
+                .event_tx
+                .send(PulseEvent::LayerSelected { track_id, layer })
+                .ok();
+        }
     }
     None
 }
 
+/// Move a consumer over to another simulcast layer. The new subscription
+/// starts at the layer's latest group, which opens with a keyframe, so the
+/// decoder can switch without a gap.
+fn switch_layer(ctx: &mut SessionCtx, shared: &Shared, track_id: &str, layer: u8) {
+    let Some(consumer) = ctx.consumers.get(track_id) else {
+        return;
+    };
+    let layer = layer.min(consumer.track.layers.saturating_sub(1));
+    if consumer.layer == layer {
+        return;
+    }
+    let task = spawn_consumer(ctx, shared, &consumer.track, layer, consumer.sink.clone());
+    if let Some(consumer) = ctx.consumers.get_mut(track_id) {
+        consumer.task.abort();
+        consumer.task = task;
+        consumer.layer = layer;
+    }
+}
+
 fn emit_mls_error(shared: &Shared, message: MlsError) {
     emit_error(shared, PulseError::Mls(message));
 }
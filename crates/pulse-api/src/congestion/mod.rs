@@ -0,0 +1,391 @@
+//! Adaptive bitrate control for produced tracks.
+//!
+//! Every consumer of a track sends receiver reports, which the server relays
+//! to us as [`PulseEvent::ReceiverReport`](crate::PulseEvent::ReceiverReport),
+//! and the client measures the round trip to its Pulse node as
+//! [`PulseEvent::RoundTrip`](crate::PulseEvent::RoundTrip). A
+//! [`BitrateController`] turns those into a target bitrate, a frame rate cap
+//! and keyframe decisions for whatever encoder feeds the track.
+//!
+//! The controller never reads the clock itself; callers pass the current time
+//! in, so it behaves the same in the simulated network tests as it does live.
+
+#[cfg(test)]
+mod simulation;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Loss ratio at which we back off.
+const HIGH_LOSS: f64 = 0.1;
+/// Loss ratio under which we may probe for more bandwidth.
+const LOW_LOSS: f64 = 0.02;
+/// Loss ratio in one report that warrants a keyframe, since the receiver
+/// likely lost a reference frame.
+const KEYFRAME_LOSS: f64 = 0.2;
+/// Jitter at which we back off.
+const HIGH_JITTER_MS: u32 = 50;
+/// How far RTT may rise above its recent minimum before we assume queues are
+/// building up on the path.
+const RTT_INFLATION: Duration = Duration::from_millis(100);
+/// How long the minimum RTT is remembered, so a route change doesn't leave a
+/// stale baseline behind.
+const MIN_RTT_WINDOW: Duration = Duration::from_secs(30);
+/// Multiplicative decrease on congestion. On loss we also drop to the rate
+/// that got through, since the excess only fills queues.
+const BACKOFF: f64 = 0.85;
+/// Never back off by more than this in one step.
+const MAX_BACKOFF: f64 = 0.5;
+/// Multiplicative increase while the path looks clean.
+const PROBE_GROWTH: f64 = 1.08;
+/// At most one increase per interval, however many consumers report.
+const INCREASE_INTERVAL: Duration = Duration::from_secs(1);
+/// At least this long between decreases, so one congestion event reported by
+/// many consumers only counts once.
+const MIN_DECREASE_INTERVAL: Duration = Duration::from_millis(300);
+/// At least this long between keyframes we ask for.
+const KEYFRAME_INTERVAL: Duration = Duration::from_secs(2);
+/// Consumers that haven't reported for this long no longer count.
+const REPORT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Bitrate changes smaller than this fraction aren't worth reconfiguring the
+/// encoder for.
+const MIN_CHANGE: f64 = 0.05;
+/// Below this fraction of the maximum bitrate the frame rate is scaled down,
+/// keeping each frame sharp rather than sending many blurry ones.
+const FRAME_RATE_KNEE: f64 = 0.5;
+
+/// Limits for one track's encoder.
+#[derive(Clone, Debug)]
+pub struct BitrateConfig {
+    pub min_bitrate_bps: u32,
+    pub start_bitrate_bps: u32,
+    pub max_bitrate_bps: u32,
+    /// Frame rate the encoder runs at, or `None` for audio.
+    pub max_fps: Option<u32>,
+    /// Lowest frame rate we cap video to.
+    pub min_fps: u32,
+    /// Whether the track is published in simulcast layers. The server moves
+    /// congested consumers to lower layers, so the top layer follows the
+    /// consumers that keep up rather than the worst one.
+    pub simulcast: bool,
+}
+
+impl BitrateConfig {
+    /// Limits for a video track encoded at `bitrate_bps` and `fps`, which
+    /// we may lower but never exceed.
+    pub fn video(bitrate_bps: u32, fps: u32, simulcast: bool) -> Self {
+        Self {
+            min_bitrate_bps: (bitrate_bps / 10).max(100_000).min(bitrate_bps),
+            start_bitrate_bps: bitrate_bps,
+            max_bitrate_bps: bitrate_bps,
+            max_fps: Some(fps.max(1)),
+            min_fps: (fps / 4).clamp(1, 10),
+            simulcast,
+        }
+    }
+
+    /// Limits for an Opus voice track.
+    pub fn opus() -> Self {
+        Self {
+            min_bitrate_bps: 16_000,
+            start_bitrate_bps: 64_000,
+            max_bitrate_bps: 96_000,
+            max_fps: None,
+            min_fps: 0,
+            simulcast: false,
+        }
+    }
+}
+
+/// What the encoder should do now.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BitrateDecision {
+    pub bitrate_bps: u32,
+    /// Frame rate cap, `None` for audio.
+    pub max_fps: Option<u32>,
+    /// Whether to encode a keyframe next.
+    pub keyframe: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ConsumerReport {
+    loss: f64,
+    jitter_ms: u32,
+    at: Instant,
+}
+
+impl ConsumerReport {
+    fn congested(&self) -> bool {
+        self.loss >= HIGH_LOSS || self.jitter_ms >= HIGH_JITTER_MS
+    }
+}
+
+/// Congestion controller for one produced track.
+#[derive(Debug)]
+pub struct BitrateController {
+    config: BitrateConfig,
+    bitrate_bps: f64,
+    consumers: HashMap<String, ConsumerReport>, // session id -> latest report
+    rtt: Option<Duration>,
+    min_rtt: Option<(Duration, Instant)>,
+    last_increase: Option<Instant>,
+    last_decrease: Option<Instant>,
+    last_keyframe: Option<Instant>,
+    applied: BitrateDecision,
+}
+
+impl BitrateController {
+    pub fn new(config: BitrateConfig) -> Self {
+        let bitrate_bps = config
+            .start_bitrate_bps
+            .clamp(config.min_bitrate_bps, config.max_bitrate_bps);
+        let applied = BitrateDecision {
+            bitrate_bps,
+            max_fps: config.max_fps,
+            keyframe: false,
+        };
+        Self {
+            config,
+            bitrate_bps: bitrate_bps as f64,
+            consumers: HashMap::new(),
+            rtt: None,
+            min_rtt: None,
+            last_increase: None,
+            last_decrease: None,
+            last_keyframe: None,
+            applied,
+        }
+    }
+
+    pub fn config(&self) -> &BitrateConfig {
+        &self.config
+    }
+
+    /// The bitrate and frame rate last handed out.
+    pub fn current(&self) -> BitrateDecision {
+        self.applied
+    }
+
+    /// Smoothed round trip time, if measured yet.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    /// Feed a round trip measurement to the controller's Pulse node.
+    pub fn on_rtt(&mut self, rtt: Duration, now: Instant) {
+        self.rtt = Some(match self.rtt {
+            Some(srtt) => (srtt * 7 + rtt) / 8,
+            None => rtt,
+        });
+        let expired = self
+            .min_rtt
+            .is_none_or(|(min, at)| rtt <= min || now.duration_since(at) >= MIN_RTT_WINDOW);
+        if expired {
+            self.min_rtt = Some((rtt, now));
+        }
+    }
+
+    /// Forget a consumer that stopped consuming the track.
+    pub fn remove_consumer(&mut self, session_id: &str) {
+        self.consumers.remove(session_id);
+    }
+
+    /// Feed a receiver report from one consumer of the track, returning a
+    /// new decision if the encoder should change anything.
+    pub fn on_report(
+        &mut self,
+        session_id: &str,
+        lost: u32,
+        received: u32,
+        jitter_ms: u32,
+        now: Instant,
+    ) -> Option<BitrateDecision> {
+        let total = lost.saturating_add(received);
+        if total == 0 {
+            return None;
+        }
+        let report = ConsumerReport {
+            loss: lost as f64 / total as f64,
+            jitter_ms,
+            at: now,
+        };
+        self.consumers.insert(session_id.to_string(), report);
+        self.consumers
+            .retain(|_, r| now.duration_since(r.at) < REPORT_TIMEOUT);
+
+        let keyframe = report.loss >= KEYFRAME_LOSS
+            && self
+                .last_keyframe
+                .is_none_or(|at| now.duration_since(at) >= KEYFRAME_INTERVAL);
+        if keyframe {
+            self.last_keyframe = Some(now);
+        }
+
+        self.adapt(now);
+        self.decide(keyframe)
+    }
+
+    /// The report that drives the bitrate: the most congested consumer, or
+    /// for simulcast the least congested one.
+    fn driving_report(&self) -> Option<ConsumerReport> {
+        let by_congestion = |a: &&ConsumerReport, b: &&ConsumerReport| {
+            a.loss
+                .total_cmp(&b.loss)
+                .then(a.jitter_ms.cmp(&b.jitter_ms))
+        };
+        let reports = self.consumers.values();
+        if self.config.simulcast {
+            reports.min_by(by_congestion).copied()
+        } else {
+            reports.max_by(by_congestion).copied()
+        }
+    }
+
+    fn rtt_inflated(&self) -> bool {
+        match (self.rtt, self.min_rtt) {
+            (Some(rtt), Some((min, _))) => rtt > min + RTT_INFLATION,
+            _ => false,
+        }
+    }
+
+    fn adapt(&mut self, now: Instant) {
+        let Some(report) = self.driving_report() else {
+            return;
+        };
+        let rtt_inflated = self.rtt_inflated();
+
+        if report.congested() || rtt_inflated {
+            // give the previous decrease a round trip to take effect
+            let interval = self.rtt.unwrap_or_default().max(MIN_DECREASE_INTERVAL);
+            if self
+                .last_decrease
+                .is_some_and(|at| now.duration_since(at) < interval)
+            {
+                return;
+            }
+            let factor = if report.loss >= HIGH_LOSS {
+                (1.0 - report.loss) * BACKOFF
+            } else {
+                BACKOFF
+            };
+            self.bitrate_bps *= factor.max(MAX_BACKOFF);
+            self.last_decrease = Some(now);
+        } else if report.loss < LOW_LOSS && report.jitter_ms < HIGH_JITTER_MS / 2 {
+            if self
+                .last_increase
+                .is_some_and(|at| now.duration_since(at) < INCREASE_INTERVAL)
+            {
+                return;
+            }
+            self.bitrate_bps *= PROBE_GROWTH;
+            self.last_increase = Some(now);
+        }
+        self.bitrate_bps = self.bitrate_bps.clamp(
+            self.config.min_bitrate_bps as f64,
+            self.config.max_bitrate_bps as f64,
+        );
+    }
+
+    fn frame_rate_cap(&self) -> Option<u32> {
+        let max_fps = self.config.max_fps?;
+        let ratio = self.bitrate_bps / self.config.max_bitrate_bps as f64;
+        if ratio >= FRAME_RATE_KNEE {
+            return Some(max_fps);
+        }
+        let min_fps = self.config.min_fps.min(max_fps) as f64;
+        let fps = min_fps + (max_fps as f64 - min_fps) * ratio / FRAME_RATE_KNEE;
+        Some(fps.round() as u32)
+    }
+
+    fn decide(&mut self, keyframe: bool) -> Option<BitrateDecision> {
+        let bitrate_bps = self.bitrate_bps.round() as u32;
+        let max_fps = self.frame_rate_cap();
+        let applied = self.applied.bitrate_bps as f64;
+        let at_limit = bitrate_bps != self.applied.bitrate_bps
+            && (bitrate_bps == self.config.min_bitrate_bps
+                || bitrate_bps == self.config.max_bitrate_bps);
+        let bitrate_changed =
+            (bitrate_bps as f64 - applied).abs() >= applied * MIN_CHANGE || at_limit;
+        if !bitrate_changed && max_fps == self.applied.max_fps && !keyframe {
+            return None;
+        }
+        if bitrate_changed {
+            self.applied.bitrate_bps = bitrate_bps;
+        }
+        self.applied.max_fps = max_fps;
+        Some(BitrateDecision {
+            keyframe,
+            ..self.applied
+        })
+    }
+}
+
+/// Loss and jitter of one consumed track, for the receiver reports a
+/// consumer sends its producer.
+#[derive(Debug)]
+pub(crate) struct ReceptionStats {
+    next_sequence: Option<u64>,
+    lost: u32,
+    received: u32,
+    jitter_us: f64, // RFC 3550 interarrival jitter
+    last_transit_us: Option<i64>,
+    since: Instant,
+}
+
+impl ReceptionStats {
+    pub(crate) fn new(now: Instant) -> Self {
+        Self {
+            next_sequence: None,
+            lost: 0,
+            received: 0,
+            jitter_us: 0.0,
+            last_transit_us: None,
+            since: now,
+        }
+    }
+
+    /// Record a frame arriving at `arrival_us` on any local clock.
+    pub(crate) fn on_frame(&mut self, sequence: u64, capture_ts_us: u64, arrival_us: u64) {
+        // frames skipped when falling behind count as lost too; the viewer
+        // never saw them
+        if let Some(next) = self.next_sequence
+            && sequence > next
+        {
+            self.lost = self
+                .lost
+                .saturating_add((sequence - next).min(u32::MAX as u64) as u32);
+        }
+        if self.next_sequence.is_none_or(|next| sequence >= next) {
+            self.next_sequence = Some(sequence + 1);
+        }
+        self.received = self.received.saturating_add(1);
+
+        let transit = arrival_us as i64 - capture_ts_us as i64;
+        if let Some(last) = self.last_transit_us {
+            let d = (transit - last).abs() as f64;
+            self.jitter_us += (d - self.jitter_us) / 16.0;
+        }
+        self.last_transit_us = Some(transit);
+    }
+
+    /// Take `(lost, received, jitter_ms)` since the last report, if a report
+    /// is due.
+    pub(crate) fn take_report(
+        &mut self,
+        interval: Duration,
+        now: Instant,
+    ) -> Option<(u32, u32, u32)> {
+        if now.duration_since(self.since) < interval || (self.lost == 0 && self.received == 0) {
+            return None;
+        }
+        let report = (
+            self.lost,
+            self.received,
+            (self.jitter_us / 1000.0).round() as u32,
+        );
+        self.lost = 0;
+        self.received = 0;
+        self.since = now;
+        Some(report)
+    }
+}
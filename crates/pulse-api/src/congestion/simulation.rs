@@ -0,0 +1,527 @@
+//! Deterministic network simulation for [`BitrateController`].
+//!
+//! A [`Scenario`] describes the producer's uplink to its Pulse node and each
+//! consumer's downlink as timelines of capacity, delay and random loss. The
+//! simulation pushes the controller's bitrate through those links in fixed
+//! steps, with queues that build up and overflow, and feeds the resulting
+//! receiver reports and round trips back into the controller the way the
+//! client would. Randomness comes from a seeded generator, so a scenario
+//! always plays out the same way, and each one runs as a test checking the
+//! controller settles near the available capacity without sustained loss.
+
+use std::time::{Duration, Instant};
+
+use pulse_types::MAX_LAYERS;
+
+use super::{BitrateConfig, BitrateController, BitrateDecision};
+
+/// Length of one simulation step.
+const STEP: Duration = Duration::from_millis(10);
+/// How often each consumer sends a receiver report, as the client does.
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the producer measures its round trip, as the client does.
+const PING_INTERVAL: Duration = Duration::from_secs(2);
+/// Size of one media packet.
+const PACKET_BITS: f64 = 1200.0 * 8.0;
+/// Queues hold at most this much delay before dropping.
+const MAX_QUEUE_DELAY: f64 = 0.25;
+/// Share of a downlink the server lets a simulcast layer fill before moving
+/// the consumer down a layer.
+const LAYER_HEADROOM: f64 = 0.9;
+
+/// Bitrate of simulcast layer `layer` when the top one runs at `bitrate`,
+/// each layer a quarter of the one above it.
+fn layer_bitrate(bitrate: f64, layer: u8) -> f64 {
+    bitrate / 4f64.powi(layer as i32)
+}
+
+/// The layer the server would settle a consumer on.
+fn fitting_layer(bitrate: f64, capacity_bps: u32) -> u8 {
+    (0..MAX_LAYERS)
+        .find(|&layer| layer_bitrate(bitrate, layer) <= capacity_bps as f64 * LAYER_HEADROOM)
+        .unwrap_or(MAX_LAYERS - 1)
+}
+
+/// Conditions on one link from some point in time on.
+#[derive(Clone, Copy, Debug)]
+pub struct LinkState {
+    pub capacity_bps: u32,
+    /// One-way propagation delay.
+    pub delay: Duration,
+    /// Fraction of packets lost regardless of load, as on a poor radio link.
+    pub random_loss: f64,
+    /// Random extra delay per packet, up to this much.
+    pub jitter: Duration,
+}
+
+impl LinkState {
+    pub fn new(capacity_bps: u32, delay: Duration) -> Self {
+        Self {
+            capacity_bps,
+            delay,
+            random_loss: 0.0,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    pub fn with_random_loss(mut self, random_loss: f64) -> Self {
+        self.random_loss = random_loss;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+}
+
+/// A link whose conditions change over time. Each entry applies from its
+/// offset into the scenario until the next one.
+#[derive(Clone, Debug)]
+pub struct LinkTimeline(pub Vec<(Duration, LinkState)>);
+
+impl LinkTimeline {
+    pub fn constant(state: LinkState) -> Self {
+        Self(vec![(Duration::ZERO, state)])
+    }
+
+    pub fn then(mut self, at: Duration, state: LinkState) -> Self {
+        self.0.push((at, state));
+        self
+    }
+
+    fn at(&self, elapsed: Duration) -> LinkState {
+        self.0
+            .iter()
+            .take_while(|(from, _)| *from <= elapsed)
+            .last()
+            .or(self.0.first())
+            .map(|(_, state)| *state)
+            .expect("link timeline is empty")
+    }
+}
+
+/// One run of the controller against simulated links.
+#[derive(Clone, Debug)]
+pub struct Scenario {
+    pub name: &'static str,
+    pub duration: Duration,
+    pub config: BitrateConfig,
+    /// From the producer to its Pulse node.
+    pub uplink: LinkTimeline,
+    /// From the Pulse node to each consumer.
+    pub downlinks: Vec<LinkTimeline>,
+    pub seed: u64,
+}
+
+impl Scenario {
+    /// Scenarios covering the situations the controller has to handle.
+    pub fn standard() -> Vec<Scenario> {
+        let ms = Duration::from_millis;
+        let secs = Duration::from_secs;
+        let screen = BitrateConfig::video(2_500_000, 30, false);
+        let fast = LinkState::new(20_000_000, ms(20));
+        vec![
+            Scenario {
+                name: "ample capacity",
+                duration: secs(60),
+                config: screen.clone(),
+                uplink: LinkTimeline::constant(fast),
+                downlinks: vec![LinkTimeline::constant(fast); 3],
+                seed: 1,
+            },
+            Scenario {
+                name: "uplink capacity drop",
+                duration: secs(90),
+                config: screen.clone(),
+                uplink: LinkTimeline::constant(LinkState::new(4_000_000, ms(25)))
+                    .then(secs(30), LinkState::new(1_000_000, ms(25)))
+                    .then(secs(60), LinkState::new(4_000_000, ms(25))),
+                downlinks: vec![LinkTimeline::constant(fast); 2],
+                seed: 2,
+            },
+            Scenario {
+                name: "one slow consumer",
+                duration: secs(60),
+                config: screen.clone(),
+                uplink: LinkTimeline::constant(fast),
+                downlinks: vec![
+                    LinkTimeline::constant(fast),
+                    LinkTimeline::constant(LinkState::new(800_000, ms(60))),
+                ],
+                seed: 3,
+            },
+            Scenario {
+                name: "one slow consumer, simulcast",
+                duration: secs(60),
+                config: BitrateConfig::video(2_500_000, 30, true),
+                uplink: LinkTimeline::constant(fast),
+                downlinks: vec![
+                    LinkTimeline::constant(fast),
+                    LinkTimeline::constant(LinkState::new(800_000, ms(60))),
+                ],
+                seed: 4,
+            },
+            Scenario {
+                name: "lossy wifi",
+                duration: secs(60),
+                config: screen,
+                uplink: LinkTimeline::constant(
+                    LinkState::new(6_000_000, ms(15))
+                        .with_random_loss(0.01)
+                        .with_jitter(ms(15)),
+                ),
+                downlinks: vec![LinkTimeline::constant(fast)],
+                seed: 5,
+            },
+            Scenario {
+                name: "voice on a congested uplink",
+                duration: secs(60),
+                config: BitrateConfig::opus(),
+                uplink: LinkTimeline::constant(LinkState::new(200_000, ms(40)))
+                    .then(secs(20), LinkState::new(40_000, ms(40)))
+                    .then(secs(40), LinkState::new(200_000, ms(40))),
+                downlinks: vec![LinkTimeline::constant(fast); 4],
+                seed: 6,
+            },
+        ]
+    }
+}
+
+/// The state of a simulation at one moment.
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    pub elapsed: Duration,
+    /// The most the track could carry to the consumers that drive it.
+    pub capacity_bps: u32,
+    pub decision: BitrateDecision,
+    /// Loss across all consumers over the last report interval.
+    pub loss: f64,
+    pub rtt: Option<Duration>,
+}
+
+/// How a scenario played out.
+#[derive(Clone, Debug)]
+pub struct Outcome {
+    pub name: &'static str,
+    /// One sample per report interval.
+    pub samples: Vec<Sample>,
+    pub keyframes: u32,
+    pub decisions: u32,
+}
+
+impl Outcome {
+    fn settled(&self) -> &[Sample] {
+        &self.samples[self.samples.len() / 4..]
+    }
+
+    /// Mean loss once the controller had time to settle.
+    pub fn settled_loss(&self) -> f64 {
+        let settled = self.settled();
+        settled.iter().map(|s| s.loss).sum::<f64>() / settled.len().max(1) as f64
+    }
+
+    /// Mean share of the usable capacity the controller used once it had
+    /// time to settle, where usable is capped by the maximum bitrate.
+    pub fn settled_utilization(&self, max_bitrate_bps: u32) -> f64 {
+        let settled = self.settled();
+        settled
+            .iter()
+            .map(|s| {
+                let usable = s.capacity_bps.min(max_bitrate_bps) as f64;
+                s.decision.bitrate_bps as f64 / usable
+            })
+            .sum::<f64>()
+            / settled.len().max(1) as f64
+    }
+}
+
+/// xorshift64*, plenty for jitter and loss.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    bits: f64,
+}
+
+struct Transmitted {
+    delivered: f64,
+    dropped: f64,
+    delay: f64,
+}
+
+impl Queue {
+    fn transmit(&mut self, bits: f64, state: &LinkState, rng: &mut Rng) -> Transmitted {
+        let capacity = state.capacity_bps.max(1) as f64;
+        self.bits += bits;
+        let delivered = self.bits.min(capacity * STEP.as_secs_f64());
+        self.bits -= delivered;
+        let overflow = (self.bits - capacity * MAX_QUEUE_DELAY).max(0.0);
+        self.bits -= overflow;
+        // random loss averages out to the configured rate
+        let random = delivered * state.random_loss * 2.0 * rng.next();
+        Transmitted {
+            delivered: delivered - random,
+            dropped: overflow + random,
+            delay: state.delay.as_secs_f64()
+                + self.bits / capacity
+                + state.jitter.as_secs_f64() * rng.next(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Consumer {
+    queue: Queue,
+    received: f64,
+    lost: f64,
+    jitter: f64, // RFC 3550 interarrival jitter, seconds
+    last_delay: Option<f64>,
+}
+
+/// Play out a scenario.
+pub fn run(scenario: &Scenario) -> Outcome {
+    let mut controller = BitrateController::new(scenario.config.clone());
+    let mut rng = Rng::new(scenario.seed);
+    let mut uplink = Queue::default();
+    let mut consumers: Vec<Consumer> = scenario
+        .downlinks
+        .iter()
+        .map(|_| Consumer::default())
+        .collect();
+    let mut outcome = Outcome {
+        name: scenario.name,
+        samples: Vec::new(),
+        keyframes: 0,
+        decisions: 0,
+    };
+
+    let layers = if scenario.config.simulcast {
+        MAX_LAYERS
+    } else {
+        1
+    };
+    // the uplink carries every layer
+    let layer_share: f64 = (0..layers).map(|layer| layer_bitrate(1.0, layer)).sum();
+
+    let start = Instant::now();
+    let steps = (scenario.duration.as_millis() / STEP.as_millis()) as u32;
+    let (mut period_sent, mut period_lost) = (0.0, 0.0);
+    for step in 1..=steps {
+        let elapsed = STEP * step;
+        let now = start + elapsed;
+
+        let bitrate = controller.current().bitrate_bps as f64;
+        let bits = (0..layers)
+            .map(|layer| layer_bitrate(bitrate, layer))
+            .sum::<f64>()
+            * STEP.as_secs_f64();
+        let up_state = scenario.uplink.at(elapsed);
+        let up = uplink.transmit(bits, &up_state, &mut rng);
+        if elapsed
+            .as_millis()
+            .is_multiple_of(PING_INTERVAL.as_millis())
+        {
+            controller.on_rtt(Duration::from_secs_f64(2.0 * up.delay), now);
+        }
+        let up_delivered = up.delivered / bits.max(1.0);
+        let up_dropped = up.dropped / bits.max(1.0);
+
+        for (consumer, downlink) in consumers.iter_mut().zip(&scenario.downlinks) {
+            let down_state = downlink.at(elapsed);
+            // each consumer only receives its own layer, sharing the uplink's
+            // fate in proportion
+            let layer = if layers > 1 {
+                fitting_layer(bitrate, down_state.capacity_bps)
+            } else {
+                0
+            };
+            let bits = layer_bitrate(bitrate, layer) * STEP.as_secs_f64();
+            let up = Transmitted {
+                delivered: bits * up_delivered,
+                dropped: bits * up_dropped,
+                delay: up.delay,
+            };
+            let down = consumer.queue.transmit(up.delivered, &down_state, &mut rng);
+            consumer.received += down.delivered;
+            consumer.lost += up.dropped + down.dropped;
+            let delay = up.delay + down.delay;
+            if let Some(last) = consumer.last_delay {
+                consumer.jitter += ((delay - last).abs() - consumer.jitter) / 16.0;
+            }
+            consumer.last_delay = Some(delay);
+            period_sent += bits;
+            period_lost += up.dropped + down.dropped;
+        }
+
+        if !elapsed
+            .as_millis()
+            .is_multiple_of(REPORT_INTERVAL.as_millis())
+        {
+            continue;
+        }
+        for (i, consumer) in consumers.iter_mut().enumerate() {
+            let decision = controller.on_report(
+                &format!("consumer-{i}"),
+                (consumer.lost / PACKET_BITS).round() as u32,
+                (consumer.received / PACKET_BITS).round() as u32,
+                (consumer.jitter * 1000.0).round() as u32,
+                now,
+            );
+            consumer.lost = 0.0;
+            consumer.received = 0.0;
+            if let Some(decision) = decision {
+                outcome.decisions += 1;
+                if decision.keyframe {
+                    outcome.keyframes += 1;
+                }
+            }
+        }
+
+        let downlink_capacity = scenario
+            .downlinks
+            .iter()
+            .map(|link| link.at(elapsed).capacity_bps);
+        let downlink_capacity = if scenario.config.simulcast {
+            downlink_capacity.max()
+        } else {
+            downlink_capacity.min()
+        };
+        outcome.samples.push(Sample {
+            elapsed,
+            capacity_bps: downlink_capacity
+                .unwrap_or(u32::MAX)
+                .min((up_state.capacity_bps as f64 / layer_share) as u32),
+            decision: controller.current(),
+            loss: if period_sent > 0.0 {
+                period_lost / period_sent
+            } else {
+                0.0
+            },
+            rtt: controller.rtt(),
+        });
+        (period_sent, period_lost) = (0.0, 0.0);
+    }
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write;
+
+    use super::*;
+
+    /// Highest mean loss acceptable once the controller settled.
+    const MAX_SETTLED_LOSS: f64 = 0.03;
+    /// Share of the usable capacity the controller must use once settled.
+    const MIN_UTILIZATION: f64 = 0.5;
+    /// Sending above capacity for long shows up as loss, but allow some
+    /// overshoot from probing.
+    const MAX_UTILIZATION: f64 = 1.1;
+
+    fn report(outcome: &Outcome) -> String {
+        let mut out = format!(
+            "{}: decisions {}, keyframes {}\n",
+            outcome.name, outcome.decisions, outcome.keyframes
+        );
+        for sample in &outcome.samples {
+            writeln!(
+                out,
+                "    {:>4}s  capacity {:>8} bps  bitrate {:>8} bps  fps {:>3}  loss {:>5.2}%  rtt {:>4} ms",
+                sample.elapsed.as_secs(),
+                sample.capacity_bps,
+                sample.decision.bitrate_bps,
+                sample
+                    .decision
+                    .max_fps
+                    .map_or("-".to_string(), |fps| fps.to_string()),
+                sample.loss * 100.0,
+                sample.rtt.map_or(0, |rtt| rtt.as_millis()),
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    fn check(name: &str) {
+        let scenario = Scenario::standard()
+            .into_iter()
+            .find(|scenario| scenario.name == name)
+            .expect("no such scenario");
+        let outcome = run(&scenario);
+
+        let loss = outcome.settled_loss();
+        assert!(
+            loss <= MAX_SETTLED_LOSS,
+            "settled loss {:.2}%\n{}",
+            loss * 100.0,
+            report(&outcome)
+        );
+        let utilization = outcome.settled_utilization(scenario.config.max_bitrate_bps);
+        assert!(
+            (MIN_UTILIZATION..=MAX_UTILIZATION).contains(&utilization),
+            "settled utilization {:.1}%\n{}",
+            utilization * 100.0,
+            report(&outcome)
+        );
+    }
+
+    #[test]
+    fn ample_capacity() {
+        check("ample capacity");
+    }
+
+    #[test]
+    fn uplink_capacity_drop() {
+        check("uplink capacity drop");
+    }
+
+    #[test]
+    fn one_slow_consumer() {
+        check("one slow consumer");
+    }
+
+    #[test]
+    fn one_slow_consumer_simulcast() {
+        check("one slow consumer, simulcast");
+    }
+
+    #[test]
+    fn lossy_wifi() {
+        check("lossy wifi");
+    }
+
+    #[test]
+    fn voice_on_a_congested_uplink() {
+        check("voice on a congested uplink");
+    }
+
+    #[test]
+    fn runs_are_deterministic() {
+        for scenario in Scenario::standard() {
+            let (a, b) = (run(&scenario), run(&scenario));
+            let bitrates = |outcome: &Outcome| {
+                outcome
+                    .samples
+                    .iter()
+                    .map(|s| s.decision.bitrate_bps)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(bitrates(&a), bitrates(&b), "{}", scenario.name);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Account-bound MLS credentials.
+//!
+//! Every MLS leaf, in a call or in a Harmony MLS channel, carries a
+//! [`CredentialBinding`] as its basic credential: the user's account identity
+//! key signing the leaf's signature key, together with the context the leaf
+//! is bound to. Call leaves are bound to one call and session, while channel
+//! leaves have no context and may join any of the account's channels.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use openmls::prelude::{BasicCredential, Credential};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+const BINDING_HEADER: &[u8; 4] = b"HMC2";
+const BINDING_SIG_DOMAIN: &[u8] = b"harmony-mls-credential-v2";
+
+/// Errors from reading or checking a credential binding.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum CredentialError {
+    #[error("malformed CBOR credential binding")]
+    MalformedBinding,
+
+    #[error("credential for user {0} endorses a different leaf signature key")]
+    CredentialWrongLeafKey(String),
+
+    #[error("credential carries an invalid identity key")]
+    InvalidIdentityKey,
+
+    #[error("identity signature on credential for user {0} is invalid")]
+    InvalidIdentitySignature(String),
+}
+
+/// The contents of an account-bound MLS credential.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CredentialBinding {
+    pub user_id: String,
+    /// What the leaf is bound to, such as the call and session it was
+    /// created for. Empty for a leaf that may join any of the account's groups.
+    pub context: Vec<String>,
+    pub identity_pk: [u8; 32],
+    pub leaf_signature_key: Vec<u8>,
+    #[serde_as(as = "[_; 64]")]
+    signature: [u8; 64],
+}
+
+impl CredentialBinding {
+    /// Bind `leaf_signature_key` to the account whose identity signing seed
+    /// is `signing_seed`.
+    pub fn sign(
+        user_id: &str,
+        context: Vec<String>,
+        leaf_signature_key: &[u8],
+        signing_seed: &[u8; 32],
+    ) -> Self {
+        let signing_key = SigningKey::from_bytes(signing_seed);
+        let mut binding = CredentialBinding {
+            user_id: user_id.to_string(),
+            context,
+            identity_pk: signing_key.verifying_key().to_bytes(),
+            leaf_signature_key: leaf_signature_key.to_vec(),
+            signature: [0u8; 64],
+        };
+        binding.signature = signing_key.sign(&binding.signed_payload()).to_bytes();
+        binding
+    }
+
+    /// The MLS credential carrying this binding.
+    pub fn credential(&self) -> Credential {
+        let mut content = BINDING_HEADER.to_vec();
+        content.extend_from_slice(&serde_cbor_2::to_vec(self).unwrap());
+        BasicCredential::new(content).into()
+    }
+
+    /// Read the binding out of a credential without checking it.
+    pub fn decode(credential: &Credential) -> Result<Self, CredentialError> {
+        let body = credential
+            .serialized_content()
+            .strip_prefix(BINDING_HEADER.as_slice())
+            .ok_or(CredentialError::MalformedBinding)?;
+        serde_cbor_2::from_slice(body).map_err(|_| CredentialError::MalformedBinding)
+    }
+
+    /// Read the binding out of a member's credential, checking that it
+    /// endorses the member's leaf key under the identity key it names.
+    /// Whether that identity key really is the user's is up to the caller.
+    pub fn verify(credential: &Credential, leaf_sig_key: &[u8]) -> Result<Self, CredentialError> {
+        let binding = Self::decode(credential)?;
+        if binding.leaf_signature_key != leaf_sig_key {
+            return Err(CredentialError::CredentialWrongLeafKey(binding.user_id));
+        }
+        let verifying_key = VerifyingKey::from_bytes(&binding.identity_pk)
+            .map_err(|_| CredentialError::InvalidIdentityKey)?;
+        verifying_key
+            .verify(
+                &binding.signed_payload(),
+                &Signature::from_bytes(&binding.signature),
+            )
+            .map_err(|_| CredentialError::InvalidIdentitySignature(binding.user_id.clone()))?;
+        Ok(binding)
+    }
+
+    fn signed_payload(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(BINDING_SIG_DOMAIN);
+        out.extend_from_slice(BINDING_HEADER);
+        out.extend_from_slice(&(self.context.len() as u64).to_le_bytes());
+        let fields = std::iter::once(self.user_id.as_bytes())
+            .chain(self.context.iter().map(String::as_bytes))
+            .chain(std::iter::once(self.leaf_signature_key.as_slice()));
+        for field in fields {
+            out.extend_from_slice(&(field.len() as u64).to_le_bytes());
+            out.extend_from_slice(field);
+        }
+        out.extend_from_slice(&self.identity_pk);
+        out
+    }
+}
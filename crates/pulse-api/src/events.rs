@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use pulse_types::{AvailableTrack, MediaHint};
 
 use crate::error::PulseError;
@@ -33,9 +35,17 @@ pub enum PulseEvent {
     Disconnected {
         reason: String,
     },
+    /// We were moved to another call and are reconnecting to it, keeping our
+    /// session ID and tracks.
+    Moved {
+        call_id: String,
+    },
 
     TrackAvailable(AvailableTrack),
     TrackUnavailable(String),
+    /// The server stopped one of our tracks because we may no longer
+    /// produce it; the Harmony event explains why.
+    ProduceRevoked(MediaHint),
     EpochReady(u64),
     // TODO:
     MembershipChanged {
@@ -44,12 +54,27 @@ pub enum PulseEvent {
     },
 
     KeyFrameRequested(MediaHint),
+    /// A consumer of one of our tracks reported how it receives it; feed
+    /// it to the track's [`BitrateController`](crate::BitrateController).
     ReceiverReport {
         media_hint: MediaHint,
+        /// The consumer's session.
+        session_id: String,
         lost: u32,
         received: u32,
         jitter_ms: u32,
     },
+    /// Round trip time to our Pulse node, measured every few seconds.
+    RoundTrip(Duration),
+    /// Sessions currently speaking, loudest first.
+    ActiveSpeakers(Vec<String>),
+    /// A consumed track switched simulcast layer, either as we asked or
+    /// because the server adapted it to our receiver reports. The frame
+    /// stream follows along on its own.
+    LayerSelected {
+        track_id: String,
+        layer: u8,
+    },
 
     Error(PulseError),
 }
@@ -3,13 +3,16 @@
 //! A Rust client library for interacting with the Harmony's voice server, Pulse.
 
 mod client;
+pub mod congestion;
+pub mod credential;
 mod error;
 mod events;
 mod mls;
 
 pub use client::{MediaFrame, PulseClient, PulseClientOptions, TrackHandle};
+pub use congestion::{BitrateConfig, BitrateController, BitrateDecision};
 pub use error::PulseError;
 pub use events::{CallMember, PulseEvent};
 pub use mls::{IdentityKeyResolver, MlsIdentity};
 
-pub use pulse_types::{AvailableTrack, MediaHint};
+pub use pulse_types::{AudioLevel, AvailableTrack, MAX_LAYERS, MediaHint};
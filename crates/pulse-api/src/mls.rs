@@ -1,20 +1,22 @@
 use chacha20poly1305::aead::{Aead, KeyInit, Payload};
 use chacha20poly1305::{ChaCha20Poly1305, Nonce};
-use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 use hkdf::Hkdf;
 use openmls::framing::MlsMessageBodyIn;
 use openmls::prelude::*;
 use openmls_basic_credential::SignatureKeyPair;
 use openmls_rust_crypto::OpenMlsRustCrypto;
 use openmls_traits::OpenMlsProvider;
-use pulse_types::{MEDIA_FRAME_HEADER_LEN, MediaHeader, decode_media_header, encode_media_header};
-use serde::{Deserialize, Serialize};
-use serde_with::serde_as;
+use pulse_types::{
+    AudioLevel, LEGACY_MEDIA_FRAME_HEADER_LEN, MEDIA_HEADER_VERSION, MediaHeader,
+    decode_media_header, encode_legacy_media_header, encode_media_header,
+};
 use sha2::Sha256;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tls_codec::{Deserialize as TlsDeserialize, Serialize as TlsSerialize};
 
+use crate::credential::{CredentialBinding, CredentialError};
 use crate::events::{CallMember, CallMemberState};
 
 /// Errors from client-side MLS state management and media frame protection.
@@ -26,11 +28,8 @@ pub enum MlsError {
     #[error("no active media epoch (MLS group not ready)")]
     NoActiveEpoch,
 
-    #[error("malformed CBOR credential binding")]
-    MalformedBinding,
-
-    #[error("invalid credential binding")]
-    InvalidBinding,
+    #[error(transparent)]
+    Credential(#[from] CredentialError),
 
     #[error("credential for user {user_id} is bound to call {bound_call}, not {call_id}")]
     CredentialWrongCall {
@@ -39,15 +38,6 @@ pub enum MlsError {
         call_id: String,
     },
 
-    #[error("credential for user {0} endorses a different leaf signature key")]
-    CredentialWrongLeafKey(String),
-
-    #[error("credential carries an invalid identity key")]
-    InvalidIdentityKey,
-
-    #[error("identity signature on credential for user {0} is invalid")]
-    InvalidIdentitySignature(String),
-
     #[error("commit adds a member that fails authentication: {0}")]
     CommitAddUnauthenticated(Box<MlsError>),
 
@@ -94,8 +84,11 @@ pub enum MlsError {
     #[error("media frame epoch {frame} does not match active epoch {active}")]
     EpochMismatch { frame: u64, active: u64 },
 
-    #[error("replayed or reordered media frame (seq {seq} <= {last})")]
-    ReplayedFrame { seq: u64, last: u64 },
+    #[error("replayed media frame (seq {seq})")]
+    ReplayedFrame { seq: u64 },
+
+    #[error("media frame too old to check for replay (seq {seq}, newest {newest})")]
+    StaleFrame { seq: u64, newest: u64 },
 
     #[error("failed to encrypt media payload")]
     Encrypt,
@@ -123,9 +116,12 @@ const MEDIA_LABEL: &str = "pulse-media";
 const MEDIA_KEY_LEN: usize = 32;
 const MEDIA_NONCE_LEN: usize = 12;
 const AEAD_TAG_LEN: usize = 16;
-
-const BINDING_HEADER: &[u8; 4] = b"HMC1";
-const BINDING_SIG_DOMAIN: &[u8] = b"harmony-mls-credential-v1";
+/// How long frames sealed under the previous epoch are still accepted after
+/// switching, since senders switch at slightly different times.
+const EPOCH_GRACE: Duration = Duration::from_secs(2);
+/// How far behind the newest frame of a track a frame may arrive and still
+/// be accepted.
+const REPLAY_WINDOW: u64 = 128;
 
 /// Resolver from a user id to their pinned account identity key.
 pub type IdentityKeyResolver = Arc<dyn Fn(&str) -> Option<[u8; 32]> + Send + Sync>;
@@ -148,86 +144,25 @@ impl std::fmt::Debug for MlsIdentity {
     }
 }
 
-/// The contents of an identity-bound MLS credential.
-#[serde_as]
-#[derive(Deserialize, Serialize)]
-struct CredentialBinding {
-    user_id: String,
-    session_id: String,
-    call_id: String,
-    identity_pk: [u8; 32],
-    leaf_signature_key: Vec<u8>,
-    #[serde_as(as = "[_; 64]")]
-    signature: [u8; 64],
-}
-
-impl CredentialBinding {
-    fn signed_payload(&self) -> Vec<u8> {
-        let mut out = Vec::with_capacity(
-            BINDING_SIG_DOMAIN.len()
-                + 4
-                + 8 * 4
-                + self.user_id.len()
-                + self.session_id.len()
-                + self.call_id.len()
-                + 32
-                + self.leaf_signature_key.len(),
-        );
-        out.extend_from_slice(BINDING_SIG_DOMAIN);
-        out.extend_from_slice(BINDING_HEADER);
-        for field in [
-            self.user_id.as_bytes(),
-            self.session_id.as_bytes(),
-            self.call_id.as_bytes(),
-            self.leaf_signature_key.as_slice(),
-        ] {
-            out.extend_from_slice(&(field.len() as u64).to_le_bytes());
-            out.extend_from_slice(field);
-        }
-        out.extend_from_slice(&self.identity_pk);
-        out
-    }
-
-    fn encode(&self) -> Vec<u8> {
-        serde_cbor_2::to_vec(self).unwrap()
-    }
-
-    fn decode(bytes: &[u8]) -> Result<Option<Self>> {
-        if !bytes.strip_prefix(BINDING_HEADER.as_slice()).is_some() {
-            return Err(MlsError::MalformedBinding);
-        };
-        serde_cbor_2::from_slice(bytes).map_err(|_| MlsError::MalformedBinding)
-    }
-}
-
 fn authenticate_member(
     call_id: &str,
     identity: &MlsIdentity,
     credential: &Credential,
     leaf_sig_key: &[u8],
 ) -> Result<CallMember> {
-    let content = credential.serialized_content();
-    let Some(binding) = CredentialBinding::decode(content)? else {
-        return Err(MlsError::InvalidBinding);
+    let binding = CredentialBinding::verify(credential, leaf_sig_key)?;
+    // call leaves are bound to [call id, session id]
+    let (bound_call, session_id) = match binding.context.as_slice() {
+        [bound_call, session_id] => (bound_call.clone(), session_id.clone()),
+        _ => (String::new(), String::new()),
     };
-    if binding.call_id != call_id {
+    if bound_call != call_id {
         return Err(MlsError::CredentialWrongCall {
             user_id: binding.user_id,
-            bound_call: binding.call_id,
+            bound_call,
             call_id: call_id.to_string(),
         });
     }
-    if binding.leaf_signature_key != leaf_sig_key {
-        return Err(MlsError::CredentialWrongLeafKey(binding.user_id));
-    }
-    let verifying_key =
-        VerifyingKey::from_bytes(&binding.identity_pk).map_err(|_| MlsError::InvalidIdentityKey)?;
-    verifying_key
-        .verify(
-            &binding.signed_payload(),
-            &Signature::from_bytes(&binding.signature),
-        )
-        .map_err(|_| MlsError::InvalidIdentitySignature(binding.user_id.clone()))?;
 
     let pinned = (identity.trusted_keys)(&binding.user_id);
     let state = match pinned {
@@ -236,7 +171,7 @@ fn authenticate_member(
         None => CallMemberState::Unverified,
     };
     Ok(CallMember {
-        session_id: binding.session_id,
+        session_id,
         user_id: binding.user_id,
         state,
     })
@@ -253,11 +188,15 @@ pub(crate) struct MlsClient {
     group: Option<MlsGroup>,
     pending_commit: Option<Vec<u8>>,
     active_epoch: Option<(u64, [u8; MEDIA_KEY_LEN])>,
+    // the epoch active before this one, with when it was replaced
+    previous_epoch: Option<(u64, [u8; MEDIA_KEY_LEN], Instant)>,
     staged_secrets: HashMap<u64, [u8; MEDIA_KEY_LEN]>,
     // track name -> next send sequence
     send_seqs: HashMap<String, u64>,
-    // (sender session id, track name) -> (epoch, highest accepted sequence)
-    recv_seqs: HashMap<(String, String), (u64, u64)>,
+    // newest media header version everyone in the call reads
+    media_header_version: u8,
+    // (epoch, sender session id, track name) -> accepted sequences
+    recv_windows: HashMap<(u64, String, String), ReplayWindow>,
     call_id: String,
     session_id: String,
     identity: MlsIdentity,
@@ -273,21 +212,14 @@ impl MlsClient {
             .store(provider.storage())
             .map_err(|_| MlsError::SignatureStore)?;
 
-        let signing_key = SigningKey::from_bytes(&identity.signing_seed);
-        let mut binding = CredentialBinding {
-            user_id: identity.user_id.clone(),
-            session_id: session_id.to_string(),
-            call_id: call_id.to_string(),
-            identity_pk: signing_key.verifying_key().to_bytes(),
-            leaf_signature_key: signer.public().to_vec(),
-            signature: [0u8; 64],
-        };
-        binding.signature = signing_key.sign(&binding.signed_payload()).to_bytes();
-        let credential_bytes = binding.encode();
-        let credential = BasicCredential::new(credential_bytes);
-
+        let binding = CredentialBinding::sign(
+            &identity.user_id,
+            vec![call_id.to_string(), session_id.to_string()],
+            signer.public(),
+            &identity.signing_seed,
+        );
         let credential_with_key = CredentialWithKey {
-            credential: credential.into(),
+            credential: binding.credential(),
             signature_key: signer.public().into(),
         };
 
@@ -298,9 +230,11 @@ impl MlsClient {
             group: None,
             pending_commit: None,
             active_epoch: None,
+            previous_epoch: None,
             staged_secrets: HashMap::new(),
             send_seqs: HashMap::new(),
-            recv_seqs: HashMap::new(),
+            media_header_version: 0,
+            recv_windows: HashMap::new(),
             call_id: call_id.to_string(),
             session_id: session_id.to_string(),
             identity: identity.clone(),
@@ -386,7 +320,8 @@ impl MlsClient {
 
         self.group = Some(group);
         self.pending_commit = None;
-        self.recv_seqs.clear();
+        self.recv_windows.clear();
+        self.previous_epoch = None;
         self.staged_secrets.clear();
 
         let secret = self.export_base_secret()?;
@@ -573,7 +508,8 @@ impl MlsClient {
         );
         self.group = Some(group);
         self.pending_commit = None;
-        self.recv_seqs.clear();
+        self.recv_windows.clear();
+        self.previous_epoch = None;
         self.staged_secrets.clear();
 
         self.active_epoch = None;
@@ -600,20 +536,70 @@ impl MlsClient {
     /// Activate the base secret exported at the latest commit for `epoch`.
     ///
     /// All members receive `EpochReady` once every member has acked the
-    /// commit; from this point only frames sealed under `epoch` are accepted.
+    /// commit; from this point we seal under `epoch`. Frames sealed under
+    /// the previous epoch are still accepted for [`EPOCH_GRACE`].
     pub fn on_epoch_ready(&mut self, epoch: u64) {
         match self.staged_secrets.remove(&epoch) {
-            Some(secret) => self.active_epoch = Some((epoch, secret)),
+            Some(secret) => {
+                self.previous_epoch = self
+                    .active_epoch
+                    .replace((epoch, secret))
+                    .map(|(previous, secret)| (previous, secret, Instant::now()));
+            }
             None => tracing::warn!(epoch, "EpochReady without a staged base secret"),
         }
         self.staged_secrets.retain(|&e, _| e > epoch);
+        let previous = self.previous_epoch.map(|(previous, ..)| previous);
+        self.recv_windows
+            .retain(|(e, ..), _| *e >= epoch || Some(*e) == previous);
     }
 
-    /// Encrypt one media frame for the track named `track_name`.
+    /// The base secret to open a frame sealed under `epoch` with: the active
+    /// epoch's, the previous one's during its grace period, or a staged one's
+    /// for senders that switched before we did.
+    fn receive_secret(&mut self, epoch: u64) -> Result<[u8; MEDIA_KEY_LEN]> {
+        if let Some((previous, _, retired_at)) = self.previous_epoch
+            && retired_at.elapsed() >= EPOCH_GRACE
+        {
+            self.previous_epoch = None;
+            self.recv_windows.retain(|(e, ..), _| *e != previous);
+        }
+        if let Some((active, secret)) = self.active_epoch
+            && active == epoch
+        {
+            return Ok(secret);
+        }
+        if let Some((previous, secret, _)) = self.previous_epoch
+            && previous == epoch
+        {
+            return Ok(secret);
+        }
+        if let Some(secret) = self.staged_secrets.get(&epoch) {
+            return Ok(*secret);
+        }
+        match self.active_epoch {
+            Some((active, _)) => Err(MlsError::EpochMismatch {
+                frame: epoch,
+                active,
+            }),
+            None => Err(MlsError::NoActiveEpoch),
+        }
+    }
+
+    /// Write media headers no newer than `version`, the newest every member
+    /// of the call reads.
+    pub fn set_media_header_version(&mut self, version: u8) {
+        self.media_header_version = version;
+    }
+
+    /// Encrypt one media frame for the track named `track_name`. The audio
+    /// level, if any, goes in the plaintext header, unless someone in the
+    /// call only reads legacy headers.
     pub fn seal_media(
         &mut self,
         track_name: &str,
         capture_ts_us: u64,
+        audio_level: Option<AudioLevel>,
         plaintext: &[u8],
     ) -> Result<Vec<u8>> {
         let (epoch, base) = self.active_epoch.ok_or(MlsError::NoActiveEpoch)?;
@@ -623,10 +609,15 @@ impl MlsClient {
             epoch,
             sequence: *seq,
             capture_ts_us,
+            audio_level,
         };
         *seq += 1;
 
-        let header_bytes = encode_media_header(&header);
+        let header_bytes = if self.media_header_version >= MEDIA_HEADER_VERSION {
+            encode_media_header(&header).to_vec()
+        } else {
+            encode_legacy_media_header(&header).to_vec()
+        };
         let key = derive_media_key(&base, &self.session_id, track_name);
         let cipher = ChaCha20Poly1305::new(&key.into());
         let aad = media_aad(&header_bytes, &self.session_id, track_name);
@@ -641,7 +632,7 @@ impl MlsClient {
             )
             .map_err(|_| MlsError::Encrypt)?;
 
-        let mut out = Vec::with_capacity(MEDIA_FRAME_HEADER_LEN + ciphertext.len());
+        let mut out = Vec::with_capacity(header_bytes.len() + ciphertext.len());
         out.extend_from_slice(&header_bytes);
         out.extend_from_slice(&ciphertext);
         Ok(out)
@@ -655,30 +646,21 @@ impl MlsClient {
         track_name: &str,
         frame: &[u8],
     ) -> Result<(MediaHeader, Vec<u8>)> {
-        if frame.len() < MEDIA_FRAME_HEADER_LEN + AEAD_TAG_LEN {
+        if frame.len() < LEGACY_MEDIA_FRAME_HEADER_LEN + AEAD_TAG_LEN {
             return Err(MlsError::FrameTooShort);
         }
-        let header = decode_media_header(frame).ok_or(MlsError::MalformedHeader)?;
-        let header_bytes = &frame[..MEDIA_FRAME_HEADER_LEN];
-        let ciphertext = &frame[MEDIA_FRAME_HEADER_LEN..];
-
-        let (epoch, base) = self.active_epoch.ok_or(MlsError::NoActiveEpoch)?;
-        if header.epoch != epoch {
-            return Err(MlsError::EpochMismatch {
-                frame: header.epoch,
-                active: epoch,
-            });
+        let (header, header_len) = decode_media_header(frame).ok_or(MlsError::MalformedHeader)?;
+        if frame.len() < header_len + AEAD_TAG_LEN {
+            return Err(MlsError::FrameTooShort);
         }
+        let header_bytes = &frame[..header_len];
+        let ciphertext = &frame[header_len..];
 
-        let seq_key = (sender_id.to_string(), track_name.to_string());
-        if let Some(&(seen_epoch, last_seq)) = self.recv_seqs.get(&seq_key)
-            && seen_epoch == epoch
-            && header.sequence <= last_seq
-        {
-            return Err(MlsError::ReplayedFrame {
-                seq: header.sequence,
-                last: last_seq,
-            });
+        let base = self.receive_secret(header.epoch)?;
+
+        let window_key = (header.epoch, sender_id.to_string(), track_name.to_string());
+        if let Some(window) = self.recv_windows.get(&window_key) {
+            window.check(header.sequence)?;
         }
 
         let key = derive_media_key(&base, sender_id, track_name);
@@ -695,7 +677,11 @@ impl MlsClient {
             )
             .map_err(|_| MlsError::Decrypt)?;
 
-        self.recv_seqs.insert(seq_key, (epoch, header.sequence));
+        // only authenticated frames move the window
+        self.recv_windows
+            .entry(window_key)
+            .or_default()
+            .accept(header.sequence);
         Ok((header, plaintext))
     }
 
@@ -713,6 +699,54 @@ impl MlsClient {
     }
 }
 
+/// Sliding window of the sequences accepted on one track, as in SRTP and
+/// DTLS: frames may arrive out of order, but each only once.
+#[derive(Debug, Default)]
+struct ReplayWindow {
+    newest: Option<u64>,
+    // bit n set: sequence newest - n was accepted
+    seen: u128,
+}
+
+impl ReplayWindow {
+    fn check(&self, seq: u64) -> Result<()> {
+        let Some(newest) = self.newest else {
+            return Ok(());
+        };
+        if seq > newest {
+            return Ok(());
+        }
+        let age = newest - seq;
+        if age >= REPLAY_WINDOW {
+            return Err(MlsError::StaleFrame { seq, newest });
+        }
+        if self.seen & (1 << age) != 0 {
+            return Err(MlsError::ReplayedFrame { seq });
+        }
+        Ok(())
+    }
+
+    fn accept(&mut self, seq: u64) {
+        match self.newest {
+            Some(newest) if seq <= newest => self.seen |= 1 << (newest - seq),
+            Some(newest) => {
+                let shift = seq - newest;
+                self.seen = if shift >= REPLAY_WINDOW {
+                    0
+                } else {
+                    self.seen << shift
+                };
+                self.seen |= 1;
+                self.newest = Some(seq);
+            }
+            None => {
+                self.seen = 1;
+                self.newest = Some(seq);
+            }
+        }
+    }
+}
+
 fn derive_media_key(
     base: &[u8; MEDIA_KEY_LEN],
     sender_id: &str,
@@ -743,3 +777,128 @@ fn nonce_for_sequence(sequence: u64) -> Nonce {
     nonce[4..].copy_from_slice(&sequence.to_le_bytes());
     nonce.into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACK: &str = "microphone";
+    const SECRET: [u8; MEDIA_KEY_LEN] = [7; MEDIA_KEY_LEN];
+
+    fn window(accepted: &[u64]) -> ReplayWindow {
+        let mut window = ReplayWindow::default();
+        for &seq in accepted {
+            window.check(seq).unwrap();
+            window.accept(seq);
+        }
+        window
+    }
+
+    /// A client with media keys for `epoch` active, skipping the MLS group.
+    fn client(session_id: &str, epoch: u64) -> MlsClient {
+        let identity = MlsIdentity {
+            user_id: format!("user-{session_id}"),
+            signing_seed: [1; 32],
+            trusted_keys: Arc::new(|_| None),
+        };
+        let mut client = MlsClient::new(session_id, "call", identity).unwrap();
+        client.active_epoch = Some((epoch, SECRET));
+        client
+    }
+
+    #[test]
+    fn window_accepts_in_order() {
+        let window = window(&[0, 1, 2, 3]);
+        assert_eq!(window.newest, Some(3));
+        window.check(4).unwrap();
+    }
+
+    #[test]
+    fn window_accepts_reordered_within_window() {
+        let mut window = window(&[0, 200]);
+        for seq in [199, 150, 200 - REPLAY_WINDOW + 1, 201] {
+            window.check(seq).unwrap();
+            window.accept(seq);
+        }
+        assert_eq!(window.newest, Some(201));
+    }
+
+    #[test]
+    fn window_rejects_duplicates() {
+        let window = window(&[0, 5, 3]);
+        for seq in [0, 3, 5] {
+            assert!(matches!(
+                window.check(seq),
+                Err(MlsError::ReplayedFrame { seq: s }) if s == seq
+            ));
+        }
+        window.check(4).unwrap();
+    }
+
+    #[test]
+    fn window_rejects_older_than_window() {
+        let window = window(&[REPLAY_WINDOW + 10]);
+        assert!(matches!(
+            window.check(10),
+            Err(MlsError::StaleFrame { seq: 10, .. })
+        ));
+        window.check(11).unwrap();
+    }
+
+    #[test]
+    fn window_forgets_after_large_jump() {
+        let mut window = window(&[0, 1]);
+        window.accept(1 + REPLAY_WINDOW);
+        window.check(2).unwrap();
+        assert!(window.check(1).is_err());
+    }
+
+    #[test]
+    fn open_media_rejects_replayed_frame() {
+        let mut sender = client("a", 1);
+        let mut receiver = client("b", 1);
+        let frame = sender.seal_media(TRACK, 0, None, b"hello").unwrap();
+
+        let (header, plaintext) = receiver.open_media("a", TRACK, &frame).unwrap();
+        assert_eq!(header.sequence, 0);
+        assert_eq!(plaintext, b"hello");
+        assert!(matches!(
+            receiver.open_media("a", TRACK, &frame),
+            Err(MlsError::ReplayedFrame { seq: 0 })
+        ));
+    }
+
+    #[test]
+    fn previous_epoch_accepted_during_grace() {
+        let mut sender = client("a", 1);
+        let mut receiver = client("b", 2);
+        receiver.previous_epoch = Some((1, SECRET, Instant::now()));
+
+        let frame = sender.seal_media(TRACK, 0, None, b"late").unwrap();
+        receiver.open_media("a", TRACK, &frame).unwrap();
+        assert_eq!(receiver.receive_secret(1).unwrap(), SECRET);
+    }
+
+    #[test]
+    fn previous_epoch_rejected_after_grace() {
+        let mut sender = client("a", 1);
+        let mut receiver = client("b", 2);
+        let retired_at = Instant::now() - EPOCH_GRACE;
+        receiver.previous_epoch = Some((1, SECRET, retired_at));
+        receiver
+            .recv_windows
+            .insert((1, "a".to_string(), TRACK.to_string()), window(&[0]));
+
+        let frame = sender.seal_media(TRACK, 0, None, b"late").unwrap();
+        assert!(matches!(
+            receiver.open_media("a", TRACK, &frame),
+            Err(MlsError::EpochMismatch {
+                frame: 1,
+                active: 2
+            })
+        ));
+        assert!(receiver.previous_epoch.is_none());
+        assert!(receiver.recv_windows.is_empty());
+        receiver.receive_secret(2).unwrap();
+    }
+}
@@ -35,6 +35,36 @@ impl FromStr for Region {
     }
 }
 
+impl Region {
+    /// Approximate location of the region's data centres, in degrees.
+    fn coordinates(&self) -> (f64, f64) {
+        match self {
+            Region::Canada => (45.5, -73.6),
+            Region::UsCentral => (41.9, -93.1),
+            Region::UsEast => (39.0, -77.5),
+            Region::UsWest => (45.6, -121.2),
+            Region::Europe => (50.1, 8.7),
+            Region::Asia => (1.35, 103.8),
+            Region::SouthAmerica => (-23.5, -46.6),
+            Region::Australia => (-33.9, 151.2),
+            Region::Africa => (-26.2, 28.0),
+        }
+    }
+
+    /// Great-circle distance between two regions in kilometres, used to
+    /// find the nearest region when the preferred one has no capacity.
+    pub fn distance_km(&self, other: &Region) -> f64 {
+        const EARTH_RADIUS_KM: f64 = 6371.0;
+        let (lat1, lon1) = self.coordinates();
+        let (lat2, lon2) = other.coordinates();
+        let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lon = (lon2 - lon1).to_radians();
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub enum MediaHint {
     Audio,
@@ -48,10 +78,17 @@ pub enum MediaHint {
 pub enum ControlC2S {
     Join {
         key_package: Vec<u8>, // Serialized MLS KeyPackage
+        // newest media header version we read, 0 for clients from before
+        // headers were versioned
+        #[serde(default)]
+        media_header_version: u8,
     },
     StartProduce {
         request_id: u64,
         media_hint: MediaHint,
+        // simulcast layers the track is published in, see `layer_track_name`
+        #[serde(default = "single_layer")]
+        layers: u8,
     },
     StopProduce {
         request_id: u64,
@@ -76,6 +113,16 @@ pub enum ControlC2S {
         received: u32,
         jitter_ms: u32,
     },
+    // Pick the simulcast layer to receive of a track; the server may still
+    // step down from it while our receiver reports show congestion
+    SelectLayer {
+        track_id: String,
+        layer: u8,
+    },
+    // Echoed back as Pong by the node we're connected to, to measure RTT
+    Ping {
+        sent_us: u64,
+    },
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -84,6 +131,9 @@ pub struct AvailableTrack {
     pub media_hint: MediaHint,
     // indicates which session (and therefore user) this track belongs to
     pub session_id: String,
+    // simulcast layers the producer publishes
+    #[serde(default = "single_layer")]
+    pub layers: u8,
 }
 
 /// Server-to-client control messages.
@@ -96,6 +146,14 @@ pub enum ControlS2C {
     Disconnected {
         reconnect: Option<(String, String)>, // (new_server_address, new_token)
     },
+    // The session was moved to another call; reconnect with the token, under
+    // the same session id, to the given server if the call is hosted elsewhere
+    Moved {
+        call_id: String,
+        token: String,
+        #[serde(default)]
+        server_url: Option<String>,
+    },
     ProduceStarted {
         request_id: u64,
         track_id: String,
@@ -113,6 +171,12 @@ pub enum ControlS2C {
     TrackUnavailable {
         id: String,
     },
+    // The server stopped one of our tracks, e.g. because a channel manager
+    // muted us or disabled our video
+    ProduceRevoked {
+        track_id: String,
+        media_hint: MediaHint,
+    },
     // MLS coordination messages
     MlsProposals {
         proposals: Vec<Vec<u8>>,
@@ -135,38 +199,179 @@ pub enum ControlS2C {
     },
     ReceiverReport {
         track_id: String,
+        // the consumer that sent the report
+        #[serde(default)]
+        session_id: String,
         lost: u32,
         received: u32,
         jitter_ms: u32,
     },
+    Pong {
+        sent_us: u64,
+    },
+    // Which simulcast layer of a track we should receive, either because we
+    // selected it or because the server adapted to our receiver reports
+    LayerSelected {
+        track_id: String,
+        layer: u8,
+    },
+    // Sessions currently speaking in the call, loudest first, going by the
+    // audio levels in their microphone frames
+    ActiveSpeakers {
+        session_ids: Vec<String>,
+    },
+    // Newest media header version every member of the call reads; frames
+    // must not be encoded with a newer one. Sent on joining and whenever it
+    // changes
+    MediaHeaderVersion {
+        version: u8,
+    },
 }
 
 // TODO: optimize
-/// Plaintext header prepended to every encrypted media frame.
+/// Plaintext header prepended to every encrypted media frame. It is part of
+/// the frame's AAD, so receivers can trust it; Pulse reads it without keys.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct MediaHeader {
     pub epoch: u64,
     pub sequence: u64,
     pub capture_ts_us: u64,
+    /// Set on microphone frames so Pulse can tell who is speaking.
+    pub audio_level: Option<AudioLevel>,
+}
+
+/// Audio level of one frame, in the spirit of RFC 6464.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AudioLevel {
+    /// Level in -dBov, from 0 (loudest) to 127 (silence).
+    pub level: u8,
+    /// Whether the sender thinks the frame contains voice.
+    pub voice: bool,
 }
 
-pub const MEDIA_FRAME_HEADER_LEN: usize = 24;
+/// Frames at least this loud count as voice when the sender has no better
+/// voice activity detection.
+pub const VOICE_LEVEL_THRESHOLD: u8 = 50;
+
+impl AudioLevel {
+    pub const SILENCE: u8 = 127;
+
+    /// Level of a frame of PCM samples in `[-1.0, 1.0]`, guessing voice
+    /// activity from the level alone.
+    pub fn from_samples(samples: &[f32]) -> Self {
+        if samples.is_empty() {
+            return Self::from_dbov(Self::SILENCE);
+        }
+        let mean_square = samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32;
+        let dbov = 10.0 * mean_square.max(f32::MIN_POSITIVE).log10();
+        Self::from_dbov((-dbov).clamp(0.0, Self::SILENCE as f32) as u8)
+    }
+
+    /// Level from -dBov, guessing voice activity from the level alone.
+    pub fn from_dbov(level: u8) -> Self {
+        let level = level.min(Self::SILENCE);
+        AudioLevel {
+            level,
+            voice: level <= VOICE_LEVEL_THRESHOLD,
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        ((self.voice as u8) << 7) | self.level.min(Self::SILENCE)
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        AudioLevel {
+            level: byte & 0x7f,
+            voice: byte & 0x80 != 0,
+        }
+    }
+}
+
+/// Length of the original, unversioned media header.
+pub const LEGACY_MEDIA_FRAME_HEADER_LEN: usize = 24;
+/// Length of the media header written by [`encode_media_header`].
+pub const MEDIA_FRAME_HEADER_LEN: usize = 26;
+/// Version of the media header written by [`encode_media_header`].
+pub const MEDIA_HEADER_VERSION: u8 = 1;
+
+const HAS_AUDIO_LEVEL: u8 = 0x01;
+
+// Epochs never come near 2^56, so the top byte of the epoch field doubles as
+// the header version. It is zero in legacy headers, which are still read.
+//
+// Receivers from before versioned headers take the version byte for part of
+// the epoch and drop the frame. Clients therefore only write version 1 once
+// the node reports, through `ControlS2C::MediaHeaderVersion`, that everyone in
+// the call reads it; until then they write legacy headers without an audio
+// level. Nodes from before that message never report it, so upgrade Pulse
+// before relying on audio levels.
+//
+// Version 1 layout:
+//   0..7   epoch (little endian, 56 bits)
+//   7      version
+//   8..16  sequence
+//   16..24 capture timestamp in microseconds
+//   24     flags
+//   25     audio level, if flagged
 
 pub fn encode_media_header(header: &MediaHeader) -> [u8; MEDIA_FRAME_HEADER_LEN] {
     let mut out = [0u8; MEDIA_FRAME_HEADER_LEN];
+    out[0..7].copy_from_slice(&header.epoch.to_le_bytes()[..7]);
+    out[7] = MEDIA_HEADER_VERSION;
+    out[8..16].copy_from_slice(&header.sequence.to_le_bytes());
+    out[16..24].copy_from_slice(&header.capture_ts_us.to_le_bytes());
+    if let Some(level) = header.audio_level {
+        out[24] |= HAS_AUDIO_LEVEL;
+        out[25] = level.to_byte();
+    }
+    out
+}
+
+/// Encode a header the way clients from before versioned headers did. The
+/// audio level doesn't fit and is dropped.
+pub fn encode_legacy_media_header(header: &MediaHeader) -> [u8; LEGACY_MEDIA_FRAME_HEADER_LEN] {
+    let mut out = [0u8; LEGACY_MEDIA_FRAME_HEADER_LEN];
     out[0..8].copy_from_slice(&header.epoch.to_le_bytes());
     out[8..16].copy_from_slice(&header.sequence.to_le_bytes());
     out[16..24].copy_from_slice(&header.capture_ts_us.to_le_bytes());
     out
 }
 
-pub fn decode_media_header(buf: &[u8]) -> Option<MediaHeader> {
-    let bytes = buf.get(..MEDIA_FRAME_HEADER_LEN)?;
-    Some(MediaHeader {
-        epoch: u64::from_le_bytes(bytes[0..8].try_into().ok()?),
-        sequence: u64::from_le_bytes(bytes[8..16].try_into().ok()?),
-        capture_ts_us: u64::from_le_bytes(bytes[16..24].try_into().ok()?),
-    })
+/// Decode the header at the start of a media frame, returning it with its
+/// length in bytes.
+pub fn decode_media_header(buf: &[u8]) -> Option<(MediaHeader, usize)> {
+    let bytes = buf.get(..LEGACY_MEDIA_FRAME_HEADER_LEN)?;
+    let sequence = u64::from_le_bytes(bytes[8..16].try_into().ok()?);
+    let capture_ts_us = u64::from_le_bytes(bytes[16..24].try_into().ok()?);
+    match bytes[7] {
+        0 => Some((
+            MediaHeader {
+                epoch: u64::from_le_bytes(bytes[0..8].try_into().ok()?),
+                sequence,
+                capture_ts_us,
+                audio_level: None,
+            },
+            LEGACY_MEDIA_FRAME_HEADER_LEN,
+        )),
+        MEDIA_HEADER_VERSION => {
+            let bytes = buf.get(..MEDIA_FRAME_HEADER_LEN)?;
+            let mut epoch = [0u8; 8];
+            epoch[..7].copy_from_slice(&bytes[0..7]);
+            let audio_level =
+                (bytes[24] & HAS_AUDIO_LEVEL != 0).then(|| AudioLevel::from_byte(bytes[25]));
+            Some((
+                MediaHeader {
+                    epoch: u64::from_le_bytes(epoch),
+                    sequence,
+                    capture_ts_us,
+                    audio_level,
+                },
+                MEDIA_FRAME_HEADER_LEN,
+            ))
+        }
+        _ => None,
+    }
 }
 
 pub mod track_names {
@@ -187,6 +392,29 @@ pub fn track_name_for_hint(hint: &MediaHint) -> &'static str {
     }
 }
 
+/// Most simulcast layers a track can be published in. Layer 0 is the full
+/// quality encode and each further layer halves the resolution again.
+pub const MAX_LAYERS: u8 = 3;
+
+fn single_layer() -> u8 {
+    1
+}
+
+/// Name of the MoQ track carrying one simulcast layer of a track. Layer 0
+/// keeps the plain track name, so tracks without simulcast are unchanged.
+pub fn layer_track_name(hint: &MediaHint, layer: u8) -> String {
+    let name = track_name_for_hint(hint);
+    match layer {
+        0 => name.to_string(),
+        layer => format!("{name}-{layer}"),
+    }
+}
+
+/// Simulcast only makes sense for video.
+pub fn supports_layers(hint: &MediaHint) -> bool {
+    matches!(hint, MediaHint::Video | MediaHint::ScreenVideo)
+}
+
 pub fn priority_for_hint(hint: &MediaHint) -> u8 {
     match hint {
         MediaHint::Audio => 3,
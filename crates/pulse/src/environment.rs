@@ -1,4 +1,5 @@
 use std::env;
+use std::path::PathBuf;
 
 use lazy_static::lazy_static;
 use pulse_types::Region;
@@ -16,6 +17,14 @@ lazy_static! {
         .expect("REGION must be set")
         .parse()
         .expect("Invalid region");
+    // PEM root other nodes' certificates are checked against on cascade
+    // links, in place of the system roots
+    pub static ref LINK_TLS_ROOT: Option<PathBuf> = env::var("LINK_TLS_ROOT").ok().map(PathBuf::from);
+    // skip checking other nodes' certificates on cascade links, for development
+    pub static ref LINK_TLS_DISABLE_VERIFY: bool = env::var("LINK_TLS_DISABLE_VERIFY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(false);
 
     /// External sender identity for MLS group management
     /// Generated once at startup and used for all Add/Remove proposals
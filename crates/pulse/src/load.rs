@@ -0,0 +1,98 @@
+use std::{
+    fs,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
+
+use common::NodeLoad;
+
+use crate::wt::{GLOBAL_CALLS, GLOBAL_SESSIONS, cascade::GLOBAL_RELAYED};
+
+/// Set while the node is draining: it keeps serving the calls it has, but
+/// Harmony places no new calls on it.
+pub static DRAINING: AtomicBool = AtomicBool::new(false);
+
+pub fn is_draining() -> bool {
+    DRAINING.load(Ordering::SeqCst)
+}
+
+/// Note when a draining node has no sessions left, neither hosted nor
+/// relayed, so it can be shut down.
+pub fn report_if_drained() {
+    if is_draining() && GLOBAL_SESSIONS.is_empty() && GLOBAL_RELAYED.is_empty() {
+        info!("Drain complete: no sessions left on this node");
+    }
+}
+
+/// Samples the load reported with every heartbeat. CPU and egress are read
+/// from `/proc` and averaged since the previous sample; where that isn't
+/// available they are reported as zero.
+#[derive(Default)]
+pub struct LoadSampler {
+    cpu: Option<(u64, u64)>,
+    egress: Option<(u64, Instant)>,
+}
+
+impl LoadSampler {
+    pub fn sample(&mut self) -> NodeLoad {
+        let cpu = read_cpu_times()
+            .and_then(|(busy, total)| {
+                let (last_busy, last_total) = self.cpu.replace((busy, total))?;
+                let elapsed = total.saturating_sub(last_total);
+                (elapsed > 0).then(|| busy.saturating_sub(last_busy) as f32 / elapsed as f32)
+            })
+            .unwrap_or_default();
+
+        let now = Instant::now();
+        let egress_bps = read_transmitted_bytes()
+            .and_then(|bytes| {
+                let (last_bytes, last_at) = self.egress.replace((bytes, now))?;
+                let elapsed = now.duration_since(last_at).as_secs_f64();
+                (elapsed > 0.0)
+                    .then(|| (bytes.saturating_sub(last_bytes) as f64 * 8.0 / elapsed) as u64)
+            })
+            .unwrap_or_default();
+
+        NodeLoad {
+            // relayed sessions count on both ends: each node carries their media
+            sessions: (GLOBAL_SESSIONS.len() + GLOBAL_RELAYED.len()) as u32,
+            calls: GLOBAL_CALLS.len() as u32,
+            egress_bps,
+            cpu,
+        }
+    }
+}
+
+/// Busy and total jiffies across all cores.
+fn read_cpu_times() -> Option<(u64, u64)> {
+    let stat = fs::read_to_string("/proc/stat").ok()?;
+    let times: Vec<u64> = stat
+        .lines()
+        .next()?
+        .strip_prefix("cpu ")?
+        .split_whitespace()
+        .take(8)
+        .filter_map(|t| t.parse().ok())
+        .collect();
+    let total: u64 = times.iter().sum();
+    // idle + iowait
+    let idle = times.get(3)? + times.get(4).copied().unwrap_or_default();
+    Some((total.saturating_sub(idle), total))
+}
+
+/// Bytes transmitted on every interface except loopback.
+fn read_transmitted_bytes() -> Option<u64> {
+    let dev = fs::read_to_string("/proc/net/dev").ok()?;
+    Some(
+        dev.lines()
+            .skip(2)
+            .filter_map(|line| {
+                let (name, counters) = line.split_once(':')?;
+                if name.trim() == "lo" {
+                    return None;
+                }
+                counters.split_whitespace().nth(8)?.parse::<u64>().ok()
+            })
+            .sum(),
+    )
+}
@@ -3,6 +3,7 @@ extern crate tracing;
 
 pub mod environment;
 pub mod errors;
+pub mod load;
 pub mod metrics;
 pub mod mls;
 pub mod nats;
@@ -13,8 +13,12 @@ use pulse_types::{ControlS2C, MediaHint};
 use tokio::{task, time};
 
 use crate::environment::{PUBLIC_ADDRESS, REGION};
+use crate::load::{DRAINING, LoadSampler, is_draining};
 use crate::redis::INSTANCE_ID;
 
+/// How often the node reports the calls and sessions it hosts.
+const INVENTORY_INTERVAL: Duration = Duration::from_secs(30);
+
 static CLIENT: OnceLock<async_nats::Client> = OnceLock::new();
 static JETSTREAM: OnceLock<Context> = OnceLock::new();
 
@@ -39,12 +43,13 @@ fn description_event() -> NodeEvent {
         event: NodeEventKind::Description(NodeDescription {
             region: *REGION,
             server_address: PUBLIC_ADDRESS.clone(),
+            draining: is_draining(),
         }),
         id: INSTANCE_ID.clone(),
     }
 }
 
-async fn publish_node(subject: String, event: &NodeEvent) {
+pub async fn publish_node(subject: String, event: &NodeEvent) {
     let payload = match serde_cbor_2::to_vec(event) {
         Ok(payload) => payload,
         Err(e) => {
@@ -103,15 +108,57 @@ pub fn listen() {
 
     // heartbeat ping
     task::spawn(async move {
+        let mut sampler = LoadSampler::default();
         loop {
             let event = NodeEvent {
-                event: NodeEventKind::Ping,
+                event: NodeEventKind::Ping {
+                    load: sampler.sample(),
+                    draining: is_draining(),
+                },
                 id: INSTANCE_ID.clone(),
             };
             publish_node(SUBJECT_NODES_ALL.to_string(), &event).await;
             time::sleep(Duration::from_secs(5)).await;
         }
     });
+
+    // inventory, so the main server can restore or end calls it lost track of
+    task::spawn(async move {
+        loop {
+            time::sleep(INVENTORY_INTERVAL).await;
+            let event = NodeEvent {
+                event: NodeEventKind::Inventory {
+                    calls: crate::wt::inventory(),
+                },
+                id: INSTANCE_ID.clone(),
+            };
+            publish_node(SUBJECT_NODES_ALL.to_string(), &event).await;
+        }
+    });
+
+    // SIGUSR1 toggles draining, re-announcing the node so it takes effect
+    // before the next ping
+    #[cfg(unix)]
+    task::spawn(async move {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut signals = match signal(SignalKind::user_defined1()) {
+            Ok(signals) => signals,
+            Err(e) => {
+                error!("Failed to listen for SIGUSR1: {:?}", e);
+                return;
+            }
+        };
+        while signals.recv().await.is_some() {
+            let draining = !DRAINING.fetch_xor(true, Ordering::SeqCst);
+            if draining {
+                info!("Draining: no new calls will be placed on this node");
+            } else {
+                info!("Drain cancelled: accepting new calls again");
+            }
+            publish_node(SUBJECT_NODES_ALL.to_string(), &description_event()).await;
+        }
+    });
 }
 
 async fn handle_node_event(payload: NodeEvent) {
@@ -164,6 +211,23 @@ async fn handle_node_event(payload: NodeEvent) {
             }
         }
 
+        NodeEvent {
+            event: NodeEventKind::UserGrantsChange { id, grants },
+            ..
+        } => {
+            if let Some(session) = crate::wt::GLOBAL_SESSIONS.get(&id).map(|s| s.clone()) {
+                session
+                    .can_listen
+                    .store(grants.can_listen, Ordering::SeqCst);
+                session.can_speak.store(grants.can_speak, Ordering::SeqCst);
+                session.can_video.store(grants.can_video, Ordering::SeqCst);
+                session
+                    .can_screen
+                    .store(grants.can_screen, Ordering::SeqCst);
+                session.revoke_producers();
+            }
+        }
+
         NodeEvent {
             event: NodeEventKind::UserDisconnect { id, .. },
             ..
@@ -199,6 +263,79 @@ async fn handle_node_event(payload: NodeEvent) {
             }
         }
 
+        NodeEvent {
+            event:
+                NodeEventKind::SessionMoved {
+                    id,
+                    call_id,
+                    target_token,
+                    target_server,
+                },
+            ..
+        } => {
+            if let Some(session) = crate::wt::GLOBAL_SESSIONS.get(&id) {
+                session
+                    .message_tx
+                    .send(ControlS2C::Moved {
+                        call_id,
+                        token: target_token,
+                        server_url: (!target_server.is_empty()).then_some(target_server),
+                    })
+                    .ok();
+
+                session.close("User moved to another call");
+            }
+        }
+
+        NodeEvent {
+            id,
+            event:
+                NodeEventKind::RelayJoin {
+                    connection,
+                    token,
+                    session,
+                    key_package,
+                    media_header_version,
+                },
+        } => {
+            let join = crate::wt::JoinRequest {
+                key_package,
+                media_header_version,
+            };
+            crate::wt::cascade::accept_join(id, connection, token, session, join).await;
+        }
+
+        NodeEvent {
+            event:
+                NodeEventKind::RelayC2S {
+                    connection,
+                    message,
+                },
+            ..
+        } => {
+            crate::wt::cascade::accept_c2s(&connection, message).await;
+        }
+
+        NodeEvent {
+            event:
+                NodeEventKind::RelayS2C {
+                    connection,
+                    message,
+                },
+            ..
+        } => {
+            crate::wt::cascade::deliver(&connection, message);
+        }
+
+        NodeEvent {
+            event: NodeEventKind::RelayClose { connection },
+            ..
+        } => {
+            // sent either way; each side knows only its own end
+            crate::wt::cascade::accept_close(&connection);
+            crate::wt::cascade::close(&connection);
+        }
+
         NodeEvent {
             event: NodeEventKind::CallEnded { call_id },
             ..
@@ -1,13 +1,19 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::HashSet,
+    sync::{
+        Arc,
+        atomic::{AtomicU8, Ordering},
+    },
+};
 
 use dashmap::DashMap;
 use opentelemetry::KeyValue;
-use pulse_types::{AvailableTrack, ControlS2C};
+use pulse_types::{AvailableTrack, ControlS2C, MEDIA_HEADER_VERSION, MediaHint};
 use tokio::sync::Mutex;
 
 use crate::{
     metrics::CALLS_ACTIVE,
-    wt::{GLOBAL_SESSIONS, TrackInfo},
+    wt::{GLOBAL_SESSIONS, TrackInfo, speakers},
 };
 
 #[derive(Clone, Debug)]
@@ -20,8 +26,9 @@ pub struct PendingMember {
 pub struct Call {
     pub id: String,
     pub tracks: DashMap<String, TrackInfo>,
-    pub members: DashMap<String, ()>, // session ids in this call
+    pub members: DashMap<String, u8>, // session id -> newest media header version it reads
     pub mls_state: Arc<Mutex<MlsState>>,
+    pub media_header_version: Arc<AtomicU8>, // the version members were last told to write
 }
 
 #[derive(Clone, Debug)]
@@ -58,8 +65,12 @@ impl Call {
         let track_id = track_info.id.clone();
         let media_hint = track_info.media_hint.clone();
         let info_session_id = track_info.session_id.clone();
+        let layers = track_info.layers;
 
         self.tracks.insert(track_info.id.clone(), track_info);
+        if media_hint == MediaHint::Audio {
+            speakers::watch(&self.id, &info_session_id, &track_id);
+        }
 
         for member in self.members.iter() {
             if member.key() == session_id {
@@ -75,6 +86,7 @@ impl Call {
                         id: track_id.clone(),
                         media_hint: media_hint.clone(),
                         session_id: info_session_id.clone(),
+                        layers,
                     },
                 })
                 .ok();
@@ -83,6 +95,7 @@ impl Call {
 
     pub fn stop_producing(&self, session_id: &str, track_id: &str) {
         self.tracks.remove(track_id);
+        speakers::unwatch(track_id);
 
         for member in self.members.iter() {
             if member.key() == session_id {
@@ -91,6 +104,7 @@ impl Call {
             let Some(session) = GLOBAL_SESSIONS.get(member.key()) else {
                 continue;
             };
+            session.layers.remove(track_id);
             session
                 .message_tx
                 .send(ControlS2C::TrackUnavailable {
@@ -108,11 +122,17 @@ impl Call {
                 id: t.id.clone(),
                 media_hint: t.media_hint.clone(),
                 session_id: t.session_id.clone(),
+                layers: t.layers,
             })
             .collect()
     }
 
-    pub async fn add_member(&self, session_id: String, key_package: Vec<u8>) {
+    pub async fn add_member(
+        &self,
+        session_id: String,
+        key_package: Vec<u8>,
+        media_header_version: u8,
+    ) {
         if self.members.contains_key(&session_id) {
             // this is probably a reconnection, so we can just ignore it
             return;
@@ -142,12 +162,39 @@ impl Call {
                 session_id, self.id
             );
         }
-        self.members.insert(session_id, ());
+        self.members.insert(session_id, media_header_version);
         if was_empty {
             CALLS_ACTIVE.add(1, &[KeyValue::new("call_id", self.id.clone())]);
         }
     }
 
+    /// The newest media header version every member reads.
+    pub fn readable_media_header_version(&self) -> u8 {
+        self.members
+            .iter()
+            .map(|member| *member.value())
+            .min()
+            .unwrap_or(MEDIA_HEADER_VERSION)
+    }
+
+    /// Tell every member which media header version to write if that changed
+    /// since they were last told. Returns whether they were told.
+    pub fn announce_media_header_version(&self) -> bool {
+        let version = self.readable_media_header_version();
+        if self.media_header_version.swap(version, Ordering::SeqCst) == version {
+            return false;
+        }
+        for member in self.members.iter() {
+            if let Some(session) = GLOBAL_SESSIONS.get(member.key()) {
+                session
+                    .message_tx
+                    .send(ControlS2C::MediaHeaderVersion { version })
+                    .ok();
+            }
+        }
+        true
+    }
+
     pub async fn remove_member(&self, session_id: &str) {
         self.members.remove(session_id);
         if self.members.is_empty() {
@@ -0,0 +1,407 @@
+//! Cascading calls across nodes. A call lives on one node, which runs its
+//! MLS group and track bookkeeping. Users far from it connect to a node near
+//! them instead, which relays their broadcasts over a MoQ link to the call's
+//! node and forwards their control messages over NATS. The call's node keeps
+//! a stand-in session for each relayed one, so the rest of the call sees no
+//! difference.
+
+use std::sync::Arc;
+
+use common::nats::subject_node;
+use common::{HomeNode, NodeEvent, NodeEventKind, SessionData};
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use moq_native::moq_net;
+use pulse_types::{ControlC2S, ControlS2C};
+use tokio::sync::mpsc;
+use tokio::task;
+
+use crate::environment::{LINK_TLS_DISABLE_VERIFY, LINK_TLS_ROOT};
+use crate::nats::publish_node;
+use crate::redis::INSTANCE_ID;
+use crate::wt::{GLOBAL_ORIGIN, GLOBAL_SESSIONS, GLOBAL_UNIQUE_SESSIONS, JoinRequest};
+
+/// How long a link token stays valid; links connect right after minting one.
+const LINK_TOKEN_SECONDS: u64 = 30;
+
+/// A session connected here but relayed to its call's node.
+#[derive(Clone, Debug)]
+pub struct RelayedSession {
+    pub session_id: String,
+    pub call_id: String,
+    pub home: String,
+    pub message_tx: mpsc::UnboundedSender<ControlS2C>,
+    pub close_tx: mpsc::UnboundedSender<()>,
+}
+
+struct Link {
+    sessions: usize,
+    task: task::JoinHandle<()>,
+}
+
+lazy_static! {
+    /// Relayed sessions by connection
+    pub static ref GLOBAL_RELAYED: Arc<DashMap<String, RelayedSession>> =
+        Arc::new(DashMap::new());
+    /// Links to other nodes by call
+    static ref GLOBAL_LINKS: DashMap<String, Link> = DashMap::new();
+}
+
+fn call_prefix(call_id: &str) -> moq_net::Path<'_> {
+    format!("calls/{call_id}").into()
+}
+
+/// The node a session should be relayed to, if it isn't this one.
+pub fn relay_home(session_data: &SessionData) -> Option<&HomeNode> {
+    session_data
+        .home
+        .as_ref()
+        .filter(|home| home.id != *INSTANCE_ID)
+}
+
+async fn send_home(home: &str, event: NodeEventKind) {
+    publish_node(
+        subject_node(home),
+        &NodeEvent {
+            id: INSTANCE_ID.clone(),
+            event,
+        },
+    )
+    .await;
+}
+
+/// Forward a session joining to its call's node, linking to that node first
+/// if no other session of the call is relayed there yet.
+pub async fn relay_join(
+    connection: &str,
+    token: &str,
+    session_data: &SessionData,
+    home: &HomeNode,
+    join: JoinRequest,
+    message_tx: mpsc::UnboundedSender<ControlS2C>,
+    close_tx: mpsc::UnboundedSender<()>,
+) {
+    // a reconnection replaces the old connection; the call's node drops its
+    // stand-in when it sees the new one join
+    let replaced: Vec<String> = GLOBAL_RELAYED
+        .iter()
+        .filter(|s| s.session_id == session_data.session_id)
+        .map(|s| s.key().clone())
+        .collect();
+    for old in replaced {
+        if let Some((_, old)) = GLOBAL_RELAYED.remove(&old) {
+            unlink(&old.call_id);
+            old.message_tx
+                .send(ControlS2C::Disconnected { reconnect: None })
+                .ok();
+            old.close_tx.send(()).ok();
+        }
+    }
+    GLOBAL_RELAYED.insert(
+        connection.to_string(),
+        RelayedSession {
+            session_id: session_data.session_id.clone(),
+            call_id: session_data.call_id.clone(),
+            home: home.id.clone(),
+            message_tx,
+            close_tx,
+        },
+    );
+    link(&session_data.call_id, home);
+    send_home(
+        &home.id,
+        NodeEventKind::RelayJoin {
+            connection: connection.to_string(),
+            token: token.to_string(),
+            session: session_data.clone(),
+            key_package: join.key_package,
+            media_header_version: join.media_header_version,
+        },
+    )
+    .await;
+    info!(
+        "Relaying session {} in call {} to node {}",
+        session_data.session_id, session_data.call_id, home.id
+    );
+}
+
+pub async fn relay_c2s(connection: &str, message: ControlC2S) {
+    let Some(home) = GLOBAL_RELAYED.get(connection).map(|s| s.home.clone()) else {
+        return;
+    };
+    send_home(
+        &home,
+        NodeEventKind::RelayC2S {
+            connection: connection.to_string(),
+            message,
+        },
+    )
+    .await;
+}
+
+/// Drop a relayed session that disconnected from here, telling its call's
+/// node unless the session was never relayed or has been replaced.
+pub async fn relay_leave(connection: &str) {
+    let Some((_, session)) = GLOBAL_RELAYED.remove(connection) else {
+        return;
+    };
+    unlink(&session.call_id);
+    send_home(
+        &session.home,
+        NodeEventKind::RelayClose {
+            connection: connection.to_string(),
+        },
+    )
+    .await;
+    crate::load::report_if_drained();
+}
+
+/// Deliver a control message from the call's node to a relayed session.
+pub fn deliver(connection: &str, message: ControlS2C) {
+    if let Some(session) = GLOBAL_RELAYED.get(connection) {
+        session.message_tx.send(message).ok();
+    }
+}
+
+/// Close a relayed session because its call's node closed it.
+pub fn close(connection: &str) {
+    if let Some(session) = GLOBAL_RELAYED.get(connection) {
+        session.close_tx.send(()).ok();
+    }
+}
+
+fn link(call_id: &str, home: &HomeNode) {
+    let mut link = GLOBAL_LINKS.entry(call_id.to_string()).or_insert_with(|| {
+        let call_id = call_id.to_string();
+        let home = home.clone();
+        Link {
+            sessions: 0,
+            task: task::spawn(async move {
+                if let Err(e) = run_link(&call_id, &home).await {
+                    warn!(
+                        "Link to node {} for call {} failed: {:?}",
+                        home.id, call_id, e
+                    );
+                }
+                // without the call's node the relayed sessions hear nothing;
+                // they reconnect wherever the call is now
+                GLOBAL_LINKS.remove(&call_id);
+                for session in GLOBAL_RELAYED.iter().filter(|s| s.call_id == call_id) {
+                    session
+                        .message_tx
+                        .send(ControlS2C::Disconnected { reconnect: None })
+                        .ok();
+                    session.close_tx.send(()).ok();
+                }
+            }),
+        }
+    });
+    link.sessions += 1;
+}
+
+fn unlink(call_id: &str) {
+    let removed = GLOBAL_LINKS.remove_if_mut(call_id, |_, link| {
+        link.sessions = link.sessions.saturating_sub(1);
+        link.sessions == 0
+    });
+    if let Some((_, link)) = removed {
+        // dropping the MoQ session closes it
+        link.task.abort();
+        info!("Closed link for call {}", call_id);
+    }
+}
+
+/// Connect to the call's node and exchange the call's broadcasts with it
+/// until either side goes away.
+async fn run_link(call_id: &str, home: &HomeNode) -> anyhow::Result<()> {
+    let token = mint_link_token(call_id).await?;
+
+    let mut client_config = moq_native::ClientConfig::default();
+    client_config.tls.root.extend(LINK_TLS_ROOT.clone());
+    if *LINK_TLS_DISABLE_VERIFY {
+        client_config.tls.disable_verify = Some(true);
+    }
+    let client = client_config.init()?;
+
+    let mut url: url::Url = home.server_address.parse()?;
+    url.set_path("/relay");
+    url.query_pairs_mut().clear().append_pair("relay", &token);
+
+    let publish_gate = GLOBAL_ORIGIN
+        .consume()
+        .scope(&[call_prefix(call_id)])
+        .ok_or_else(|| anyhow::anyhow!("failed to scope link publish origin"))?;
+    let consume_gate = GLOBAL_ORIGIN
+        .scope(&[call_prefix(call_id)])
+        .ok_or_else(|| anyhow::anyhow!("failed to scope link consume origin"))?;
+    // the origin counts hops, so broadcasts don't echo back over the link
+    let session = client
+        .with_publish(publish_gate)
+        .with_consume(consume_gate)
+        .connect(url)
+        .await?;
+    info!("Linked to node {} for call {}", home.id, call_id);
+
+    let err = session.closed().await;
+    info!(
+        "Link to node {} for call {} closed: {:?}",
+        home.id, call_id, err
+    );
+    Ok(())
+}
+
+fn link_key(token: &str) -> String {
+    format!("relay:{token}")
+}
+
+/// Nodes share Redis, so a node proves it may link into a call by storing a
+/// token there for the call's node to check.
+async fn mint_link_token(call_id: &str) -> anyhow::Result<String> {
+    let token: String = (0..32)
+        .map(|_| format!("{:02x}", rand::random::<u8>()))
+        .collect();
+    let mut redis_conn = crate::redis::get_connection().await;
+    redis::pipe()
+        .atomic()
+        .hset(link_key(&token), "call_id", call_id)
+        .ignore()
+        .hset(link_key(&token), "node", INSTANCE_ID.as_str())
+        .ignore()
+        .expire(link_key(&token), LINK_TOKEN_SECONDS as i64)
+        .ignore()
+        .query_async::<()>(&mut redis_conn)
+        .await?;
+    Ok(token)
+}
+
+/// Accept a link from a node relaying sessions of one of our calls.
+pub async fn handle_link(request: moq_native::Request, token: &str) -> anyhow::Result<()> {
+    let mut redis_conn = crate::redis::get_connection().await;
+    let (call_id, node): (Option<String>, Option<String>) = redis::pipe()
+        .atomic()
+        .hget(link_key(token), "call_id")
+        .hget(link_key(token), "node")
+        .del(link_key(token))
+        .ignore()
+        .query_async(&mut redis_conn)
+        .await?;
+    let (Some(call_id), Some(node)) = (call_id, node) else {
+        warn!("Rejecting link: invalid token");
+        request.close(0).await.ok();
+        return Ok(());
+    };
+
+    let publish_gate = GLOBAL_ORIGIN
+        .consume()
+        .scope(&[call_prefix(&call_id)])
+        .ok_or_else(|| anyhow::anyhow!("failed to scope link publish origin"))?;
+    let consume_gate = GLOBAL_ORIGIN
+        .scope(&[call_prefix(&call_id)])
+        .ok_or_else(|| anyhow::anyhow!("failed to scope link consume origin"))?;
+    let session = request
+        .with_publish(publish_gate)
+        .with_consume(consume_gate)
+        .ok()
+        .await?;
+    info!("Node {} linked for call {}", node, call_id);
+
+    let err = session.closed().await;
+    info!("Node {} unlinked from call {}: {:?}", node, call_id, err);
+    // whatever the node relayed is gone with the link
+    for session in GLOBAL_SESSIONS
+        .iter()
+        .filter(|s| s.call_id == call_id && s.relayed_by.as_deref() == Some(node.as_str()))
+    {
+        session.close("relaying node unlinked");
+    }
+    Ok(())
+}
+
+/// Take in a session another node relays to one of our calls. It gets a
+/// stand-in whose messages go back to that node.
+pub async fn accept_join(
+    node: String,
+    connection: String,
+    token: String,
+    session_data: SessionData,
+    join: JoinRequest,
+) {
+    let (message_tx, mut message_rx) = mpsc::unbounded_channel::<ControlS2C>();
+    let (close_tx, mut close_rx) = mpsc::unbounded_channel::<()>();
+
+    let forward_node = node.clone();
+    let forward_connection = connection.clone();
+    task::spawn(async move {
+        loop {
+            tokio::select! {
+                biased;
+                Some(message) = message_rx.recv() => {
+                    send_home(
+                        &forward_node,
+                        NodeEventKind::RelayS2C {
+                            connection: forward_connection.clone(),
+                            message,
+                        },
+                    )
+                    .await;
+                }
+                _ = close_rx.recv() => break,
+            }
+        }
+        // whatever was sent before closing still goes out first
+        while let Ok(message) = message_rx.try_recv() {
+            send_home(
+                &forward_node,
+                NodeEventKind::RelayS2C {
+                    connection: forward_connection.clone(),
+                    message,
+                },
+            )
+            .await;
+        }
+        send_home(
+            &forward_node,
+            NodeEventKind::RelayClose {
+                connection: forward_connection.clone(),
+            },
+        )
+        .await;
+        super::cleanup_session(&forward_connection).await;
+    });
+
+    if let Err(e) = super::handle_join(
+        &connection,
+        &token,
+        &session_data,
+        join,
+        message_tx,
+        close_tx.clone(),
+        Some(node),
+    )
+    .await
+    {
+        warn!(
+            "Failed to join relayed session {}: {:?}",
+            session_data.session_id, e
+        );
+        close_tx.send(()).ok();
+    }
+}
+
+/// A control message from a session another node relays.
+pub async fn accept_c2s(connection: &str, message: ControlC2S) {
+    if let Err(e) = super::dispatch_message(connection, message).await {
+        warn!("Failed to handle relayed control message: {:?}", e);
+    }
+}
+
+/// The relaying node dropped a session.
+pub fn accept_close(connection: &str) {
+    let Some(session) = GLOBAL_UNIQUE_SESSIONS
+        .get(connection)
+        .and_then(|id| GLOBAL_SESSIONS.get(id.value()).map(|s| s.clone()))
+    else {
+        return;
+    };
+    session.close("relaying node closed the session");
+}
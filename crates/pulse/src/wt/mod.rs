@@ -1,14 +1,22 @@
 pub mod call;
+pub mod cascade;
+pub mod simulcast;
+pub mod speakers;
 
-use common::{NodeEvent, NodeEventKind, SessionData};
+use common::{
+    CallInventory, NodeEvent, NodeEventKind, SessionData, SessionGrants, SessionInventory,
+};
 use dashmap::DashMap;
 use lazy_static::lazy_static;
 use moq_native::moq_net::{self, BroadcastProducer, Origin, OriginProducer, Track};
-use pulse_types::{ControlC2S, ControlS2C, MediaHint, track_names};
+use pulse_types::{
+    ControlC2S, ControlS2C, MAX_LAYERS, MEDIA_HEADER_VERSION, MediaHint, supports_layers,
+    track_names,
+};
 use redis::AsyncCommands;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::time::Duration;
 use tokio::sync::{Mutex, mpsc};
 use tokio::{task, time};
@@ -17,6 +25,7 @@ use ulid::Ulid;
 use crate::metrics::CONNECTIONS_ACTIVE;
 use crate::redis::INSTANCE_ID;
 use crate::wt::call::{Call, MlsState, PendingProposal};
+use crate::wt::simulcast::LayerChoice;
 
 #[derive(Clone, Debug)]
 pub struct TrackInfo {
@@ -24,12 +33,21 @@ pub struct TrackInfo {
     pub media_hint: MediaHint,
     pub session_id: String,
     pub producer_session: SessionState,
+    pub layers: u8, // simulcast layers the producer publishes
+}
+
+/// What a session sent to join its call.
+#[derive(Clone, Debug)]
+pub struct JoinRequest {
+    pub key_package: Vec<u8>,
+    pub media_header_version: u8, // newest media header version the client reads
 }
 
 #[derive(Clone, Debug)]
 pub struct SessionState {
     pub id: String,
     pub session_id: String,
+    pub user_id: String,
     pub call_id: String,
     pub session_token: String,
 
@@ -41,12 +59,56 @@ pub struct SessionState {
     pub can_video: Arc<AtomicBool>,
     pub can_screen: Arc<AtomicBool>,
     pub producers: Arc<DashMap<String, TrackInfo>>, // track_id -> TrackInfo
+    pub layers: Arc<DashMap<String, LayerChoice>>,  // track_id -> simulcast layer we receive
+    pub relayed_by: Option<String>, // the node relaying this session, if it isn't connected here
 }
 
 impl SessionState {
     pub fn close(&self, _reason: &str) {
         self.close_tx.send(()).ok();
     }
+
+    pub fn grants(&self) -> SessionGrants {
+        SessionGrants {
+            can_listen: self.can_listen.load(Ordering::SeqCst),
+            can_speak: self.can_speak.load(Ordering::SeqCst),
+            can_video: self.can_video.load(Ordering::SeqCst),
+            can_screen: self.can_screen.load(Ordering::SeqCst),
+        }
+    }
+
+    pub fn may_produce(&self, media_hint: &MediaHint) -> bool {
+        match media_hint {
+            MediaHint::Audio => self.can_speak.load(Ordering::SeqCst),
+            MediaHint::Video => self.can_video.load(Ordering::SeqCst),
+            MediaHint::ScreenAudio | MediaHint::ScreenVideo => {
+                self.can_screen.load(Ordering::SeqCst)
+            }
+        }
+    }
+
+    /// Stop every track the session may no longer produce, telling it and
+    /// everyone else in the call.
+    pub fn revoke_producers(&self) {
+        let revoked: Vec<TrackInfo> = self
+            .producers
+            .iter()
+            .filter(|track| !self.may_produce(&track.media_hint))
+            .map(|track| track.clone())
+            .collect();
+        for track in revoked {
+            if let Some(call) = GLOBAL_CALLS.get(&self.call_id) {
+                call.stop_producing(&self.session_id, &track.id);
+            }
+            self.producers.remove(&track.id);
+            self.message_tx
+                .send(ControlS2C::ProduceRevoked {
+                    track_id: track.id,
+                    media_hint: track.media_hint,
+                })
+                .ok();
+        }
+    }
 }
 
 lazy_static! {
@@ -56,6 +118,26 @@ lazy_static! {
     pub static ref GLOBAL_UNIQUE_SESSIONS: Arc<DashMap<String, String>> = Arc::new(DashMap::new());
 }
 
+/// The calls hosted here with their connected sessions, for the main server
+/// to check against what it has stored.
+pub fn inventory() -> Vec<CallInventory> {
+    let mut calls: HashMap<String, Vec<SessionInventory>> = HashMap::new();
+    for session in GLOBAL_SESSIONS.iter() {
+        calls
+            .entry(session.call_id.clone())
+            .or_default()
+            .push(SessionInventory {
+                id: session.session_id.clone(),
+                user_id: session.user_id.clone(),
+                grants: session.grants(),
+            });
+    }
+    calls
+        .into_iter()
+        .map(|(call_id, sessions)| CallInventory { call_id, sessions })
+        .collect()
+}
+
 fn broadcast_path(call_id: &str, session_id: &str, track: &str) -> String {
     format!("calls/{call_id}/{session_id}/{track}")
 }
@@ -69,6 +151,7 @@ pub async fn listen() -> anyhow::Result<()> {
 
     let mut server = config.init()?;
     info!("Pulse MoQ endpoint listening on [::]:4433");
+    speakers::spawn_detector();
 
     loop {
         let Some(request) = server.accept().await else {
@@ -103,10 +186,27 @@ async fn authorize(request: &moq_native::Request) -> anyhow::Result<(String, Ses
         ));
     }
 
+    // a draining node keeps serving its calls until they are moved away
+    if crate::load::is_draining() && !GLOBAL_CALLS.contains_key(&session_data.call_id) {
+        return Err(anyhow::anyhow!(
+            "node is draining, not accepting call {}",
+            session_data.call_id
+        ));
+    }
+
     Ok((token, session_data))
 }
 
 async fn handle_request(request: moq_native::Request) -> anyhow::Result<()> {
+    let link_token = request.url().and_then(|url| {
+        url.query_pairs()
+            .find(|(k, _)| k == "relay")
+            .map(|(_, v)| v.to_string())
+    });
+    if let Some(link_token) = link_token {
+        return cascade::handle_link(request, &link_token).await;
+    }
+
     let (token, session_data) = match authorize(&request).await {
         Ok(v) => v,
         Err(e) => {
@@ -165,7 +265,11 @@ async fn handle_request(request: moq_native::Request) -> anyhow::Result<()> {
 
     session.close(moq_net::Error::Cancel);
     CONNECTIONS_ACTIVE.add(-1, &[]);
-    cleanup_session(&unique_id).await;
+    if cascade::relay_home(&session_data).is_some() {
+        cascade::relay_leave(&unique_id).await;
+    } else {
+        cleanup_session(&unique_id).await;
+    }
     Ok(())
 }
 
@@ -219,6 +323,7 @@ async fn run_control(
         .ok_or_else(|| anyhow::anyhow!("client control track never announced"))?;
     let mut ctl = ctl_bc.subscribe_track(&Track::new(track_names::CTL_C2S))?;
 
+    let relay_home = cascade::relay_home(session_data);
     let mut joined = false;
     while let Some(mut group) = ctl.next_group().await? {
         while let Some(frame) = group.read_frame().await? {
@@ -232,23 +337,55 @@ async fn run_control(
 
             if !joined {
                 // the first message must be Join
-                let ControlC2S::Join { key_package } = message else {
+                let ControlC2S::Join {
+                    key_package,
+                    media_header_version,
+                } = message
+                else {
                     return Err(anyhow::anyhow!("first control frame was not Join"));
                 };
-                handle_join(
-                    unique_id,
-                    token,
-                    session_data,
+                let join = JoinRequest {
                     key_package,
-                    message_tx.clone(),
-                    close_tx.clone(),
-                )
-                .await?;
+                    media_header_version,
+                };
+                if let Some(home) = relay_home {
+                    cascade::relay_join(
+                        unique_id,
+                        token,
+                        session_data,
+                        home,
+                        join,
+                        message_tx.clone(),
+                        close_tx.clone(),
+                    )
+                    .await;
+                } else {
+                    handle_join(
+                        unique_id,
+                        token,
+                        session_data,
+                        join,
+                        message_tx.clone(),
+                        close_tx.clone(),
+                        None,
+                    )
+                    .await?;
+                }
                 joined = true;
                 continue;
             }
 
-            dispatch_message(unique_id, message).await?;
+            // round trips are measured to this node, wherever the call lives
+            if let ControlC2S::Ping { sent_us } = message {
+                message_tx.send(ControlS2C::Pong { sent_us }).ok();
+                continue;
+            }
+
+            if relay_home.is_some() {
+                cascade::relay_c2s(unique_id, message).await;
+            } else {
+                dispatch_message(unique_id, message).await?;
+            }
         }
     }
     Ok(())
@@ -274,9 +411,10 @@ async fn handle_join(
     unique_id: &str,
     token: &str,
     session_data: &SessionData,
-    key_package: Vec<u8>,
+    join: JoinRequest,
     message_tx: mpsc::UnboundedSender<ControlS2C>,
     close_tx: mpsc::UnboundedSender<()>,
+    relayed_by: Option<String>,
 ) -> anyhow::Result<()> {
     // reconnection
     if let Some((_, old)) = GLOBAL_SESSIONS.remove(&session_data.session_id) {
@@ -285,11 +423,16 @@ async fn handle_join(
             .send(ControlS2C::Disconnected { reconnect: None })
             .ok();
         old.close("replaced by reconnection");
+        // moved to another call before the old session was cleaned up
+        if old.call_id != session_data.call_id {
+            leave_call(&old).await;
+        }
     }
 
     let state = SessionState {
         id: unique_id.to_string(),
         session_id: session_data.session_id.clone(),
+        user_id: session_data.user_id.clone(),
         call_id: session_data.call_id.clone(),
         session_token: token.to_string(),
         message_tx: message_tx.clone(),
@@ -299,6 +442,8 @@ async fn handle_join(
         can_video: Arc::new(AtomicBool::new(session_data.can_video)),
         can_screen: Arc::new(AtomicBool::new(session_data.can_screen)),
         producers: Arc::new(DashMap::new()),
+        layers: Arc::new(DashMap::new()),
+        relayed_by,
     };
     GLOBAL_SESSIONS.insert(state.session_id.clone(), state.clone());
     GLOBAL_UNIQUE_SESSIONS.insert(state.id.clone(), state.session_id.clone());
@@ -318,12 +463,21 @@ async fn handle_join(
                 pending_epoch_change: false,
                 pending_members: Vec::new(),
             })),
+            media_header_version: Arc::new(AtomicU8::new(MEDIA_HEADER_VERSION)),
         });
 
     let is_first_member = call.members.is_empty();
-    call.add_member(state.session_id.clone(), key_package).await;
+    call.add_member(
+        state.session_id.clone(),
+        join.key_package,
+        join.media_header_version,
+    )
+    .await;
     broadcast_proposals(&call).await;
     let available_tracks = call.get_available_tracks(&state.session_id);
+    // the others hear about it if the newcomer changes what can be read
+    let announced = call.announce_media_header_version();
+    let media_header_version = call.readable_media_header_version();
     drop(call);
 
     message_tx
@@ -332,6 +486,19 @@ async fn handle_join(
             available_tracks,
         })
         .ok();
+    if !announced {
+        message_tx
+            .send(ControlS2C::MediaHeaderVersion {
+                version: media_header_version,
+            })
+            .ok();
+    }
+    let session_ids = speakers::current(&state.call_id);
+    if !session_ids.is_empty() {
+        message_tx
+            .send(ControlS2C::ActiveSpeakers { session_ids })
+            .ok();
+    }
 
     if is_first_member {
         let external_sender_credential = crate::environment::EXTERNAL_SENDER
@@ -388,8 +555,9 @@ async fn dispatch_message(unique_id: &str, message: ControlC2S) -> anyhow::Resul
         ControlC2S::StartProduce {
             request_id,
             media_hint,
+            layers,
         } => {
-            handle_start_produce(request_id, media_hint, &state).await;
+            handle_start_produce(request_id, media_hint, layers, &state).await;
         }
         ControlC2S::StopProduce {
             request_id,
@@ -408,17 +576,23 @@ async fn dispatch_message(unique_id: &str, message: ControlC2S) -> anyhow::Resul
             received,
             jitter_ms,
         } => handle_receiver_report(track_id, lost, received, jitter_ms, &state),
+        ControlC2S::SelectLayer { track_id, layer } => {
+            simulcast::select_layer(track_id, layer, &state)
+        }
+        ControlC2S::Ping { sent_us } => {
+            state.message_tx.send(ControlS2C::Pong { sent_us }).ok();
+        }
     }
     Ok(())
 }
 
-async fn handle_start_produce(request_id: u64, media_hint: MediaHint, state: &SessionState) {
-    let allowed = match media_hint {
-        MediaHint::Audio => state.can_speak.load(Ordering::SeqCst),
-        MediaHint::Video => state.can_video.load(Ordering::SeqCst),
-        MediaHint::ScreenAudio | MediaHint::ScreenVideo => state.can_screen.load(Ordering::SeqCst),
-    };
-    if !allowed {
+async fn handle_start_produce(
+    request_id: u64,
+    media_hint: MediaHint,
+    layers: u8,
+    state: &SessionState,
+) {
+    if !state.may_produce(&media_hint) {
         warn!("User lacks permission to produce {:?}", media_hint);
         state
             .message_tx
@@ -451,6 +625,11 @@ async fn handle_start_produce(request_id: u64, media_hint: MediaHint, state: &Se
         media_hint: media_hint.clone(),
         session_id: state.session_id.clone(),
         producer_session: state.clone(),
+        layers: if supports_layers(&media_hint) {
+            layers.clamp(1, MAX_LAYERS)
+        } else {
+            1
+        },
     };
     state
         .producers
@@ -521,11 +700,13 @@ fn handle_receiver_report(
         return;
     };
     if let Some(track) = call.tracks.get(&track_id) {
+        simulcast::adapt_layer(&track, lost, received, jitter_ms, state);
         track
             .producer_session
             .message_tx
             .send(ControlS2C::ReceiverReport {
                 track_id: track.id.clone(),
+                session_id: state.session_id.clone(),
                 lost,
                 received,
                 jitter_ms,
@@ -666,7 +847,13 @@ async fn cleanup_session(unique_id: &str) {
     let Some((_, state)) = GLOBAL_SESSIONS.remove(&id) else {
         return;
     };
+    leave_call(&state).await;
+
+    crate::load::report_if_drained();
+}
 
+/// Take a session out of its call and report that it left.
+async fn leave_call(state: &SessionState) {
     if let Some(call) = GLOBAL_CALLS.get(&state.call_id) {
         call.remove_member(&state.session_id).await;
         let producer_ids: Vec<String> = state.producers.iter().map(|t| t.id.clone()).collect();
@@ -674,6 +861,7 @@ async fn cleanup_session(unique_id: &str) {
             call.stop_producing(&state.session_id, &global_id);
         }
         broadcast_proposals(&call).await;
+        call.announce_media_header_version();
     }
 
     let event = NodeEvent {
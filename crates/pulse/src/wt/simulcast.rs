@@ -0,0 +1,100 @@
+//! Simulcast layer selection. Producers publish each layer of a video track
+//! as its own MoQ track, so a consumer only receives the layer it subscribes
+//! to. Consumers pick a layer, and we step them down a layer while their
+//! receiver reports show loss or jitter, then back up once they recover.
+
+use pulse_types::ControlS2C;
+
+use crate::wt::{GLOBAL_CALLS, SessionState, TrackInfo};
+
+/// Loss ratio in a receiver report that counts as congestion.
+const CONGESTED_LOSS: f64 = 0.1;
+/// Jitter in a receiver report that counts as congestion.
+const CONGESTED_JITTER_MS: u32 = 60;
+/// Clean receiver reports in a row before stepping back up a layer.
+const RECOVERY_REPORTS: u32 = 5;
+
+/// The layer a consumer receives of one track.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LayerChoice {
+    /// The layer the consumer asked for.
+    pub preferred: u8,
+    /// The layer it's told to receive, never better than `preferred`.
+    pub current: u8,
+    clean_reports: u32,
+}
+
+/// The consumer picked a layer of a track.
+pub fn select_layer(track_id: String, layer: u8, state: &SessionState) {
+    let Some(layers) = GLOBAL_CALLS
+        .get(&state.call_id)
+        .and_then(|call| call.tracks.get(&track_id).map(|track| track.layers))
+    else {
+        warn!("SelectLayer for unknown track {}", track_id);
+        return;
+    };
+    let layer = layer.min(layers.saturating_sub(1));
+    state.layers.insert(
+        track_id.clone(),
+        LayerChoice {
+            preferred: layer,
+            current: layer,
+            clean_reports: 0,
+        },
+    );
+    state
+        .message_tx
+        .send(ControlS2C::LayerSelected { track_id, layer })
+        .ok();
+}
+
+/// Adapt the layer a consumer receives of `track` to its receiver report.
+pub fn adapt_layer(
+    track: &TrackInfo,
+    lost: u32,
+    received: u32,
+    jitter_ms: u32,
+    state: &SessionState,
+) {
+    if track.layers <= 1 {
+        return;
+    }
+    let total = lost.saturating_add(received);
+    let loss = if total == 0 {
+        0.0
+    } else {
+        lost as f64 / total as f64
+    };
+    let congested = loss >= CONGESTED_LOSS || jitter_ms >= CONGESTED_JITTER_MS;
+
+    let mut choice = state.layers.entry(track.id.clone()).or_default();
+    let previous = choice.current;
+    if congested {
+        choice.clean_reports = 0;
+        if choice.current + 1 < track.layers {
+            choice.current += 1;
+        }
+    } else if choice.current > choice.preferred {
+        choice.clean_reports += 1;
+        if choice.clean_reports >= RECOVERY_REPORTS {
+            choice.clean_reports = 0;
+            choice.current -= 1;
+        }
+    }
+    let layer = choice.current;
+    drop(choice);
+
+    if layer != previous {
+        debug!(
+            "Switching session {} to layer {} of track {} (loss {:.2}, jitter {}ms)",
+            state.session_id, layer, track.id, loss, jitter_ms
+        );
+        state
+            .message_tx
+            .send(ControlS2C::LayerSelected {
+                track_id: track.id.clone(),
+                layer,
+            })
+            .ok();
+    }
+}
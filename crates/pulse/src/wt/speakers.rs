@@ -0,0 +1,187 @@
+//! Active speaker detection. Media is end-to-end encrypted, but clients put
+//! an audio level in the plaintext header of every microphone frame. We read
+//! those headers off each microphone track and periodically tell every call
+//! who is speaking.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use moq_native::moq_net::Track;
+use pulse_types::{AudioLevel, ControlS2C, decode_media_header, track_names};
+use tokio::{task, time};
+
+use crate::wt::{GLOBAL_CALLS, GLOBAL_ORIGIN, GLOBAL_SESSIONS, broadcast_path};
+
+/// How often speakers are re-evaluated.
+const DETECT_INTERVAL: Duration = Duration::from_millis(250);
+/// How long after their last voice frame someone still counts as speaking.
+const SPEAKING_HOLD: Duration = Duration::from_millis(600);
+/// Most speakers reported at once.
+const MAX_ACTIVE_SPEAKERS: usize = 3;
+/// Weight of a new frame in the smoothed level.
+const LEVEL_SMOOTHING: f32 = 0.2;
+/// Groups a level reader may fall behind before skipping ahead.
+const MAX_GROUP_BACKLOG: u64 = 10;
+
+struct SpeakerLevel {
+    smoothed: f32, // -dBov, lower is louder
+    last_voice: Option<Instant>,
+}
+
+#[derive(Default)]
+struct CallSpeakers {
+    levels: HashMap<String, SpeakerLevel>, // session id -> level
+    active: Vec<String>,
+}
+
+struct Reader {
+    call_id: String,
+    session_id: String,
+    task: task::JoinHandle<()>,
+}
+
+lazy_static! {
+    static ref SPEAKERS: DashMap<String, CallSpeakers> = DashMap::new(); // call id -> speakers
+    static ref READERS: DashMap<String, Reader> = DashMap::new(); // track id -> level reader
+}
+
+/// Start reading the audio levels of a microphone track.
+pub fn watch(call_id: &str, session_id: &str, track_id: &str) {
+    let reader = Reader {
+        call_id: call_id.to_string(),
+        session_id: session_id.to_string(),
+        task: task::spawn(read_levels(call_id.to_string(), session_id.to_string())),
+    };
+    if let Some(old) = READERS.insert(track_id.to_string(), reader) {
+        old.task.abort();
+    }
+}
+
+/// Stop reading a track's audio levels; its producer no longer speaks.
+pub fn unwatch(track_id: &str) {
+    let Some((_, reader)) = READERS.remove(track_id) else {
+        return;
+    };
+    reader.task.abort();
+    if let Some(mut speakers) = SPEAKERS.get_mut(&reader.call_id) {
+        speakers.levels.remove(&reader.session_id);
+    }
+}
+
+/// The speakers last reported to a call.
+pub fn current(call_id: &str) -> Vec<String> {
+    SPEAKERS
+        .get(call_id)
+        .map(|speakers| speakers.active.clone())
+        .unwrap_or_default()
+}
+
+async fn read_levels(call_id: String, session_id: String) {
+    let path = broadcast_path(&call_id, &session_id, track_names::MICROPHONE);
+    let origin = GLOBAL_ORIGIN.consume();
+    let Some(broadcast) = origin.announced_broadcast(&path).await else {
+        return;
+    };
+    let mut track = match broadcast.subscribe_track(&Track::new(track_names::MICROPHONE)) {
+        Ok(track) => track,
+        Err(e) => {
+            warn!("Failed to subscribe to {} for audio levels: {:?}", path, e);
+            return;
+        }
+    };
+
+    let mut last_group_seq: u64 = 0;
+    loop {
+        if let Some(latest) = track.latest()
+            && latest.saturating_sub(last_group_seq) > MAX_GROUP_BACKLOG
+        {
+            track.start_at(latest);
+        }
+        let mut group = match track.next_group().await {
+            Ok(Some(group)) => group,
+            Ok(None) | Err(_) => return,
+        };
+        last_group_seq = group.sequence;
+        while let Ok(Some(frame)) = group.read_frame().await {
+            if let Some((header, _)) = decode_media_header(&frame)
+                && let Some(level) = header.audio_level
+            {
+                record(&call_id, &session_id, level);
+            }
+        }
+    }
+}
+
+fn record(call_id: &str, session_id: &str, level: AudioLevel) {
+    let mut speakers = SPEAKERS.entry(call_id.to_string()).or_default();
+    let entry = speakers
+        .levels
+        .entry(session_id.to_string())
+        .or_insert(SpeakerLevel {
+            smoothed: AudioLevel::SILENCE as f32,
+            last_voice: None,
+        });
+    entry.smoothed += (level.level as f32 - entry.smoothed) * LEVEL_SMOOTHING;
+    if level.voice {
+        entry.last_voice = Some(Instant::now());
+    }
+}
+
+/// Periodically work out who is speaking in each call, telling its members
+/// when that changes.
+pub fn spawn_detector() {
+    task::spawn(async {
+        let mut interval = time::interval(DETECT_INTERVAL);
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+        loop {
+            interval.tick().await;
+            detect();
+        }
+    });
+}
+
+fn detect() {
+    let now = Instant::now();
+    let mut changed: Vec<(String, Vec<String>)> = Vec::new();
+    SPEAKERS.retain(|call_id, speakers| {
+        let mut speaking: Vec<(&String, f32)> = speakers
+            .levels
+            .iter()
+            .filter(|(_, level)| {
+                level
+                    .last_voice
+                    .is_some_and(|at| now.duration_since(at) < SPEAKING_HOLD)
+            })
+            .map(|(session_id, level)| (session_id, level.smoothed))
+            .collect();
+        speaking.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let active: Vec<String> = speaking
+            .into_iter()
+            .take(MAX_ACTIVE_SPEAKERS)
+            .map(|(session_id, _)| session_id.clone())
+            .collect();
+        if active != speakers.active {
+            speakers.active = active.clone();
+            changed.push((call_id.clone(), active));
+        }
+        !speakers.levels.is_empty() || !speakers.active.is_empty()
+    });
+
+    for (call_id, session_ids) in changed {
+        let Some(call) = GLOBAL_CALLS.get(&call_id) else {
+            continue;
+        };
+        for member in call.members.iter() {
+            if let Some(session) = GLOBAL_SESSIONS.get(member.key()) {
+                session
+                    .message_tx
+                    .send(ControlS2C::ActiveSpeakers {
+                        session_ids: session_ids.clone(),
+                    })
+                    .ok();
+            }
+        }
+    }
+}
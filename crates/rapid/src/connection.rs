@@ -0,0 +1,13 @@
+use async_trait::async_trait;
+
+/// A trait for observing authenticated connections.
+///
+/// `on_connect` is called once a connection has identified, and
+/// `on_disconnect` once that connection is gone. Every connection that was
+/// reported as connected is reported as disconnected exactly once.
+#[async_trait]
+pub trait ConnectionListener: Send + Sync {
+    async fn on_connect(&self, client_id: &str, user_id: &str);
+
+    async fn on_disconnect(&self, client_id: &str, user_id: &str);
+}
@@ -1,4 +1,6 @@
+pub mod connection;
 pub mod errors;
 pub mod rate_limit;
+pub mod resume;
 pub mod socket;
 mod utilities;
@@ -0,0 +1,19 @@
+use async_trait::async_trait;
+use serde_cbor_2::Value;
+
+/// A trait for replaying events a client missed while it was disconnected.
+///
+/// Events emitted with [`RpcClients::emit_sequenced`] carry a number that
+/// increases with every event of a user. A client that identifies with the
+/// last number it saw is sent every later event before any new one.
+///
+/// [`RpcClients::emit_sequenced`]: crate::socket::RpcClients::emit_sequenced
+#[async_trait]
+pub trait ResumeHandler: Send + Sync {
+    /// The sequence number of the latest event of `user_id`.
+    async fn cursor(&self, user_id: &str) -> u64;
+
+    /// Every event of `user_id` after `cursor`, oldest first, or `None` if
+    /// some of them are no longer retained.
+    async fn replay(&self, user_id: &str, cursor: u64) -> Option<Vec<(u64, Value)>>;
+}
@@ -1,4 +1,9 @@
-use std::{future::Future, pin::Pin, sync::Arc};
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
 
 use async_tungstenite::{accept_async, tokio::TokioAdapter, tungstenite::Message};
 use dashmap::DashMap;
@@ -14,16 +19,19 @@ use serde_cbor_2::{
 };
 use tokio::{
     net::{TcpListener, TcpStream},
-    task,
+    sync::Semaphore,
+    task::{self, JoinSet},
     time::timeout,
 };
 use tracing::{Instrument, debug, info};
 use uuid::Uuid;
 
 use crate::{
+    connection::ConnectionListener,
     errors::Error,
     rate_limit::RateLimiter,
-    utilities::{HEARTBEAT_TIMEOUT, generate_id},
+    resume::ResumeHandler,
+    utilities::{DEFAULT_MAX_IN_FLIGHT, HEARTBEAT_TIMEOUT, generate_id},
 };
 
 const MAX_PRE_AUTH_MESSAGES: usize = 5;
@@ -97,16 +105,11 @@ pub struct RpcClient {
     socket: UnboundedSender<Message>,
     user_id: Option<String>,
     heartbeat_tx: UnboundedSender<()>,
+    // events emitted while missed events are being replayed
+    held: Arc<Mutex<Option<Vec<Vec<u8>>>>>,
 }
 
 impl RpcClient {
-    async fn send(&mut self, data: Vec<u8>) {
-        self.socket
-            .send(Message::Binary(data.into()))
-            .await
-            .expect("Failed to send message");
-    }
-
     pub fn unique_id(&self) -> &str {
         &self.id
     }
@@ -118,19 +121,47 @@ impl RpcClient {
     pub fn emit<T: Serialize + Send + Clone + 'static>(&self, data: T) {
         let bytes = serialize(&RpcMessageS2C::Event {
             event: to_value(data).expect("Failed to serialize"),
+            seq: None,
         })
         .expect("Failed to serialize");
         self.emit_raw(bytes);
     }
 
     fn emit_raw(&self, bytes: Vec<u8>) {
-        let mut socket = self.socket.clone();
-        task::spawn(async move {
-            socket
-                .send(Message::Binary(bytes.into()))
-                .await
-                .expect("Failed to send message");
-        });
+        let mut held = self.held.lock().expect("Failed to lock held events");
+        match held.as_mut() {
+            Some(held) => held.push(bytes),
+            None => self.send_raw(bytes),
+        }
+    }
+
+    fn send_raw(&self, bytes: Vec<u8>) {
+        // the socket is closed once the client is gone
+        self.socket
+            .unbounded_send(Message::Binary(bytes.into()))
+            .ok();
+    }
+
+    /// Hold back events until [`RpcClient::release`] is called.
+    fn hold(&self) {
+        let mut held = self.held.lock().expect("Failed to lock held events");
+        held.get_or_insert_with(Vec::new);
+    }
+
+    /// Send `replay`, then whatever was held back since [`RpcClient::hold`].
+    fn release(&self, replay: Vec<(u64, Value)>) {
+        let mut held = self.held.lock().expect("Failed to lock held events");
+        for (seq, event) in replay {
+            let bytes = serialize(&RpcMessageS2C::Event {
+                event,
+                seq: Some(seq),
+            })
+            .expect("Failed to serialize");
+            self.send_raw(bytes);
+        }
+        for bytes in held.take().unwrap_or_default() {
+            self.send_raw(bytes);
+        }
     }
 }
 
@@ -268,6 +299,7 @@ impl RpcClients {
     pub fn emit_all<T: Serialize + Send + Clone + 'static>(&self, data: T) {
         let bytes = serialize(&RpcMessageS2C::Event {
             event: to_value(&data).expect("Failed to serialize"),
+            seq: None,
         })
         .expect("Failed to serialize");
         for client in self.0.iter() {
@@ -282,12 +314,41 @@ impl RpcClients {
     ) {
         let bytes = serialize(&RpcMessageS2C::Event {
             event: to_value(&data).expect("Failed to serialize"),
+            seq: None,
         })
         .expect("Failed to serialize");
         for client in self.0.iter().filter(|c| filter(c.value())) {
             client.value().emit_raw(bytes.clone());
         }
     }
+
+    /// Emit to every client that `sequence` returns a number for, tagged with
+    /// that number so that the client can resume after it. See
+    /// [`ResumeHandler`].
+    pub fn emit_sequenced<
+        T: Serialize + Send + Clone + 'static,
+        F: Fn(&RpcClient) -> Option<u64>,
+    >(
+        &self,
+        data: T,
+        sequence: F,
+    ) {
+        let event = to_value(&data).expect("Failed to serialize");
+        let mut frames: HashMap<u64, Vec<u8>> = HashMap::new();
+        for client in self.0.iter() {
+            let Some(seq) = sequence(client.value()) else {
+                continue;
+            };
+            let bytes = frames.entry(seq).or_insert_with(|| {
+                serialize(&RpcMessageS2C::Event {
+                    event: event.clone(),
+                    seq: Some(seq),
+                })
+                .expect("Failed to serialize")
+            });
+            client.value().emit_raw(bytes.clone());
+        }
+    }
 }
 
 pub struct RpcState {
@@ -321,11 +382,20 @@ impl RpcState {
     }
 }
 
+/// Per-server settings every connection is handled with.
+#[derive(Clone)]
+struct ConnectionOptions {
+    connection_listener: Option<Arc<dyn ConnectionListener>>,
+    resume_handler: Option<Arc<dyn ResumeHandler>>,
+    max_in_flight: usize,
+}
+
 pub struct RpcServer {
     clients: RpcClients,
     authenticate: AuthenticateFn,
     methods: Arc<DashMap<String, Box<dyn MethodFn>>>,
     rate_limiter: Option<Arc<dyn RateLimiter>>,
+    options: ConnectionOptions,
 }
 
 impl RpcServer {
@@ -335,14 +405,37 @@ impl RpcServer {
             authenticate,
             methods: Arc::new(DashMap::new()),
             rate_limiter: None,
+            options: ConnectionOptions {
+                connection_listener: None,
+                resume_handler: None,
+                max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            },
         }
     }
 
+    /// Maximum number of method calls a single connection may have in flight.
+    /// Once reached, further calls are answered with a rate limit error until
+    /// one completes.
+    pub fn max_in_flight(mut self, limit: usize) -> Self {
+        self.options.max_in_flight = limit.max(1);
+        self
+    }
+
     pub fn rate_limiter(mut self, limiter: impl RateLimiter + 'static) -> Self {
         self.rate_limiter = Some(Arc::new(limiter));
         self
     }
 
+    pub fn connection_listener(mut self, listener: impl ConnectionListener + 'static) -> Self {
+        self.options.connection_listener = Some(Arc::new(listener));
+        self
+    }
+
+    pub fn resume_handler(mut self, handler: impl ResumeHandler + 'static) -> Self {
+        self.options.resume_handler = Some(Arc::new(handler));
+        self
+    }
+
     pub fn clients(&self) -> RpcClients {
         self.clients.clone()
     }
@@ -379,9 +472,10 @@ impl RpcServer {
             let fnc = self.authenticate.clone();
             let methods = self.methods.clone();
             let rate_limiter = self.rate_limiter.clone();
-            task::spawn(
-                async move { start_client(stream, clients, fnc, methods, rate_limiter).await },
-            );
+            let options = self.options.clone();
+            task::spawn(async move {
+                start_client(stream, clients, fnc, methods, rate_limiter, options).await
+            });
         }
     }
 }
@@ -392,6 +486,10 @@ pub enum RpcMessageC2S {
     #[serde(rename_all = "camelCase")]
     Identify {
         token: String,
+        /// The sequence number of the last event received before the
+        /// previous connection was lost.
+        #[serde(default)]
+        resume: Option<u64>,
     },
     Heartbeat {},
     Message {
@@ -406,7 +504,15 @@ pub enum RpcMessageC2S {
 pub enum RpcMessageS2C {
     #[serde(rename_all = "camelCase")]
     Hello {},
-    Identify {},
+    #[serde(rename_all = "camelCase")]
+    Identify {
+        /// Whether every missed event is replayed after this message.
+        resumed: bool,
+        /// The sequence number to resume after, if events are sequenced:
+        /// the client's own cursor when resumed, otherwise the latest one.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cursor: Option<u64>,
+    },
     Heartbeat {},
     Error {
         error: Error,
@@ -418,6 +524,8 @@ pub enum RpcMessageS2C {
     },
     Event {
         event: Value,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
     },
 }
 
@@ -427,7 +535,13 @@ async fn start_client(
     authenticate: AuthenticateFn,
     methods: Arc<DashMap<String, Box<dyn MethodFn>>>,
     rate_limiter: Option<Arc<dyn RateLimiter>>,
+    options: ConnectionOptions,
 ) {
+    let ConnectionOptions {
+        connection_listener,
+        resume_handler,
+        max_in_flight,
+    } = options;
     info!("Socket connected: {}", connection.peer_addr().unwrap());
     #[cfg(feature = "otel")]
     rpc_connections().add(1, &[]);
@@ -471,11 +585,17 @@ async fn start_client(
         socket: s,
         user_id: None,
         heartbeat_tx: tx,
+        held: Arc::new(Mutex::new(None)),
     };
     clients.0.insert(id.clone(), client);
 
     let mut is_authenticated = false;
+    // the user reported to the connection listener, if any
+    let mut connected_user: Option<String> = None;
     let mut pre_auth_count: usize = 0;
+    let in_flight = Arc::new(Semaphore::new(max_in_flight));
+    // method calls still running, aborted once the socket is gone
+    let mut calls = JoinSet::new();
 
     while let Some(data) = read.next().await {
         let Ok(data) = data else {
@@ -498,26 +618,101 @@ async fn start_client(
                     }
                 }
 
-                let response = handle_packet(
-                    bin.to_vec(),
-                    &clients,
-                    &id,
-                    authenticate.clone(),
-                    methods.clone(),
-                    &rate_limiter,
-                )
-                .await;
-
-                if matches!(&response, RpcMessageS2C::Identify {}) {
-                    is_authenticated = true;
-                }
-
-                let serialized = serialize(&response).expect("Failed to serialize");
-                debug!("Sent: {:?}", response);
-                if let Some(mut client) = clients.0.get_mut(&id) {
-                    client.send(serialized).await;
-                } else {
-                    debug!("Client {} disconnected before response could be sent", id);
+                let Ok(packet) = deserialize::<RpcMessageC2S>(&bin) else {
+                    send_response(
+                        &clients,
+                        &id,
+                        RpcMessageS2C::Error {
+                            error: Error::InvalidMethod,
+                        },
+                    )
+                    .await;
+                    continue;
+                };
+                debug!("Received: {:?}", packet);
+
+                match packet {
+                    // method calls run concurrently; the response carries the request id,
+                    // so it is written as soon as it completes regardless of order
+                    RpcMessageC2S::Message {
+                        id: request_id,
+                        method,
+                        data,
+                    } => {
+                        // refuse rather than wait for a slot, so heartbeats and identify
+                        // behind a burst of slow calls are still read in time
+                        let Ok(permit) = in_flight.clone().try_acquire_owned() else {
+                            send_response(
+                                &clients,
+                                &id,
+                                RpcMessageS2C::Message {
+                                    id: request_id,
+                                    ok: false,
+                                    data: to_value(&Error::RateLimited)
+                                        .expect("Failed to serialize"),
+                                },
+                            )
+                            .await;
+                            continue;
+                        };
+                        let clients = clients.clone();
+                        let id = id.clone();
+                        let methods = methods.clone();
+                        let rate_limiter = rate_limiter.clone();
+                        while calls.try_join_next().is_some() {}
+                        calls.spawn(async move {
+                            let response = handle_message(
+                                request_id,
+                                method,
+                                data,
+                                &clients,
+                                &id,
+                                methods,
+                                &rate_limiter,
+                            )
+                            .await;
+                            drop(permit);
+                            send_response(&clients, &id, response).await;
+                        });
+                    }
+                    // identify and heartbeat stay in order with the rest of the stream
+                    RpcMessageC2S::Identify { token, resume } => {
+                        let (response, replay) = identify(
+                            token,
+                            resume,
+                            &clients,
+                            &id,
+                            authenticate.clone(),
+                            &resume_handler,
+                        )
+                        .await;
+                        let identified = matches!(&response, RpcMessageS2C::Identify { .. });
+                        if identified {
+                            is_authenticated = true;
+                            let user_id = clients.0.get(&id).and_then(|c| c.user_id.clone());
+                            if let (Some(listener), Some(user_id)) = (&connection_listener, user_id)
+                                && connected_user.as_ref() != Some(&user_id)
+                            {
+                                // identifying again as someone else ends the previous session
+                                if let Some(previous) = connected_user.take() {
+                                    listener.on_disconnect(&id, &previous).await;
+                                }
+                                listener.on_connect(&id, &user_id).await;
+                                connected_user = Some(user_id);
+                            }
+                        }
+                        send_response(&clients, &id, response).await;
+                        if identified
+                            && resume_handler.is_some()
+                            && let Some(client) = clients.0.get(&id).map(|c| c.value().clone())
+                        {
+                            client.release(replay);
+                        }
+                    }
+                    RpcMessageC2S::Heartbeat {} => {
+                        let response = handle_heartbeat(&clients, &id).await;
+                        send_response(&clients, &id, response).await;
+                    }
                 }
             }
             Message::Close(_) => {
@@ -531,97 +726,143 @@ async fn start_client(
             }
         }
     }
+    calls.abort_all();
+    if let Some((_, mut client)) = clients.0.remove(&id) {
+        client.socket.close().await.ok();
+    }
+    if let (Some(listener), Some(user_id)) = (&connection_listener, connected_user) {
+        listener.on_disconnect(&id, &user_id).await;
+    }
     #[cfg(feature = "otel")]
     rpc_connections().add(-1, &[]);
     debug!("Connection {} closed", id);
 }
 
-pub async fn handle_packet(
-    bin: Vec<u8>,
+async fn send_response(clients: &RpcClients, id: &str, response: RpcMessageS2C) {
+    let serialized = serialize(&response).expect("Failed to serialize");
+    debug!("Sent: {:?}", response);
+    let socket = clients.0.get(id).map(|client| client.socket.clone());
+    if let Some(mut socket) = socket {
+        socket.send(Message::Binary(serialized.into())).await.ok();
+    } else {
+        debug!("Client {} disconnected before response could be sent", id);
+    }
+}
+
+pub async fn handle_heartbeat(clients: &RpcClients, user_id: &String) -> RpcMessageS2C {
+    let mut heartbeat_tx = clients.0.get(user_id).unwrap().heartbeat_tx.clone();
+    heartbeat_tx.send(()).await.unwrap();
+    RpcMessageS2C::Heartbeat {}
+}
+
+/// Authenticate a client. With a resume handler, events to the client are
+/// held back from here on; the caller sends the returned missed events and
+/// releases the held ones once the response is out.
+async fn identify(
+    token: String,
+    resume: Option<u64>,
     clients: &RpcClients,
-    user_id: &String,
+    id: &str,
     authenticate: AuthenticateFn,
+    resume_handler: &Option<Arc<dyn ResumeHandler>>,
+) -> (RpcMessageS2C, Vec<(u64, Value)>) {
+    let uid = match authenticate(token).await {
+        Ok(uid) => uid,
+        Err(error) => return (RpcMessageS2C::Error { error }, Vec::new()),
+    };
+    let Some(handler) = resume_handler else {
+        clients.0.get_mut(id).unwrap().user_id = Some(uid);
+        let response = RpcMessageS2C::Identify {
+            resumed: false,
+            cursor: None,
+        };
+        return (response, Vec::new());
+    };
+    {
+        let mut client = clients.0.get_mut(id).unwrap();
+        client.hold();
+        client.user_id = Some(uid.clone());
+    }
+    // anything emitted from here on is held back and either replayed or
+    // deduplicated by the client against the returned cursor
+    if let Some(after) = resume
+        && let Some(events) = handler.replay(&uid, after).await
+    {
+        // the replayed events follow, so the client must not skip past them
+        let response = RpcMessageS2C::Identify {
+            resumed: true,
+            cursor: Some(after),
+        };
+        return (response, events);
+    }
+    let response = RpcMessageS2C::Identify {
+        resumed: false,
+        cursor: Some(handler.cursor(&uid).await),
+    };
+    (response, Vec::new())
+}
+
+pub async fn handle_message(
+    id: String,
+    method: String,
+    data: Value,
+    clients: &RpcClients,
+    user_id: &String,
     methods: Arc<DashMap<String, Box<dyn MethodFn>>>,
     rate_limiter: &Option<Arc<dyn RateLimiter>>,
 ) -> RpcMessageS2C {
-    let result = deserialize::<RpcMessageC2S>(bin.as_slice());
-    if let Ok(r) = result {
-        debug!("Received: {:?}", r);
-        match r {
-            RpcMessageC2S::Identify { token } => authenticate(token.clone())
-                .await
-                .map(|uid| {
-                    let mut client = clients.0.get_mut(user_id).unwrap();
-                    client.user_id = Some(uid);
-                    RpcMessageS2C::Identify {}
-                })
-                .unwrap_or_else(|e| RpcMessageS2C::Error { error: e }),
-            RpcMessageC2S::Heartbeat {} => {
-                let mut client = clients.0.get_mut(user_id).unwrap();
-                client.heartbeat_tx.send(()).await.unwrap();
-                RpcMessageS2C::Heartbeat {}
-            }
-            RpcMessageC2S::Message { id, method, data } => {
-                // check if id is a uuid
-                if Uuid::try_parse(&id).is_err() {
-                    return RpcMessageS2C::Error {
-                        error: Error::InvalidRequestId,
-                    };
-                }
-
-                if let Some(rl) = rate_limiter {
-                    let client_user_id = clients.0.get(user_id).and_then(|c| c.user_id.clone());
-                    if let Some(uid) = client_user_id
-                        && !rl.check_rate_limit(&uid, &method).await
-                    {
-                        #[cfg(feature = "otel")]
-                        rpc_rate_limited().add(1, &[KeyValue::new("method", method.clone())]);
-                        return RpcMessageS2C::Message {
-                            id,
-                            ok: false,
-                            data: to_value(&Error::RateLimited).expect("Failed to serialize"),
-                        };
-                    }
-                }
+    // check if id is a uuid
+    if Uuid::try_parse(&id).is_err() {
+        return RpcMessageS2C::Error {
+            error: Error::InvalidRequestId,
+        };
+    }
 
-                let method_fn = methods.get(&method);
-                let Some(method_fn) = method_fn else {
-                    return RpcMessageS2C::Error {
-                        error: Error::InvalidMethod,
-                    };
-                };
-                #[cfg(feature = "otel")]
-                let method_attrs = [KeyValue::new("method", method.clone())];
-                #[cfg(feature = "otel")]
-                rpc_calls().add(1, &method_attrs);
-                #[cfg(feature = "otel")]
-                let start = std::time::Instant::now();
-                let span = tracing::info_span!("rpc.method", method = %method);
-                let result = method_fn(
-                    RpcState {
-                        clients: clients.clone(),
-                        id: user_id.clone(),
-                    },
-                    data,
-                )
-                .instrument(span)
-                .await;
-                #[cfg(feature = "otel")]
-                rpc_duration_ms().record(start.elapsed().as_secs_f64() * 1000.0, &method_attrs);
-                match result {
-                    RpcResponse::Success(data) => RpcMessageS2C::Message { id, ok: true, data },
-                    RpcResponse::Error(data) => RpcMessageS2C::Message {
-                        id,
-                        ok: false,
-                        data,
-                    },
-                }
-            }
+    if let Some(rl) = rate_limiter {
+        let client_user_id = clients.0.get(user_id).and_then(|c| c.user_id.clone());
+        if let Some(uid) = client_user_id
+            && !rl.check_rate_limit(&uid, &method).await
+        {
+            #[cfg(feature = "otel")]
+            rpc_rate_limited().add(1, &[KeyValue::new("method", method.clone())]);
+            return RpcMessageS2C::Message {
+                id,
+                ok: false,
+                data: to_value(&Error::RateLimited).expect("Failed to serialize"),
+            };
         }
-    } else {
-        RpcMessageS2C::Error {
+    }
+
+    let Some(method_fn) = methods.get(&method).map(|m| m.value().clone()) else {
+        return RpcMessageS2C::Error {
             error: Error::InvalidMethod,
-        }
+        };
+    };
+    #[cfg(feature = "otel")]
+    let method_attrs = [KeyValue::new("method", method.clone())];
+    #[cfg(feature = "otel")]
+    rpc_calls().add(1, &method_attrs);
+    #[cfg(feature = "otel")]
+    let start = std::time::Instant::now();
+    let span = tracing::info_span!("rpc.method", method = %method);
+    let result = method_fn(
+        RpcState {
+            clients: clients.clone(),
+            id: user_id.clone(),
+        },
+        data,
+    )
+    .instrument(span)
+    .await;
+    #[cfg(feature = "otel")]
+    rpc_duration_ms().record(start.elapsed().as_secs_f64() * 1000.0, &method_attrs);
+    match result {
+        RpcResponse::Success(data) => RpcMessageS2C::Message { id, ok: true, data },
+        RpcResponse::Error(data) => RpcMessageS2C::Message {
+            id,
+            ok: false,
+            data,
+        },
     }
 }
 
@@ -43,3 +43,4 @@ pub fn generate_id() -> String {
 }
 
 pub const HEARTBEAT_TIMEOUT: u64 = 60000;
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 32;
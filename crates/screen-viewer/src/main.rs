@@ -93,6 +93,7 @@ impl App {
                     // In production: RTP-packetize and send over network.
                     debug!("encoded {} bytes", dat.len());
                 }),
+                layers: Vec::new(),
             };
 
             let mut encoder = match create_encoder(encoder_config) {
@@ -1,5 +1,6 @@
 pub mod error;
 mod platform;
+mod simulcast;
 mod wgpu_import;
 
 use std::sync::Arc;
@@ -132,6 +133,21 @@ pub struct EncodeConfig {
     pub bitrate_bps: u32,
     pub codec: Codec,
     pub output: EncodeOutput,
+    /// Lower-quality simulcast layers encoded from the same frames, best
+    /// first. Leave empty for a single-resolution stream.
+    pub layers: Vec<EncodeLayer>,
+}
+
+/// An additional resolution of a simulcast stream.
+///
+/// Each layer is scaled from the captured frame on the GPU and encoded
+/// independently, so receivers can switch between layers at any keyframe.
+#[derive(Debug)]
+pub struct EncodeLayer {
+    pub width: u32,
+    pub height: u32,
+    pub bitrate_bps: u32,
+    pub output: EncodeOutput,
 }
 
 /// A live hardware encoding session.
@@ -158,6 +174,15 @@ pub trait EncodeSession: Send {
 }
 
 /// Creates a hardware encoder session.
+///
+/// With simulcast layers configured, the session encodes every submitted
+/// frame once per layer. [`EncodeSession::set_bitrate`] then sets the main
+/// layer's bitrate and scales the others by the same factor, and keyframe
+/// requests apply to all layers.
 pub fn create_encoder(config: EncodeConfig) -> Result<Box<dyn EncodeSession>> {
-    platform::create_encoder(config)
+    if config.layers.is_empty() {
+        platform::create_encoder(config)
+    } else {
+        simulcast::SimulcastEncoder::new(config).map(|e| Box::new(e) as _)
+    }
 }
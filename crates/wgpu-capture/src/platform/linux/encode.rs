@@ -382,7 +382,7 @@ impl SrcSurface {
     }
 }
 
-/// Converts any RGB DMA-buf to NV12.
+/// Converts any RGB DMA-buf to NV12, scaling it to the encoder's size.
 struct VaapiVpp {
     display: Arc<Display>,
     context: Rc<Context>,
@@ -437,27 +437,42 @@ impl VaapiVpp {
             drm_fourcc::DrmFourcc::Argb8888 => u32::from_le_bytes(*b"BGRA"),
             drm_fourcc::DrmFourcc::Xbgr8888 => u32::from_le_bytes(*b"RGBX"),
             drm_fourcc::DrmFourcc::Abgr8888 => u32::from_le_bytes(*b"RGBA"),
+            // NV12 only needs the VPP when scaling to a simulcast layer
+            drm_fourcc::DrmFourcc::Nv12 => u32::from_le_bytes(*b"NV12"),
             _ => u32::from_le_bytes(*b"BGRX"),
         };
+        let is_nv12 = src_drm_fourcc == drm_fourcc::DrmFourcc::Nv12;
 
         let src_surface = if stride.is_multiple_of(SURFACE_PITCH_ALIGNMENT) {
             debug!("VPP convert: zero-copy path (stride={stride}, {width}x{height})");
             let src_cros_fourcc = Fourcc::from(&va_src_fourcc.to_le_bytes());
+            let mut planes = vec![PlaneLayout {
+                buffer_index: 0,
+                offset: 0,
+                stride: stride as usize,
+            }];
+            if is_nv12 {
+                planes.push(PlaneLayout {
+                    buffer_index: 0,
+                    offset: (stride * height) as usize,
+                    stride: stride as usize,
+                });
+            }
             let src_layout = FrameLayout {
                 format: (src_cros_fourcc, 0u64),
                 size: Resolution { width, height },
-                planes: vec![PlaneLayout {
-                    buffer_index: 0,
-                    offset: 0,
-                    stride: stride as usize,
-                }],
+                planes,
             };
             let src_frame = DmabufFrame {
                 fds: vec![src_fd],
                 layout: src_layout,
             };
             let mut src_surfaces = self.display.create_surfaces(
-                VA_RT_FORMAT_RGB32,
+                if is_nv12 {
+                    libva::VA_RT_FORMAT_YUV420
+                } else {
+                    VA_RT_FORMAT_RGB32
+                },
                 Some(va_src_fourcc),
                 width,
                 height,
@@ -465,6 +480,10 @@ impl VaapiVpp {
                 vec![src_frame],
             )?;
             SrcSurface::DmaBuf(src_surfaces.remove(0))
+        } else if is_nv12 {
+            return Err(crate::Error::Import(format!(
+                "cannot scale NV12 frame with unaligned stride {stride}"
+            )));
         } else {
             debug!(
                 "VPP convert: aligned-upload path (stride={stride} not aligned to {SURFACE_PITCH_ALIGNMENT})"
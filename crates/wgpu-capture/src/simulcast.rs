@@ -0,0 +1,89 @@
+use crate::{CaptureFrame, EncodeConfig, EncodeSession, Result, platform};
+
+struct Layer {
+    session: Box<dyn EncodeSession>,
+    bitrate_bps: u32,
+}
+
+/// Runs one platform encoder per simulcast layer. The platform encoders
+/// already scale frames to their configured size during color conversion.
+pub(crate) struct SimulcastEncoder {
+    layers: Vec<Layer>,
+}
+
+impl SimulcastEncoder {
+    pub(crate) fn new(mut config: EncodeConfig) -> Result<Self> {
+        let extra = std::mem::take(&mut config.layers);
+        let (fps, codec) = (config.fps, config.codec);
+
+        let mut layers = Vec::with_capacity(extra.len() + 1);
+        let bitrate_bps = config.bitrate_bps;
+        layers.push(Layer {
+            session: platform::create_encoder(config)?,
+            bitrate_bps,
+        });
+        for layer in extra {
+            let session = platform::create_encoder(EncodeConfig {
+                width: layer.width,
+                height: layer.height,
+                fps,
+                bitrate_bps: layer.bitrate_bps,
+                codec,
+                output: layer.output,
+                layers: Vec::new(),
+            });
+            match session {
+                Ok(session) => layers.push(Layer {
+                    session,
+                    bitrate_bps: layer.bitrate_bps,
+                }),
+                Err(e) => {
+                    for layer in layers {
+                        layer.session.finish().ok();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(SimulcastEncoder { layers })
+    }
+}
+
+impl EncodeSession for SimulcastEncoder {
+    fn submit_frame(&mut self, frame: &CaptureFrame) -> Result<()> {
+        for layer in &mut self.layers {
+            layer.session.submit_frame(frame)?;
+        }
+        Ok(())
+    }
+
+    fn set_bitrate(&mut self, bitrate_bps: u32) -> Result<()> {
+        let base = self.layers[0].bitrate_bps.max(1) as f64;
+        let factor = bitrate_bps as f64 / base;
+        for layer in &mut self.layers {
+            let scaled = (layer.bitrate_bps as f64 * factor).round() as u32;
+            layer.session.set_bitrate(scaled.max(1))?;
+        }
+        Ok(())
+    }
+
+    fn request_keyframe(&mut self) -> Result<()> {
+        for layer in &mut self.layers {
+            layer.session.request_keyframe()?;
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        let mut result = Ok(());
+        for layer in self.layers {
+            if let Err(e) = layer.session.finish()
+                && result.is_ok()
+            {
+                result = Err(e);
+            }
+        }
+        result
+    }
+}